@@ -0,0 +1,98 @@
+//! Built-in actions, commands starting with `@` run in-process instead of through `sh -c`
+
+use miette::{miette, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// `$x`, or `${x}` when followed by other characters as in `${w}x${h}`
+static REGEX_REGION_VAR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$(?:\{(x|y|w|h|geometry)\}|(x|y|w|h|geometry)\b)").unwrap());
+
+pub fn run(action: &str) -> Result<()> {
+    let (name, arg) = action
+        .split_once(char::is_whitespace)
+        .map_or((action, ""), |(name, arg)| (name, arg.trim()));
+
+    match name {
+        "macro" => crate::macros::play(arg),
+        "zoom" => Err(miette!(
+            "`@zoom` only works as the update action of a pinch gesture"
+        )),
+        "screenshot" => Err(miette!(
+            "`@screenshot` only works as the end action of a swipe with direction=\"any\""
+        )),
+        "power-profile" => {
+            let profile =
+                crate::conditions::set_power_profile(arg).map_err(|e| miette!("{}", e))?;
+            log::info!("Switched power profile to {}", profile);
+            Ok(())
+        }
+        "snap" => {
+            let target = arg.parse().map_err(|e| miette!("{}", e))?;
+            crate::mouse_handler::snap(target).map_err(|e| miette!("{}", e))
+        }
+        _ => Err(miette!("Unknown built-in action `@{}`", name)),
+    }
+}
+
+/// Screen region selected by a `@screenshot` drag, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Region {
+    /// Rectangle spanned by the pointer moving `moved` pixels from `start`
+    pub fn from_drag(start: (i32, i32), moved: (i32, i32)) -> Self {
+        let end = (start.0 + moved.0, start.1 + moved.1);
+        Self {
+            x: start.0.min(end.0),
+            y: start.1.min(end.1),
+            w: moved.0.abs(),
+            h: moved.1.abs(),
+        }
+    }
+}
+
+pub fn is_screenshot(command: &str) -> bool {
+    let command = command.trim_start();
+    command == "@screenshot" || command.starts_with("@screenshot ")
+}
+
+/// Tool command of a `@screenshot <command>` action with `$x $y $w $h` (or `${w}`) and `$geometry`
+/// (`X,Y WxH` as taken by `grim -g`) substituted
+pub fn screenshot_command(command: &str, region: Region) -> Option<String> {
+    let tool = command.trim_start().strip_prefix("@screenshot")?.trim();
+    if tool.is_empty() {
+        return None;
+    }
+    let command = REGEX_REGION_VAR.replace_all(tool, |caps: &regex::Captures| {
+        match caps
+            .get(1)
+            .or_else(|| caps.get(2))
+            .map_or("", |m| m.as_str())
+        {
+            "x" => region.x.to_string(),
+            "y" => region.y.to_string(),
+            "w" => region.w.to_string(),
+            "h" => region.h.to_string(),
+            _ => format!("{},{} {}x{}", region.x, region.y, region.w, region.h),
+        }
+    });
+    Some(command.into_owned())
+}
+
+/// Sensitivity of a `@zoom [sensitivity]` pinch update action, `None` for any other command
+pub fn zoom_sensitivity(command: &str) -> Option<f64> {
+    let arg = command.trim().strip_prefix("@zoom")?;
+    if arg.is_empty() {
+        return Some(1.0);
+    }
+    if !arg.starts_with(char::is_whitespace) {
+        return None;
+    }
+    arg.trim().parse().ok()
+}
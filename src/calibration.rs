@@ -0,0 +1,272 @@
+//! Per-device scaling of swipe deltas and pinch scales, measured by `gestures calibrate`
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use input::event::gesture::{
+    GestureEndEvent, GestureEventCoordinates, GesturePinchEvent, GesturePinchEventTrait,
+    GestureSwipeEvent,
+};
+use input::event::{Event, EventTrait, GestureEvent};
+use knuffel::Decode;
+use miette::{miette, Result};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use crate::event_handler::Interface;
+use crate::gestures::Float;
+use crate::platform::DEFAULT_SEAT;
+
+/// Total distance of a deliberate swipe on a typical touchpad, in libinput's normalized units
+const REFERENCE_SWIPE_DISTANCE: f64 = 250.0;
+/// `|ln(scale)|` at the end of a deliberate pinch, roughly a 1.8x scale change
+const REFERENCE_PINCH_LOG_SCALE: f64 = 0.6;
+/// Factors are clamped so one sloppy calibration can't make a pad unusable
+const FACTOR_RANGE: (f64, f64) = (0.25, 4.0);
+
+/// Contents of the calibration state file
+#[derive(Decode, Debug, Clone, Default, PartialEq)]
+pub struct Calibration {
+    #[knuffel(children(name = "device"))]
+    pub devices: Vec<DeviceCalibration>,
+}
+
+/// `device "<libinput name>" swipe-scale=1.2 pinch-scale=0.8`
+#[derive(Decode, Debug, Clone, PartialEq)]
+pub struct DeviceCalibration {
+    #[knuffel(argument)]
+    pub name: String,
+    #[knuffel(property, default = Float(1.0))]
+    pub swipe_scale: Float,
+    #[knuffel(property, default = Float(1.0))]
+    pub pinch_scale: Float,
+}
+
+impl DeviceCalibration {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            swipe_scale: Float(1.0),
+            pinch_scale: Float(1.0),
+        }
+    }
+
+    pub fn scale_delta(&self, dx: f64, dy: f64) -> (f64, f64) {
+        (dx * self.swipe_scale.0, dy * self.swipe_scale.0)
+    }
+
+    /// Pinch scales are adjusted in the log domain so an unchanged pinch stays at 1.0
+    pub fn scale_pinch(&self, scale: f64) -> f64 {
+        if scale > 0.0 {
+            scale.powf(self.pinch_scale.0)
+        } else {
+            scale
+        }
+    }
+}
+
+impl Calibration {
+    pub fn device(&self, name: &str) -> Option<&DeviceCalibration> {
+        self.devices.iter().find(|d| d.name == name)
+    }
+
+    fn device_mut(&mut self, name: &str) -> &mut DeviceCalibration {
+        if let Some(i) = self.devices.iter().position(|d| d.name == name) {
+            return &mut self.devices[i];
+        }
+        self.devices.push(DeviceCalibration::new(name));
+        self.devices.last_mut().unwrap()
+    }
+
+    pub fn to_kdl(&self) -> String {
+        let mut text = String::from("// Written by `gestures calibrate`\n");
+        for d in &self.devices {
+            text.push_str(&format!(
+                "device \"{}\" swipe-scale={:.3} pinch-scale={:.3}\n",
+                d.name.replace('\\', "\\\\").replace('"', "\\\""),
+                d.swipe_scale.0,
+                d.pinch_scale.0
+            ));
+        }
+        text
+    }
+
+    /// Stored calibration, empty when none was recorded yet or the file is unreadable
+    pub fn load() -> Self {
+        let Ok(path) = path() else {
+            return Self::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        knuffel::parse(&path.to_string_lossy(), &text).unwrap_or_else(|e| {
+            log::warn!("Ignoring calibration {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    fn save(&self) -> Result<PathBuf> {
+        let path = path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| miette!("Failed to create directory {}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, self.to_kdl())
+            .map_err(|e| miette!("Failed to write calibration {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+/// `$XDG_STATE_HOME/gestures/calibration.kdl`, falling back to `~/.local/state`
+pub fn path() -> Result<PathBuf> {
+    let state_home = match env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var("HOME").map_err(|_| {
+                miette!("Both XDG_STATE_HOME and HOME environment variables are unset")
+            })?;
+            PathBuf::from(home).join(".local/state")
+        }
+    };
+    Ok(state_home.join("gestures/calibration.kdl"))
+}
+
+/// Factor that brings the median of `samples` to `reference`
+pub fn factor(samples: &[f64], reference: f64) -> Option<f64> {
+    let mut sorted: Vec<f64> = samples.iter().copied().filter(|s| *s > 0.0).collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    Some((reference / median).clamp(FACTOR_RANGE.0, FACTOR_RANGE.1))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Swipe,
+    Pinch,
+}
+
+/// Collect `samples` gestures of one kind, keyed by device name
+fn measure(
+    input: &mut input::Libinput,
+    phase: Phase,
+    samples: usize,
+    timeout: Duration,
+) -> Result<HashMap<String, Vec<f64>>> {
+    let deadline = Instant::now() + timeout;
+    let mut measured: HashMap<String, Vec<f64>> = HashMap::new();
+    let mut current = 0.0;
+
+    while Instant::now() < deadline && !crate::SHUTDOWN.load(Ordering::Relaxed) {
+        let mut fds = [PollFd::new(input.as_fd(), PollFlags::POLLIN)];
+        if let Err(e) = poll(&mut fds, PollTimeout::from(100u16)) {
+            if e != nix::errno::Errno::EINTR {
+                return Err(miette!("Poll error: {}", e));
+            }
+        }
+        input
+            .dispatch()
+            .map_err(|e| miette!("Failed to dispatch input events: {}", e))?;
+
+        for event in &mut *input {
+            let Event::Gesture(event) = event else {
+                continue;
+            };
+            let device = event.device().name().to_string();
+            let done = match (phase, event) {
+                (Phase::Swipe, GestureEvent::Swipe(GestureSwipeEvent::Begin(_)))
+                | (Phase::Pinch, GestureEvent::Pinch(GesturePinchEvent::Begin(_))) => {
+                    current = 0.0;
+                    None
+                }
+                (Phase::Swipe, GestureEvent::Swipe(GestureSwipeEvent::Update(e))) => {
+                    current += e.dx().hypot(e.dy());
+                    None
+                }
+                (Phase::Pinch, GestureEvent::Pinch(GesturePinchEvent::Update(e))) => {
+                    current = e.scale().max(f64::MIN_POSITIVE).ln().abs();
+                    None
+                }
+                (Phase::Swipe, GestureEvent::Swipe(GestureSwipeEvent::End(e)))
+                    if !e.cancelled() =>
+                {
+                    Some(current)
+                }
+                (Phase::Pinch, GestureEvent::Pinch(GesturePinchEvent::End(e)))
+                    if !e.cancelled() =>
+                {
+                    Some(current)
+                }
+                _ => None,
+            };
+            if let Some(value) = done {
+                let values = measured.entry(device.clone()).or_default();
+                values.push(value);
+                println!("  {} {}/{}", device, values.len(), samples);
+                if values.len() >= samples {
+                    return Ok(measured);
+                }
+            }
+        }
+    }
+    Ok(measured)
+}
+
+/// Guide the user through swipes and pinches and store the resulting factors
+pub fn run(samples: usize, timeout: Duration) -> Result<()> {
+    let mut input = input::Libinput::new_with_udev(Interface);
+    input
+        .udev_assign_seat(DEFAULT_SEAT)
+        .map_err(|_| miette!("Could not initialize libinput"))?;
+
+    let mut calibration = Calibration::load();
+    let mut updated = false;
+
+    for (phase, prompt, reference) in [
+        (
+            Phase::Swipe,
+            "Swipe with three or four fingers across most of the touchpad",
+            REFERENCE_SWIPE_DISTANCE,
+        ),
+        (
+            Phase::Pinch,
+            "Pinch in or out with two fingers as for zooming a page",
+            REFERENCE_PINCH_LOG_SCALE,
+        ),
+    ] {
+        println!("{} ({} times)", prompt, samples);
+        for (device, values) in measure(&mut input, phase, samples, timeout)? {
+            let Some(factor) = factor(&values, reference) else {
+                continue;
+            };
+            let entry = calibration.device_mut(&device);
+            match phase {
+                Phase::Swipe => entry.swipe_scale = Float(factor),
+                Phase::Pinch => entry.pinch_scale = Float(factor),
+            }
+            updated = true;
+        }
+    }
+
+    if !updated {
+        return Err(miette!("No gestures measured, calibration unchanged"));
+    }
+    let path = calibration.save()?;
+    for d in &calibration.devices {
+        println!(
+            "✓ {}: swipe-scale={:.3} pinch-scale={:.3}",
+            d.name, d.swipe_scale.0, d.pinch_scale.0
+        );
+    }
+    println!(
+        "Saved to {}, restart the daemon to apply it",
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! `gestures check`: parse errors, conflicting rules and unknown variables of the config files,
+//! without a running daemon
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use miette::{miette, LabeledSpan, NamedSource, Report, Result, Severity};
+
+use crate::config::Config;
+use crate::gestures::Gesture;
+use crate::lsp;
+use crate::table;
+use crate::utils::{referenced_vars, COMMAND_VARIABLES};
+use crate::watch;
+
+/// Problems found in one file
+#[derive(Debug, Default)]
+pub struct Findings {
+    pub errors: Vec<Report>,
+    pub warnings: Vec<Report>,
+}
+
+pub fn run(config_path: Option<&Path>, table: bool) -> Result<()> {
+    if let Some(path) = config_path {
+        if !path.exists() {
+            return Err(miette!("Config file {} does not exist", path.display()));
+        }
+    }
+    let files: Vec<PathBuf> = watch::config_files(config_path)
+        .into_iter()
+        .filter(|f| f.exists())
+        .collect();
+    if files.is_empty() {
+        return Err(miette!("Could not find config file"));
+    }
+
+    let (mut errors, mut warnings) = (0, 0);
+    for file in &files {
+        let text = fs::read_to_string(file)
+            .map_err(|e| miette!("Could not read {}: {}", file.display(), e))?;
+        if table {
+            println!("# {}", file.display());
+            print!("{}", table::gesture_table(&file.to_string_lossy(), &text)?);
+            continue;
+        }
+        let findings = check(&file.to_string_lossy(), &text);
+        for report in findings.errors.iter().chain(&findings.warnings) {
+            eprintln!("{:?}", report);
+        }
+        errors += findings.errors.len();
+        warnings += findings.warnings.len();
+        println!("checked {}", file.display());
+    }
+    if table {
+        return Ok(());
+    }
+
+    println!("{} error(s), {} warning(s)", errors, warnings);
+    if errors > 0 {
+        return Err(miette!("The configuration has errors"));
+    }
+    Ok(())
+}
+
+/// Parse errors of `text`, or the rules defined twice and unknown `$variables` of its config
+pub fn check(name: &str, text: &str) -> Findings {
+    let parse_errors = lsp::diagnostics(text);
+    if !parse_errors.is_empty() {
+        let errors = parse_errors
+            .into_iter()
+            .map(|(start, end, message)| {
+                miette!(
+                    labels = vec![LabeledSpan::at(start..end, "here")],
+                    "{}",
+                    message
+                )
+                .with_source_code(NamedSource::new(name, text.to_string()))
+            })
+            .collect();
+        return Findings {
+            errors,
+            warnings: Vec::new(),
+        };
+    }
+    let mut config: Config = match knuffel::parse(name, text) {
+        Ok(config) => config,
+        Err(e) => {
+            return Findings {
+                errors: vec![miette!("{}", e)],
+                warnings: Vec::new(),
+            }
+        }
+    };
+    config.flatten_groups();
+
+    let mut findings = Findings::default();
+    if let Err(e) = config.validate() {
+        findings.errors.push(miette!("{}: {}", name, e));
+    }
+    let mut blocks = vec![(String::from("top level"), &config.gestures)];
+    blocks.extend(
+        config
+            .profiles
+            .iter()
+            .map(|p| (format!("profile \"{}\"", p.name), &p.gestures)),
+    );
+    blocks.extend(
+        config
+            .devices
+            .iter()
+            .map(|d| (format!("device \"{}\"", d.name), &d.gestures)),
+    );
+    for (block, gestures) in &blocks {
+        for (i, a) in gestures.iter().enumerate() {
+            if let Some(b) = gestures[..i].iter().find(|b| conflicts(a, b)) {
+                findings.errors.push(miette!(
+                    help = "both rules fire for the same gesture, merge them or set different conditions",
+                    "{}: {} is defined twice in {}",
+                    name,
+                    describe(b),
+                    block
+                ));
+            }
+        }
+    }
+
+    let user_vars: HashSet<&str> = config
+        .vars
+        .entries
+        .iter()
+        .chain(config.profiles.iter().flat_map(|p| &p.vars.entries))
+        .map(|v| v.name.as_str())
+        .collect();
+    let mut reported = HashSet::new();
+    for gesture in blocks.iter().flat_map(|(_, gestures)| gestures.iter()) {
+        for var in gesture.commands().into_iter().flat_map(referenced_vars) {
+            if COMMAND_VARIABLES.contains(&var)
+                || user_vars.contains(var)
+                || env::var_os(var).is_some()
+                || !reported.insert(var.to_string())
+            {
+                continue;
+            }
+            findings.warnings.push(miette!(
+                severity = Severity::Warning,
+                help = "define it in `vars`, or set it in the environment the daemon runs in",
+                "{}: unknown variable `${}` in a command of {}",
+                name,
+                var,
+                describe(gesture)
+            ));
+        }
+    }
+    findings
+}
+
+/// Same trigger and conditions, so both rules fire together. Hold rules firing on release and
+/// after a timeout are distinct.
+fn conflicts(a: &Gesture, b: &Gesture) -> bool {
+    if let (Gesture::Hold(a), Gesture::Hold(b)) = (a, b) {
+        if a.trigger() != b.trigger() || a.duration != b.duration {
+            return false;
+        }
+    }
+    a.same_trigger(b) && a.conditions() == b.conditions()
+}
+
+fn describe(gesture: &Gesture) -> String {
+    match gesture {
+        Gesture::Swipe(s) => format!(
+            "`swipe fingers={} direction={}`",
+            s.fingers,
+            format!("{:?}", s.direction).to_lowercase()
+        ),
+        Gesture::Pinch(p) => format!(
+            "`pinch fingers={} direction={}`",
+            p.fingers,
+            format!("{:?}", p.direction).to_lowercase()
+        ),
+        Gesture::Hold(h) => format!("`hold fingers={}`", h.fingers),
+        Gesture::Rotate(r) => format!(
+            "`rotate fingers={} direction={}`",
+            r.fingers,
+            r.direction.name()
+        ),
+        Gesture::None => "an empty rule".to_string(),
+    }
+}
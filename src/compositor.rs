@@ -0,0 +1,447 @@
+//! Display server, compositor and Wayland global probing at startup, choosing the output backend
+
+use std::env;
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::focus::FocusProvider;
+use crate::json::Json;
+use crate::mouse_handler::ydotool_available;
+use crate::platform::runtime_dir;
+use crate::uinput;
+
+pub const VIRTUAL_POINTER_MANAGER: &str = "zwlr_virtual_pointer_manager_v1";
+pub const FOREIGN_TOPLEVEL_MANAGER: &str = "zwlr_foreign_toplevel_manager_v1";
+
+/// Wayland globals relevant to gestures, reported in `gestures status`
+const INTERESTING_GLOBALS: [&str; 5] = [
+    VIRTUAL_POINTER_MANAGER,
+    FOREIGN_TOPLEVEL_MANAGER,
+    "zwlr_layer_shell_v1",
+    "ext_idle_notifier_v1",
+    "zwp_idle_inhibit_manager_v1",
+];
+
+const WAYLAND_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// wl_display is always object 1, the registry and sync callback get the next ids
+const WL_DISPLAY: u32 = 1;
+pub const WL_REGISTRY: u32 = 2;
+const WL_CALLBACK: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayServer {
+    X11,
+    Wayland,
+}
+
+/// How pointer events (drag, zoom) are emitted
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// libxdo on the X server
+    Xdo,
+    /// zwlr_virtual_pointer_v1 spoken directly to wlroots based compositors
+    #[value(name = "wlr-virtual-pointer")]
+    VirtualPointer,
+    /// ydotool through uinput, works under any Wayland compositor
+    Ydotool,
+    /// A virtual mouse created on /dev/uinput by gestures itself, works everywhere
+    Uinput,
+    /// Nothing usable, commands still run but drag/zoom are dropped
+    None,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Xdo => "xdo",
+            Self::VirtualPointer => "wlr-virtual-pointer",
+            Self::Ydotool => "ydotool",
+            Self::Uinput => "uinput",
+            Self::None => "none",
+        })
+    }
+}
+
+/// Compositor and its IPC socket, when it has one we know of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compositor {
+    pub name: String,
+    pub ipc_socket: Option<PathBuf>,
+}
+
+/// Everything chosen at startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stack {
+    pub display_server: DisplayServer,
+    pub compositor: Option<Compositor>,
+    /// Interesting globals the compositor advertises, `None` when the probe failed
+    pub wayland_globals: Option<Vec<String>>,
+    pub backend: Backend,
+    /// Where `app=` and `window-*` conditions learn the focused window from
+    pub focus: FocusProvider,
+}
+
+static STACK: OnceLock<Stack> = OnceLock::new();
+
+impl Stack {
+    /// Probe the session, `forced` comes from `--wayland`/`--x11` and `backend` from `--backend`
+    pub fn detect(forced: Option<DisplayServer>, backend: Option<Backend>) -> Self {
+        let display_server = forced.unwrap_or_else(detect_display_server);
+        let wayland_globals = match display_server {
+            DisplayServer::Wayland => match probe_wayland_globals() {
+                Ok(globals) => Some(globals),
+                Err(e) => {
+                    log::warn!("Could not list Wayland globals: {}", e);
+                    None
+                }
+            },
+            DisplayServer::X11 => None,
+        };
+        let virtual_pointer = cfg!(feature = "wlr")
+            && wayland_globals
+                .as_ref()
+                .is_some_and(|g| g.iter().any(|g| g == VIRTUAL_POINTER_MANAGER));
+        let backend = backend.unwrap_or_else(|| {
+            select_backend(
+                display_server,
+                Available {
+                    virtual_pointer,
+                    ydotool: cfg!(feature = "ydotool") && ydotool_available(),
+                    uinput: cfg!(feature = "uinput") && uinput::available(),
+                },
+            )
+        });
+        let compositor = detect_compositor();
+        let focus = FocusProvider::select(
+            compositor.as_ref().map(|c| c.name.as_str()),
+            display_server == DisplayServer::X11,
+            wayland_globals.as_deref(),
+        );
+        Self {
+            display_server,
+            compositor,
+            wayland_globals,
+            backend,
+            focus,
+        }
+    }
+
+    /// Remember the stack for `gestures status`
+    pub fn publish(self) -> &'static Stack {
+        STACK.get_or_init(|| self)
+    }
+
+    pub fn current() -> Option<&'static Stack> {
+        STACK.get()
+    }
+
+    fn server_name(&self) -> &'static str {
+        match self.display_server {
+            DisplayServer::X11 => "x11",
+            DisplayServer::Wayland => "wayland",
+        }
+    }
+
+    /// Same fields as the `Display` form, for `--json`
+    pub fn to_json(&self) -> Json {
+        let compositor = self.compositor.as_ref();
+        Json::object([
+            ("display_server", Json::from(self.server_name())),
+            ("backend", Json::from(self.backend.to_string())),
+            ("focus", Json::from(self.focus.to_string())),
+            (
+                "compositor",
+                compositor.map_or(Json::Null, |c| Json::from(c.name.as_str())),
+            ),
+            (
+                "ipc_socket",
+                compositor
+                    .and_then(|c| c.ipc_socket.as_ref())
+                    .map_or(Json::Null, |s| Json::from(s.display().to_string())),
+            ),
+            (
+                "wayland_globals",
+                self.wayland_globals.as_ref().map_or(Json::Null, |g| {
+                    Json::from(g.iter().map(|g| Json::from(g.as_str())).collect::<Vec<_>>())
+                }),
+            ),
+        ])
+    }
+}
+
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} backend={} focus={}",
+            self.server_name(),
+            self.backend,
+            self.focus
+        )?;
+        if let Some(c) = &self.compositor {
+            write!(f, " compositor={}", c.name)?;
+            if let Some(socket) = &c.ipc_socket {
+                write!(f, " ipc={}", socket.display())?;
+            }
+        }
+        if let Some(globals) = &self.wayland_globals {
+            write!(f, " globals=[{}]", globals.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// WAYLAND_DISPLAY first (most reliable indicator), then XDG_SESSION_TYPE, defaulting to X11
+fn detect_display_server() -> DisplayServer {
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        return DisplayServer::Wayland;
+    }
+    match env::var("XDG_SESSION_TYPE") {
+        Ok(t) if t.eq_ignore_ascii_case("wayland") => DisplayServer::Wayland,
+        _ => DisplayServer::X11,
+    }
+}
+
+/// Backends usable in this session besides libxdo
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Available {
+    /// The compositor offers the wlr virtual pointer
+    pub virtual_pointer: bool,
+    pub ydotool: bool,
+    /// /dev/uinput is writable
+    pub uinput: bool,
+}
+
+/// The wlr virtual pointer is preferred over ydotool as it needs no daemon, uinput is the last resort
+pub fn select_backend(display_server: DisplayServer, available: Available) -> Backend {
+    match display_server {
+        DisplayServer::X11 if cfg!(feature = "x11") => Backend::Xdo,
+        DisplayServer::Wayland if available.virtual_pointer => Backend::VirtualPointer,
+        _ if available.ydotool => Backend::Ydotool,
+        _ if available.uinput => Backend::Uinput,
+        _ => Backend::None,
+    }
+}
+
+fn existing(path: PathBuf) -> Option<PathBuf> {
+    path.exists().then_some(path)
+}
+
+/// Request socket of the Hyprland instance in `HYPRLAND_INSTANCE_SIGNATURE`, in the runtime dir
+/// or where Hyprland before 0.40 put it
+pub fn hyprland_socket(signature: &str) -> Option<PathBuf> {
+    runtime_dir()
+        .ok()
+        .map(|dir| dir.join("hypr").join(signature).join(".socket.sock"))
+        .and_then(existing)
+        .or_else(|| existing(PathBuf::from(format!("/tmp/hypr/{signature}/.socket.sock"))))
+}
+
+/// Compositors are recognized by the IPC socket variables they export
+fn detect_compositor() -> Option<Compositor> {
+    if let Ok(signature) = env::var("HYPRLAND_INSTANCE_SIGNATURE") {
+        return Some(Compositor {
+            name: "hyprland".to_string(),
+            ipc_socket: hyprland_socket(&signature),
+        });
+    }
+    for (var, name) in [("SWAYSOCK", "sway"), ("NIRI_SOCKET", "niri")] {
+        if let Some(socket) = env::var_os(var) {
+            return Some(Compositor {
+                name: name.to_string(),
+                ipc_socket: existing(PathBuf::from(socket)),
+            });
+        }
+    }
+    let desktop = env::var("XDG_CURRENT_DESKTOP").ok()?;
+    let name = desktop.split(':').next()?.trim().to_lowercase();
+    (!name.is_empty()).then_some(Compositor {
+        name,
+        ipc_socket: None,
+    })
+}
+
+fn wayland_socket() -> Result<PathBuf, String> {
+    let display = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string());
+    let path = PathBuf::from(&display);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    runtime_dir()
+        .map(|dir| dir.join(display))
+        .map_err(|e| e.to_string())
+}
+
+/// A request or event: object id, size and opcode, then the already encoded arguments
+pub fn message(object: u32, opcode: u32, args: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(8 + args.len());
+    msg.extend_from_slice(&object.to_ne_bytes());
+    msg.extend_from_slice(&(((8 + args.len() as u32) << 16) | opcode).to_ne_bytes());
+    msg.extend_from_slice(args);
+    msg
+}
+
+/// Wire string: length including the NUL, then the bytes padded to 32 bits
+pub fn wire_string(s: &str) -> Vec<u8> {
+    let mut encoded = (s.len() as u32 + 1).to_ne_bytes().to_vec();
+    encoded.extend_from_slice(s.as_bytes());
+    encoded.resize(4 + (s.len() + 1).next_multiple_of(4), 0);
+    encoded
+}
+
+/// One `wl_registry.global` announcement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+    pub name: u32,
+    pub interface: String,
+    pub version: u32,
+}
+
+/// Connect to the compositor and list its globals: `wl_display.get_registry`, then a roundtrip
+pub fn connect_registry() -> Result<(UnixStream, Vec<Global>), String> {
+    let path = wayland_socket()?;
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| format!("cannot connect to {}: {}", path.display(), e))?;
+    stream
+        .set_read_timeout(Some(WAYLAND_PROBE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(&message(WL_DISPLAY, 1, &WL_REGISTRY.to_ne_bytes()))
+        .map_err(|e| e.to_string())?;
+
+    let mut globals = Vec::new();
+    roundtrip(&mut stream, WL_CALLBACK, |object, opcode, payload| {
+        if (object, opcode) == (WL_REGISTRY, 0) {
+            globals.extend(parse_global(payload));
+        }
+    })?;
+    Ok((stream, globals))
+}
+
+/// Send `wl_display.sync` with the new id `callback` and read events until it is done
+pub fn roundtrip(
+    stream: &mut UnixStream,
+    callback: u32,
+    mut on_event: impl FnMut(u32, u32, &[u8]),
+) -> Result<(), String> {
+    stream
+        .write_all(&message(WL_DISPLAY, 0, &callback.to_ne_bytes()))
+        .map_err(|e| e.to_string())?;
+    read_events(stream, callback, &mut on_event)
+}
+
+/// Pass every event to `on_event` until the connection fails or the compositor closes it
+pub fn dispatch(
+    stream: &mut UnixStream,
+    mut on_event: impl FnMut(u32, u32, &[u8]),
+) -> Result<(), String> {
+    stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+    // Object 0 is the null object, no callback ever completes
+    read_events(stream, 0, &mut on_event)
+}
+
+fn read_events(
+    stream: &mut UnixStream,
+    callback: u32,
+    on_event: &mut impl FnMut(u32, u32, &[u8]),
+) -> Result<(), String> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("compositor closed the connection".to_string());
+        }
+        received.extend_from_slice(&buf[..n]);
+        let (consumed, done) = scan_events(&received, callback, on_event)?;
+        if done {
+            return Ok(());
+        }
+        received.drain(..consumed);
+    }
+}
+
+/// Ask the compositor for the globals gestures cares about
+fn probe_wayland_globals() -> Result<Vec<String>, String> {
+    let (_, globals) = connect_registry()?;
+    Ok(globals
+        .into_iter()
+        .map(|g| g.interface)
+        .filter(|g| INTERESTING_GLOBALS.contains(&g.as_str()))
+        .collect())
+}
+
+pub fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// String argument at `at`: length including the NUL, then the padded bytes
+pub fn read_string(bytes: &[u8], at: usize) -> Option<String> {
+    let len = read_u32(bytes, at)? as usize;
+    let string = bytes.get(at + 4..at + 4 + len.saturating_sub(1))?;
+    Some(String::from_utf8_lossy(string).into_owned())
+}
+
+/// name: uint, interface: string, version: uint
+fn parse_global(payload: &[u8]) -> Option<Global> {
+    let len = read_u32(payload, 4)? as usize;
+    Some(Global {
+        name: read_u32(payload, 0)?,
+        interface: read_string(payload, 4)?,
+        version: read_u32(payload, 8 + len.next_multiple_of(4))?,
+    })
+}
+
+/// Pass every complete event to `on_event` until the `wl_callback.done` of `callback`.
+/// Returns the bytes consumed and whether the callback is done.
+fn scan_events(
+    events: &[u8],
+    callback: u32,
+    on_event: &mut impl FnMut(u32, u32, &[u8]),
+) -> Result<(usize, bool), String> {
+    let mut at = 0;
+    while let (Some(object), Some(header)) = (read_u32(events, at), read_u32(events, at + 4)) {
+        let size = (header >> 16) as usize;
+        let opcode = header & 0xffff;
+        if size < 8 {
+            return Err("malformed Wayland message".to_string());
+        }
+        let Some(payload) = events.get(at + 8..at + size) else {
+            break;
+        };
+        at += size;
+        match (object, opcode) {
+            (WL_DISPLAY, 0) => {
+                // object_id: object, code: uint, message: string
+                let len = read_u32(payload, 8).unwrap_or(0) as usize;
+                let text = payload
+                    .get(12..12 + len.saturating_sub(1))
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                return Err(format!("compositor reported a protocol error: {}", text));
+            }
+            (object, 0) if object == callback => return Ok((at, true)),
+            _ => on_event(object, opcode, payload),
+        }
+    }
+    Ok((at, false))
+}
+
+/// Interfaces announced in the wire events received so far, `None` until the sync callback is done
+#[cfg(test)]
+pub fn registry_globals(events: &[u8]) -> Result<Option<Vec<String>>, String> {
+    let mut globals = Vec::new();
+    let (_, done) = scan_events(events, WL_CALLBACK, &mut |object, opcode, payload| {
+        if (object, opcode) == (WL_REGISTRY, 0) {
+            globals.extend(parse_global(payload).map(|g| g.interface));
+        }
+    })?;
+    Ok(done.then_some(globals))
+}
@@ -0,0 +1,613 @@
+//! Per-gesture conditions, rules whose conditions don't hold are left out of the gesture cache
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use knuffel::{Decode, DecodeScalar};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::{Regex, RegexBuilder};
+
+/// How long a queried power profile is reused before asking the daemon again
+const POWER_PROFILE_TTL: Duration = Duration::from_secs(5);
+
+/// D-Bus names of power-profiles-daemon, newer releases moved to the UPower namespace
+const POWER_PROFILES_BUSES: [(&str, &str); 2] = [
+    (
+        "org.freedesktop.UPower.PowerProfiles",
+        "/org/freedesktop/UPower/PowerProfiles",
+    ),
+    ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles"),
+];
+
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerProfile {
+    PowerSaver,
+    Balanced,
+    Performance,
+}
+
+impl PowerProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PowerSaver => "power-saver",
+            Self::Balanced => "balanced",
+            Self::Performance => "performance",
+        }
+    }
+}
+
+impl fmt::Display for PowerProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PowerProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "power-saver" => Ok(Self::PowerSaver),
+            "balanced" => Ok(Self::Balanced),
+            "performance" => Ok(Self::Performance),
+            _ => Err(format!("unknown power profile `{s}`")),
+        }
+    }
+}
+
+/// Daily `HH:MM-HH:MM` window, wrapping past midnight when the end is earlier than the start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected `HH:MM-HH:MM`, got `{s}`"))?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|_| format!("invalid time `{}`, expected `HH:MM`", t.trim()))
+        };
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Set of weekdays such as `mon-fri` or `sat,sun`, bit 0 is Monday
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weekdays(u8);
+
+impl Weekdays {
+    pub fn contains(&self, day: chrono::Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+}
+
+impl FromStr for Weekdays {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let day = |d: &str| {
+            WEEKDAYS
+                .iter()
+                .position(|w| d.trim().eq_ignore_ascii_case(w))
+                .ok_or_else(|| format!("unknown weekday `{}`, expected mon..sun", d.trim()))
+        };
+        let mut mask = 0u8;
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (day(first)?, day(last)?),
+                None => (day(part)?, day(part)?),
+            };
+            // Ranges may wrap around the week, e.g. `fri-mon`
+            let mut d = first;
+            loop {
+                mask |= 1 << d;
+                if d == last {
+                    break;
+                }
+                d = (d + 1) % 7;
+            }
+        }
+        Ok(Self(mask))
+    }
+}
+
+impl fmt::Display for Weekdays {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days: Vec<&str> = WEEKDAYS
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, day)| day)
+            .collect();
+        f.write_str(&days.join(","))
+    }
+}
+
+/// `window-title="YouTube|Netflix"`, a case-insensitive regex searched in the focused window's title
+#[derive(Clone)]
+pub struct TitlePattern(Regex);
+
+impl fmt::Debug for TitlePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+impl FromStr for TitlePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RegexBuilder::new(s)
+            .case_insensitive(true)
+            .build()
+            .map(Self)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl fmt::Display for TitlePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl PartialEq for TitlePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for TitlePattern {}
+
+/// Keyboard modifiers, `modifiers="super+shift"`
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+/// Modifier names and their bits, `altgr` counts as alt
+const MODIFIER_NAMES: [(&str, u8); 8] = [
+    ("ctrl", 1),
+    ("control", 1),
+    ("shift", 2),
+    ("alt", 4),
+    ("super", 8),
+    ("meta", 8),
+    ("logo", 8),
+    ("altgr", 4),
+];
+
+/// Evdev codes of the left and right modifier keys and their bits
+const MODIFIER_KEYS: [(u32, u8); 8] = [
+    (29, 1),
+    (97, 1),
+    (42, 2),
+    (54, 2),
+    (56, 4),
+    (100, 4),
+    (125, 8),
+    (126, 8),
+];
+
+impl FromStr for Modifiers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('+')
+            .map(|name| {
+                MODIFIER_NAMES
+                    .iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name.trim()))
+                    .map(|(_, bit)| *bit)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown modifier `{}`, expected ctrl, shift, alt or super",
+                            name.trim()
+                        )
+                    })
+            })
+            .try_fold(0, |mask, bit| bit.map(|bit| mask | bit))
+            .map(Self)
+    }
+}
+
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = ["ctrl", "shift", "alt", "super"]
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, name)| name)
+            .collect();
+        f.write_str(&names.join("+"))
+    }
+}
+
+impl fmt::Debug for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+/// Modifier keys held on any keyboard of the seat, left and right keys counted apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifierKeys(u8);
+
+impl ModifierKeys {
+    /// Track a key press or release, other keys are ignored
+    pub fn key(&mut self, code: u32, pressed: bool) {
+        if let Some(i) = MODIFIER_KEYS.iter().position(|(c, _)| *c == code) {
+            if pressed {
+                self.0 |= 1 << i;
+            } else {
+                self.0 &= !(1 << i);
+            }
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers(
+            MODIFIER_KEYS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| self.0 & (1 << i) != 0)
+                .fold(0, |mask, (_, (_, bit))| mask | bit),
+        )
+    }
+}
+
+/// Whether `focused` is one of the comma separated names in `names`, ignoring case
+fn one_of(names: &str, focused: Option<&str>) -> bool {
+    focused.is_some_and(|focused| {
+        names
+            .split(',')
+            .any(|n| n.trim().eq_ignore_ascii_case(focused))
+    })
+}
+
+/// Conditions shared by all gesture kinds, every set condition must hold for the rule to be active
+#[derive(Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conditions {
+    /// Only active while power-profiles-daemon reports this profile
+    #[knuffel(property)]
+    pub power_profile: Option<PowerProfile>,
+    /// Inactive while power-profiles-daemon reports this profile
+    #[knuffel(property)]
+    pub not_power_profile: Option<PowerProfile>,
+    /// Only active with at least this many connected displays, e.g. `2` when docked
+    #[knuffel(property)]
+    pub min_displays: Option<u32>,
+    /// Only active with at most this many connected displays
+    #[knuffel(property)]
+    pub max_displays: Option<u32>,
+    /// Only active during this daily window of the local clock, e.g. `09:00-17:30`
+    #[knuffel(property, str)]
+    pub time: Option<TimeWindow>,
+    /// Only active on these days, e.g. `mon-fri`
+    #[knuffel(property, str)]
+    pub days: Option<Weekdays>,
+    /// Only active while one of these apps has focus, comma separated app ids, e.g. `firefox`
+    #[knuffel(property)]
+    pub app: Option<String>,
+    /// Only active while the focused window has one of these classes, the X11 name for app ids,
+    /// e.g. `Firefox,kitty`
+    #[knuffel(property)]
+    pub window_class: Option<String>,
+    /// Only active while the focused window's title matches this regex, e.g. `YouTube`
+    #[knuffel(property, str)]
+    pub window_title: Option<TitlePattern>,
+    /// Only active while exactly these keyboard modifiers are held, e.g. `super+shift`
+    #[knuffel(property, str)]
+    pub modifiers: Option<Modifiers>,
+}
+
+impl Conditions {
+    /// Take the conditions of an enclosing group for every condition not set here
+    pub fn inherit(&mut self, outer: &Conditions) {
+        self.power_profile = self.power_profile.or(outer.power_profile);
+        self.not_power_profile = self.not_power_profile.or(outer.not_power_profile);
+        self.min_displays = self.min_displays.or(outer.min_displays);
+        self.max_displays = self.max_displays.or(outer.max_displays);
+        self.time = self.time.or(outer.time);
+        self.days = self.days.or(outer.days);
+        if self.app.is_none() {
+            self.app.clone_from(&outer.app);
+        }
+        if self.window_class.is_none() {
+            self.window_class.clone_from(&outer.window_class);
+        }
+        if self.window_title.is_none() {
+            self.window_title.clone_from(&outer.window_title);
+        }
+        self.modifiers = self.modifiers.or(outer.modifiers);
+    }
+
+    pub fn matches(&self, state: &SystemState) -> bool {
+        if self.power_profile.is_some() || self.not_power_profile.is_some() {
+            let active = state.power_profile();
+            if self.power_profile.is_some_and(|p| active != Some(p)) {
+                return false;
+            }
+            if self.not_power_profile.is_some_and(|p| active == Some(p)) {
+                return false;
+            }
+        }
+        if self.min_displays.is_some() || self.max_displays.is_some() {
+            let Some(count) = state.display_count() else {
+                return false;
+            };
+            if self.min_displays.is_some_and(|min| count < min)
+                || self.max_displays.is_some_and(|max| count > max)
+            {
+                return false;
+            }
+        }
+        if self.time.is_some() || self.days.is_some() {
+            let now = state.local_time();
+            if self.time.is_some_and(|t| !t.contains(now.time()))
+                || self.days.is_some_and(|d| !d.contains(now.weekday()))
+            {
+                return false;
+            }
+        }
+        if self
+            .app
+            .iter()
+            .chain(&self.window_class)
+            .any(|apps| !one_of(apps, state.focused_app()))
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.window_title {
+            if !state.focused_title().is_some_and(|t| pattern.0.is_match(t)) {
+                return false;
+            }
+        }
+        if self.modifiers.is_some_and(|m| m != state.modifiers) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Snapshot of the system state conditions are evaluated against, probed lazily
+#[derive(Debug, Default)]
+pub struct SystemState {
+    power_profile: std::cell::OnceCell<Option<PowerProfile>>,
+    display_count: std::cell::OnceCell<Option<u32>>,
+    local_time: std::cell::OnceCell<NaiveDateTime>,
+    focused_app: std::cell::OnceCell<Option<String>>,
+    focused_title: std::cell::OnceCell<Option<String>>,
+    /// libinput name of the device the gesture comes from, selects `device` blocks
+    device: Option<String>,
+    /// Keyboard modifiers held when the gesture began
+    modifiers: Modifiers,
+}
+
+impl SystemState {
+    /// State whose clock reads `now` instead of the local time
+    #[cfg(test)]
+    pub fn at(now: NaiveDateTime) -> Self {
+        let state = Self::default();
+        let _ = state.local_time.set(now);
+        state
+    }
+
+    /// State where `app` has focus
+    #[cfg(test)]
+    pub fn focused_on(app: Option<&str>) -> Self {
+        let state = Self::default();
+        let _ = state.focused_app.set(app.map(str::to_string));
+        state
+    }
+
+    /// State where a window of `app` titled `title` has focus
+    #[cfg(test)]
+    pub fn focused_window(app: Option<&str>, title: Option<&str>) -> Self {
+        let state = Self::focused_on(app);
+        let _ = state.focused_title.set(title.map(str::to_string));
+        state
+    }
+
+    /// State for gestures of `device`
+    pub fn on_device(device: Option<&str>) -> Self {
+        Self {
+            device: device.map(str::to_string),
+            ..Self::default()
+        }
+    }
+
+    /// The same state with `modifiers` held
+    pub fn with_modifiers(self, modifiers: Modifiers) -> Self {
+        Self { modifiers, ..self }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn local_time(&self) -> NaiveDateTime {
+        *self
+            .local_time
+            .get_or_init(|| chrono::Local::now().naive_local())
+    }
+
+    pub fn power_profile(&self) -> Option<PowerProfile> {
+        *self.power_profile.get_or_init(cached_power_profile)
+    }
+
+    pub fn display_count(&self) -> Option<u32> {
+        *self.display_count.get_or_init(connected_displays)
+    }
+
+    pub fn focused_app(&self) -> Option<&str> {
+        self.focused_app
+            .get_or_init(crate::focus::focused_app)
+            .as_deref()
+    }
+
+    pub fn focused_title(&self) -> Option<&str> {
+        self.focused_title
+            .get_or_init(crate::focus::focused_title)
+            .as_deref()
+    }
+}
+
+/// Profiles forced on or off over IPC, ignoring their conditions until set back to `auto`
+static PROFILE_OVERRIDES: Lazy<Mutex<HashMap<String, bool>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Force a profile on (`Some(true)`) or off, `None` lets its conditions decide again
+pub fn set_profile_override(name: &str, active: Option<bool>) {
+    let mut overrides = PROFILE_OVERRIDES.lock();
+    match active {
+        Some(active) => overrides.insert(name.to_string(), active),
+        None => overrides.remove(name),
+    };
+}
+
+pub fn profile_override(name: &str) -> Option<bool> {
+    PROFILE_OVERRIDES.lock().get(name).copied()
+}
+
+/// Force `name` on and every other profile in `profiles` off, switching to one set of bindings
+pub fn switch_profile<'a>(name: &str, profiles: impl IntoIterator<Item = &'a str>) {
+    let mut overrides = PROFILE_OVERRIDES.lock();
+    for profile in profiles {
+        overrides.insert(profile.to_string(), profile == name);
+    }
+}
+
+/// Let the conditions of every profile decide again
+pub fn clear_profile_overrides() {
+    PROFILE_OVERRIDES.lock().clear();
+}
+
+/// Connector status is exposed here on Linux for X11 and Wayland sessions alike
+const DRM_CLASS_DIR: &str = "/sys/class/drm";
+
+pub fn connected_displays() -> Option<u32> {
+    count_connected_displays(Path::new(DRM_CLASS_DIR))
+}
+
+/// Count DRM connectors (`card0-HDMI-A-1`, ...) whose status is `connected`
+pub fn count_connected_displays(drm_dir: &Path) -> Option<u32> {
+    let count = fs::read_dir(drm_dir)
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().contains('-'))
+        .filter(|e| {
+            fs::read_to_string(e.path().join("status"))
+                .is_ok_and(|status| status.trim() == "connected")
+        })
+        .count();
+    u32::try_from(count).ok()
+}
+
+/// Last queried profile and when it was queried
+type CachedProfile = Option<(Instant, Option<PowerProfile>)>;
+
+static POWER_PROFILE: Lazy<Mutex<CachedProfile>> = Lazy::new(|| Mutex::new(None));
+
+fn cached_power_profile() -> Option<PowerProfile> {
+    let mut cached = POWER_PROFILE.lock();
+    if let Some((at, profile)) = *cached {
+        if at.elapsed() < POWER_PROFILE_TTL {
+            return profile;
+        }
+    }
+    let profile = query_power_profile();
+    *cached = Some((Instant::now(), profile));
+    profile
+}
+
+/// Parse the value printed by `busctl get-property` for a string property, e.g. `s "balanced"`
+pub fn parse_busctl_string(output: &str) -> Option<&str> {
+    output
+        .trim()
+        .strip_prefix("s ")
+        .map(|v| v.trim_matches('"'))
+}
+
+fn busctl(args: &[&str]) -> Option<String> {
+    let output = Command::new("busctl").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+fn query_power_profile() -> Option<PowerProfile> {
+    POWER_PROFILES_BUSES.iter().find_map(|(bus, path)| {
+        let output = busctl(&["get-property", bus, path, bus, "ActiveProfile"])?;
+        parse_busctl_string(&output)?.parse().ok()
+    })
+}
+
+/// Switch the active power profile, `next` cycles power-saver → balanced → performance
+pub fn set_power_profile(profile: &str) -> Result<PowerProfile, String> {
+    let profile = match profile {
+        "next" => match query_power_profile() {
+            Some(PowerProfile::PowerSaver) => PowerProfile::Balanced,
+            Some(PowerProfile::Balanced) => PowerProfile::Performance,
+            Some(PowerProfile::Performance) | None => PowerProfile::PowerSaver,
+        },
+        p => p.parse()?,
+    };
+
+    let set = POWER_PROFILES_BUSES.iter().any(|(bus, path)| {
+        busctl(&[
+            "set-property",
+            bus,
+            path,
+            bus,
+            "ActiveProfile",
+            "s",
+            profile.as_str(),
+        ])
+        .is_some()
+    });
+    if !set {
+        return Err("power-profiles-daemon is not reachable over D-Bus".to_string());
+    }
+
+    *POWER_PROFILE.lock() = Some((Instant::now(), Some(profile)));
+    Ok(profile)
+}
@@ -1,28 +1,405 @@
-use std::{env, fs, path::Path};
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use miette::{bail, IntoDiagnostic, Result};
+use miette::{bail, miette, IntoDiagnostic, Result};
 // use serde::{Deserialize, Serialize};
 use knuffel::{parse, Decode};
+use regex::Regex;
 
+use crate::conditions::{profile_override, Conditions, SystemState};
+use crate::contacts::PalmRejection;
 use crate::gestures::Gesture;
+use crate::touch::Touchscreen;
+use crate::units::OutputScale;
+use crate::utils::expand_path;
+
+/// Base layer shipped by distributions/admins, merged under the user config
+pub const SYSTEM_CONFIG: &str = "/etc/gestures/gestures.kdl";
 
 #[derive(Decode, PartialEq, Debug, Default)]
 pub struct Config {
-    // pub device: Option<String>,
+    #[knuffel(child, default)]
+    pub vars: Vars,
+    #[knuffel(child)]
+    pub palm_rejection: Option<PalmRejection>,
+    /// `finger-change "rematch"`, what to do when fingers are added or lifted mid-gesture
+    #[knuffel(child, unwrap(argument))]
+    pub finger_change: Option<FingerChange>,
+    /// Gestures from touchscreen contacts, touchscreens are ignored without it
+    #[knuffel(child)]
+    pub touchscreen: Option<Touchscreen>,
+    /// `watch true` reloads the configuration whenever its file changes
+    #[knuffel(child, unwrap(argument))]
+    pub watch: Option<bool>,
+    /// `output-scale 2`, screen pixels per logical pixel for `px` values, `"auto"` queries the
+    /// output under the pointer
+    #[knuffel(child, unwrap(argument))]
+    pub output_scale: Option<OutputScale>,
+    /// `idle-pause 10`, minutes without input on the seat after which the event loop pauses
+    #[knuffel(child, unwrap(argument))]
+    pub idle_pause: Option<u64>,
+    /// `update-rate 30`, most `update` commands and drag moves per second, `0` for every event
+    #[knuffel(child, unwrap(argument))]
+    pub update_rate: Option<u32>,
+    /// `notify-failures 3`, desktop notification when a command failed this many times in a row
+    #[knuffel(child, unwrap(argument))]
+    pub notify_failures: Option<u32>,
+    /// `ignore-device "SynPS/2 *"`, gestures of matching devices are dropped
+    #[knuffel(children(name = "ignore-device"), unwrap(argument, str))]
+    pub ignore_devices: Vec<DevicePattern>,
+    /// `only-device "..."`, when given only gestures of matching devices are handled
+    #[knuffel(children(name = "only-device"), unwrap(argument, str))]
+    pub only_devices: Vec<DevicePattern>,
+    /// `seat "seat1"`, libinput seats read by their own event handler, `seat0` without any
+    #[knuffel(children(name = "seat"), unwrap(argument))]
+    pub seats: Vec<String>,
+    /// Merged under this file's rules by `read_from_file`, emptied after that
+    #[knuffel(children(name = "include"))]
+    pub includes: Vec<Include>,
+    #[knuffel(children(name = "profile"))]
+    pub profiles: Vec<Profile>,
+    /// Rules that only apply to gestures from one touchpad
+    #[knuffel(children(name = "device"))]
+    pub devices: Vec<DeviceRules>,
+    /// Moved into `gestures` by `flatten_groups` after parsing
+    #[knuffel(children(name = "group"))]
+    pub groups: Vec<Group>,
+    #[knuffel(children)]
+    pub gestures: Vec<Gesture>,
+}
+
+/// `include "hyprland.kdl"`, another config file whose rules, profiles and variables this file
+/// builds on, a relative path is relative to the including file
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct Include {
+    #[knuffel(argument)]
+    pub path: String,
+}
+
+impl Include {
+    fn resolve(&self, dir: &Path) -> PathBuf {
+        dir.join(expand_path(&self.path))
+    }
+}
+
+/// Pattern of `ignore-device`/`only-device` matched against a libinput device name or sysname
+/// (`event5`): a glob with `*` and `?`, or a regex between slashes like `/^ELAN\d+/`
+#[derive(Clone)]
+pub struct DevicePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl DevicePattern {
+    pub fn matches(&self, name: &str, sysname: &str) -> bool {
+        self.regex.is_match(name) || self.regex.is_match(sysname)
+    }
+}
+
+impl FromStr for DevicePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pattern = match s.strip_prefix('/').and_then(|r| r.strip_suffix('/')) {
+            Some(regex) => regex.to_string(),
+            // A glob matches the whole name
+            None => {
+                let escaped: Vec<String> = s
+                    .split('*')
+                    .map(|part| {
+                        part.split('?')
+                            .map(regex::escape)
+                            .collect::<Vec<_>>()
+                            .join(".")
+                    })
+                    .collect();
+                format!("^{}$", escaped.join(".*"))
+            }
+        };
+        Regex::new(&pattern)
+            .map(|regex| Self {
+                source: s.to_string(),
+                regex,
+            })
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl fmt::Debug for DevicePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl PartialEq for DevicePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+/// `ignore-device` and `only-device` of a config, which devices' gestures are handled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceFilter {
+    pub ignore: Vec<DevicePattern>,
+    pub only: Vec<DevicePattern>,
+}
+
+impl DeviceFilter {
+    /// No `ignore-device` pattern matches and, with `only-device`, one of those does
+    pub fn accepts(&self, name: &str, sysname: &str) -> bool {
+        !self.ignore.iter().any(|p| p.matches(name, sysname))
+            && (self.only.is_empty() || self.only.iter().any(|p| p.matches(name, sysname)))
+    }
+}
+
+/// Handling of a swipe or pinch whose finger count changes before it ends
+#[derive(knuffel::DecodeScalar, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FingerChange {
+    /// Release any drag and ignore the rest of the gesture, no end command runs
+    #[default]
+    Cancel,
+    /// Cancel, then continue as a new gesture with the new finger count
+    Rematch,
+}
+
+/// Named set of gestures that is only active while its conditions hold,
+/// its rules replace top-level rules bound to the same gesture
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct Profile {
+    #[knuffel(argument)]
+    pub name: String,
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    /// Override top-level variables while the profile is active
+    #[knuffel(child, default)]
+    pub vars: Vars,
+    #[knuffel(children(name = "group"))]
+    pub groups: Vec<Group>,
+    #[knuffel(children)]
+    pub gestures: Vec<Gesture>,
+}
+
+/// `device name="..." { ... }`, gestures only matched for events of the libinput device with this
+/// name, replacing top-level rules bound to the same gesture
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct DeviceRules {
+    /// Device name as listed by `libinput list-devices`
+    #[knuffel(property)]
+    pub name: String,
+    #[knuffel(flatten(property))]
+    pub fingers: FingerRemap,
+    #[knuffel(children(name = "group"))]
+    pub groups: Vec<Group>,
     #[knuffel(children)]
     pub gestures: Vec<Gesture>,
 }
 
+/// `finger-offset=-1` and `finger-map="4:3"` of a device block: finger counts the device reports
+/// are corrected before any rule is matched, for touchpads whose firmware miscounts
+#[derive(Decode, PartialEq, Eq, Debug, Clone, Default)]
+pub struct FingerRemap {
+    /// Added to every reported count
+    #[knuffel(property)]
+    pub finger_offset: Option<i32>,
+    /// `reported:actual` pairs, a listed count is replaced instead of offset
+    #[knuffel(property, str)]
+    pub finger_map: Option<FingerMap>,
+}
+
+impl FingerRemap {
+    pub fn apply(&self, reported: i32) -> i32 {
+        if let Some(&(_, actual)) = self
+            .finger_map
+            .iter()
+            .flat_map(|m| &m.0)
+            .find(|(from, _)| *from == reported)
+        {
+            return actual;
+        }
+        reported + self.finger_offset.unwrap_or(0)
+    }
+}
+
+/// `4:3 5:4`, pairs of reported and actual finger counts separated by spaces or commas
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FingerMap(Vec<(i32, i32)>);
+
+impl FromStr for FingerMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let invalid = || format!("expected `reported:actual` finger counts, got `{pair}`");
+                let (from, to) = pair.split_once(':').ok_or_else(invalid)?;
+                let count = |n: &str| n.trim().parse::<i32>().map_err(|_| invalid());
+                Ok((count(from)?, count(to)?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for FingerMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .0
+            .iter()
+            .map(|(reported, actual)| format!("{}:{}", reported, actual))
+            .collect();
+        f.write_str(&pairs.join(" "))
+    }
+}
+
+/// `group power-profile=... { ... }`, its conditions apply to every gesture inside,
+/// a condition set on a gesture itself takes precedence. Groups can be nested.
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct Group {
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    #[knuffel(children(name = "group"))]
+    pub groups: Vec<Group>,
+    #[knuffel(children)]
+    pub gestures: Vec<Gesture>,
+}
+
+impl Group {
+    /// Gestures of this group and its nested groups with the group conditions applied
+    fn into_gestures(self, outer: &Conditions) -> Vec<Gesture> {
+        let mut conditions = self.conditions;
+        conditions.inherit(outer);
+        let mut gestures = self.gestures;
+        for gesture in &mut gestures {
+            if let Some(c) = gesture.conditions_mut() {
+                c.inherit(&conditions);
+            }
+        }
+        for group in self.groups {
+            gestures.extend(group.into_gestures(&conditions));
+        }
+        gestures
+    }
+}
+
+/// Append the gestures of `groups` to `gestures`, leaving no groups behind
+fn flatten_groups(groups: &mut Vec<Group>, gestures: &mut Vec<Gesture>) {
+    for group in groups.drain(..) {
+        gestures.extend(group.into_gestures(&Conditions::default()));
+    }
+}
+
+/// `vars { name "value" ... }`, referenced as `$name` in commands
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+pub struct Vars {
+    #[knuffel(children)]
+    pub entries: Vec<Var>,
+}
+
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct Var {
+    #[knuffel(node_name)]
+    pub name: String,
+    #[knuffel(argument)]
+    pub value: String,
+}
+
 impl Config {
     pub fn read_from_file(file: &Path) -> Result<Self> {
+        Self::read_including(file, &mut Vec::new())
+    }
+
+    /// Read `file` with its includes merged under it, `reading` holds the files further up the
+    /// include chain, including one of them again is a cycle
+    fn read_including(file: &Path, reading: &mut Vec<PathBuf>) -> Result<Self> {
         log::debug!("{:?}", &file);
-        match fs::read_to_string(file) {
-            Ok(s) => {
-                let source_name = file.to_string_lossy();
-                Ok(parse::<Config>(&source_name, &s).into_diagnostic()?)
-            }
+        let config = match fs::read_to_string(file) {
+            Ok(s) => Self::parse_text(&file.to_string_lossy(), &s)?,
             _ => bail!("Could not read config file"),
+        };
+        reading.push(fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf()));
+        let dir = file.parent().unwrap_or(Path::new(""));
+        let config = config.with_includes(dir, reading);
+        reading.pop();
+        config
+    }
+
+    /// `self` merged over the files it includes (relative to `dir`), in the order they are listed
+    fn with_includes(mut self, dir: &Path, reading: &mut Vec<PathBuf>) -> Result<Self> {
+        let mut base = Config::default();
+        for include in std::mem::take(&mut self.includes) {
+            let path = include.resolve(dir);
+            if reading.contains(&fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) {
+                bail!("{} includes itself", path.display());
+            }
+            let included = Self::read_including(&path, reading)
+                .map_err(|e| miette!("Could not include {}: {}", path.display(), e))?;
+            base = base.merge(included);
+        }
+        Ok(base.merge(self))
+    }
+
+    /// Files `file` includes, directly or through other included files
+    pub fn included_files(file: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut pending = vec![file.to_path_buf()];
+        while let Some(file) = pending.pop() {
+            let Ok(text) = fs::read_to_string(&file) else {
+                continue;
+            };
+            let Ok(config) = parse::<Config>(&file.to_string_lossy(), &text) else {
+                continue;
+            };
+            let dir = file.parent().unwrap_or(Path::new(""));
+            for include in &config.includes {
+                let path = include.resolve(dir);
+                if path != file && !files.contains(&path) {
+                    files.push(path.clone());
+                    pending.push(path);
+                }
+            }
+        }
+        files
+    }
+
+    fn parse_text(source_name: &str, text: &str) -> Result<Self> {
+        let mut config = parse::<Config>(source_name, text).into_diagnostic()?;
+        config.flatten_groups();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Values that parse but the rules can't run with
+    pub fn validate(&self) -> Result<()> {
+        let gestures = self
+            .gestures
+            .iter()
+            .chain(self.profiles.iter().flat_map(|p| &p.gestures))
+            .chain(self.devices.iter().flat_map(|d| &d.gestures));
+        for gesture in gestures {
+            if let Gesture::Hold(h) = gesture {
+                // A deadline of now would fire on every pass of the event loop
+                if h.repeat == Some(0) {
+                    bail!(
+                        "`repeat` of `hold fingers={}` must be at least 1 millisecond",
+                        h.fingers
+                    );
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Config text that isn't saved anywhere (sent by a GUI over IPC), merged over the system
+    /// config like the user's file
+    pub fn read_unsaved(text: &str) -> Result<Self> {
+        let user = Self::parse_text("unsaved.kdl", text)?;
+        let user = user.with_includes(Path::new(&Self::get_config_home()?), &mut Vec::new())?;
+        Self::over_system(Path::new(SYSTEM_CONFIG), Ok(user))
     }
 
     pub fn get_config_home() -> Result<String> {
@@ -52,10 +429,170 @@ impl Config {
     }
 
     pub fn read_from_optional_path(path: Option<&Path>) -> Result<Self> {
-        if let Some(path) = path {
+        let user = if let Some(path) = path {
             Self::read_from_file(path)
         } else {
             Self::read_default_config()
+        };
+
+        Self::over_system(Path::new(SYSTEM_CONFIG), user)
+    }
+
+    /// The user config merged over the `system` one. Either is used alone when the other can't
+    /// be read, so a broken system config doesn't take a working user config down with it.
+    pub fn over_system(system: &Path, user: Result<Self>) -> Result<Self> {
+        if !system.exists() {
+            return user;
+        }
+        match (Self::read_from_file(system), user) {
+            (Ok(system), Ok(user)) => Ok(system.merge(user)),
+            (Ok(system_config), Err(e)) => {
+                log::warn!("{}, using system config {} only", e, system.display());
+                Ok(system_config)
+            }
+            (Err(e), Ok(user)) => {
+                log::warn!("Ignoring system config {}: {}", system.display(), e);
+                Ok(user)
+            }
+            (Err(_), Err(e)) => Err(e),
+        }
+    }
+
+    /// Move the gestures of `group` blocks (top-level, in profiles and in device blocks) next to the
+    /// ungrouped ones
+    pub fn flatten_groups(&mut self) {
+        flatten_groups(&mut self.groups, &mut self.gestures);
+        for profile in &mut self.profiles {
+            flatten_groups(&mut profile.groups, &mut profile.gestures);
+        }
+        for device in &mut self.devices {
+            flatten_groups(&mut device.groups, &mut device.gestures);
+        }
+    }
+
+    /// Names of the profiles whose conditions hold (or that are forced on over IPC) and the rules currently in effect: gestures of
+    /// active profiles first, then those of the device block of the state's device, then top-level rules, each layer without the
+    /// rules the layers before it override, all with their own conditions met
+    pub fn active_gestures(&self, state: &SystemState) -> (Vec<&str>, Vec<&Gesture>) {
+        let profiles: Vec<&Profile> = self
+            .profiles
+            .iter()
+            .filter(|p| profile_override(&p.name).unwrap_or_else(|| p.conditions.matches(state)))
+            .collect();
+        let device_rules = self
+            .devices
+            .iter()
+            .filter(|d| state.device() == Some(d.name.as_str()))
+            .flat_map(|d| &d.gestures);
+
+        let mut gestures: Vec<&Gesture> = profiles.iter().flat_map(|p| &p.gestures).collect();
+        let device_rules: Vec<&Gesture> = device_rules
+            .filter(|rule| !gestures.iter().any(|g| g.same_trigger(rule)))
+            .collect();
+        gestures.extend(device_rules);
+        let base_rules: Vec<&Gesture> = self
+            .gestures
+            .iter()
+            .filter(|base| !gestures.iter().any(|g| g.same_trigger(base)))
+            .collect();
+        gestures.extend(base_rules);
+        gestures.retain(|g| g.conditions().is_none_or(|c| c.matches(state)));
+        // A rule for the held modifiers replaces the plain rule bound to the same gesture
+        let modified: Vec<&Gesture> = gestures
+            .iter()
+            .copied()
+            .filter(|g| g.modifiers().is_some())
+            .collect();
+        gestures.retain(|g| g.modifiers().is_some() || !modified.iter().any(|m| m.same_gesture(g)));
+
+        (profiles.iter().map(|p| p.name.as_str()).collect(), gestures)
+    }
+
+    pub fn device_filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            ignore: self.ignore_devices.clone(),
+            only: self.only_devices.clone(),
+        }
+    }
+
+    /// Finger count correction of the device block named `device`, none without one
+    pub fn finger_remap(&self, device: Option<&str>) -> FingerRemap {
+        self.devices
+            .iter()
+            .find(|d| device == Some(d.name.as_str()))
+            .map(|d| d.fingers.clone())
+            .unwrap_or_default()
+    }
+
+    /// Variables in effect, those of active profiles override the top-level ones
+    pub fn active_vars(&self, active_profiles: &[&str]) -> HashMap<String, String> {
+        let profile_vars = self
+            .profiles
+            .iter()
+            .filter(|p| active_profiles.contains(&p.name.as_str()))
+            .flat_map(|p| &p.vars.entries);
+        self.vars
+            .entries
+            .iter()
+            .chain(profile_vars)
+            .map(|v| (v.name.clone(), v.value.clone()))
+            .collect()
+    }
+
+    /// Layer `overlay` on top of `self`, rules in `overlay` replace base rules bound to the same gesture
+    /// and profiles/variables with the same name
+    pub fn merge(self, overlay: Config) -> Config {
+        let mut gestures: Vec<Gesture> = self
+            .gestures
+            .into_iter()
+            .filter(|base| !overlay.gestures.iter().any(|g| g.same_trigger(base)))
+            .collect();
+        gestures.extend(overlay.gestures);
+
+        let mut profiles: Vec<Profile> = self
+            .profiles
+            .into_iter()
+            .filter(|base| !overlay.profiles.iter().any(|p| p.name == base.name))
+            .collect();
+        profiles.extend(overlay.profiles);
+
+        let mut devices: Vec<DeviceRules> = self
+            .devices
+            .into_iter()
+            .filter(|base| !overlay.devices.iter().any(|d| d.name == base.name))
+            .collect();
+        devices.extend(overlay.devices);
+
+        let mut vars = self.vars.entries;
+        vars.retain(|base| !overlay.vars.entries.iter().any(|v| v.name == base.name));
+        vars.extend(overlay.vars.entries);
+
+        Config {
+            vars: Vars { entries: vars },
+            palm_rejection: overlay.palm_rejection.or(self.palm_rejection),
+            finger_change: overlay.finger_change.or(self.finger_change),
+            touchscreen: overlay.touchscreen.or(self.touchscreen),
+            watch: overlay.watch.or(self.watch),
+            output_scale: overlay.output_scale.or(self.output_scale),
+            idle_pause: overlay.idle_pause.or(self.idle_pause),
+            update_rate: overlay.update_rate.or(self.update_rate),
+            notify_failures: overlay.notify_failures.or(self.notify_failures),
+            ignore_devices: [self.ignore_devices, overlay.ignore_devices].concat(),
+            only_devices: if overlay.only_devices.is_empty() {
+                self.only_devices
+            } else {
+                overlay.only_devices
+            },
+            seats: if overlay.seats.is_empty() {
+                self.seats
+            } else {
+                overlay.seats
+            },
+            includes: Vec::new(),
+            profiles,
+            devices,
+            groups: Vec::new(),
+            gestures,
         }
     }
 }
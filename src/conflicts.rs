@@ -0,0 +1,160 @@
+//! Other gesture handlers running next to the daemon, found at startup and by `gestures doctor`
+//!
+//! Touchpads are never grabbed, so a gesture bound here and in one of these handlers runs twice.
+//! Grabbing the device (`EVIOCGRAB`) can't resolve it for some finger counts only: the grab takes
+//! the whole touchpad from the compositor, pointer movement and taps included.
+
+use std::fs;
+
+use crate::config::Config;
+use crate::gestures::Gesture;
+
+/// Gesture handler that may act on the same gestures
+#[derive(Debug, PartialEq)]
+pub struct Handler {
+    pub name: &'static str,
+    /// Process name, or script name for handlers run by an interpreter
+    process: &'static str,
+    /// Finger counts it always handles, empty when that depends on its own configuration
+    fingers: &'static [i32],
+    /// How to keep it from handling the gestures bound here
+    pub hint: &'static str,
+}
+
+const HANDLERS: &[Handler] = &[
+    Handler {
+        name: "touchegg",
+        process: "touchegg",
+        fingers: &[],
+        hint: "remove its bindings for these finger counts or stop it with `systemctl stop touchegg`",
+    },
+    Handler {
+        name: "libinput-gestures",
+        process: "libinput-gestures",
+        fingers: &[],
+        hint: "remove its bindings for these finger counts or stop it with `libinput-gestures-setup stop`",
+    },
+    Handler {
+        name: "fusuma",
+        process: "fusuma",
+        fingers: &[],
+        hint: "remove its bindings for these finger counts or stop it",
+    },
+    Handler {
+        name: "GNOME Shell",
+        process: "gnome-shell",
+        fingers: &[3],
+        hint: "its built-in 3-finger swipes can't be turned off in the settings, bind other finger \
+               counts or disable them with an extension",
+    },
+];
+
+/// Handler found running and the finger counts the config binds that it may handle too
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub handler: &'static Handler,
+    pub fingers: Vec<i32>,
+}
+
+impl Conflict {
+    pub fn describe(&self) -> String {
+        let fingers: Vec<String> = self.fingers.iter().map(i32::to_string).collect();
+        format!(
+            "{} is running and may also handle {}-finger gestures: {}",
+            self.handler.name,
+            fingers.join("/"),
+            self.handler.hint
+        )
+    }
+}
+
+/// Name and arguments of each process
+fn processes() -> Vec<(String, Vec<String>)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+        })
+        .filter_map(|e| {
+            let comm = fs::read_to_string(e.path().join("comm")).ok()?;
+            let cmdline = fs::read(e.path().join("cmdline")).unwrap_or_default();
+            let args = cmdline
+                .split(|&b| b == 0)
+                .filter(|a| !a.is_empty())
+                .map(|a| String::from_utf8_lossy(a).into_owned())
+                .collect();
+            Some((comm.trim_end().to_string(), args))
+        })
+        .collect()
+}
+
+/// Handlers among `processes`, a script counts when one of the first arguments of its
+/// interpreter is the script
+fn running(processes: &[(String, Vec<String>)]) -> Vec<&'static Handler> {
+    HANDLERS
+        .iter()
+        .filter(|h| {
+            processes.iter().any(|(comm, args)| {
+                comm == h.process
+                    || args
+                        .iter()
+                        .take(3)
+                        .any(|a| a.rsplit('/').next() == Some(h.process))
+            })
+        })
+        .collect()
+}
+
+/// Finger counts of the rules in every block of `config`
+fn bound_fingers(config: &Config) -> Vec<i32> {
+    let mut fingers: Vec<i32> = config
+        .gestures
+        .iter()
+        .chain(config.profiles.iter().flat_map(|p| &p.gestures))
+        .chain(config.devices.iter().flat_map(|d| &d.gestures))
+        .filter_map(|g| match g {
+            Gesture::Swipe(s) => Some(s.fingers),
+            Gesture::Pinch(p) => Some(p.fingers),
+            Gesture::Hold(h) => Some(h.fingers),
+            Gesture::Rotate(r) => Some(r.fingers),
+            Gesture::None => None,
+        })
+        .collect();
+    fingers.sort_unstable();
+    fingers.dedup();
+    fingers
+}
+
+/// Handlers among `processes` (name and arguments) that may handle gestures `config` binds
+pub fn conflicts_with(config: &Config, processes: &[(String, Vec<String>)]) -> Vec<Conflict> {
+    let bound = bound_fingers(config);
+    running(processes)
+        .into_iter()
+        .filter_map(|handler| {
+            let fingers: Vec<i32> = bound
+                .iter()
+                .copied()
+                .filter(|f| handler.fingers.is_empty() || handler.fingers.contains(f))
+                .collect();
+            (!fingers.is_empty()).then_some(Conflict { handler, fingers })
+        })
+        .collect()
+}
+
+/// Running handlers that may handle gestures `config` binds
+pub fn find(config: &Config) -> Vec<Conflict> {
+    conflicts_with(config, &processes())
+}
+
+/// Log a warning for every conflict, done once at startup
+pub fn warn(config: &Config) {
+    for conflict in find(config) {
+        log::warn!("{}", conflict.describe());
+    }
+}
@@ -0,0 +1,248 @@
+//! Raw multitouch contact tracking for palm/thumb rejection and edge swipes
+//!
+//! libinput doesn't expose individual touchpad contacts, so the touchpad's evdev node is read
+//! a second time (non-exclusively) to follow the MT slots.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use knuffel::{Decode, DecodeScalar};
+use parking_lot::Mutex;
+
+use crate::gestures::Float;
+
+const EV_ABS: u16 = 0x03;
+const ABS_MT_SLOT: u16 = 0x2f;
+const ABS_MT_TOUCH_MAJOR: u16 = 0x30;
+const ABS_MT_POSITION_X: u16 = 0x35;
+const ABS_MT_POSITION_Y: u16 = 0x36;
+const ABS_MT_TRACKING_ID: u16 = 0x39;
+
+/// Touchpads report at most this many contacts
+const MAX_SLOTS: usize = 10;
+
+/// Size of `struct input_event`, which depends on the size of its timeval
+const INPUT_EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+
+/// Contacts that moved less than this fraction of the pad width since touching down count as resting
+const RESTING_MOVEMENT: f64 = 0.03;
+
+/// Contacts touching down within this percent of the pad size from a border start an edge swipe
+const EDGE_MARGIN: f64 = 10.0;
+
+/// Border of the touchpad a swipe started at
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadEdge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// `palm-rejection` config node
+#[derive(Decode, Debug, Clone, PartialEq)]
+pub struct PalmRejection {
+    /// Percent of the pad width at the left/right edges where resting palms are ignored
+    #[knuffel(property, default = Float(5.0))]
+    pub side_margin: Float,
+    /// Percent of the pad height at the bottom edge where a resting thumb is ignored
+    #[knuffel(property, default = Float(15.0))]
+    pub bottom_margin: Float,
+    /// Contacts with a larger touch major axis (device units) are palms anywhere on the pad
+    #[knuffel(property)]
+    pub max_touch_size: Option<i32>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Slot {
+    active: bool,
+    x: Option<i32>,
+    y: Option<i32>,
+    start: Option<(i32, i32)>,
+    touch_major: i32,
+}
+
+/// Current MT slots of one touchpad
+#[derive(Debug)]
+pub struct PadState {
+    slots: [Slot; MAX_SLOTS],
+    current: usize,
+    x_range: (i32, i32),
+    y_range: (i32, i32),
+}
+
+impl PadState {
+    pub fn new(x_range: (i32, i32), y_range: (i32, i32)) -> Self {
+        Self {
+            slots: [Slot::default(); MAX_SLOTS],
+            current: 0,
+            x_range,
+            y_range,
+        }
+    }
+
+    /// Apply one evdev event
+    pub fn apply(&mut self, type_: u16, code: u16, value: i32) {
+        if type_ != EV_ABS {
+            return;
+        }
+        if code == ABS_MT_SLOT {
+            self.current = usize::try_from(value).unwrap_or(0).min(MAX_SLOTS - 1);
+            return;
+        }
+
+        let slot = &mut self.slots[self.current];
+        match code {
+            ABS_MT_TRACKING_ID if value < 0 => *slot = Slot::default(),
+            ABS_MT_TRACKING_ID => {
+                *slot = Slot {
+                    active: true,
+                    ..Slot::default()
+                }
+            }
+            ABS_MT_POSITION_X => slot.x = Some(value),
+            ABS_MT_POSITION_Y => slot.y = Some(value),
+            ABS_MT_TOUCH_MAJOR => slot.touch_major = value,
+            _ => {}
+        }
+        if let (None, Some(x), Some(y)) = (slot.start, slot.x, slot.y) {
+            slot.start = Some((x, y));
+        }
+    }
+
+    /// Number of contacts that look like a resting thumb or palm
+    pub fn resting_contacts(&self, rejection: &PalmRejection) -> usize {
+        let width = f64::from((self.x_range.1 - self.x_range.0).max(1));
+        let height = f64::from((self.y_range.1 - self.y_range.0).max(1));
+        let side = width * rejection.side_margin.0 / 100.0;
+        let bottom = height * rejection.bottom_margin.0 / 100.0;
+
+        self.slots
+            .iter()
+            .filter(|s| s.active)
+            .filter(|s| {
+                if rejection
+                    .max_touch_size
+                    .is_some_and(|max| s.touch_major > max)
+                {
+                    return true;
+                }
+                let (Some(x), Some(y), Some((x0, y0))) = (s.x, s.y, s.start) else {
+                    return false;
+                };
+                let (x, y) = (f64::from(x), f64::from(y));
+                let at_edge = x < f64::from(self.x_range.0) + side
+                    || x > f64::from(self.x_range.1) - side
+                    || y > f64::from(self.y_range.1) - bottom;
+                let moved = (x - f64::from(x0)).hypot(y - f64::from(y0));
+                at_edge && moved < width * RESTING_MOVEMENT
+            })
+            .count()
+    }
+}
+
+impl PadState {
+    /// Border the active contacts touched down at, the one closest to its border wins in corners
+    pub fn start_edge(&self) -> Option<PadEdge> {
+        let width = f64::from((self.x_range.1 - self.x_range.0).max(1));
+        let height = f64::from((self.y_range.1 - self.y_range.0).max(1));
+        let starts: Vec<(f64, f64)> = self
+            .slots
+            .iter()
+            .filter(|s| s.active)
+            .filter_map(|s| s.start)
+            .map(|(x, y)| {
+                (
+                    f64::from(x - self.x_range.0) / width,
+                    f64::from(y - self.y_range.0) / height,
+                )
+            })
+            .collect();
+        if starts.is_empty() {
+            return None;
+        }
+
+        // Distance of the outermost contact from each border, as a fraction of the pad size
+        let min = |f: fn(&(f64, f64)) -> f64| starts.iter().map(f).fold(f64::INFINITY, f64::min);
+        [
+            (PadEdge::Left, min(|s| s.0)),
+            (PadEdge::Right, min(|s| 1.0 - s.0)),
+            (PadEdge::Top, min(|s| s.1)),
+            (PadEdge::Bottom, min(|s| 1.0 - s.1)),
+        ]
+        .into_iter()
+        .filter(|(_, distance)| *distance < EDGE_MARGIN / 100.0)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(edge, _)| edge)
+    }
+}
+
+/// `EVIOCGABS(code)`, reads `struct input_absinfo` (six i32)
+fn abs_range(file: &File, code: u16) -> io::Result<(i32, i32)> {
+    let mut info = [0i32; 6];
+    let size = std::mem::size_of_val(&info) as libc::c_ulong;
+    let request =
+        (2 << 30) | (size << 16) | ((b'E' as libc::c_ulong) << 8) | (0x40 + code as libc::c_ulong);
+    // SAFETY: `info` matches the layout of struct input_absinfo expected by EVIOCGABS
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, info.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // value, minimum, maximum, fuzz, flat, resolution
+    Ok((info[1], info[2]))
+}
+
+/// Follows the contacts of one touchpad from a background thread
+#[derive(Debug)]
+pub struct ContactTracker {
+    state: Arc<Mutex<PadState>>,
+}
+
+impl ContactTracker {
+    pub fn start(devnode: &Path) -> io::Result<Self> {
+        let mut file = File::open(devnode)?;
+        let state = Arc::new(Mutex::new(PadState::new(
+            abs_range(&file, ABS_MT_POSITION_X)?,
+            abs_range(&file, ABS_MT_POSITION_Y)?,
+        )));
+
+        let thread_state = state.clone();
+        let name = devnode.display().to_string();
+        thread::spawn(move || {
+            let mut buf = [0u8; INPUT_EVENT_SIZE * 64];
+            loop {
+                let n = match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        log::warn!("Stopped tracking contacts on {}: {}", name, e);
+                        break;
+                    }
+                };
+                let mut state = thread_state.lock();
+                for event in buf[..n].as_chunks::<INPUT_EVENT_SIZE>().0 {
+                    // SAFETY: the chunk holds a whole `input_event` as written by the kernel, and
+                    // any bytes are a valid `input_event`
+                    let event: libc::input_event =
+                        unsafe { std::ptr::read_unaligned(event.as_ptr().cast()) };
+                    state.apply(event.type_, event.code, event.value);
+                }
+            }
+        });
+
+        Ok(Self { state })
+    }
+
+    pub fn resting_contacts(&self, rejection: &PalmRejection) -> usize {
+        self.state.lock().resting_contacts(rejection)
+    }
+
+    pub fn start_edge(&self) -> Option<PadEdge> {
+        self.state.lock().start_edge()
+    }
+}
@@ -0,0 +1,406 @@
+//! `dbus-method=` on swipe rules: method calls sent straight to the session bus on every update,
+//! e.g. to drive a desktop's gesture progress without spawning `gdbus` 60 times a second
+//!
+//! Only what fire-and-forget calls need is spoken: EXTERNAL authentication, `Hello` and method
+//! calls flagged NO_REPLY_EXPECTED with basic argument types.
+
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use knuffel::Decode;
+
+use crate::platform::{current_uid, runtime_dir};
+use crate::utils::{render_command, skipped_by_dry_run, CommandVars};
+
+/// Calls queued while the bus is slow, further updates are dropped
+const CALL_QUEUE_SIZE: usize = 64;
+/// Calls are dropped for this long after connecting failed instead of retrying on every update
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const METHOD_CALL: u8 = 1;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+/// Header field codes
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Method call of a swipe rule, made where its `update` command runs
+#[derive(Decode, Debug, Clone, Default, PartialEq)]
+pub struct DbusCall {
+    /// Bus name, e.g. `org.gnome.Shell`
+    #[knuffel(property)]
+    pub dbus_dest: Option<String>,
+    #[knuffel(property)]
+    pub dbus_path: Option<String>,
+    /// Interface and member, e.g. `org.gnome.Shell.Eval`
+    #[knuffel(property)]
+    pub dbus_method: Option<String>,
+    /// Typed arguments, e.g. `d:$delta_x s:workspace`
+    #[knuffel(property, str)]
+    pub dbus_args: Option<DbusArgs>,
+}
+
+impl DbusCall {
+    /// Render the arguments with `vars` and queue the call, a no-op without `dbus-method`
+    pub fn send(&self, vars: &CommandVars) {
+        let Some(method) = &self.dbus_method else {
+            return;
+        };
+        let Some((interface, member)) = method.rsplit_once('.') else {
+            log::warn!(
+                "dbus-method `{}` needs an interface, e.g. org.example.Method",
+                method
+            );
+            return;
+        };
+        let args = match &self.dbus_args {
+            Some(args) => match args.render(vars) {
+                Ok(args) => args,
+                Err(e) => {
+                    log::warn!("Skipping D-Bus call {}: {}", method, e);
+                    return;
+                }
+            },
+            None => Vec::new(),
+        };
+        if skipped_by_dry_run(format_args!("dbus {} {:?}", method, args)) {
+            return;
+        }
+        let call = encode_call(
+            self.dbus_dest.as_deref(),
+            self.dbus_path.as_deref().unwrap_or("/"),
+            interface,
+            member,
+            &args,
+        );
+        let Some(tx) = WORKER.get_or_init(start_worker) else {
+            return;
+        };
+        if tx.try_send(call).is_err() {
+            log::debug!("D-Bus worker busy, dropping call to {}", method);
+        }
+    }
+}
+
+/// Argument value for a method call
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbusArg {
+    String(String),
+    ObjectPath(String),
+    Bool(bool),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Double(f64),
+}
+
+impl DbusArg {
+    fn signature(&self) -> char {
+        match self {
+            Self::String(_) => 's',
+            Self::ObjectPath(_) => 'o',
+            Self::Bool(_) => 'b',
+            Self::Int32(_) => 'i',
+            Self::Uint32(_) => 'u',
+            Self::Int64(_) => 'x',
+            Self::Double(_) => 'd',
+        }
+    }
+
+    /// `value` converted to the type with signature `kind`
+    fn parse(kind: char, value: &str) -> Result<Self, String> {
+        let invalid = || format!("`{}` is not a valid `{}` argument", value, kind);
+        // Substituted variables are formatted as floats, integers take their whole part
+        let number = || value.parse::<f64>().map_err(|_| invalid());
+        Ok(match kind {
+            's' => Self::String(value.to_string()),
+            'o' => Self::ObjectPath(value.to_string()),
+            'b' => Self::Bool(value.parse().map_err(|_| invalid())?),
+            'i' => Self::Int32(number()?.trunc() as i32),
+            'u' => Self::Uint32(number()?.trunc() as u32),
+            'x' => Self::Int64(number()?.trunc() as i64),
+            'd' => Self::Double(number()?),
+            _ => return Err(format!("unknown D-Bus argument type `{}`", kind)),
+        })
+    }
+}
+
+/// `dbus-args`: whitespace separated `type:value` pairs, types are D-Bus signatures
+/// (`s`, `o`, `b`, `i`, `u`, `x`, `d`) and values may contain `$delta_x` and friends
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbusArgs(Vec<(char, String)>);
+
+impl FromStr for DbusArgs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(|arg| {
+                let (kind, value) = arg
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected `type:value`, got `{}`", arg))?;
+                let mut chars = kind.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(kind @ ('s' | 'o' | 'b' | 'i' | 'u' | 'x' | 'd')), None) => {
+                        Ok((kind, value.to_string()))
+                    }
+                    _ => Err(format!(
+                        "unknown D-Bus argument type `{}`, expected s, o, b, i, u, x or d",
+                        kind
+                    )),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for DbusArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self
+            .0
+            .iter()
+            .map(|(kind, value)| format!("{}:{}", kind, value))
+            .collect();
+        f.write_str(&args.join(" "))
+    }
+}
+
+impl DbusArgs {
+    pub fn render(&self, vars: &CommandVars) -> Result<Vec<DbusArg>, String> {
+        self.0
+            .iter()
+            .map(|(kind, value)| {
+                let value = render_command(value, vars).unwrap_or_default();
+                DbusArg::parse(*kind, &value)
+            })
+            .collect()
+    }
+}
+
+/// Little endian message buffer, alignment is relative to the start of the message
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn align(&mut self, to: usize) {
+        while !self.0.len().is_multiple_of(to) {
+            self.0.push(0);
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.0.push(value.len() as u8);
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(0);
+    }
+
+    fn arg(&mut self, arg: &DbusArg) {
+        match arg {
+            DbusArg::String(s) | DbusArg::ObjectPath(s) => self.string(s),
+            DbusArg::Bool(b) => self.u32(u32::from(*b)),
+            DbusArg::Int32(v) => self.u32(*v as u32),
+            DbusArg::Uint32(v) => self.u32(*v),
+            DbusArg::Int64(v) => {
+                self.align(8);
+                self.0.extend_from_slice(&v.to_le_bytes());
+            }
+            DbusArg::Double(v) => {
+                self.align(8);
+                self.0.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+
+    /// Header field: code, then a variant holding a string-like value
+    fn field(&mut self, code: u8, kind: char, value: &str) {
+        self.align(8);
+        self.0.push(code);
+        self.signature(&kind.to_string());
+        match kind {
+            'g' => self.signature(value),
+            _ => self.string(value),
+        }
+    }
+}
+
+/// Method call message expecting no reply, the serial is patched in by the worker
+pub fn encode_call(
+    dest: Option<&str>,
+    path: &str,
+    interface: &str,
+    member: &str,
+    args: &[DbusArg],
+) -> Vec<u8> {
+    let mut body = Writer(Vec::new());
+    for arg in args {
+        body.arg(arg);
+    }
+    let signature: String = args.iter().map(DbusArg::signature).collect();
+
+    let mut msg = Writer(vec![b'l', METHOD_CALL, NO_REPLY_EXPECTED, 1]);
+    msg.u32(body.0.len() as u32);
+    msg.u32(0);
+    // Array of header fields, its length is filled in once they are written
+    msg.u32(0);
+    let fields_start = msg.0.len();
+    msg.field(FIELD_PATH, 'o', path);
+    msg.field(FIELD_INTERFACE, 's', interface);
+    msg.field(FIELD_MEMBER, 's', member);
+    if let Some(dest) = dest {
+        msg.field(FIELD_DESTINATION, 's', dest);
+    }
+    if !signature.is_empty() {
+        msg.field(FIELD_SIGNATURE, 'g', &signature);
+    }
+    let fields_len = (msg.0.len() - fields_start) as u32;
+    msg.0[12..16].copy_from_slice(&fields_len.to_le_bytes());
+    msg.align(8);
+    msg.0.extend(body.0);
+    msg.0
+}
+
+fn set_serial(msg: &mut [u8], serial: u32) {
+    msg[8..12].copy_from_slice(&serial.to_le_bytes());
+}
+
+/// Socket of `DBUS_SESSION_BUS_ADDRESS` (`unix:path=` only) or `$XDG_RUNTIME_DIR/bus`
+fn session_bus_path() -> io::Result<PathBuf> {
+    if let Some(address) = env::var_os("DBUS_SESSION_BUS_ADDRESS") {
+        let address = address.to_string_lossy().into_owned();
+        return address
+            .split(';')
+            .filter_map(|a| a.strip_prefix("unix:"))
+            .flat_map(|a| a.split(','))
+            .find_map(|kv| kv.strip_prefix("path="))
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("no unix:path= in DBUS_SESSION_BUS_ADDRESS `{}`", address),
+                )
+            });
+    }
+    runtime_dir()
+        .map(|dir| dir.join("bus"))
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.to_string()))
+}
+
+struct Connection {
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+    fn open() -> io::Result<Self> {
+        let mut stream = UnixStream::connect(session_bus_path()?)?;
+        let uid: String = current_uid()
+            .to_string()
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", uid).as_bytes())?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("authentication refused: {}", line.trim()),
+            ));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+        // Replies (to Hello only) are never read, drained so the bus never blocks on them
+        stream.set_nonblocking(true)?;
+        let mut connection = Self { stream, serial: 0 };
+        connection.send(encode_call(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            &[],
+        ))?;
+        Ok(connection)
+    }
+
+    fn send(&mut self, mut msg: Vec<u8>) -> io::Result<()> {
+        self.serial = self.serial.wrapping_add(1).max(1);
+        set_serial(&mut msg, self.serial);
+        self.stream.set_nonblocking(false)?;
+        self.stream.write_all(&msg)?;
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+static WORKER: OnceLock<Option<mpsc::SyncSender<Vec<u8>>>> = OnceLock::new();
+
+/// Connects on the first call and again after the bus went away
+fn start_worker() -> Option<mpsc::SyncSender<Vec<u8>>> {
+    let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(CALL_QUEUE_SIZE);
+    let spawned = thread::Builder::new()
+        .name("dbus".to_string())
+        .spawn(move || {
+            let mut connection: Option<Connection> = None;
+            let mut failed_at: Option<Instant> = None;
+            for msg in rx {
+                if connection.is_none() {
+                    if failed_at.is_some_and(|at| at.elapsed() < RECONNECT_DELAY) {
+                        continue;
+                    }
+                    match Connection::open() {
+                        Ok(c) => {
+                            log::info!("Connected to the session bus");
+                            connection = Some(c);
+                            failed_at = None;
+                        }
+                        Err(e) => {
+                            log::warn!("Cannot connect to the session bus: {}", e);
+                            failed_at = Some(Instant::now());
+                            continue;
+                        }
+                    }
+                }
+                if let Some(c) = &mut connection {
+                    if let Err(e) = c.send(msg) {
+                        log::warn!("Session bus connection lost: {}", e);
+                        connection = None;
+                    }
+                }
+            }
+        });
+    match spawned {
+        Ok(_) => Some(tx),
+        Err(e) => {
+            log::warn!("Could not start the D-Bus worker: {}", e);
+            None
+        }
+    }
+}
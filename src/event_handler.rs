@@ -4,7 +4,7 @@ use std::{
         fd::{AsFd, OwnedFd},
         unix::prelude::OpenOptionsExt,
     },
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
@@ -14,29 +14,242 @@ use input::{
             GestureEndEvent, GestureEventCoordinates, GestureEventTrait, GestureHoldEvent,
             GesturePinchEvent, GesturePinchEventTrait, GestureSwipeEvent,
         },
-        Event, EventTrait, GestureEvent,
+        keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait},
+        touch::{TouchEvent, TouchEventPosition, TouchEventSlot, TouchEventTrait},
+        DeviceEvent, Event, EventTrait, GestureEvent,
     },
     DeviceCapability, Libinput, LibinputInterface,
 };
-use miette::{miette, Result};
+use miette::Result;
 use nix::{
     fcntl::OFlag,
     poll::{poll, PollFd, PollFlags, PollTimeout},
 };
 
-use crate::config::Config;
+use crate::builtin;
+use crate::calibration::{Calibration, DeviceCalibration};
+use crate::conditions::{self, Conditions, ModifierKeys, Modifiers, SystemState};
+use crate::config::{Config, DeviceFilter, FingerChange, FingerRemap};
+use crate::contacts::{ContactTracker, PadEdge, PalmRejection};
+use crate::failures;
+use crate::focus;
+use crate::gestures::rotate::{knob_steps, RotateDir};
 use crate::gestures::{hold::*, pinch::*, swipe::*, *};
-use crate::mouse_handler::MouseHandler;
-use crate::utils::{exec_command_from_string, exec_update_command_from_string};
+use crate::history::{self, EntryId};
+use crate::hyprland;
+use crate::keys::{self, KeyCombo};
+use crate::logs::RuleLog;
+use crate::mouse_handler::{Output, HI_RES_UNITS_PER_NOTCH};
+use crate::pipe::ActionPipe;
+use crate::script::{Script, ScriptHost};
+use crate::session_lock;
+use crate::smoothing::DeltaFilter;
+use crate::status;
+use crate::sway;
+use crate::touch::TouchTracker;
+use crate::trace::{monitor_event, trace_event};
+use crate::units::{DeviceUnits, OutputScale};
+use crate::utils::{
+    exec_command_from_string, exec_fired_command, exec_update_command_from_string,
+    skipped_by_dry_run, CommandVars, SpawnOptions,
+};
+use crate::wakeups::{self, Subsystem, Wakeup, WakeupProfile};
 
 use parking_lot::RwLock;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Events handled per loop iteration, a backlog after a stall is worked off over several ticks
+const EVENT_BUDGET: usize = 64;
+/// Gestures that began this long before they are handled come from a backlog (e.g. after resume
+/// from suspend) and are ignored rather than firing their rules late
+const STALE_GESTURE: Duration = Duration::from_secs(1);
+/// How often connected displays are counted while a drag holds the button
+const DISPLAY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Poll timeout (ms) of a loop paused by `idle-pause`, it still notices shutdown and takeover
+const PAUSED_POLL_TIMEOUT: u16 = 1000;
+
+/// Where an event handler's libinput context gets its devices from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSource {
+    /// All devices udev assigns to this seat, hotplug included
+    Seat(String),
+    /// `--device` nodes opened directly, for systems without udev
+    Paths(Vec<PathBuf>),
+}
+
+impl InputSource {
+    pub fn libinput(&self) -> Libinput {
+        match self {
+            Self::Seat(_) => Libinput::new_with_udev(Interface),
+            Self::Paths(_) => Libinput::new_from_path(Interface),
+        }
+    }
+}
+
+impl std::fmt::Display for InputSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Seat(seat) => write!(f, "seat {}", seat),
+            Self::Paths(paths) => {
+                let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "{}", paths.join(", "))
+            }
+        }
+    }
+}
+
+/// Why the event loop stopped, carried in the `miette::Report` it returns so `main` can pick the
+/// exit code and whether restarting it may help
+#[derive(Debug)]
+pub enum EventLoopError {
+    /// libinput couldn't be set up on this seat
+    Libinput(String),
+    /// A `--device` node couldn't be opened
+    OpenDevice(PathBuf),
+    /// Waiting for input failed
+    Poll(nix::errno::Errno),
+    /// Reading the devices failed, e.g. after the touchpad went away
+    Dispatch(std::io::Error),
+}
+
+impl EventLoopError {
+    /// Exit code of the daemon when it stops for this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Libinput(_) | Self::OpenDevice(_) => 3,
+            Self::Poll(_) | Self::Dispatch(_) => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for EventLoopError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Libinput(seat) => write!(f, "Could not initialize libinput on seat {}", seat),
+            Self::OpenDevice(path) => write!(f, "Could not open input device {}", path.display()),
+            Self::Poll(e) => write!(f, "Poll error: {}", e),
+            Self::Dispatch(e) => write!(f, "Failed to dispatch input events: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EventLoopError {}
+
+impl miette::Diagnostic for EventLoopError {
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        let help = match self {
+            Self::Libinput(_) | Self::OpenDevice(_) | Self::Dispatch(_) => {
+                "the user needs read access to /dev/input/event*, usually through the `input` group"
+            }
+            Self::Poll(_) => return None,
+        };
+        Some(Box::new(help))
+    }
+}
+
+/// Press the `key=` combination of a rule that fired
+fn press_key(key: Option<&KeyCombo>) {
+    if let Some(key) = key {
+        trace_event!("key {}", key);
+        if !skipped_by_dry_run(format_args!("key {}", key)) {
+            keys::press(key);
+        }
+    }
+}
+
+/// Commands and keys of a `script=` go through the same paths as the rule's own `end` and `key`
+struct RuleScriptHost<'a> {
+    vars: &'a CommandVars,
+    spawn: &'a SpawnOptions,
+}
+
+impl ScriptHost for RuleScriptHost<'_> {
+    fn exec(&mut self, command: &str) {
+        if let Err(e) = exec_command_from_string(command, self.vars, self.spawn) {
+            log::error!("Script command '{}' failed: {}", command, e);
+        }
+    }
+
+    fn key(&mut self, key: &KeyCombo) {
+        press_key(Some(key));
+    }
+
+    fn notify(&mut self, message: &str) {
+        let quoted = format!("'{}'", message.replace('\'', r"'\''"));
+        self.exec(&format!("notify-send -a gestures -- {}", quoted));
+    }
+}
+
+/// Run the `script=` of a rule that fired
+fn run_script(script: Option<&Script>, vars: &CommandVars, spawn: &SpawnOptions) {
+    if let Some(script) = script {
+        trace_event!("script {}", script);
+        if let Err(e) = script.run(vars, &mut RuleScriptHost { vars, spawn }) {
+            log::warn!("Script `{}` stopped: {}", script, e);
+        }
+    }
+}
+
+/// Type or paste the `type-text=` snippet of a rule that fired
+fn type_text(text: Option<&str>, paste: Option<bool>) {
+    if let Some(text) = text {
+        trace_event!("type {:?}", text);
+        if !skipped_by_dry_run(format_args!("type {:?}", text)) {
+            keys::type_text(text, paste.unwrap_or(false));
+        }
+    }
+}
+
+/// `trace_event!` that also goes to the log of a rule with `log=`
+macro_rules! rule_event {
+    ($log:expr, $($arg:tt)+) => {{
+        trace_event!($($arg)+);
+        $log.record(format_args!($($arg)+));
+    }};
+}
+
+/// A rule ran its end or action command: shown in status bars, kept for `gestures history` and
+/// logged as its `log=` asks. The command reports its exit status to the returned entry
+fn fired(log: &RuleLog, gesture: String) -> EntryId {
+    log.record(format_args!("{} fired", gesture));
+    let entry = history::fired(&gesture, log.log_label.as_deref());
+    status::fired(gesture);
+    entry
+}
+
+/// Age of a libinput event, its timestamps are CLOCK_MONOTONIC
+fn event_age(time_usec: u64) -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid out pointer
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    let now_usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    Duration::from_micros(now_usec.saturating_sub(time_usec))
+}
 
 #[derive(Debug)]
 struct GestureCache {
     swipe_gestures: HashMap<i32, Vec<Gesture>>,
     pinch_gestures: HashMap<i32, Vec<Gesture>>,
     hold_gestures: HashMap<i32, Vec<Gesture>>,
+    rotate_gestures: HashMap<i32, Vec<Gesture>>,
+    palm_rejection: Option<PalmRejection>,
+    finger_change: FingerChange,
+    /// Finger count correction of the device block of `device`
+    finger_remap: FingerRemap,
+    active_profiles: Vec<String>,
+    /// Focus generation the cache was built for, `app=` rules go stale when it moves
+    focus_generation: u64,
+    /// Device whose `device` block the cache holds
+    device: Option<String>,
+    /// Modifiers the `modifiers=` rules were picked for
+    modifiers: Modifiers,
+    /// `ignore-device`/`only-device` of the config
+    device_filter: DeviceFilter,
     last_update: std::time::Instant,
 }
 
@@ -46,22 +259,50 @@ impl GestureCache {
             swipe_gestures: HashMap::new(),
             pinch_gestures: HashMap::new(),
             hold_gestures: HashMap::new(),
+            rotate_gestures: HashMap::new(),
+            palm_rejection: None,
+            finger_change: FingerChange::default(),
+            finger_remap: FingerRemap::default(),
+            active_profiles: Vec::new(),
+            focus_generation: 0,
+            device: None,
+            modifiers: Modifiers::default(),
+            device_filter: DeviceFilter::default(),
             last_update: std::time::Instant::now() - std::time::Duration::from_secs(2),
         }
     }
 }
 
+/// Updates per second when `update-rate` is unset
+const DEFAULT_UPDATE_RATE: u32 = 60;
+
 #[derive(Debug)]
 struct ThrottleState {
+    fps: u32,
     last_update: std::time::Instant,
     min_interval: std::time::Duration,
 }
 
 impl ThrottleState {
+    /// At most `fps` updates per second, `0` lets every update through. The first update always
+    /// goes through
     fn new(fps: u32) -> Self {
+        let min_interval = match fps {
+            0 => std::time::Duration::ZERO,
+            fps => std::time::Duration::from_micros(1_000_000 / fps as u64),
+        };
+        let now = std::time::Instant::now();
         Self {
-            last_update: std::time::Instant::now(),
-            min_interval: std::time::Duration::from_micros(1_000_000 / fps as u64),
+            fps,
+            last_update: now.checked_sub(min_interval).unwrap_or(now),
+            min_interval,
+        }
+    }
+
+    /// Follow a changed `update-rate`, the same rate keeps the current state
+    fn set_rate(&mut self, fps: u32) {
+        if fps != self.fps {
+            *self = Self::new(fps);
         }
     }
 
@@ -76,32 +317,485 @@ impl ThrottleState {
     }
 }
 
+const DEFAULT_SMOOTHING_BETA: f64 = 0.01;
+
+/// Fractional pointer movement carried over between drag updates
+#[derive(Debug, Default, Clone, Copy)]
+struct DragRemainder {
+    x: f64,
+    y: f64,
+    /// Movement taken in steps but not yet counted as whole pixels by `take_moved`
+    moved: (f64, f64),
+}
+
+impl DragRemainder {
+    fn add(&mut self, dx: f64, dy: f64) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    /// Split off the whole pixels accumulated so far, keeping the fractional part
+    fn take_pixels(&mut self) -> (i32, i32) {
+        self.take_steps(1)
+    }
+
+    /// Split off the whole `1 / steps` of a pixel accumulated so far, as a number of steps
+    fn take_steps(&mut self, steps: i32) -> (i32, i32) {
+        let steps = f64::from(steps);
+        let (sx, sy) = ((self.x * steps).trunc(), (self.y * steps).trunc());
+        self.x -= sx / steps;
+        self.y -= sy / steps;
+        self.moved.0 += sx / steps;
+        self.moved.1 += sy / steps;
+        (sx as i32, sy as i32)
+    }
+
+    /// Whole pixels the pointer moved by the steps taken so far
+    fn take_moved(&mut self) -> (i32, i32) {
+        let (px, py) = (self.moved.0.trunc(), self.moved.1.trunc());
+        self.moved.0 -= px;
+        self.moved.1 -= py;
+        (px as i32, py as i32)
+    }
+}
+
+/// Friction of an `inertia` glide when `friction` is unset
+const DEFAULT_FRICTION: f64 = 4.0;
+/// Pointer speed (px/s) below which a glide stops
+const GLIDE_STOP_SPEED: f64 = 30.0;
+/// Time between pointer moves of a glide
+const GLIDE_FRAME: Duration = Duration::from_millis(16);
+
+/// Pointer of a drag with `inertia` still moving after the fingers lifted, slowing down until it
+/// stops and the button is released after `mouse-up-delay`
+#[derive(Debug)]
+struct DragGlide {
+    /// Pointer speed in pixels per second
+    velocity: (f64, f64),
+    friction: f64,
+    remainder: DragRemainder,
+    mouse_up_delay: i64,
+    last: Instant,
+    deadline: Instant,
+}
+
+impl DragGlide {
+    /// Glide at `velocity`, `None` when the drag was released too slowly to glide
+    fn new(velocity: (f64, f64), friction: f64, mouse_up_delay: i64, now: Instant) -> Option<Self> {
+        (velocity.0.hypot(velocity.1) >= GLIDE_STOP_SPEED).then(|| Self {
+            velocity,
+            friction: friction.max(0.1),
+            remainder: DragRemainder::default(),
+            mouse_up_delay,
+            last: now,
+            deadline: now + GLIDE_FRAME,
+        })
+    }
+
+    /// Whole pixels travelled since the last step while the speed decayed
+    fn step(&mut self, now: Instant) -> (i32, i32) {
+        let dt = now.saturating_duration_since(self.last).as_secs_f64();
+        let decay = (-self.friction * dt).exp();
+        // Integral of the exponentially decaying speed over `dt`
+        let travel = (1.0 - decay) / self.friction;
+        self.remainder
+            .add(self.velocity.0 * travel, self.velocity.1 * travel);
+        self.velocity = (self.velocity.0 * decay, self.velocity.1 * decay);
+        self.last = now;
+        self.deadline = now + GLIDE_FRAME;
+        self.remainder.take_pixels()
+    }
+
+    fn stopped(&self) -> bool {
+        self.velocity.0.hypot(self.velocity.1) < GLIDE_STOP_SPEED
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RegionSelect {
+    start: Option<(i32, i32)>,
+    moved: (i32, i32),
+    /// Screen the drag happens on, only looked up for hot corner rules
+    bounds: Option<(i32, i32, i32, i32)>,
+}
+
+impl RegionSelect {
+    /// Screen corner the pointer ended up in, if it was tracked
+    fn corner(&self) -> Option<ScreenCorner> {
+        let (x, y) = self.start?;
+        ScreenCorner::at(x + self.moved.0, y + self.moved.1, self.bounds?)
+    }
+}
+
+/// Wheel notch per this much pinch scale change, close to a browser zoom step
+const ZOOM_STEP: f64 = 1.1;
+
+/// Pinch scale changes converted to high-resolution wheel units for `@zoom`
+#[derive(Debug, Clone, Copy)]
+struct ZoomAccumulator {
+    last_scale: f64,
+    units: f64,
+}
+
+impl Default for ZoomAccumulator {
+    fn default() -> Self {
+        Self {
+            last_scale: 1.0,
+            units: 0.0,
+        }
+    }
+}
+
+impl ZoomAccumulator {
+    /// Whole units for the scale change since the last update, keeping the fractional part
+    fn take_units(&mut self, scale: f64, sensitivity: f64) -> i32 {
+        if scale > 0.0 {
+            self.units += (scale / self.last_scale).ln() / ZOOM_STEP.ln()
+                * f64::from(HI_RES_UNITS_PER_NOTCH)
+                * sensitivity;
+            self.last_scale = scale;
+        }
+        let whole = self.units.trunc();
+        self.units -= whole;
+        whole as i32
+    }
+}
+
+/// Gesture event generated without a touchpad
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticEvent {
+    SwipeBegin { fingers: i32 },
+    SwipeUpdate { dx: f64, dy: f64 },
+    SwipeEnd,
+    SwipeCancel,
+    PinchBegin { fingers: i32 },
+    PinchUpdate { scale: f64, angle: f64 },
+    PinchEnd,
+    PinchCancel,
+    HoldBegin { fingers: i32 },
+    HoldEnd,
+    HoldCancel,
+}
+
+/// Rotation of the current pinch and the rotate rules (cache indices) that already started
+#[derive(Debug, Default)]
+struct Rotation {
+    angle: f64,
+    started: Vec<usize>,
+    /// Steps reached by `rotate-step-deg` rotate and pinch rules, by cache index
+    steps: HashMap<usize, i64>,
+    pinch_steps: HashMap<usize, i64>,
+}
+
+/// Swipe rule with a `repeat-interval`, `deadline` is unset while the swipe isn't held past its
+/// `repeat-distance`
+#[derive(Debug)]
+struct SwipeRepeat {
+    rule: Box<Swipe>,
+    deadline: Option<Instant>,
+    interval: Duration,
+    repeated: bool,
+}
+
+/// Longest gap between a hold the fingers moved out of and the swipe libinput begins for them
+const HOLD_MOVE_WINDOW: Duration = Duration::from_millis(200);
+
+/// Hold that ended because the fingers moved, long enough for an `after-hold` swipe rule
+#[derive(Debug)]
+struct HoldRest {
+    fingers: i32,
+    held_ms: u64,
+    at: Instant,
+}
+
+/// Pending `trigger="timeout"` hold action
+#[derive(Debug)]
+struct HoldTimer {
+    fingers: i32,
+    action: String,
+    key: Option<KeyCombo>,
+    script: Option<Script>,
+    type_text: Option<String>,
+    paste: Option<bool>,
+    spawn: SpawnOptions,
+    log: RuleLog,
+    /// When the hold began, for `$elapsed_ms`
+    began: std::time::Instant,
+    deadline: std::time::Instant,
+    repeat: Option<std::time::Duration>,
+    done: bool,
+}
+
+impl HoldTimer {
+    fn schedule(gestures: &[Gesture], now: std::time::Instant) -> Vec<Self> {
+        gestures
+            .iter()
+            .filter_map(|g| match g {
+                Gesture::Hold(h) if h.trigger() == HoldTrigger::Timeout => Some(h),
+                _ => None,
+            })
+            .filter(|h| {
+                h.action.is_some() || h.key.is_some() || h.script.is_some() || h.type_text.is_some()
+            })
+            .map(|h| {
+                let duration = h.duration.unwrap_or(DEFAULT_HOLD_DURATION_MS);
+                Self {
+                    fingers: h.fingers,
+                    action: h.action.clone().unwrap_or_default(),
+                    key: h.key.clone(),
+                    script: h.script.clone(),
+                    type_text: h.type_text.clone(),
+                    paste: h.paste,
+                    spawn: h.spawn.clone(),
+                    log: h.log.clone(),
+                    began: now,
+                    deadline: now + std::time::Duration::from_millis(duration),
+                    repeat: h.repeat.map(std::time::Duration::from_millis),
+                    done: false,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Derives velocities from libinput event timestamps
+#[derive(Debug, Default)]
+struct MotionTracker {
+    /// `swipe`, `pinch` or `hold`, for `$gesture_type`
+    kind: &'static str,
+    fingers: i32,
+    start_usec: u64,
+    last_usec: u64,
+    /// Path length of the swipe so far, in libinput units
+    distance: f64,
+    /// Where the fingers are relative to where the swipe began
+    offset: (f64, f64),
+    velocity_x: f64,
+    velocity_y: f64,
+    scale: f64,
+    scale_rate: f64,
+}
+
+impl MotionTracker {
+    fn reset(&mut self, kind: &'static str, fingers: i32, time_usec: u64) {
+        *self = Self {
+            kind,
+            fingers,
+            start_usec: time_usec,
+            last_usec: time_usec,
+            scale: 1.0,
+            ..Self::default()
+        };
+    }
+
+    /// Seconds since the previous event, `None` for events sharing a timestamp
+    fn elapsed(&mut self, time_usec: u64) -> Option<f64> {
+        let dt = time_usec.saturating_sub(self.last_usec);
+        self.last_usec = time_usec;
+        (dt > 0).then(|| dt as f64 / 1_000_000.0)
+    }
+
+    fn swipe(&mut self, dx: f64, dy: f64, time_usec: u64) {
+        self.distance += dx.hypot(dy);
+        self.offset.0 += dx;
+        self.offset.1 += dy;
+        if let Some(dt) = self.elapsed(time_usec) {
+            self.velocity_x = dx / dt;
+            self.velocity_y = dy / dt;
+        }
+    }
+
+    fn pinch(&mut self, scale: f64, time_usec: u64) {
+        if let Some(dt) = self.elapsed(time_usec) {
+            self.scale_rate = (scale - self.scale) / dt;
+        }
+        self.scale = scale;
+    }
+
+    /// Average speed since the gesture began in libinput units per second, unlike the velocity
+    /// of the last update it isn't thrown off by fingers slowing down before they lift
+    fn mean_speed(&self) -> f64 {
+        let dt = self.last_usec.saturating_sub(self.start_usec) as f64 / 1_000_000.0;
+        if dt > 0.0 {
+            self.distance / dt
+        } else {
+            0.0
+        }
+    }
+
+    fn vars(&self) -> CommandVars {
+        let direction = match self.kind {
+            "swipe" => SwipeDir::dir(self.offset.0, self.offset.1).name(),
+            "pinch" if self.scale != 1.0 => PinchDir::dir(self.scale, 0.0).name(),
+            _ => "",
+        };
+        CommandVars {
+            scale: self.scale,
+            velocity_x: self.velocity_x,
+            velocity_y: self.velocity_y,
+            scale_rate: self.scale_rate,
+            direction,
+            fingers: self.fingers,
+            gesture_type: self.kind,
+            elapsed_ms: self.last_usec.saturating_sub(self.start_usec) / 1000,
+            ..CommandVars::default()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EventHandler {
     config: Arc<RwLock<Config>>,
     event: Gesture,
     cache: GestureCache,
     throttle: ThrottleState,
+    /// Throttles of the rules with their own `update-rate`, one per rate
+    rule_throttles: HashMap<u32, ThrottleState>,
+    drag_remainder: DragRemainder,
+    /// Pointer start and pixels moved so far while a `@screenshot` swipe selects a region or a
+    /// drag may end in a hot corner
+    region: Option<RegionSelect>,
+    drag_filter: Option<DeltaFilter>,
+    motion: MotionTracker,
+    hold_timers: Vec<HoldTimer>,
+    swipe_repeats: Vec<SwipeRepeat>,
+    drag_glide: Option<DragGlide>,
+    zoom: ZoomAccumulator,
+    /// Steps reached by pinch rules with a scale `step`, by cache index
+    scale_steps: HashMap<usize, i64>,
+    /// `pipe` commands of the current swipe or pinch
+    pipes: Vec<ActionPipe>,
+    rotation: Rotation,
+    /// Device node of the touchpad, until the contact tracker for palm rejection is started
+    gesture_device: Option<PathBuf>,
+    /// Sysnames of the touchpads libinput reports, kept up to date on hotplug
+    touchpads: Vec<String>,
+    /// Sysnames of the touchscreens, only followed with `touchscreen`
+    touchscreens: Vec<String>,
+    contacts: Option<ContactTracker>,
+    /// Touchpad border the current swipe started at, rules with another `edge` are skipped
+    swipe_edge: Option<PadEdge>,
+    /// Event loop accounting, only with `--profile-wakeups`
+    wakeups: Option<WakeupProfile>,
+    /// libinput still holds events the last `handle_event` left over its budget
+    backlog: bool,
+    calibration: Calibration,
+    /// Calibration of the device the current gesture comes from
+    device_calibration: Option<DeviceCalibration>,
+    /// Name of the device the current gesture comes from
+    device: Option<String>,
+    /// Size of that device and the output scale, for config values in `mm` or `px`
+    units: DeviceUnits,
+    /// Button pressed by a direct mouse drag, released early when the session locks
+    held_drag: Option<HeldDrag>,
+    /// Outputs the current drag can cross, only with `output-scale "auto"`
+    drag_outputs: Option<DragOutputs>,
+    /// `session_lock::locks()` when last checked
+    locks_seen: u64,
+    /// Gestures from touchscreen contacts, only with a `touchscreen` node
+    touch: Option<TouchTracker>,
+    /// Modifier keys held on the seat's keyboards, for `modifiers=` rules
+    modifier_keys: ModifierKeys,
+    /// Hold the fingers just moved out of, picked up by the swipe that follows
+    hold_rest: Option<HoldRest>,
+    /// Milliseconds the fingers rested before the current swipe, for `after-hold` rules
+    swipe_rest: Option<u64>,
 }
 
-trait MouseActions {
-    fn mouse_down(&mut self, button: i32);
-    fn mouse_up_delay(&mut self, button: i32, delay_ms: i64);
-    fn move_mouse_relative(&mut self, x_val: i32, y_val: i32);
+/// A drag holding the virtual mouse button
+#[derive(Debug)]
+struct HeldDrag {
+    /// Connected displays when the button was pressed
+    displays: Option<u32>,
+    /// When the `mouse-up-delay` of a released drag lets go of the button
+    release_at: Option<Instant>,
+    checked: Instant,
+}
+
+/// Outputs of a drag with `output-scale "auto"` and the pointer position followed through the
+/// injected moves, the compositor isn't asked again until the next drag
+#[derive(Debug)]
+struct DragOutputs {
+    outputs: Vec<Output>,
+    /// Pointer position in logical pixels
+    position: (f64, f64),
+    /// Index of the output under the pointer
+    current: usize,
 }
 
-impl MouseActions for MouseHandler {
-    fn mouse_down(&mut self, button: i32) {
-        MouseHandler::mouse_down(self, button);
+impl DragOutputs {
+    /// Starts on the output under `position`, or on the focused output where it is unknown
+    fn new(outputs: Vec<Output>, position: Option<(i32, i32)>) -> Option<Self> {
+        let position = position.map(|(x, y)| (f64::from(x), f64::from(y)));
+        let current = match position {
+            Some(p) => outputs.iter().position(|o| o.contains(p)),
+            None => None,
+        }
+        .or_else(|| outputs.iter().position(|o| o.focused))
+        .or_else(|| (!outputs.is_empty()).then_some(0))?;
+        let output = &outputs[current];
+        Some(Self {
+            position: position.unwrap_or((
+                output.x + output.width / 2.0,
+                output.y + output.height / 2.0,
+            )),
+            outputs,
+            current,
+        })
+    }
+
+    fn output(&self) -> &Output {
+        &self.outputs[self.current]
     }
 
-    fn mouse_up_delay(&mut self, button: i32, delay_ms: i64) {
-        MouseHandler::mouse_up_delay(self, button, delay_ms);
+    /// Follow a move, the output the pointer crossed to if it left the current one. A move into
+    /// no output is clamped, as the compositor keeps the pointer on the layout.
+    fn moved(&mut self, (dx, dy): (i32, i32)) -> Option<&Output> {
+        let position = (
+            self.position.0 + f64::from(dx),
+            self.position.1 + f64::from(dy),
+        );
+        if self.output().contains(position) {
+            self.position = position;
+            return None;
+        }
+        match self.outputs.iter().position(|o| o.contains(position)) {
+            Some(next) => {
+                self.position = position;
+                self.current = next;
+                Some(self.output())
+            }
+            None => {
+                let o = self.output();
+                self.position = (
+                    position.0.clamp(o.x, o.x + o.width - 1.0),
+                    position.1.clamp(o.y, o.y + o.height - 1.0),
+                );
+                None
+            }
+        }
     }
+}
 
-    fn move_mouse_relative(&mut self, x_val: i32, y_val: i32) {
-        MouseHandler::move_mouse_relative(self, x_val, y_val);
+/// Pointer output used by the handler, implemented by `MouseHandler` for every injection backend
+/// (a counting sink stands in for it in `gestures stress`), so gesture logic never names one
+pub trait MouseActions {
+    fn configure_drag(&mut self, drag: Option<&Swipe>);
+    fn mouse_down(&mut self, button: i32);
+    fn mouse_up_delay(&mut self, button: i32, delay_ms: i64);
+    fn move_mouse_relative(&mut self, x_val: i32, y_val: i32);
+    /// Steps of a pixel `move_mouse_steps` takes, more than one for a backend with sub-pixel
+    /// motion
+    fn motion_steps(&mut self) -> i32 {
+        1
     }
+    fn move_mouse_steps(&mut self, x_val: i32, y_val: i32) {
+        self.move_mouse_relative(x_val, y_val);
+    }
+    fn zoom(&mut self, units: i32);
+    fn pointer_position(&mut self) -> Option<(i32, i32)>;
+    fn screen_bounds(&mut self) -> Option<(i32, i32, i32, i32)>;
+    fn outputs(&mut self) -> Vec<Output>;
 }
 
 impl EventHandler {
@@ -110,154 +804,766 @@ impl EventHandler {
             config,
             event: Gesture::None,
             cache: GestureCache::new(),
-            throttle: ThrottleState::new(60),
+            throttle: ThrottleState::new(DEFAULT_UPDATE_RATE),
+            rule_throttles: HashMap::new(),
+            drag_remainder: DragRemainder::default(),
+            region: None,
+            drag_filter: None,
+            motion: MotionTracker::default(),
+            hold_timers: Vec::new(),
+            swipe_repeats: Vec::new(),
+            drag_glide: None,
+            zoom: ZoomAccumulator::default(),
+            scale_steps: HashMap::new(),
+            pipes: Vec::new(),
+            rotation: Rotation::default(),
+            gesture_device: None,
+            touchpads: Vec::new(),
+            touchscreens: Vec::new(),
+            contacts: None,
+            swipe_edge: None,
+            wakeups: None,
+            backlog: false,
+            calibration: Calibration::load(),
+            device_calibration: None,
+            device: None,
+            units: DeviceUnits::default(),
+            held_drag: None,
+            drag_outputs: None,
+            locks_seen: session_lock::locks(),
+            touch: None,
+            modifier_keys: ModifierKeys::default(),
+            hold_rest: None,
+            swipe_rest: None,
         };
         handler.update_cache();
         handler
     }
 
-    pub fn init(&mut self, input: &mut Libinput) -> Result<()> {
+    /// Assign libinput to the seat or add the devices of `source`, and pick up the devices
+    /// already plugged in
+    pub fn init(&mut self, input: &mut Libinput, source: &InputSource) -> Result<()> {
         log::debug!("{:?}  {:?}", &self, &input);
-        self.init_ctx(input)
-            .map_err(|_| miette!("Could not initialize libinput"))?;
-        if self.has_gesture_device(input) {
-            Ok(())
-        } else {
-            Err(miette!("Could not find gesture device"))
+        log::info!("Reading gestures on {}", source);
+        match source {
+            InputSource::Seat(seat) => input
+                .udev_assign_seat(seat)
+                .map_err(|_| EventLoopError::Libinput(seat.to_string()))?,
+            InputSource::Paths(paths) => {
+                for path in paths {
+                    let added = path.to_str().and_then(|p| input.path_add_device(p));
+                    if added.is_none() {
+                        return Err(EventLoopError::OpenDevice(path.clone()).into());
+                    }
+                }
+            }
+        }
+        log::debug!("Looking for gesture device");
+        input.dispatch().map_err(EventLoopError::Dispatch)?;
+        // Only devices are reported before the first dispatch of the event loop
+        for event in &mut *input {
+            if let Event::Device(DeviceEvent::Added(e)) = event {
+                if let Some((sysname, touchpad)) = self.followed_device(&e.device()) {
+                    log::info!("Found gesture device {} ({})", e.device().name(), sysname);
+                    self.device_added(sysname, touchpad);
+                }
+            }
+        }
+        if self.waiting_for_device() {
+            log::warn!("No gesture device found, waiting for a touchpad to be plugged in");
         }
+        Ok(())
     }
 
-    fn init_ctx(&mut self, input: &mut Libinput) -> Result<(), ()> {
-        input.udev_assign_seat("seat0")?;
-        Ok(())
+    /// No touchpad, nor a touchscreen with `touchscreen`, is plugged in
+    fn waiting_for_device(&self) -> bool {
+        self.touchpads.is_empty() && self.touchscreens.is_empty()
     }
 
-    fn has_gesture_device(&mut self, input: &mut Libinput) -> bool {
-        log::debug!("Looking for gesture device");
-        if let Err(e) = input.dispatch() {
-            log::error!("Failed to dispatch input events: {}", e);
-            return false;
+    /// Sysname of a touchpad (true) or, with `touchscreen`, a touchscreen (false)
+    fn followed_device(&self, device: &input::Device) -> Option<(String, bool)> {
+        log::debug!("Device: {:?}", device);
+        let touchpad = device.has_capability(DeviceCapability::Gesture);
+        let touchscreen = self.touch.is_some() && device.has_capability(DeviceCapability::Touch);
+        if !self
+            .cache
+            .device_filter
+            .accepts(device.name(), device.sysname())
+        {
+            if touchpad || touchscreen {
+                log::info!("Ignoring {} ({})", device.name(), device.sysname());
+            }
+            return None;
+        }
+        (touchpad || touchscreen).then(|| (device.sysname().to_string(), touchpad))
+    }
+
+    /// Whether gestures of `device` are handled, checked when one begins
+    fn accepts_device(&self, device: &input::Device) -> bool {
+        let accepted = self
+            .cache
+            .device_filter
+            .accepts(device.name(), device.sysname());
+        if !accepted {
+            trace_event!("gesture ignored: device {} filtered", device.name());
         }
+        accepted
+    }
 
-        for event in &mut *input {
-            if let Event::Device(e) = event {
-                log::debug!("Device: {:?}", &e);
-                if e.device().has_capability(DeviceCapability::Gesture) {
-                    log::debug!("Found gesture device");
-                    return true;
-                }
+    fn handle_device_event(
+        &mut self,
+        event: DeviceEvent,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
+        let device = event.device();
+        let Some((sysname, touchpad)) = self.followed_device(&device) else {
+            return Ok(());
+        };
+        match event {
+            DeviceEvent::Added(_) => {
+                log::info!("Gesture device added: {} ({})", device.name(), sysname);
+                self.device_added(sysname, touchpad);
+                Ok(())
+            }
+            DeviceEvent::Removed(_) => {
+                log::info!("Gesture device removed: {} ({})", device.name(), sysname);
+                self.device_removed(&sysname, touchpad, mh)
             }
+            _ => Ok(()),
         }
+    }
 
-        log::debug!("No gesture device found");
-        false
+    /// Follow a touchpad or touchscreen that was plugged in, the first touchpad gets the
+    /// contact tracker
+    fn device_added(&mut self, sysname: String, touchpad: bool) {
+        trace_event!("device added: {}", sysname);
+        if !touchpad {
+            self.touchscreens.push(sysname);
+            return;
+        }
+        if self.touchpads.is_empty() && self.contacts.is_none() {
+            self.gesture_device = Some(Path::new("/dev/input").join(&sysname));
+            self.update_cache();
+        }
+        self.touchpads.push(sysname);
+    }
+
+    /// Forget a device that went away, a gesture in flight on it is cancelled so no drag or
+    /// hold is left behind, and contacts are tracked on a remaining touchpad
+    fn device_removed(
+        &mut self,
+        sysname: &str,
+        touchpad: bool,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
+        trace_event!("device removed: {}", sysname);
+        if !touchpad {
+            self.touchscreens.retain(|s| s != sysname);
+            return Ok(());
+        }
+        self.touchpads.retain(|s| s != sysname);
+        self.abandon_gesture("device removal", mh)?;
+        self.contacts = None;
+        self.gesture_device = self
+            .touchpads
+            .first()
+            .map(|s| Path::new("/dev/input").join(s));
+        if self.waiting_for_device() {
+            log::warn!("Last gesture device removed, waiting for a touchpad to be plugged in");
+        } else {
+            self.update_cache();
+        }
+        Ok(())
     }
 
-    pub fn main_loop(&mut self, input: &mut Libinput, mh: &mut MouseHandler) -> Result<()> {
+    pub fn main_loop(&mut self, input: &mut Libinput, mh: &mut impl MouseActions) -> Result<()> {
+        let mut takeover_since = None;
+        let mut last_input = Instant::now();
+        let mut paused = false;
+        if wakeups::enabled() {
+            log::info!("Profiling event loop wakeups");
+            self.wakeups = Some(WakeupProfile::new(Instant::now()));
+        }
         loop {
+            crate::systemd::heartbeat();
             if crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
                 log::info!("Received shutdown signal, exiting event loop");
                 break;
             }
+            if crate::ipc::takeover_requested() {
+                let since = *takeover_since.get_or_insert_with(Instant::now);
+                if self.settle_for_takeover(since.elapsed() >= crate::ipc::TAKEOVER_GRACE, mh)? {
+                    log::info!("Releasing devices for the new instance");
+                    break;
+                }
+            }
 
+            if !paused && self.idle_for(last_input.elapsed()) {
+                log::info!(
+                    "No input for {:?}, pausing until the next event",
+                    last_input.elapsed()
+                );
+                trace_event!("paused after idle");
+                paused = true;
+            }
+
+            // Left over events are handled right away, without waiting for the fd
+            let timeout = if self.backlog {
+                0
+            } else if paused || self.waiting_for_device() {
+                PAUSED_POLL_TIMEOUT
+            } else {
+                self.next_deadline().map_or(100, |d| {
+                    d.saturating_duration_since(Instant::now())
+                        .as_millis()
+                        .min(100) as u16
+                })
+            };
             let mut fds = [PollFd::new(input.as_fd(), PollFlags::POLLIN)];
-            match poll(&mut fds, PollTimeout::from(100u16)) {
-                Ok(_) => {
+            let polled = Instant::now();
+            let result = poll(&mut fds, PollTimeout::from(timeout));
+            if let Some(profile) = &mut self.wakeups {
+                profile.spent(Subsystem::Poll, polled.elapsed());
+                profile.wakeup(match result {
+                    Ok(0) => Wakeup::Timeout,
+                    Ok(_) => Wakeup::Input,
+                    Err(_) => Wakeup::Interrupted,
+                });
+            }
+            match result {
+                Ok(ready) => {
+                    if ready > 0 {
+                        last_input = Instant::now();
+                        if paused {
+                            paused = false;
+                            self.resume_after_idle();
+                        }
+                    }
                     self.handle_event(input, mh)?;
+                    let fired = Instant::now();
+                    self.fire_hold_timers(fired)?;
+                    self.fire_swipe_repeats(fired)?;
+                    self.glide_drag(fired, mh);
+                    self.release_drag_if_session_lost(fired, mh)?;
+                    if let Some(profile) = &mut self.wakeups {
+                        profile.spent(Subsystem::HoldTimers, fired.elapsed());
+                        profile.report_if_due(Instant::now());
+                    }
                 }
                 Err(e) => {
                     if e != nix::errno::Errno::EINTR {
-                        return Err(miette!("Poll error: {}", e));
+                        return Err(EventLoopError::Poll(e).into());
                     }
                 }
             }
         }
+        if let Some(profile) = &self.wakeups {
+            log::info!("{}", profile.summary(Instant::now()));
+        }
         Ok(())
     }
 
-    pub fn handle_event(&mut self, input: &mut Libinput, mh: &mut MouseHandler) -> Result<()> {
-        input
-            .dispatch()
-            .map_err(|e| miette!("Failed to dispatch input events: {}", e))?;
-        for event in input {
-            if let Event::Gesture(e) = event {
-                match e {
-                    GestureEvent::Pinch(e) => self.handle_pinch_event(e)?,
-                    GestureEvent::Swipe(e) => self.handle_swipe_event(e, mh)?,
-                    GestureEvent::Hold(e) => self.handle_hold_event(e)?,
-                    _ => (),
-                }
+    /// Whether the handler is idle so a new instance can take over, `force` cancels the gesture
+    /// in flight instead of waiting for it so no drag is left pressed
+    pub fn settle_for_takeover(&mut self, force: bool, mh: &mut impl MouseActions) -> Result<bool> {
+        if !force && (self.drag_glide.is_some() || !matches!(self.event, Gesture::None)) {
+            return Ok(false);
+        }
+        self.abandon_gesture("takeover", mh)?;
+        Ok(true)
+    }
+
+    /// Cancel the gesture in flight and let go of a gliding drag
+    fn abandon_gesture(&mut self, reason: &str, mh: &mut impl MouseActions) -> Result<()> {
+        if self.drag_glide.take().is_some() {
+            trace_event!("drag glide stopped for {}", reason);
+            mh.configure_drag(None);
+            mh.mouse_up_delay(1, 0);
+        }
+        match self.event {
+            Gesture::None => return Ok(()),
+            Gesture::Swipe(_) => self.handle_swipe_cancel(mh)?,
+            Gesture::Pinch(_) => self.handle_pinch_cancel()?,
+            _ => {
+                trace_event!("gesture cancelled for {}", reason);
+                self.event = Gesture::None;
             }
         }
+        self.hold_timers.clear();
+        self.held_drag = None;
         Ok(())
     }
 
-    fn handle_hold_event(&mut self, event: GestureHoldEvent) -> Result<()> {
-        self.refresh_cache_if_needed();
-        match event {
-            GestureHoldEvent::Begin(e) => {
-                self.event = Gesture::Hold(Hold {
-                    fingers: e.finger_count(),
-                    action: None,
-                })
-            }
-            GestureHoldEvent::End(_e) => {
-                if let Gesture::Hold(s) = &self.event {
-                    log::debug!("Hold: {:?}", &s.fingers);
-                    if let Some(gestures) = self.cache.hold_gestures.get(&s.fingers) {
-                        for gesture in gestures {
-                            if let Gesture::Hold(j) = gesture {
-                                exec_command_from_string(
-                                    j.action.as_deref().unwrap_or(""),
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                )?;
-                            }
-                        }
-                    }
-                }
+    /// Let go of a held drag right away when the session locks or a display is disconnected,
+    /// the compositor would otherwise see the button still pressed after unlocking
+    fn release_drag_if_session_lost(
+        &mut self,
+        now: Instant,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
+        let locks = session_lock::locks();
+        let locked = locks != self.locks_seen;
+        self.locks_seen = locks;
+        let Some(drag) = &mut self.held_drag else {
+            return Ok(());
+        };
+        if drag.release_at.is_some_and(|at| at <= now) {
+            self.held_drag = None;
+            return Ok(());
+        }
+        let reason = if locked {
+            "session locked"
+        } else if now.duration_since(drag.checked) >= DISPLAY_CHECK_INTERVAL {
+            drag.checked = now;
+            match (drag.displays, conditions::connected_displays()) {
+                (Some(before), Some(now)) if now < before => "display disconnected",
+                _ => return Ok(()),
             }
-            _ => (),
+        } else {
+            return Ok(());
+        };
+
+        log::info!("Releasing the held drag: {}", reason);
+        trace_event!("drag released: {}", reason);
+        self.held_drag = None;
+        self.hold_timers.clear();
+        self.drag_glide = None;
+        if let Gesture::Swipe(_) = self.event {
+            self.handle_swipe_cancel(mh)?;
+        } else {
+            mh.configure_drag(None);
+            mh.mouse_up_delay(1, 0);
         }
         Ok(())
     }
 
-    fn handle_pinch_event(&mut self, event: GesturePinchEvent) -> Result<()> {
-        self.refresh_cache_if_needed();
+    /// Whether `idle-pause` applies after `idle` without input, never in the middle of a gesture
+    /// or while something is still due
+    fn idle_for(&self, idle: Duration) -> bool {
+        let Some(minutes) = self.config.read().idle_pause else {
+            return false;
+        };
+        idle >= Duration::from_secs(minutes * 60)
+            && matches!(self.event, Gesture::None)
+            && self.held_drag.is_none()
+            && self.next_deadline().is_none()
+    }
+
+    /// Forget state that went stale while paused, the first gesture after it starts like the
+    /// first one after startup
+    fn resume_after_idle(&mut self) {
+        log::info!("Input after idle, resuming");
+        trace_event!("resumed after idle");
+        self.throttle.last_update = Instant::now();
+        self.rule_throttles.clear();
+        self.motion = MotionTracker::default();
+        self.drag_remainder = DragRemainder::default();
+        self.drag_filter = None;
+        self.zoom = ZoomAccumulator::default();
+        self.scale_steps.clear();
+        self.pipes.clear();
+        self.rotation = Rotation::default();
+        self.update_cache();
+    }
+
+    /// When the next hold timer or swipe repeat is due
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        let repeats = self.swipe_repeats.iter().filter_map(|r| r.deadline);
+        let glide = self.drag_glide.as_ref().map(|g| g.deadline);
+        self.hold_timers
+            .iter()
+            .map(|t| t.deadline)
+            .chain(repeats)
+            .chain(glide)
+            .min()
+    }
+
+    /// Run `trigger="timeout"` hold actions whose duration has elapsed
+    fn fire_hold_timers(&mut self, now: std::time::Instant) -> Result<()> {
+        for timer in &mut self.hold_timers {
+            if timer.deadline > now {
+                continue;
+            }
+            log::debug!("Hold timeout reached: {:?}", timer.action);
+            trace_event!("hold timeout reached");
+            let entry = fired(&timer.log, format!("{}-finger hold", timer.fingers));
+            let vars = CommandVars {
+                elapsed_ms: now.duration_since(timer.began).as_millis() as u64,
+                ..self.motion.vars()
+            };
+            exec_fired_command(&timer.action, &vars, &timer.spawn, entry)?;
+            press_key(timer.key.as_ref());
+            run_script(timer.script.as_ref(), &vars, &timer.spawn);
+            type_text(timer.type_text.as_deref(), timer.paste);
+            match timer.repeat {
+                Some(repeat) => timer.deadline = now + repeat,
+                None => timer.done = true,
+            }
+        }
+        self.hold_timers.retain(|t| !t.done);
+        Ok(())
+    }
+
+    /// Move the pointer of a gliding drag, and release the button once it has stopped
+    fn glide_drag(&mut self, now: Instant, mh: &mut impl MouseActions) {
+        let Some(glide) = &mut self.drag_glide else {
+            return;
+        };
+        if glide.deadline > now {
+            return;
+        }
+        let (x, y) = glide.step(now);
+        mh.move_mouse_relative(x, y);
+        if !glide.stopped() {
+            return;
+        }
+        let delay = glide.mouse_up_delay;
+        trace_event!("drag glide stopped: release drag after {}ms", delay);
+        self.drag_glide = None;
+        mh.mouse_up_delay(1, delay);
+        if let Some(drag) = &mut self.held_drag {
+            drag.release_at = Some(now + Duration::from_millis(delay.max(0) as u64));
+        }
+    }
+
+    /// Repeat swipes held past their `repeat-distance` whose interval has elapsed, a held swipe
+    /// sends no updates so this runs from the event loop's timeout
+    fn fire_swipe_repeats(&mut self, now: std::time::Instant) -> Result<()> {
+        let vars = self.motion.vars();
+        for repeat in &mut self.swipe_repeats {
+            if repeat.deadline.is_none_or(|deadline| deadline > now) {
+                continue;
+            }
+            let j = &repeat.rule;
+            trace_event!("swipe rule direction={:?} repeated", j.direction);
+            let entry = fired(
+                &j.log,
+                format!(
+                    "{}-finger swipe {} (repeat)",
+                    j.fingers,
+                    format!("{:?}", j.direction).to_lowercase()
+                ),
+            );
+            if let Some(command) = &j.repeating.repeat {
+                exec_fired_command(command, &vars, &j.spawn, entry)?;
+            } else {
+                exec_fired_command(j.end.as_deref().unwrap_or(""), &vars, &j.spawn, entry)?;
+                press_key(j.key.as_ref());
+                run_script(j.script.as_ref(), &vars, &j.spawn);
+                type_text(j.type_text.as_deref(), j.paste);
+                hyprland::dispatch(j.hyprland.as_deref(), &vars);
+                sway::run(j.sway.as_deref(), &vars);
+            }
+            repeat.deadline = Some(now + repeat.interval);
+            repeat.repeated = true;
+        }
+        Ok(())
+    }
+
+    pub fn handle_event(&mut self, input: &mut Libinput, mh: &mut impl MouseActions) -> Result<()> {
+        let dispatched = Instant::now();
+        // Read the devices only once the queue is drained, so a backlog is handled in order
+        if !self.backlog {
+            input.dispatch().map_err(EventLoopError::Dispatch)?;
+        }
+        let handled = Instant::now();
+        let mut events = 0;
+        for event in input.by_ref().take(EVENT_BUDGET) {
+            events += 1;
+            match event {
+                Event::Gesture(GestureEvent::Pinch(e)) => self.handle_pinch_event(e, mh)?,
+                Event::Gesture(GestureEvent::Swipe(e)) => self.handle_swipe_event(e, mh)?,
+                Event::Gesture(GestureEvent::Hold(e)) => self.handle_hold_event(e)?,
+                Event::Touch(e) if self.touch.is_some() => self.handle_touch_event(e, mh)?,
+                Event::Keyboard(KeyboardEvent::Key(e)) => self
+                    .modifier_keys
+                    .key(e.key(), e.key_state() == KeyState::Pressed),
+                Event::Device(e) => self.handle_device_event(e, mh)?,
+                _ => (),
+            }
+        }
+        if events == EVENT_BUDGET && !self.backlog {
+            log::debug!(
+                "More than {} queued input events, deferring the rest",
+                EVENT_BUDGET
+            );
+        }
+        self.backlog = events == EVENT_BUDGET;
+        if let Some(profile) = &mut self.wakeups {
+            profile.spent(Subsystem::Dispatch, handled.duration_since(dispatched));
+            profile.spent(Subsystem::Gestures, handled.elapsed());
+            profile.batch(events);
+        }
+        Ok(())
+    }
+
+    /// Handle a generated event as if libinput had reported it, for `gestures stress`
+    pub fn handle_synthetic(
+        &mut self,
+        event: SyntheticEvent,
+        time_usec: u64,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
         match event {
-            GesturePinchEvent::Begin(e) => {
-                self.event = Gesture::Pinch(Pinch {
-                    fingers: e.finger_count(),
-                    direction: PinchDir::Any,
-                    update: None,
-                    start: None,
-                    end: None,
-                });
-                if let Gesture::Pinch(s) = &self.event {
-                    if let Some(gestures) = self.cache.pinch_gestures.get(&s.fingers) {
-                        for gesture in gestures {
-                            if let Gesture::Pinch(j) = gesture {
-                                if (j.direction == s.direction || j.direction == PinchDir::Any)
-                                    && j.fingers == s.fingers
-                                {
-                                    exec_command_from_string(
-                                        j.start.as_deref().unwrap_or(""),
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                    )?;
-                                }
-                            }
+            SyntheticEvent::SwipeBegin { fingers } => {
+                self.refresh_cache_if_needed();
+                self.motion.reset("swipe", fingers, time_usec);
+                self.handle_swipe_begin(fingers, mh)
+            }
+            SyntheticEvent::SwipeUpdate { dx, dy } => {
+                self.motion.swipe(dx, dy, time_usec);
+                self.handle_swipe_update(dx, dy, mh)
+            }
+            SyntheticEvent::SwipeEnd => self.handle_swipe_end(mh),
+            SyntheticEvent::SwipeCancel => self.handle_swipe_cancel(mh),
+            SyntheticEvent::PinchBegin { fingers } => {
+                self.refresh_cache_if_needed();
+                self.handle_pinch_begin(fingers, time_usec)
+            }
+            SyntheticEvent::PinchUpdate { scale, angle } => {
+                self.motion.pinch(scale, time_usec);
+                let Gesture::Pinch(p) = &self.event else {
+                    return Ok(());
+                };
+                let fingers = p.fingers;
+                let dir = PinchDir::dir(scale, angle);
+                self.handle_pinch_update(fingers, &dir, scale, angle, mh)?;
+                if let Gesture::Pinch(p) = &mut self.event {
+                    p.direction = dir;
+                }
+                Ok(())
+            }
+            SyntheticEvent::PinchEnd => self.handle_pinch_end(),
+            SyntheticEvent::PinchCancel => self.handle_pinch_cancel(),
+            SyntheticEvent::HoldBegin { fingers } => {
+                self.refresh_cache_if_needed();
+                self.motion.reset("hold", fingers, time_usec);
+                self.handle_hold_begin(fingers)
+            }
+            SyntheticEvent::HoldEnd => self.handle_hold_end(time_usec),
+            SyntheticEvent::HoldCancel => {
+                if let Gesture::Hold(_) = self.event {
+                    if self.hold_turns_into_move(time_usec) {
+                        return Ok(());
+                    }
+                    trace_event!("hold cancelled");
+                    self.hold_timers.clear();
+                    self.event = Gesture::None;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Feed a touchscreen event to the tracker, the gestures it recognizes are handled like the
+    /// touchpad's
+    fn handle_touch_event(&mut self, event: TouchEvent, mh: &mut impl MouseActions) -> Result<()> {
+        let device = event.device();
+        if !self
+            .cache
+            .device_filter
+            .accepts(device.name(), device.sysname())
+        {
+            return Ok(());
+        }
+        let Some(tracker) = &mut self.touch else {
+            return Ok(());
+        };
+        let time_usec = event.time_usec();
+        let events = match &event {
+            TouchEvent::Down(e) => {
+                tracker.down(e.seat_slot(), e.x(), e.y());
+                return Ok(());
+            }
+            TouchEvent::Motion(e) => {
+                tracker.motion(e.seat_slot(), e.x(), e.y());
+                return Ok(());
+            }
+            TouchEvent::Up(e) => {
+                tracker.up(e.seat_slot());
+                return Ok(());
+            }
+            TouchEvent::Cancel(_) => tracker.cancel(),
+            TouchEvent::Frame(_) => tracker.frame(),
+            _ => return Ok(()),
+        };
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.select_device(&event.device());
+        for synthetic in events {
+            self.handle_synthetic(synthetic, time_usec, mh)?;
+        }
+        Ok(())
+    }
+
+    /// Fingers of the configured swipe and pinch rules, used to generate matching events
+    pub fn configured_fingers(&self) -> (Vec<i32>, Vec<i32>) {
+        let sorted = |map: &HashMap<i32, Vec<Gesture>>| {
+            let mut fingers: Vec<i32> = map.keys().copied().collect();
+            fingers.sort_unstable();
+            fingers
+        };
+        let mut pinch = sorted(&self.cache.pinch_gestures);
+        // Rotations arrive as pinch events
+        pinch.extend(sorted(&self.cache.rotate_gestures));
+        pinch.sort_unstable();
+        pinch.dedup();
+        (sorted(&self.cache.swipe_gestures), pinch)
+    }
+
+    fn handle_hold_event(&mut self, event: GestureHoldEvent) -> Result<()> {
+        match event {
+            GestureHoldEvent::Begin(e) => {
+                self.select_device(&e.device());
+                self.refresh_cache_if_needed();
+                let fingers = self.cache.finger_remap.apply(e.finger_count());
+                if !self.accepts_device(&e.device())
+                    || self.rejects_gesture(fingers)
+                    || Self::is_stale(e.time_usec())
+                {
+                    self.event = Gesture::None;
+                    return Ok(());
+                }
+                self.motion.reset("hold", fingers, e.time_usec());
+                self.handle_hold_begin(fingers)?;
+            }
+            GestureHoldEvent::End(e) if e.cancelled() => {
+                if !self.hold_turns_into_move(e.time_usec()) {
+                    self.handle_hold_end(e.time_usec())?;
+                }
+            }
+            GestureHoldEvent::End(e) => self.handle_hold_end(e.time_usec())?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_hold_begin(&mut self, fingers: i32) -> Result<()> {
+        trace_event!("hold begin fingers={}", fingers);
+        self.event = Gesture::Hold(Hold::new(fingers));
+        let Some(gestures) = self.cache.hold_gestures.get(&fingers) else {
+            self.hold_timers.clear();
+            return Ok(());
+        };
+        self.hold_timers = HoldTimer::schedule(gestures, std::time::Instant::now());
+        for gesture in gestures {
+            if let Gesture::Hold(j) = gesture {
+                if let Some(start) = &j.start {
+                    exec_command_from_string(start, &self.motion.vars(), &j.spawn)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The fingers of the hold moved at `time_usec`: when they rested long enough for an
+    /// `after-hold` swipe rule, the hold ends without firing and the swipe libinput begins next
+    /// picks it up
+    fn hold_turns_into_move(&mut self, time_usec: u64) -> bool {
+        let Gesture::Hold(h) = &self.event else {
+            return false;
+        };
+        let fingers = h.fingers;
+        let held_ms = time_usec.saturating_sub(self.motion.start_usec) / 1000;
+        let moves = self
+            .cache
+            .swipe_gestures
+            .get(&fingers)
+            .is_some_and(|gestures| {
+                gestures.iter().any(
+                |g| matches!(g, Gesture::Swipe(j) if j.after_hold.is_some_and(|ms| held_ms >= ms)),
+            )
+            });
+        if !moves {
+            return false;
+        }
+        trace_event!(
+            "hold fingers={} held {}ms, turns into a move",
+            fingers,
+            held_ms
+        );
+        self.hold_rest = Some(HoldRest {
+            fingers,
+            held_ms,
+            at: Instant::now(),
+        });
+        self.hold_timers.clear();
+        self.event = Gesture::None;
+        true
+    }
+
+    /// Fire the rules of the hold that began at `motion.start_usec`, lifted at `time_usec`
+    fn handle_hold_end(&mut self, time_usec: u64) -> Result<()> {
+        self.hold_timers.clear();
+        self.motion.last_usec = self.motion.last_usec.max(time_usec);
+        let vars = self.motion.vars();
+        if let Gesture::Hold(s) = &self.event {
+            let held_ms = time_usec.saturating_sub(self.motion.start_usec) / 1000;
+            log::debug!("Hold: {:?} for {}ms", &s.fingers, held_ms);
+            trace_event!("hold end fingers={} held={}ms", s.fingers, held_ms);
+            if let Some(gestures) = self.cache.hold_gestures.get(&s.fingers) {
+                for gesture in gestures {
+                    if let Gesture::Hold(j) = gesture {
+                        if !j.held_for(held_ms) {
+                            trace_event!("hold rule skipped: held {}ms", held_ms);
+                            continue;
+                        }
+                        if let Some(end) = &j.end {
+                            exec_command_from_string(end, &vars, &j.spawn)?;
                         }
+                        if j.trigger() != HoldTrigger::Release {
+                            trace_event!("hold rule skipped: fires on timeout");
+                            continue;
+                        }
+                        trace_event!("hold rule fingers={} matched", j.fingers);
+                        let entry = fired(&j.log, format!("{}-finger hold", j.fingers));
+                        exec_fired_command(
+                            j.action.as_deref().unwrap_or(""),
+                            &vars,
+                            &j.spawn,
+                            entry,
+                        )?;
+                        press_key(j.key.as_ref());
+                        run_script(j.script.as_ref(), &vars, &j.spawn);
+                        type_text(j.type_text.as_deref(), j.paste);
                     }
                 }
             }
+        }
+        Ok(())
+    }
+
+    fn handle_pinch_event(
+        &mut self,
+        event: GesturePinchEvent,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
+        match event {
+            GesturePinchEvent::Begin(e) => {
+                self.select_device(&e.device());
+                self.refresh_cache_if_needed();
+                let fingers = self.cache.finger_remap.apply(e.finger_count());
+                if !self.accepts_device(&e.device())
+                    || self.rejects_gesture(fingers)
+                    || Self::is_stale(e.time_usec())
+                {
+                    self.event = Gesture::None;
+                    return Ok(());
+                }
+                self.handle_pinch_begin(fingers, e.time_usec())?;
+            }
             GesturePinchEvent::Update(e) => {
-                let scale = e.scale();
+                let fingers = self.cache.finger_remap.apply(e.finger_count());
+                if !self.handle_finger_change(fingers, e.time_usec(), mh)? {
+                    return Ok(());
+                }
+                let scale = self
+                    .device_calibration
+                    .as_ref()
+                    .map_or(e.scale(), |c| c.scale_pinch(e.scale()));
                 let delta_angle = e.angle_delta();
+                self.motion.pinch(scale, e.time_usec());
                 if let Gesture::Pinch(s) = &self.event {
                     let dir = PinchDir::dir(scale, delta_angle);
                     let fingers = s.fingers;
@@ -268,52 +1574,384 @@ impl EventHandler {
                         &dir,
                         &s.fingers
                     );
-                    if let Some(gestures) = self.cache.pinch_gestures.get(&fingers) {
-                        for gesture in gestures {
-                            if let Gesture::Pinch(j) = gesture {
-                                if j.direction == dir || j.direction == PinchDir::Any {
-                                    exec_update_command_from_string(
-                                        j.update.as_deref().unwrap_or(""),
-                                        0.0,
-                                        0.0,
-                                        delta_angle,
-                                        scale,
-                                    )?;
-                                }
-                            }
-                        }
-                    }
+                    self.handle_pinch_update(fingers, &dir, scale, delta_angle, mh)?;
                     self.event = Gesture::Pinch(Pinch {
                         fingers,
                         direction: dir,
                         update: None,
                         start: None,
                         end: None,
+                        cancel: None,
+                        key: None,
+                        script: None,
+                        type_text: None,
+                        paste: None,
+                        hyprland: None,
+                        hyprland_update: None,
+                        sway: None,
+                        sway_update: None,
+                        rotate_step_deg: None,
+                        min_scale_delta: None,
+                        step: None,
+                        update_rate: None,
+                        conditions: Conditions::default(),
+                        spawn: SpawnOptions::default(),
+                        log: RuleLog::default(),
+                        pipe: Box::default(),
                     })
                 }
             }
-            GesturePinchEvent::End(_e) => {
-                if let Gesture::Pinch(s) = &self.event {
-                    if let Some(gestures) = self.cache.pinch_gestures.get(&s.fingers) {
-                        for gesture in gestures {
-                            if let Gesture::Pinch(j) = gesture {
-                                if (j.direction == s.direction || j.direction == PinchDir::Any)
-                                    && j.fingers == s.fingers
-                                {
-                                    exec_command_from_string(
-                                        j.end.as_deref().unwrap_or(""),
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                    )?;
-                                }
+            GesturePinchEvent::End(e) => {
+                if e.cancelled() {
+                    self.handle_pinch_cancel()?
+                } else {
+                    self.handle_pinch_end()?
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_pinch_begin(&mut self, fingers: i32, time_usec: u64) -> Result<()> {
+        self.motion.reset("pinch", fingers, time_usec);
+        let vars = self.motion.vars();
+        trace_event!("pinch begin fingers={}", fingers);
+        self.zoom = ZoomAccumulator::default();
+        self.scale_steps.clear();
+        self.pipes.clear();
+        self.rotation = Rotation::default();
+        self.event = Gesture::Pinch(Pinch {
+            fingers,
+            direction: PinchDir::Any,
+            update: None,
+            start: None,
+            end: None,
+            cancel: None,
+            key: None,
+            script: None,
+            type_text: None,
+            paste: None,
+            hyprland: None,
+            hyprland_update: None,
+            sway: None,
+            sway_update: None,
+            rotate_step_deg: None,
+            min_scale_delta: None,
+            step: None,
+            update_rate: None,
+            conditions: Conditions::default(),
+            spawn: SpawnOptions::default(),
+            log: RuleLog::default(),
+            pipe: Box::default(),
+        });
+        if let Some(gestures) = self.cache.pinch_gestures.get(&fingers) {
+            for gesture in gestures {
+                if let Gesture::Pinch(j) = gesture {
+                    self.pipes.extend(j.pipe.open(&j.spawn));
+                    if j.direction == PinchDir::Any && j.fingers == fingers {
+                        exec_command_from_string(
+                            j.start.as_deref().unwrap_or(""),
+                            &vars,
+                            &j.spawn,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_pinch_end(&mut self) -> Result<()> {
+        self.pipes.clear();
+        let vars = self.motion.vars();
+        let recorded = history::recorded();
+        if let Gesture::Pinch(s) = &self.event {
+            trace_event!(
+                "pinch end fingers={} direction={:?}",
+                s.fingers,
+                s.direction
+            );
+            if let Some(gestures) = self.cache.pinch_gestures.get(&s.fingers) {
+                for gesture in gestures {
+                    if let Gesture::Pinch(j) = gesture {
+                        if j.ends_on(&s.direction, self.motion.scale) && j.fingers == s.fingers {
+                            trace_event!("pinch rule direction={:?} matched", j.direction);
+                            let entry = fired(
+                                &j.log,
+                                format!(
+                                    "{}-finger pinch {}",
+                                    s.fingers,
+                                    format!("{:?}", s.direction).to_lowercase()
+                                ),
+                            );
+                            exec_fired_command(
+                                j.end.as_deref().unwrap_or(""),
+                                &vars,
+                                &j.spawn,
+                                entry,
+                            )?;
+                            press_key(j.key.as_ref());
+                            run_script(j.script.as_ref(), &vars, &j.spawn);
+                            type_text(j.type_text.as_deref(), j.paste);
+                            hyprland::dispatch(j.hyprland.as_deref(), &vars);
+                            sway::run(j.sway.as_deref(), &vars);
+                        } else {
+                            rule_event!(
+                                j.log,
+                                "pinch rule direction={:?} skipped: direction mismatch or scale {:.2} \
+                                 short of min-scale-delta",
+                                j.direction,
+                                self.motion.scale
+                            );
+                        }
+                    }
+                }
+            }
+            let gesture = format!(
+                "{}-finger pinch {}",
+                s.fingers,
+                format!("{:?}", s.direction).to_lowercase()
+            );
+            let fingers = s.fingers;
+            self.handle_rotate_end(fingers)?;
+            if history::recorded() == recorded {
+                history::unmatched(&gesture);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the `cancel` command of the pinch rules instead of their end, started rotate rules
+    /// don't finish
+    fn handle_pinch_cancel(&mut self) -> Result<()> {
+        self.pipes.clear();
+        let vars = self.motion.vars();
+        let Gesture::Pinch(s) = &self.event else {
+            return Ok(());
+        };
+        trace_event!("pinch cancelled fingers={}", s.fingers);
+        if let Some(gestures) = self.cache.pinch_gestures.get(&s.fingers) {
+            for gesture in gestures {
+                if let Gesture::Pinch(j) = gesture {
+                    if let Some(cancel) = &j.cancel {
+                        rule_event!(j.log, "pinch rule direction={:?} cancelled", j.direction);
+                        exec_command_from_string(cancel, &vars, &j.spawn)?;
+                    }
+                }
+            }
+        }
+        self.rotation = Rotation::default();
+        self.event = Gesture::None;
+        Ok(())
+    }
+
+    /// Accumulate the pinch's angle and run rotate rules that turned past their threshold
+    fn handle_rotate_update(&mut self, fingers: i32, delta_angle: f64) -> Result<()> {
+        self.rotation.angle += delta_angle;
+        let Some(gestures) = self.cache.rotate_gestures.get(&fingers) else {
+            return Ok(());
+        };
+        let vars = CommandVars {
+            delta_angle,
+            angle: self.rotation.angle,
+            direction: RotateDir::of(self.rotation.angle).name(),
+            gesture_type: "rotate",
+            ..self.motion.vars()
+        };
+        for (i, gesture) in gestures.iter().enumerate() {
+            let Gesture::Rotate(j) = gesture else {
+                continue;
+            };
+            if let Some(step) = j.step() {
+                let fired = self.rotation.steps.entry(i).or_default();
+                let steps = knob_steps(self.rotation.angle, step, fired);
+                let dir = RotateDir::of(steps as f64);
+                if steps == 0 || (j.direction != RotateDir::Any && j.direction != dir) {
+                    continue;
+                }
+                let vars = CommandVars {
+                    direction: dir.name(),
+                    ..vars
+                };
+                if !self.rotation.started.contains(&i) {
+                    self.rotation.started.push(i);
+                    exec_command_from_string(j.start.as_deref().unwrap_or(""), &vars, &j.spawn)?;
+                }
+                trace_event!(
+                    "rotate rule stepped {} time(s) {} at {:.1} degrees",
+                    steps.abs(),
+                    dir.name(),
+                    self.rotation.angle
+                );
+                // Every detent counts, unlike continuous updates none may be dropped
+                for _ in 0..steps.abs() {
+                    exec_command_from_string(j.update.as_deref().unwrap_or(""), &vars, &j.spawn)?;
+                }
+                continue;
+            }
+            if !j.triggered_by(self.rotation.angle) {
+                continue;
+            }
+            if !self.rotation.started.contains(&i) {
+                trace_event!(
+                    "rotate rule direction={:?} started at {:.1} degrees",
+                    j.direction,
+                    self.rotation.angle
+                );
+                self.rotation.started.push(i);
+                exec_command_from_string(j.start.as_deref().unwrap_or(""), &vars, &j.spawn)?;
+            }
+            exec_update_command_from_string(j.update.as_deref().unwrap_or(""), &vars, &j.spawn)?;
+        }
+        Ok(())
+    }
+
+    /// Run the end of started rotate rules whose direction still matches the total rotation
+    fn handle_rotate_end(&mut self, fingers: i32) -> Result<()> {
+        let rotation = std::mem::take(&mut self.rotation);
+        let Some(gestures) = self.cache.rotate_gestures.get(&fingers) else {
+            return Ok(());
+        };
+        let vars = CommandVars {
+            angle: rotation.angle,
+            direction: RotateDir::of(rotation.angle).name(),
+            gesture_type: "rotate",
+            ..self.motion.vars()
+        };
+        for i in rotation.started {
+            let Some(Gesture::Rotate(j)) = gestures.get(i) else {
+                continue;
+            };
+            // Knob rules finish wherever the fingers stopped
+            if j.step().is_some() || j.triggered_by(rotation.angle) {
+                trace_event!("rotate rule direction={:?} matched", j.direction);
+                let entry = fired(
+                    &j.log,
+                    format!(
+                        "{}-finger rotate {}",
+                        fingers,
+                        RotateDir::of(rotation.angle).name()
+                    ),
+                );
+                exec_fired_command(j.end.as_deref().unwrap_or(""), &vars, &j.spawn, entry)?;
+            } else {
+                rule_event!(
+                    j.log,
+                    "rotate rule direction={:?} skipped: turned back to {:.1} degrees",
+                    j.direction,
+                    rotation.angle
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_pinch_update(
+        &mut self,
+        fingers: i32,
+        dir: &PinchDir,
+        scale: f64,
+        delta_angle: f64,
+        mh: &mut impl MouseActions,
+    ) -> Result<()> {
+        monitor_event!(
+            "pinch update fingers={} direction={:?} scale={:.3} angle={:.2}",
+            fingers,
+            dir,
+            scale,
+            delta_angle
+        );
+        let vars = CommandVars {
+            delta_angle,
+            ..self.motion.vars()
+        };
+        let mut zoom_sensitivity = None;
+        let angle = self.rotation.angle + delta_angle;
+        let throttled = Self::throttled_rates(
+            &mut self.rule_throttles,
+            self.cache.pinch_gestures.get(&fingers),
+        );
+        if let Some(gestures) = self.cache.pinch_gestures.get(&fingers) {
+            for (i, gesture) in gestures.iter().enumerate() {
+                if let Gesture::Pinch(j) = gesture {
+                    if let Some(step) = j.rotate_step_deg {
+                        let fired = self.rotation.pinch_steps.entry(i).or_default();
+                        let steps = knob_steps(angle, step.0, fired);
+                        let (dir, turned) = if steps > 0 {
+                            (PinchDir::Clockwise, RotateDir::Cw)
+                        } else {
+                            (PinchDir::CounterClockwise, RotateDir::Ccw)
+                        };
+                        if steps != 0 && (j.direction == dir || j.direction == PinchDir::Any) {
+                            let vars = CommandVars {
+                                angle,
+                                direction: turned.name(),
+                                ..vars
+                            };
+                            for _ in 0..steps.abs() {
+                                exec_command_from_string(
+                                    j.update.as_deref().unwrap_or(""),
+                                    &vars,
+                                    &j.spawn,
+                                )?;
+                                hyprland::dispatch(j.hyprland_update.as_deref(), &vars);
+                                sway::run(j.sway_update.as_deref(), &vars);
+                            }
+                        }
+                        continue;
+                    }
+                    if let Some(step) = j.step {
+                        let fired = self.scale_steps.entry(i).or_default();
+                        let steps = knob_steps(scale - 1.0, step.0, fired);
+                        let dir = if steps > 0 {
+                            PinchDir::Out
+                        } else {
+                            PinchDir::In
+                        };
+                        if steps != 0 && (j.direction == dir || j.direction == PinchDir::Any) {
+                            let vars = CommandVars {
+                                direction: dir.name(),
+                                ..vars
+                            };
+                            for _ in 0..steps.abs() {
+                                exec_command_from_string(
+                                    j.update.as_deref().unwrap_or(""),
+                                    &vars,
+                                    &j.spawn,
+                                )?;
+                                hyprland::dispatch(j.hyprland_update.as_deref(), &vars);
+                                sway::run(j.sway_update.as_deref(), &vars);
                             }
                         }
+                        continue;
+                    }
+                    if j.direction == *dir || j.direction == PinchDir::Any {
+                        let update = j.update.as_deref().unwrap_or("");
+                        match builtin::zoom_sensitivity(update) {
+                            Some(sensitivity) => zoom_sensitivity = Some(sensitivity),
+                            // Zoom follows every update, only commands are held back
+                            None if j.update_rate.is_some_and(|fps| throttled[&fps]) => continue,
+                            None => exec_update_command_from_string(update, &vars, &j.spawn)?,
+                        }
+                        hyprland::dispatch(j.hyprland_update.as_deref(), &vars);
+                        sway::run(j.sway_update.as_deref(), &vars);
                     }
                 }
             }
-            _ => (),
+        }
+
+        self.send_to_pipes(vars);
+        self.handle_rotate_update(fingers, delta_angle)?;
+
+        // Zoom follows the scale in both directions, whichever rule matched
+        if let Some(sensitivity) = zoom_sensitivity {
+            let units = self.zoom.take_units(scale, sensitivity);
+            if units != 0 {
+                mh.zoom(units);
+            }
+        } else {
+            self.zoom.last_scale = scale;
         }
         Ok(())
     }
@@ -324,8 +1962,32 @@ impl EventHandler {
         mh: &mut impl MouseActions,
     ) -> Result<()> {
         match event {
-            GestureSwipeEvent::Begin(e) => self.handle_swipe_begin(e.finger_count(), mh),
-            GestureSwipeEvent::Update(e) => self.handle_swipe_update(e.dx(), e.dy(), mh),
+            GestureSwipeEvent::Begin(e) => {
+                self.select_device(&e.device());
+                self.refresh_cache_if_needed();
+                let fingers = self.cache.finger_remap.apply(e.finger_count());
+                if !self.accepts_device(&e.device())
+                    || self.rejects_gesture(fingers)
+                    || Self::is_stale(e.time_usec())
+                {
+                    self.event = Gesture::None;
+                    return Ok(());
+                }
+                self.motion.reset("swipe", fingers, e.time_usec());
+                self.handle_swipe_begin(fingers, mh)
+            }
+            GestureSwipeEvent::Update(e) => {
+                let fingers = self.cache.finger_remap.apply(e.finger_count());
+                if !self.handle_finger_change(fingers, e.time_usec(), mh)? {
+                    return Ok(());
+                }
+                let (dx, dy) = self
+                    .device_calibration
+                    .as_ref()
+                    .map_or((e.dx(), e.dy()), |c| c.scale_delta(e.dx(), e.dy()));
+                self.motion.swipe(dx, dy, e.time_usec());
+                self.handle_swipe_update(dx, dy, mh)
+            }
             GestureSwipeEvent::End(e) => {
                 if e.cancelled() {
                     self.handle_swipe_cancel(mh)
@@ -342,8 +2004,25 @@ impl EventHandler {
         let mut swipe_map: HashMap<i32, Vec<Gesture>> = HashMap::new();
         let mut pinch_map: HashMap<i32, Vec<Gesture>> = HashMap::new();
         let mut hold_map: HashMap<i32, Vec<Gesture>> = HashMap::new();
+        let mut rotate_map: HashMap<i32, Vec<Gesture>> = HashMap::new();
+        let modifiers = self.modifier_keys.modifiers();
+        let state = SystemState::on_device(self.device.as_deref()).with_modifiers(modifiers);
+        self.cache.focus_generation = focus::generation();
+        self.cache.device.clone_from(&self.device);
+        self.cache.modifiers = modifiers;
+        self.cache.device_filter = config.device_filter();
+        let (profiles, gestures) = config.active_gestures(&state);
+        let vars = config.active_vars(&profiles);
 
-        for gesture in &config.gestures {
+        if profiles != self.cache.active_profiles {
+            log::info!("Active profiles: [{}]", profiles.join(", "));
+            trace_event!("profiles [{}]", profiles.join(", "));
+            self.cache.active_profiles = profiles.iter().map(|p| p.to_string()).collect();
+            status::set_profiles(&profiles);
+        }
+
+        for gesture in gestures {
+            let gesture = &gesture.with_vars(&vars);
             match gesture {
                 Gesture::Swipe(swipe) => {
                     swipe_map
@@ -363,6 +2042,12 @@ impl EventHandler {
                         .or_default()
                         .push(gesture.clone());
                 }
+                Gesture::Rotate(rotate) => {
+                    rotate_map
+                        .entry(rotate.fingers)
+                        .or_default()
+                        .push(gesture.clone());
+                }
                 Gesture::None => {}
             }
         }
@@ -370,11 +2055,134 @@ impl EventHandler {
         self.cache.swipe_gestures = swipe_map;
         self.cache.pinch_gestures = pinch_map;
         self.cache.hold_gestures = hold_map;
+        self.cache.rotate_gestures = rotate_map;
+        self.cache.palm_rejection = config.palm_rejection.clone();
+        self.cache.finger_change = config.finger_change.unwrap_or_default();
+        self.throttle
+            .set_rate(config.update_rate.unwrap_or(DEFAULT_UPDATE_RATE));
+        failures::set_notify_after(config.notify_failures);
+        self.cache.finger_remap = config.finger_remap(self.device.as_deref());
+        (self.units.output_scale, self.units.auto_scale) = match config.output_scale {
+            Some(OutputScale::Fixed(scale)) => (scale, false),
+            // Set from the output under the pointer when a drag begins
+            Some(OutputScale::Auto) => (1.0, true),
+            None => (1.0, false),
+        };
+        match (&config.touchscreen, &mut self.touch) {
+            (Some(settings), Some(tracker)) => tracker.settings.clone_from(settings),
+            (Some(settings), None) => self.touch = Some(TouchTracker::new(settings.clone())),
+            (None, _) => self.touch = None,
+        }
         self.cache.last_update = std::time::Instant::now();
+
+        let edge_swipes = self
+            .cache
+            .swipe_gestures
+            .values()
+            .flatten()
+            .any(|g| matches!(g, Gesture::Swipe(s) if s.edge.is_some()));
+        if (self.cache.palm_rejection.is_some() || edge_swipes) && self.contacts.is_none() {
+            if let Some(devnode) = self.gesture_device.take() {
+                match ContactTracker::start(&devnode) {
+                    Ok(tracker) => self.contacts = Some(tracker),
+                    Err(e) => log::warn!(
+                        "Palm rejection and edge swipes disabled, cannot read {}: {}",
+                        devnode.display(),
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Apply the `finger-change` policy when an update reports a different finger count than the
+    /// gesture began with, returns false when the update should be ignored
+    fn handle_finger_change(
+        &mut self,
+        fingers: i32,
+        time_usec: u64,
+        mh: &mut impl MouseActions,
+    ) -> Result<bool> {
+        let (began, pinch) = match &self.event {
+            Gesture::Swipe(s) => (s.fingers, false),
+            Gesture::Pinch(p) => (p.fingers, true),
+            _ => return Ok(true),
+        };
+        if began == fingers {
+            return Ok(true);
+        }
+
+        log::debug!("Finger count changed from {} to {}", began, fingers);
+        trace_event!(
+            "fingers changed {} -> {}: {:?}",
+            began,
+            fingers,
+            self.cache.finger_change
+        );
+        if pinch {
+            self.handle_pinch_cancel()?;
+        } else {
+            self.handle_swipe_cancel(mh)?;
+        }
+        if self.cache.finger_change == FingerChange::Cancel {
+            return Ok(false);
+        }
+
+        if pinch {
+            self.handle_pinch_begin(fingers, time_usec)?;
+        } else {
+            self.motion.reset("swipe", fingers, time_usec);
+            self.handle_swipe_begin(fingers, mh)?;
+        }
+        Ok(true)
+    }
+
+    /// Use the rules, stored scaling factors and size of `device` for the gesture that is
+    /// beginning
+    fn select_device(&mut self, device: &input::Device) {
+        let name = device.name();
+        self.device_calibration = self.calibration.device(name).cloned();
+        self.units.size_mm = device.size();
+        if self.device.as_deref() != Some(name) {
+            self.device = Some(name.to_string());
+        }
+    }
+
+    /// True for a gesture that began long before it is handled, it is then ignored
+    fn is_stale(time_usec: u64) -> bool {
+        let age = event_age(time_usec);
+        if age < STALE_GESTURE {
+            return false;
+        }
+        trace_event!("gesture begin skipped: stale by {}ms", age.as_millis());
+        true
+    }
+
+    /// True when a resting thumb or palm is on the pad, the gesture is then ignored
+    fn rejects_gesture(&self, fingers: i32) -> bool {
+        let (Some(rejection), Some(contacts)) = (&self.cache.palm_rejection, &self.contacts) else {
+            return false;
+        };
+        let resting = contacts.resting_contacts(rejection);
+        if resting > 0 {
+            trace_event!(
+                "gesture fingers={} rejected: {} resting contact(s)",
+                fingers,
+                resting
+            );
+            return true;
+        }
+        false
     }
 
+    /// Only called when a gesture begins: the rules matched then stay in effect until it ends, so a
+    /// reload mid-gesture can't skip the end or cancel handling (e.g. orphan a drag's mouse-down)
     fn refresh_cache_if_needed(&mut self) {
-        if self.cache.last_update.elapsed() > std::time::Duration::from_secs(1) {
+        if self.cache.last_update.elapsed() > std::time::Duration::from_secs(1)
+            || self.cache.focus_generation != focus::generation()
+            || self.cache.device != self.device
+            || self.cache.modifiers != self.modifier_keys.modifiers()
+        {
             self.update_cache();
         }
     }
@@ -388,11 +2196,28 @@ impl EventHandler {
     where
         F: Fn(&Gesture, &mut dyn MouseActions) -> Result<()>,
     {
-        self.refresh_cache_if_needed();
-
         if let Gesture::Swipe(_) = &self.event {
             if let Some(gestures) = self.cache.swipe_gestures.get(&fingers) {
+                let rested = |j: &Swipe| {
+                    j.after_hold
+                        .is_some_and(|ms| self.swipe_rest.is_some_and(|rest| rest >= ms))
+                };
+                // A rule for the rest before the move replaces the plain rule of the same swipe
+                let after_hold: Vec<&Gesture> = gestures
+                    .iter()
+                    .filter(|g| matches!(g, Gesture::Swipe(j) if rested(j)))
+                    .collect();
                 for gesture in gestures {
+                    if let Gesture::Swipe(j) = gesture {
+                        if j.edge.is_some() && j.edge != self.swipe_edge {
+                            continue;
+                        }
+                        let replaced = j.after_hold.is_none()
+                            && after_hold.iter().any(|a| a.same_gesture(gesture));
+                        if (j.after_hold.is_some() && !rested(j)) || replaced {
+                            continue;
+                        }
+                    }
                     handler(gesture, mh)?;
                 }
             }
@@ -402,26 +2227,146 @@ impl EventHandler {
 
     fn is_direct_mouse_gesture(gesture: &Gesture) -> bool {
         if let Gesture::Swipe(j) = gesture {
-            j.acceleration.is_some() && j.mouse_up_delay.is_some() && j.direction == SwipeDir::Any
+            j.acceleration.is_some()
+                && j.mouse_up_delay.is_some()
+                && j.direction == SwipeDir::Any
+                && j.corner.is_none()
+                && !Self::is_region_gesture(gesture)
         } else {
             false
         }
     }
 
-    fn handle_swipe_begin(&mut self, fingers: i32, mh: &mut impl MouseActions) -> Result<()> {
-        self.event = Gesture::Swipe(Swipe::new(fingers));
-
-        self.handle_matching_gesture(fingers, mh, |gesture, mh| {
-            if Self::is_direct_mouse_gesture(gesture) {
-                log::debug!("Using direct mouse control");
-                mh.mouse_down(1);
-            } else if let Gesture::Swipe(j) = gesture {
-                if j.direction == SwipeDir::Any {
-                    exec_command_from_string(j.start.as_deref().unwrap_or(""), 0.0, 0.0, 0.0, 0.0)?;
+    /// `end="@screenshot ..."` on a swipe with direction="any": the pointer moves like a drag
+    /// without pressing a button, the region it spans is passed to the screenshot tool
+    fn is_region_gesture(gesture: &Gesture) -> bool {
+        if let Gesture::Swipe(j) = gesture {
+            j.direction == SwipeDir::Any && j.end.as_deref().is_some_and(builtin::is_screenshot)
+        } else {
+            false
+        }
+    }
+
+    fn handle_swipe_begin(&mut self, fingers: i32, mh: &mut impl MouseActions) -> Result<()> {
+        self.event = Gesture::Swipe(Swipe::new(fingers));
+        self.drag_remainder = DragRemainder::default();
+        self.drag_filter = None;
+        self.region = None;
+        self.drag_outputs = None;
+        self.swipe_repeats.clear();
+        let glide = self.drag_glide.take();
+        let vars = self.motion.vars();
+
+        self.refresh_cache_if_needed();
+        self.swipe_edge = self.contacts.as_ref().and_then(ContactTracker::start_edge);
+        self.swipe_rest = self
+            .hold_rest
+            .take()
+            .filter(|r| r.fingers == fingers && r.at.elapsed() < HOLD_MOVE_WINDOW)
+            .map(|r| r.held_ms);
+        if let Some(rest) = self.swipe_rest {
+            trace_event!("swipe fingers={} after a {}ms hold", fingers, rest);
+        }
+        if !self.cache.swipe_gestures.contains_key(&fingers) {
+            // Devices are never grabbed, so the compositor still sees this gesture
+            log::debug!("No swipe rule for {} fingers, passing through", fingers);
+            trace_event!("swipe begin fingers={}: no rule, passing through", fingers);
+        } else {
+            trace_event!("swipe begin fingers={} edge={:?}", fingers, self.swipe_edge);
+        }
+
+        let rules = self.cache.swipe_gestures.get(&fingers);
+        let selects_region =
+            rules.is_some_and(|gestures| gestures.iter().any(Self::is_region_gesture));
+        let has_corners = rules.is_some_and(|gestures| {
+            gestures
+                .iter()
+                .any(|g| matches!(g, Gesture::Swipe(j) if j.corner.is_some()))
+        });
+        if selects_region || has_corners {
+            let start = mh.pointer_position();
+            if start.is_none() {
+                log::warn!(
+                    "Pointer position unavailable, screenshot region or hot corner can't be placed"
+                );
+            }
+            let bounds = if has_corners {
+                mh.screen_bounds()
+            } else {
+                None
+            };
+            trace_event!(
+                "swipe fingers={}: tracking pointer from {:?} on {:?}",
+                fingers,
+                start,
+                bounds
+            );
+            self.region = Some(RegionSelect {
+                start,
+                moved: (0, 0),
+                bounds,
+            });
+        }
+
+        let pressed = Cell::new(false);
+        let pipes = RefCell::new(Vec::new());
+        self.handle_matching_gesture(fingers, mh, |gesture, mh| {
+            if let Gesture::Swipe(j) = gesture {
+                pipes.borrow_mut().extend(j.pipe.open(&j.spawn));
+                if Self::is_direct_mouse_gesture(gesture) {
+                    log::debug!("Using direct mouse control");
+                    trace_event!("swipe fingers={}: direct mouse drag", fingers);
+                    mh.configure_drag(Some(j));
+                    mh.mouse_down(1);
+                    pressed.set(true);
+                } else if j.direction == SwipeDir::Any {
+                    exec_command_from_string(j.start.as_deref().unwrap_or(""), &vars, &j.spawn)?;
                 }
             }
             Ok(())
-        })
+        })?;
+        self.pipes = pipes.into_inner();
+        if glide.is_some() {
+            if pressed.get() {
+                trace_event!("swipe fingers={}: caught the gliding drag", fingers);
+            } else {
+                // The glide's button must not stay pressed through another gesture
+                trace_event!("drag glide stopped by a new swipe");
+                mh.mouse_up_delay(1, 0);
+                if let Some(drag) = &mut self.held_drag {
+                    drag.release_at = Some(Instant::now());
+                }
+            }
+        }
+        if pressed.get() && self.units.auto_scale {
+            self.drag_outputs = DragOutputs::new(mh.outputs(), mh.pointer_position());
+            if let Some(drag) = &self.drag_outputs {
+                self.units.output_scale = drag.output().scale;
+                trace_event!(
+                    "swipe fingers={}: drag starts on {} at scale {}",
+                    fingers,
+                    drag.output().name,
+                    drag.output().scale
+                );
+            }
+        }
+        if pressed.get() {
+            let now = Instant::now();
+            match &mut self.held_drag {
+                // Resumed within the previous drag's `mouse-up-delay`, the button stayed down
+                Some(drag) if drag.release_at.is_none_or(|at| at > now) => {
+                    drag.release_at = None;
+                }
+                _ => {
+                    self.held_drag = Some(HeldDrag {
+                        displays: conditions::connected_displays(),
+                        release_at: None,
+                        checked: now,
+                    })
+                }
+            }
+        }
+        Ok(())
     }
 
     fn handle_swipe_update(&mut self, dx: f64, dy: f64, mh: &mut impl MouseActions) -> Result<()> {
@@ -433,56 +2378,334 @@ impl EventHandler {
         };
 
         log::debug!("{:?} {:?}", &current_dir, &fingers);
+        monitor_event!(
+            "swipe update fingers={} direction={:?} dx={:.2} dy={:.2}",
+            fingers,
+            current_dir,
+            dx,
+            dy
+        );
 
-        let is_throttled = !self.throttle.should_update();
+        let global_throttled = !self.throttle.should_update();
+        let rule_throttled = Self::throttled_rates(
+            &mut self.rule_throttles,
+            self.cache.swipe_gestures.get(&fingers),
+        );
 
         let current_dir = current_dir.clone();
-        self.handle_matching_gesture(fingers, mh, move |gesture, mh| {
+        let vars = self.motion.vars();
+        let units = self.units;
+        let remainder = Cell::new(self.drag_remainder);
+        let filter = Cell::new(self.drag_filter);
+        let moved = Cell::new((0, 0));
+        let offset = self.motion.offset;
+        let held_past = RefCell::new(Vec::new());
+        self.handle_matching_gesture(fingers, mh, |gesture, mh| {
             if let Gesture::Swipe(j) = gesture {
-                if Self::is_direct_mouse_gesture(gesture) {
+                let is_throttled = j
+                    .update_rate
+                    .map_or(global_throttled, |fps| rule_throttled[&fps]);
+                if j.repeats_at(offset, &units) {
+                    held_past.borrow_mut().push(j.clone());
+                }
+                if Self::is_direct_mouse_gesture(gesture) || Self::is_region_gesture(gesture) {
+                    let (dx, dy) = match j.smoothing {
+                        Some(Float(cutoff)) => {
+                            let beta = j.smoothing_beta.map_or(DEFAULT_SMOOTHING_BETA, |b| b.0);
+                            let mut f = filter
+                                .get()
+                                .unwrap_or_else(|| DeltaFilter::new(cutoff, beta));
+                            let filtered = f.filter(dx, dy);
+                            filter.set(Some(f));
+                            filtered
+                        }
+                        None => (dx, dy),
+                    };
+                    let speed = dx.hypot(dy);
+                    let curve_factor = j
+                        .acceleration_curve
+                        .as_ref()
+                        .map_or(1.0, |curve| curve.factor(speed));
+                    let acceleration = j
+                        .acceleration
+                        .map_or(units.compensation(), |a| units.pointer_factor(a))
+                        * curve_factor;
+
+                    // Throttled deltas are kept in the remainder instead of being dropped
+                    let mut r = remainder.get();
+                    r.add(dx * acceleration, dy * acceleration);
                     if !is_throttled {
-                        let acceleration = j.acceleration.unwrap_or_default() as f64 / 10.0;
-                        mh.move_mouse_relative(
-                            (dx * acceleration) as i32,
-                            (dy * acceleration) as i32,
-                        );
+                        let steps = mh.motion_steps();
+                        let (x, y) = r.take_steps(steps);
+                        mh.move_mouse_steps(x, y);
+                        moved.set(r.take_moved());
                     }
+                    remainder.set(r);
                 } else if (j.direction == current_dir || j.direction == SwipeDir::Any)
                     && !is_throttled
                 {
+                    let vars = CommandVars {
+                        delta_x: dx,
+                        delta_y: dy,
+                        ..vars
+                    };
                     exec_update_command_from_string(
                         j.update.as_deref().unwrap_or(""),
-                        dx,
-                        dy,
-                        0.0,
-                        0.0,
+                        &vars,
+                        &j.spawn,
                     )?;
+                    j.dbus.send(&vars);
+                    hyprland::dispatch(j.hyprland_update.as_deref(), &vars);
+                    sway::run(j.sway_update.as_deref(), &vars);
                 }
             }
             Ok(())
         })?;
+        self.send_to_pipes(CommandVars {
+            delta_x: dx,
+            delta_y: dy,
+            ..vars
+        });
+        self.drag_remainder = remainder.get();
+        self.drag_filter = filter.get();
+        self.hold_swipe_repeats(held_past.into_inner(), Instant::now());
+        if let Some(output) = self
+            .drag_outputs
+            .as_mut()
+            .and_then(|d| d.moved(moved.get()))
+        {
+            self.units.output_scale = output.scale;
+            trace_event!(
+                "swipe fingers={}: pointer crossed to {} at scale {}",
+                fingers,
+                output.name,
+                output.scale
+            );
+        }
+        if let Some(region) = &mut self.region {
+            region.moved.0 += moved.get().0;
+            region.moved.1 += moved.get().1;
+        }
 
         self.event = Gesture::Swipe(Swipe::with_direction(fingers, swipe_dir));
         Ok(())
     }
 
+    /// Which `update-rate`s of the rules in `gestures` hold this update back, each throttle is
+    /// asked once per update however many rules share its rate
+    fn throttled_rates(
+        throttles: &mut HashMap<u32, ThrottleState>,
+        gestures: Option<&Vec<Gesture>>,
+    ) -> HashMap<u32, bool> {
+        let rates = gestures.into_iter().flatten().filter_map(|g| match g {
+            Gesture::Swipe(j) => j.update_rate,
+            Gesture::Pinch(j) => j.update_rate,
+            _ => None,
+        });
+        let mut throttled = HashMap::new();
+        for fps in rates {
+            throttled.entry(fps).or_insert_with(|| {
+                !throttles
+                    .entry(fps)
+                    .or_insert_with(|| ThrottleState::new(fps))
+                    .should_update()
+            });
+        }
+        throttled
+    }
+
+    /// Write an update line to the `pipe` commands of the gesture
+    fn send_to_pipes(&mut self, vars: CommandVars) {
+        for pipe in &mut self.pipes {
+            pipe.send(&vars);
+        }
+    }
+
+    /// Start repeating the rules `held_past` their `repeat-distance`, the others pause until the
+    /// swipe is back past theirs
+    fn hold_swipe_repeats(&mut self, held_past: Vec<Swipe>, now: Instant) {
+        for repeat in &mut self.swipe_repeats {
+            if !held_past.contains(&repeat.rule) {
+                repeat.deadline = None;
+            }
+        }
+        for j in held_past {
+            match self.swipe_repeats.iter_mut().find(|r| *r.rule == j) {
+                Some(repeat) => {
+                    repeat.deadline.get_or_insert(now);
+                }
+                None => {
+                    trace_event!("swipe rule direction={:?} held, repeating", j.direction);
+                    self.swipe_repeats.push(SwipeRepeat {
+                        interval: Duration::from_millis(
+                            j.repeating.repeat_interval.unwrap_or_default(),
+                        ),
+                        rule: Box::new(j),
+                        deadline: Some(now),
+                        repeated: false,
+                    });
+                }
+            }
+        }
+    }
+
     fn handle_swipe_end(&mut self, mh: &mut impl MouseActions) -> Result<()> {
         let (fingers, direction) = if let Gesture::Swipe(s) = &self.event {
             (s.fingers, s.direction.clone())
         } else {
             return Ok(());
         };
+        self.pipes.clear();
+        // `end` sees the movement of the whole swipe
+        let vars = CommandVars {
+            delta_x: self.motion.offset.0,
+            delta_y: self.motion.offset.1,
+            ..self.motion.vars()
+        };
+        let speed = self.motion.mean_speed();
+        let units = self.units;
+        trace_event!(
+            "swipe end fingers={} direction={:?} speed={:.0} ({:.0}mm/s, {:.0}px/s)",
+            fingers,
+            direction,
+            speed,
+            units.mm(speed),
+            units.px(speed)
+        );
+        let region = self.region.take();
+        let corner = region.as_ref().and_then(RegionSelect::corner);
+        let repeated: Vec<Box<Swipe>> = std::mem::take(&mut self.swipe_repeats)
+            .into_iter()
+            .filter(|r| r.repeated && r.rule.repeating.repeat.is_none())
+            .map(|r| r.rule)
+            .collect();
+        let release_at = Cell::new(None);
+        let velocity = (self.motion.velocity_x, self.motion.velocity_y);
+        let glide = Cell::new(None);
+        // Drags and repeats did what the swipe was for without firing at its end
+        let handled = Cell::new(!repeated.is_empty());
+        let recorded = history::recorded();
         self.handle_matching_gesture(fingers, mh, |gesture, mh| {
             if let Gesture::Swipe(j) = gesture {
-                if Self::is_direct_mouse_gesture(gesture) {
+                if Self::is_region_gesture(gesture) || Self::is_direct_mouse_gesture(gesture) {
+                    handled.set(true);
+                }
+                if Self::is_region_gesture(gesture) {
+                    let Some(RegionSelect {
+                        start: Some(start),
+                        moved,
+                        ..
+                    }) = region
+                    else {
+                        return Ok(());
+                    };
+                    let region = builtin::Region::from_drag(start, moved);
+                    if region.w == 0 || region.h == 0 {
+                        trace_event!("swipe end: empty screenshot region skipped");
+                        return Ok(());
+                    }
+                    trace_event!("swipe end: screenshot region {:?}", region);
+                    if let Some(command) =
+                        builtin::screenshot_command(j.end.as_deref().unwrap_or(""), region)
+                    {
+                        exec_command_from_string(&command, &vars, &j.spawn)?;
+                    }
+                } else if Self::is_direct_mouse_gesture(gesture) {
                     let delay = j.mouse_up_delay.unwrap_or_default();
+                    let factor = j
+                        .acceleration
+                        .map_or(units.compensation(), |a| units.pointer_factor(a));
+                    let friction = j.glide.friction.map_or(DEFAULT_FRICTION, |f| f.0);
+                    let velocity = (velocity.0 * factor, velocity.1 * factor);
+                    if let Some(g) = (j.glide.inertia == Some(true))
+                        .then(|| DragGlide::new(velocity, friction, delay, Instant::now()))
+                        .flatten()
+                    {
+                        trace_event!(
+                            "swipe end: drag glides at {:.0}px/s",
+                            velocity.0.hypot(velocity.1)
+                        );
+                        glide.set(Some(g));
+                        return Ok(());
+                    }
+                    trace_event!("swipe end: release drag after {}ms", delay);
                     mh.mouse_up_delay(1, delay);
+                    release_at.set(Some(
+                        Instant::now() + Duration::from_millis(delay.max(0) as u64),
+                    ));
+                } else if let Some(wanted) = j.corner {
+                    if corner == Some(wanted) {
+                        trace_event!("swipe rule corner={:?} matched", wanted);
+                        let entry = fired(
+                            &j.log,
+                            format!("{}-finger drag to {:?} corner", fingers, wanted),
+                        );
+                        exec_fired_command(j.end.as_deref().unwrap_or(""), &vars, &j.spawn, entry)?;
+                        press_key(j.key.as_ref());
+                        run_script(j.script.as_ref(), &vars, &j.spawn);
+                        type_text(j.type_text.as_deref(), j.paste);
+                        hyprland::dispatch(j.hyprland.as_deref(), &vars);
+                        sway::run(j.sway.as_deref(), &vars);
+                    } else {
+                        rule_event!(
+                            j.log,
+                            "swipe rule corner={:?} skipped: released in {:?}",
+                            wanted,
+                            corner
+                        );
+                    }
+                } else if !j.matches_velocity(speed, &units) {
+                    rule_event!(
+                        j.log,
+                        "swipe rule direction={:?} skipped: speed {:.0} outside {:?}..{:?}",
+                        j.direction,
+                        speed,
+                        j.min_velocity.map(|v| units.units(v)),
+                        j.max_velocity.map(|v| units.units(v))
+                    );
+                } else if repeated.iter().any(|r| **r == *j) {
+                    rule_event!(
+                        j.log,
+                        "swipe rule direction={:?} skipped: already repeated",
+                        j.direction
+                    );
                 } else if j.direction == direction || j.direction == SwipeDir::Any {
-                    exec_command_from_string(j.end.as_deref().unwrap_or(""), 0.0, 0.0, 0.0, 0.0)?;
+                    trace_event!("swipe rule direction={:?} matched", j.direction);
+                    let entry = fired(
+                        &j.log,
+                        format!(
+                            "{}-finger swipe {}",
+                            fingers,
+                            format!("{:?}", direction).to_lowercase()
+                        ),
+                    );
+                    exec_fired_command(j.end.as_deref().unwrap_or(""), &vars, &j.spawn, entry)?;
+                    press_key(j.key.as_ref());
+                    run_script(j.script.as_ref(), &vars, &j.spawn);
+                    type_text(j.type_text.as_deref(), j.paste);
+                    hyprland::dispatch(j.hyprland.as_deref(), &vars);
+                    sway::run(j.sway.as_deref(), &vars);
+                } else {
+                    rule_event!(
+                        j.log,
+                        "swipe rule direction={:?} skipped: direction mismatch",
+                        j.direction
+                    );
                 }
             }
             Ok(())
         })?;
+        if let (Some(drag), Some(at)) = (&mut self.held_drag, release_at.get()) {
+            drag.release_at = Some(at);
+        }
+        if !handled.get() && history::recorded() == recorded {
+            history::unmatched(&format!(
+                "{}-finger swipe {}",
+                fingers,
+                format!("{:?}", direction).to_lowercase()
+            ));
+        }
+        self.drag_glide = glide.take();
         self.event = Gesture::None;
         Ok(())
     }
@@ -493,10 +2716,22 @@ impl EventHandler {
         } else {
             return Ok(());
         };
+        trace_event!("swipe cancelled fingers={}", fingers);
+        self.pipes.clear();
+        self.region = None;
+        self.held_drag = None;
+        self.swipe_repeats.clear();
+        let vars = self.motion.vars();
 
         self.handle_matching_gesture(fingers, mh, |gesture, mh| {
             if Self::is_direct_mouse_gesture(gesture) {
+                mh.configure_drag(None);
                 mh.mouse_up_delay(1, 0);
+            } else if let Gesture::Swipe(j) = gesture {
+                if let Some(cancel) = &j.cancel {
+                    rule_event!(j.log, "swipe rule direction={:?} cancelled", j.direction);
+                    exec_command_from_string(cancel, &vars, &j.spawn)?;
+                }
             }
             Ok(())
         })?;
@@ -505,6 +2740,29 @@ impl EventHandler {
     }
 }
 
+impl Hold {
+    fn new(fingers: i32) -> Self {
+        Self {
+            fingers,
+            action: None,
+            start: None,
+            end: None,
+            key: None,
+            script: None,
+            type_text: None,
+            paste: None,
+            trigger: None,
+            duration: None,
+            repeat: None,
+            min_duration: None,
+            max_duration: None,
+            conditions: Conditions::default(),
+            spawn: SpawnOptions::default(),
+            log: RuleLog::default(),
+        }
+    }
+}
+
 // Add this helper impl
 impl Swipe {
     fn new(fingers: i32) -> Self {
@@ -514,8 +2772,35 @@ impl Swipe {
             update: None,
             start: None,
             end: None,
+            cancel: None,
+            repeating: Box::default(),
+            key: None,
+            script: None,
+            type_text: None,
+            paste: None,
+            hyprland: None,
+            hyprland_update: None,
+            sway: None,
+            sway_update: None,
             acceleration: None,
+            acceleration_curve: None,
+            smoothing: None,
+            smoothing_beta: None,
             mouse_up_delay: None,
+            update_rate: None,
+            glide: Box::default(),
+            idle_inhibit: None,
+            edge: None,
+            after_hold: None,
+            corner: None,
+            min_velocity: None,
+            max_velocity: None,
+            dbus: Box::default(),
+            pipe: Box::default(),
+            drop_zones: Vec::new(),
+            conditions: Conditions::default(),
+            spawn: SpawnOptions::default(),
+            log: RuleLog::default(),
         }
     }
 
@@ -526,8 +2811,35 @@ impl Swipe {
             update: None,
             start: None,
             end: None,
+            cancel: None,
+            repeating: Box::default(),
+            key: None,
+            script: None,
+            type_text: None,
+            paste: None,
+            hyprland: None,
+            hyprland_update: None,
+            sway: None,
+            sway_update: None,
             acceleration: None,
+            acceleration_curve: None,
+            smoothing: None,
+            smoothing_beta: None,
             mouse_up_delay: None,
+            update_rate: None,
+            glide: Box::default(),
+            idle_inhibit: None,
+            edge: None,
+            after_hold: None,
+            corner: None,
+            min_velocity: None,
+            max_velocity: None,
+            dbus: Box::default(),
+            pipe: Box::default(),
+            drop_zones: Vec::new(),
+            conditions: Conditions::default(),
+            spawn: SpawnOptions::default(),
+            log: RuleLog::default(),
         }
     }
 }
@@ -555,41 +2867,126 @@ impl LibinputInterface for Interface {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Vars;
+    use crate::units::Acceleration;
     use std::sync::Arc;
 
     struct MockMouseHandler {
         mouse_up_calls: Vec<(i32, i64)>,
+        move_calls: Vec<(i32, i32)>,
+        zoom_calls: Vec<i32>,
+        /// Steps of a pixel in `move_calls`
+        motion_steps: i32,
     }
 
     impl MockMouseHandler {
         fn new() -> Self {
             Self {
                 mouse_up_calls: Vec::new(),
+                move_calls: Vec::new(),
+                zoom_calls: Vec::new(),
+                motion_steps: 1,
             }
         }
     }
 
     impl MouseActions for MockMouseHandler {
+        fn configure_drag(&mut self, _drag: Option<&Swipe>) {}
+
         fn mouse_down(&mut self, _button: i32) {}
 
         fn mouse_up_delay(&mut self, button: i32, delay_ms: i64) {
             self.mouse_up_calls.push((button, delay_ms));
         }
 
-        fn move_mouse_relative(&mut self, _x_val: i32, _y_val: i32) {}
+        fn move_mouse_relative(&mut self, x_val: i32, y_val: i32) {
+            self.move_calls.push((x_val, y_val));
+        }
+
+        fn motion_steps(&mut self) -> i32 {
+            self.motion_steps
+        }
+
+        fn zoom(&mut self, units: i32) {
+            self.zoom_calls.push(units);
+        }
+
+        fn pointer_position(&mut self) -> Option<(i32, i32)> {
+            Some((500, 400))
+        }
+
+        fn screen_bounds(&mut self) -> Option<(i32, i32, i32, i32)> {
+            Some((0, 0, 1920, 1080))
+        }
+
+        fn outputs(&mut self) -> Vec<Output> {
+            let output = |name: &str, x, scale| Output {
+                name: name.to_string(),
+                x,
+                y: 0.0,
+                width: 1000.0,
+                height: 1000.0,
+                scale,
+                focused: false,
+            };
+            vec![output("DP-1", 0.0, 1.0), output("eDP-1", 1000.0, 2.0)]
+        }
     }
 
     #[test]
     fn cancelled_swipe_releases_direct_mouse_drag() {
         let config = Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
             gestures: vec![Gesture::Swipe(Swipe {
                 direction: SwipeDir::Any,
                 fingers: 3,
                 update: None,
                 start: None,
                 end: None,
-                acceleration: Some(20),
+                cancel: None,
+                repeating: Box::default(),
+                key: None,
+                script: None,
+                type_text: None,
+                paste: None,
+                hyprland: None,
+                hyprland_update: None,
+                sway: None,
+                sway_update: None,
+                acceleration: Some(Acceleration::Factor(20.0)),
+                acceleration_curve: None,
+                smoothing: None,
+                smoothing_beta: None,
                 mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                idle_inhibit: None,
+                edge: None,
+                after_hold: None,
+                corner: None,
+                min_velocity: None,
+                max_velocity: None,
+                dbus: Box::default(),
+                pipe: Box::default(),
+                drop_zones: Vec::new(),
+                conditions: Conditions::default(),
+                spawn: SpawnOptions::default(),
+                log: RuleLog::default(),
             })],
         };
         let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
@@ -603,4 +3000,1055 @@ mod tests {
         assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
         assert_eq!(handler.event, Gesture::None);
     }
+    #[test]
+    fn idle_inhibitor_is_released_when_the_drag_ends() {
+        use crate::idle_inhibit::IdleInhibitor;
+        use crate::mouse_handler::MouseHandler;
+
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            swipe direction="any" fingers=3 acceleration=20 mouse-up-delay=0 idle-inhibit=true
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MouseHandler::detached(IdleInhibitor::with_command(&["sleep", "30"]));
+        let released = |mh: &MouseHandler| {
+            (0..200).any(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                !mh.inhibitor_held()
+            })
+        };
+
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeBegin { fingers: 3 }, 0, &mut mh)
+            .unwrap();
+        assert!(mh.inhibitor_held());
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeEnd, 100, &mut mh)
+            .unwrap();
+        assert!(released(&mh));
+
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeBegin { fingers: 3 }, 200, &mut mh)
+            .unwrap();
+        assert!(mh.inhibitor_held());
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeCancel, 300, &mut mh)
+            .unwrap();
+        assert!(released(&mh));
+    }
+
+    #[test]
+    fn slow_drag_accumulates_fractional_movement() {
+        let config = Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
+            gestures: vec![Gesture::Swipe(Swipe {
+                direction: SwipeDir::Any,
+                fingers: 3,
+                update: None,
+                start: None,
+                end: None,
+                cancel: None,
+                repeating: Box::default(),
+                key: None,
+                script: None,
+                type_text: None,
+                paste: None,
+                hyprland: None,
+                hyprland_update: None,
+                sway: None,
+                sway_update: None,
+                acceleration: Some(Acceleration::Factor(10.0)),
+                acceleration_curve: None,
+                smoothing: None,
+                smoothing_beta: None,
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                idle_inhibit: None,
+                edge: None,
+                after_hold: None,
+                corner: None,
+                min_velocity: None,
+                max_velocity: None,
+                dbus: Box::default(),
+                pipe: Box::default(),
+                drop_zones: Vec::new(),
+                conditions: Conditions::default(),
+                spawn: SpawnOptions::default(),
+                log: RuleLog::default(),
+            })],
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+
+        let mut mock_mouse = MockMouseHandler::new();
+        handler
+            .handle_swipe_begin(3, &mut mock_mouse)
+            .expect("swipe begin should be handled");
+        for _ in 0..5 {
+            handler
+                .handle_swipe_update(0.4, -0.4, &mut mock_mouse)
+                .expect("swipe update should be handled");
+        }
+
+        let total = mock_mouse
+            .move_calls
+            .iter()
+            .fold((0, 0), |acc, m| (acc.0 + m.0, acc.1 + m.1));
+        assert_eq!(total, (2, -2));
+    }
+
+    #[test]
+    fn sub_pixel_backend_moves_on_every_drag_update() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=500"#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+
+        let mut mock_mouse = MockMouseHandler::new();
+        mock_mouse.motion_steps = 256;
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        handler.region = Some(RegionSelect::default());
+        for _ in 0..5 {
+            handler
+                .handle_swipe_update(0.4, -0.4, &mut mock_mouse)
+                .unwrap();
+        }
+
+        assert_eq!(mock_mouse.move_calls.len(), 5);
+        let total = mock_mouse
+            .move_calls
+            .iter()
+            .fold((0, 0), |acc, m| (acc.0 + m.0, acc.1 + m.1));
+        assert!((total.0 - 2 * 256).abs() <= 1 && (total.1 + 2 * 256).abs() <= 1);
+        // Followed in whole pixels, whatever the steps
+        let moved = handler.region.unwrap().moved;
+        assert!((1..=2).contains(&moved.0) && (-2..=-1).contains(&moved.1));
+    }
+
+    #[test]
+    fn update_rate_throttles_globally_and_per_rule() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            update-rate 0
+            swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=500
+            swipe direction="any" fingers=4 acceleration=10 mouse-up-delay=500 update-rate=1
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mock_mouse = MockMouseHandler::new();
+        for fingers in [3, 4] {
+            handler
+                .handle_swipe_begin(fingers, &mut mock_mouse)
+                .unwrap();
+            for _ in 0..5 {
+                handler
+                    .handle_swipe_update(5.0, 0.0, &mut mock_mouse)
+                    .unwrap();
+            }
+            handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        }
+        // Uncapped for the 3 finger drag, once a second for the 4 finger one
+        assert_eq!(mock_mouse.move_calls.len(), 6);
+    }
+
+    #[test]
+    fn drag_with_inertia_glides_before_releasing() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=200 inertia=true"#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeBegin { fingers: 3 }, 0, &mut mh)
+            .unwrap();
+        // Released while moving right at 1000 units per second
+        handler
+            .handle_synthetic(
+                SyntheticEvent::SwipeUpdate { dx: 10.0, dy: 0.0 },
+                10_000,
+                &mut mh,
+            )
+            .unwrap();
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeEnd, 20_000, &mut mh)
+            .unwrap();
+        assert!(mh.mouse_up_calls.is_empty());
+        let moved_by_fingers = mh.move_calls.len();
+
+        let mut now = handler.next_deadline().expect("the drag should glide");
+        while handler.drag_glide.is_some() {
+            handler.glide_drag(now, &mut mh);
+            now += GLIDE_FRAME;
+        }
+        let glided: i32 = mh.move_calls[moved_by_fingers..].iter().map(|m| m.0).sum();
+        // 1000 px/s slowing down with friction 4 travels up to 250 px
+        assert!((200..=250).contains(&glided), "glided {}", glided);
+        assert!(mh.move_calls[moved_by_fingers..].iter().all(|m| m.1 == 0));
+        assert_eq!(mh.mouse_up_calls, vec![(1, 200)]);
+        assert_eq!(handler.next_deadline(), None);
+    }
+
+    #[test]
+    fn reload_mid_drag_still_releases_mouse() {
+        let config = Arc::new(RwLock::new(Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
+            gestures: vec![Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                ..Swipe::new(3)
+            })],
+        }));
+        let mut handler = EventHandler::new(config.clone());
+        let mut mock_mouse = MockMouseHandler::new();
+        handler
+            .handle_swipe_begin(3, &mut mock_mouse)
+            .expect("swipe begin should be handled");
+
+        // Reload to a config without the drag rule, with the cache due for a refresh
+        *config.write() = Config::default();
+        handler.cache.last_update -= std::time::Duration::from_secs(2);
+
+        handler
+            .handle_swipe_update(1.0, 1.0, &mut mock_mouse)
+            .expect("swipe update should be handled");
+        handler
+            .handle_swipe_end(&mut mock_mouse)
+            .expect("swipe end should be handled");
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 500)]);
+
+        // The next gesture uses the reloaded rules
+        handler
+            .handle_swipe_begin(3, &mut mock_mouse)
+            .expect("swipe begin should be handled");
+        assert!(handler.cache.swipe_gestures.is_empty());
+    }
+
+    #[test]
+    fn session_lock_releases_held_drag() {
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(Config {
+            gestures: vec![Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                ..Swipe::new(3)
+            })],
+            ..Config::default()
+        })));
+        let mut mock_mouse = MockMouseHandler::new();
+
+        // Locked mid-drag: released at once and the rest of the gesture is ignored
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        crate::session_lock::notify_locked();
+        handler
+            .release_drag_if_session_lost(Instant::now(), &mut mock_mouse)
+            .unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+        assert_eq!(handler.event, Gesture::None);
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+
+        // Locked during mouse-up-delay: the pending release is brought forward
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        crate::session_lock::notify_locked();
+        handler
+            .release_drag_if_session_lost(Instant::now(), &mut mock_mouse)
+            .unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0), (1, 500), (1, 0)]);
+
+        // Nothing held
+        crate::session_lock::notify_locked();
+        handler
+            .release_drag_if_session_lost(Instant::now(), &mut mock_mouse)
+            .unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls.len(), 3);
+    }
+
+    #[test]
+    fn finger_change_cancels_or_rematches_swipe() {
+        let drag = |fingers| {
+            Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                ..Swipe::new(fingers)
+            })
+        };
+        let mut config = Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
+            gestures: vec![drag(3), drag(4)],
+        };
+
+        // Default policy: release the drag and drop the rest of the gesture
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(Config {
+            gestures: config.gestures.clone(),
+            ..Config::default()
+        })));
+        let mut mock_mouse = MockMouseHandler::new();
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        assert!(!handler.handle_finger_change(4, 0, &mut mock_mouse).unwrap());
+        assert_eq!(handler.event, Gesture::None);
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+
+        // Rematch: the gesture continues with the new finger count
+        config.finger_change = Some(FingerChange::Rematch);
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mock_mouse = MockMouseHandler::new();
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        assert!(handler.handle_finger_change(4, 0, &mut mock_mouse).unwrap());
+        assert_eq!(handler.event, Gesture::Swipe(Swipe::new(4)));
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0), (1, 500)]);
+    }
+
+    #[test]
+    fn screenshot_swipe_tracks_region_without_pressing() {
+        let config = Config {
+            gestures: vec![Gesture::Swipe(Swipe {
+                end: Some("@screenshot true $geometry".to_string()),
+                ..Swipe::new(3)
+            })],
+            ..Config::default()
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+        let mut mock_mouse = MockMouseHandler::new();
+
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        for _ in 0..4 {
+            handler
+                .handle_swipe_update(-10.0, 5.0, &mut mock_mouse)
+                .unwrap();
+        }
+        let region = handler.region.expect("region selection should be active");
+        assert_eq!(region.start, Some((500, 400)));
+        assert_eq!(region.moved, (-40, 20));
+        assert_eq!(
+            builtin::Region::from_drag((500, 400), region.moved),
+            builtin::Region {
+                x: 460,
+                y: 400,
+                w: 40,
+                h: 20
+            }
+        );
+
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert!(mock_mouse.mouse_up_calls.is_empty());
+        assert!(handler.region.is_none());
+    }
+
+    #[test]
+    fn edge_swipe_rules_need_the_swipe_to_start_at_their_edge() {
+        let config = Config {
+            gestures: vec![Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(0),
+                update_rate: None,
+                glide: Box::default(),
+                edge: Some(PadEdge::Left),
+                ..Swipe::new(3)
+            })],
+            ..Config::default()
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mock_mouse = MockMouseHandler::new();
+
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert!(mock_mouse.mouse_up_calls.is_empty());
+
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        handler.swipe_edge = Some(PadEdge::Left);
+        handler.handle_swipe_end(&mut mock_mouse).unwrap();
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn takeover_waits_for_drag_then_cancels_it() {
+        let config = Config {
+            gestures: vec![Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                glide: Box::default(),
+                ..Swipe::new(3)
+            })],
+            ..Config::default()
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mock_mouse = MockMouseHandler::new();
+
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+        assert!(!handler.settle_for_takeover(false, &mut mock_mouse).unwrap());
+        assert!(mock_mouse.mouse_up_calls.is_empty());
+
+        assert!(handler.settle_for_takeover(true, &mut mock_mouse).unwrap());
+        assert_eq!(handler.event, Gesture::None);
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+        assert!(handler.settle_for_takeover(false, &mut mock_mouse).unwrap());
+    }
+
+    #[test]
+    fn removed_touchpad_cancels_its_gesture() {
+        let config = Config {
+            gestures: vec![Gesture::Swipe(Swipe {
+                acceleration: Some(Acceleration::Factor(10.0)),
+                mouse_up_delay: Some(500),
+                update_rate: None,
+                ..Swipe::new(3)
+            })],
+            ..Config::default()
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mock_mouse = MockMouseHandler::new();
+        assert!(handler.waiting_for_device());
+
+        handler.device_added("event5".to_string(), true);
+        handler.device_added("event7".to_string(), true);
+        assert!(!handler.waiting_for_device());
+        handler.handle_swipe_begin(3, &mut mock_mouse).unwrap();
+
+        handler
+            .device_removed("event5", true, &mut mock_mouse)
+            .unwrap();
+        assert_eq!(handler.event, Gesture::None);
+        assert_eq!(mock_mouse.mouse_up_calls, vec![(1, 0)]);
+        assert_eq!(handler.touchpads, vec!["event7".to_string()]);
+
+        handler
+            .device_removed("event7", true, &mut mock_mouse)
+            .unwrap();
+        assert!(handler.waiting_for_device());
+        assert_eq!(handler.gesture_device, None);
+    }
+
+    #[test]
+    fn hold_timeout_fires_and_repeats_while_held() {
+        let hold = |repeat| {
+            Gesture::Hold(Hold {
+                action: Some("true".to_string()),
+                trigger: Some(HoldTrigger::Timeout),
+                duration: Some(100),
+                repeat,
+                ..Hold::new(4)
+            })
+        };
+        let config = Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
+            gestures: vec![hold(Some(50)), hold(None)],
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let start = std::time::Instant::now();
+        handler.hold_timers = HoldTimer::schedule(&handler.cache.hold_gestures[&4], start);
+        assert_eq!(handler.hold_timers.len(), 2);
+
+        let ms = std::time::Duration::from_millis;
+        handler.fire_hold_timers(start + ms(50)).unwrap();
+        assert_eq!(handler.hold_timers.len(), 2);
+
+        handler.fire_hold_timers(start + ms(100)).unwrap();
+        assert_eq!(handler.hold_timers.len(), 1);
+        assert_eq!(handler.next_deadline(), Some(start + ms(150)));
+    }
+
+    #[test]
+    fn held_swipe_repeats_past_its_distance() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"swipe direction="w" fingers=4 repeat-interval=300 repeat-distance=100"#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+        let mut swipe = |handler: &mut EventHandler, event, time| {
+            handler.handle_synthetic(event, time, &mut mh).unwrap();
+        };
+
+        swipe(&mut handler, SyntheticEvent::SwipeBegin { fingers: 4 }, 0);
+        swipe(
+            &mut handler,
+            SyntheticEvent::SwipeUpdate { dx: -60.0, dy: 0.0 },
+            10_000,
+        );
+        assert!(handler.swipe_repeats.is_empty());
+        swipe(
+            &mut handler,
+            SyntheticEvent::SwipeUpdate { dx: -60.0, dy: 0.0 },
+            20_000,
+        );
+        assert_eq!(handler.swipe_repeats.len(), 1);
+
+        let now = Instant::now();
+        handler.fire_swipe_repeats(now).unwrap();
+        assert!(handler.swipe_repeats[0].repeated);
+        assert_eq!(
+            handler.next_deadline(),
+            Some(now + Duration::from_millis(300))
+        );
+
+        // Back short of the distance pauses, past it again repeats right away
+        swipe(
+            &mut handler,
+            SyntheticEvent::SwipeUpdate { dx: 40.0, dy: 0.0 },
+            30_000,
+        );
+        assert_eq!(handler.next_deadline(), None);
+        swipe(
+            &mut handler,
+            SyntheticEvent::SwipeUpdate { dx: -40.0, dy: 0.0 },
+            40_000,
+        );
+        assert!(handler.next_deadline().is_some_and(|d| d <= Instant::now()));
+
+        swipe(&mut handler, SyntheticEvent::SwipeEnd, 50_000);
+        assert!(handler.swipe_repeats.is_empty());
+        assert_eq!(handler.next_deadline(), None);
+    }
+
+    #[test]
+    fn idle_pause_waits_for_the_gesture_to_end() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            idle-pause 10
+            swipe direction="w" fingers=4 end="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert!(!handler.idle_for(minutes(9)));
+        assert!(handler.idle_for(minutes(10)));
+
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeBegin { fingers: 4 }, 0, &mut mh)
+            .unwrap();
+        assert!(!handler.idle_for(minutes(60)));
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeCancel, 10_000, &mut mh)
+            .unwrap();
+        handler.resume_after_idle();
+        assert!(handler.idle_for(minutes(60)));
+    }
+
+    #[test]
+    fn pinch_zoom_emits_wheel_units_for_scale_changes() {
+        let config = Config {
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: Vec::new(),
+            devices: Vec::new(),
+            groups: Vec::new(),
+            gestures: vec![Gesture::Pinch(Pinch {
+                fingers: 2,
+                direction: PinchDir::Any,
+                update: Some("@zoom".to_string()),
+                start: None,
+                end: None,
+                cancel: None,
+                key: None,
+                script: None,
+                type_text: None,
+                paste: None,
+                hyprland: None,
+                hyprland_update: None,
+                sway: None,
+                sway_update: None,
+                rotate_step_deg: None,
+                min_scale_delta: None,
+                step: None,
+                update_rate: None,
+                conditions: Conditions::default(),
+                spawn: SpawnOptions::default(),
+                log: RuleLog::default(),
+                pipe: Box::default(),
+            })],
+        };
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        // One zoom step in, then back out to the starting scale
+        handler
+            .handle_pinch_update(2, &PinchDir::Out, ZOOM_STEP, 0.0, &mut mh)
+            .unwrap();
+        handler
+            .handle_pinch_update(2, &PinchDir::In, 1.0, 0.0, &mut mh)
+            .unwrap();
+
+        let total: i32 = mh.zoom_calls.iter().sum();
+        assert_eq!(mh.zoom_calls[0], HI_RES_UNITS_PER_NOTCH);
+        assert!(total.abs() <= 1, "zoom should return to start, got {total}");
+    }
+
+    #[test]
+    fn rotate_rules_start_past_threshold_in_their_direction() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            rotate fingers=2 direction="cw" threshold=10 start="true" end="true"
+            rotate fingers=2 direction="ccw" start="true" end="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+        assert_eq!(handler.configured_fingers().1, vec![2]);
+
+        handler
+            .handle_synthetic(SyntheticEvent::PinchBegin { fingers: 2 }, 0, &mut mh)
+            .unwrap();
+        for (i, angle) in [4.0, 4.0, -1.0, 4.0].into_iter().enumerate() {
+            let event = SyntheticEvent::PinchUpdate { scale: 1.0, angle };
+            handler
+                .handle_synthetic(event, (i as u64 + 1) * 10_000, &mut mh)
+                .unwrap();
+        }
+        // 11 degrees clockwise: past the cw threshold only, the ccw rule needs 15 the other way
+        assert_eq!(handler.rotation.angle, 11.0);
+        assert_eq!(handler.rotation.started, vec![0]);
+
+        handler
+            .handle_synthetic(SyntheticEvent::PinchEnd, 60_000, &mut mh)
+            .unwrap();
+        assert_eq!(handler.rotation.angle, 0.0);
+        assert!(handler.rotation.started.is_empty());
+    }
+
+    #[test]
+    fn drag_released_in_a_corner_matches_corner_rules() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=0
+            swipe direction="any" fingers=3 corner="top-left" end="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+        let mut mh = MockMouseHandler::new();
+
+        handler.handle_swipe_begin(3, &mut mh).unwrap();
+        // From (500, 400) past the top left, where the real pointer would have stopped
+        for _ in 0..6 {
+            handler.handle_swipe_update(-100.0, -80.0, &mut mh).unwrap();
+        }
+        let region = handler.region.expect("hot corner rules track the pointer");
+        assert_eq!(region.bounds, Some((0, 0, 1920, 1080)));
+        assert_eq!(region.moved, (-600, -480));
+        assert_eq!(region.corner(), Some(ScreenCorner::TopLeft));
+        // The drag is still released normally
+        handler.handle_swipe_end(&mut mh).unwrap();
+        assert_eq!(mh.mouse_up_calls, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn swipe_speed_is_averaged_over_the_gesture() {
+        let mut motion = MotionTracker::default();
+        motion.reset("swipe", 3, 1_000_000);
+        assert_eq!(motion.mean_speed(), 0.0);
+        // A quick 300 unit flick over 100ms that slows down at the end
+        motion.swipe(180.0, 0.0, 1_050_000);
+        motion.swipe(0.0, 100.0, 1_090_000);
+        motion.swipe(20.0, 0.0, 1_100_000);
+        assert!((motion.mean_speed() - 3000.0).abs() < 1e-6);
+        assert!((motion.vars().velocity_x - 2000.0).abs() < 1e-6);
+        let vars = motion.vars();
+        assert_eq!(
+            (
+                vars.fingers,
+                vars.gesture_type,
+                vars.direction,
+                vars.elapsed_ms
+            ),
+            (3, "swipe", "se", 100)
+        );
+
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            swipe direction="e" fingers=3 min-velocity=1500 end="flick"
+            swipe direction="e" fingers=3 max-velocity=1500 end="slow"
+            "#,
+        )
+        .unwrap();
+        let rules: Vec<_> = config
+            .gestures
+            .iter()
+            .filter_map(|g| match g {
+                Gesture::Swipe(s) => Some(s),
+                _ => None,
+            })
+            .collect();
+        assert!(!config.gestures[0].same_trigger(&config.gestures[1]));
+        let units = DeviceUnits::default();
+        assert!(rules[0].matches_velocity(motion.mean_speed(), &units));
+        assert!(!rules[1].matches_velocity(motion.mean_speed(), &units));
+        assert!(!rules[0].matches_velocity(400.0, &units));
+        assert!(rules[1].matches_velocity(400.0, &units));
+    }
+
+    #[test]
+    fn gestures_from_a_backlog_are_stale() {
+        let now = event_age(0).as_micros() as u64;
+        assert!(!EventHandler::is_stale(now));
+        assert!(!EventHandler::is_stale(now - 500_000));
+        assert!(EventHandler::is_stale(now - 5_000_000));
+    }
+
+    #[test]
+    fn knob_rules_step_once_per_rotate_step_deg() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            rotate fingers=2 direction="any" rotate-step-deg=10 update="true"
+            pinch fingers=2 direction="clockwise" rotate-step-deg=5 update="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        handler
+            .handle_synthetic(SyntheticEvent::PinchBegin { fingers: 2 }, 0, &mut mh)
+            .unwrap();
+        for (i, angle) in [6.0, 8.0, 13.0, -30.0].into_iter().enumerate() {
+            let event = SyntheticEvent::PinchUpdate { scale: 1.0, angle };
+            handler
+                .handle_synthetic(event, (i as u64 + 1) * 10_000, &mut mh)
+                .unwrap();
+        }
+        // 27 degrees clockwise then back to -3: up two detents and down two again
+        assert_eq!(handler.rotation.started, vec![0]);
+        assert_eq!(handler.rotation.steps[&0], 0);
+        assert_eq!(handler.rotation.pinch_steps[&0], 0);
+    }
+
+    #[test]
+    fn stepped_pinch_counts_scale_steps() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            pinch fingers=2 direction="out" step=0.1 update="true"
+            pinch fingers=2 direction="any" step=0.25 update="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        handler
+            .handle_synthetic(SyntheticEvent::PinchBegin { fingers: 2 }, 0, &mut mh)
+            .unwrap();
+        let mut pinch = |handler: &mut EventHandler, scales: &[f64]| {
+            for scale in scales {
+                let event = SyntheticEvent::PinchUpdate {
+                    scale: *scale,
+                    angle: 0.0,
+                };
+                handler.handle_synthetic(event, 10_000, &mut mh).unwrap();
+            }
+        };
+        // Out to 1.31 passes three 0.1 steps and one 0.25 step, back to 0.95 undoes them
+        pinch(&mut handler, &[1.05, 1.12, 1.31]);
+        assert_eq!(handler.scale_steps[&0], 3);
+        assert_eq!(handler.scale_steps[&1], 1);
+        pinch(&mut handler, &[0.95]);
+        assert_eq!(handler.scale_steps[&0], 0);
+        assert_eq!(handler.scale_steps[&1], 0);
+        let mut mh = MockMouseHandler::new();
+        handler
+            .handle_synthetic(SyntheticEvent::PinchBegin { fingers: 2 }, 60_000, &mut mh)
+            .unwrap();
+        assert!(handler.scale_steps.is_empty());
+    }
+
+    #[test]
+    fn pipe_gets_a_line_per_update_until_the_swipe_ends() {
+        let dir = std::env::temp_dir().join(format!("gestures-pipe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            &format!(
+                r#"swipe direction="any" fingers=4 pipe="cat > updates" cwd="{}""#,
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        for (event, time) in [
+            (SyntheticEvent::SwipeBegin { fingers: 4 }, 0),
+            (SyntheticEvent::SwipeUpdate { dx: 2.0, dy: 0.0 }, 10_000),
+            (SyntheticEvent::SwipeUpdate { dx: 3.0, dy: 0.0 }, 20_000),
+        ] {
+            handler.handle_synthetic(event, time, &mut mh).unwrap();
+        }
+        assert_eq!(handler.pipes.len(), 1);
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeEnd, 30_000, &mut mh)
+            .unwrap();
+        assert!(handler.pipes.is_empty());
+
+        // `cat` exits once its stdin is closed at the end of the swipe
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let lines = loop {
+            let text = std::fs::read_to_string(dir.join("updates")).unwrap_or_default();
+            if text.lines().count() == 2 || Instant::now() > deadline {
+                break text;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        let _ = std::fs::remove_dir_all(&dir);
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("gesture_type=swipe fingers=4 direction=e delta_x=2.00"));
+        assert!(lines[1].ends_with("elapsed_ms=20"));
+    }
+
+    #[test]
+    fn cancelled_gestures_run_cancel_instead_of_end() {
+        let dir = std::env::temp_dir().join(format!("gestures-cancel-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            &format!(
+                r#"
+                swipe direction="e" fingers=4 end="touch end" cancel="touch swipe-cancelled" cwd="{dir}"
+                pinch direction="any" fingers=2 end="touch end" cancel="touch pinch-cancelled" cwd="{dir}"
+                "#,
+                dir = dir.display()
+            ),
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeBegin { fingers: 4 }, 0, &mut mh)
+            .unwrap();
+        handler
+            .handle_synthetic(SyntheticEvent::SwipeCancel, 10_000, &mut mh)
+            .unwrap();
+        handler
+            .handle_synthetic(SyntheticEvent::PinchBegin { fingers: 2 }, 20_000, &mut mh)
+            .unwrap();
+        handler
+            .handle_synthetic(SyntheticEvent::PinchCancel, 30_000, &mut mh)
+            .unwrap();
+        assert_eq!(handler.event, Gesture::None);
+        // Nothing left to end
+        handler
+            .handle_synthetic(SyntheticEvent::PinchEnd, 40_000, &mut mh)
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !(dir.join("swipe-cancelled").exists() && dir.join("pinch-cancelled").exists()) {
+            assert!(Instant::now() < deadline, "cancel commands should run");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!dir.join("end").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hold_then_move_drags_with_the_after_hold_rule() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=0
+            swipe direction="any" fingers=3 acceleration=20 mouse-up-delay=0 after-hold=300
+            hold fingers=3 action="true"
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+        let drag = |handler: &mut EventHandler, rest_usec: u64| {
+            let mut mh = MockMouseHandler::new();
+            for (event, time) in [
+                (SyntheticEvent::HoldBegin { fingers: 3 }, 0),
+                (SyntheticEvent::HoldCancel, rest_usec),
+                (SyntheticEvent::SwipeBegin { fingers: 3 }, rest_usec),
+                (
+                    SyntheticEvent::SwipeUpdate { dx: 100.0, dy: 0.0 },
+                    rest_usec + 10_000,
+                ),
+                (SyntheticEvent::SwipeEnd, rest_usec + 20_000),
+            ] {
+                handler.handle_synthetic(event, time, &mut mh).unwrap();
+            }
+            mh.move_calls.iter().map(|(x, _)| x).sum::<i32>()
+        };
+
+        // A short rest is a plain drag, a long one picks the after-hold rule instead
+        assert_eq!(drag(&mut handler, 100_000), 100);
+        assert_eq!(drag(&mut handler, 400_000), 200);
+        assert!(handler.hold_rest.is_none());
+    }
+
+    #[test]
+    fn hold_duration_picks_the_rule_and_runs_start_and_end() {
+        let dir = std::env::temp_dir().join(format!("gestures-hold-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            &format!(
+                r#"
+                hold fingers=3 start="touch pressed" max-duration=300 action="touch tap" cwd="{dir}"
+                hold fingers=3 min-duration=300 action="touch long" end="touch lifted" cwd="{dir}"
+                "#,
+                dir = dir.display()
+            ),
+        )
+        .unwrap();
+        assert!(!config.gestures[0].same_trigger(&config.gestures[1]));
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        let mut mh = MockMouseHandler::new();
+
+        handler
+            .handle_synthetic(SyntheticEvent::HoldBegin { fingers: 3 }, 0, &mut mh)
+            .unwrap();
+        handler
+            .handle_synthetic(SyntheticEvent::HoldEnd, 800_000, &mut mh)
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !["pressed", "long", "lifted"]
+            .iter()
+            .all(|f| dir.join(f).exists())
+        {
+            assert!(
+                Instant::now() < deadline,
+                "start, action and end should run"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!dir.join("tap").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn auto_output_scale_follows_pointer_across_outputs() {
+        let config: Config = knuffel::parse(
+            "test.kdl",
+            r#"
+            output-scale "auto"
+            swipe direction="any" fingers=3 acceleration=10 mouse-up-delay=500
+            "#,
+        )
+        .unwrap();
+        let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+        handler.throttle.min_interval = std::time::Duration::ZERO;
+        let mut mh = MockMouseHandler::new();
+
+        // The pointer starts at (500, 400) on DP-1, at scale 1
+        handler.handle_swipe_begin(3, &mut mh).unwrap();
+        for _ in 0..6 {
+            handler.handle_swipe_update(100.0, 0.0, &mut mh).unwrap();
+        }
+        // The fifth move reaches eDP-1, where the same finger travel moves half as many pixels
+        assert_eq!(
+            mh.move_calls,
+            vec![(100, 0), (100, 0), (100, 0), (100, 0), (100, 0), (50, 0)]
+        );
+        assert_eq!(handler.units.output_scale, 2.0);
+
+        // A move past the layout keeps the pointer on eDP-1
+        handler.handle_swipe_update(4000.0, 0.0, &mut mh).unwrap();
+        handler.handle_swipe_update(-10.0, 0.0, &mut mh).unwrap();
+        assert_eq!(handler.units.output_scale, 2.0);
+    }
 }
@@ -0,0 +1,133 @@
+//! Commands that keep failing: their non-zero exits are logged at most once a minute per
+//! command, and with `notify-failures` a desktop notification tells about a command that failed
+//! that many times in a row, e.g. a typo like `hyprcl dispatch`
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::history::CommandStatus;
+
+/// Failures of the same command logged in between are only counted
+const FAILURE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `notify-failures` of the config, 0 without it
+static NOTIFY_AFTER: AtomicU32 = AtomicU32::new(0);
+
+static FAILURES: Lazy<Mutex<FailureLog>> = Lazy::new(|| Mutex::new(FailureLog::default()));
+
+/// Follow `notify-failures` of the config
+pub fn set_notify_after(failures: Option<u32>) {
+    NOTIFY_AFTER.store(failures.unwrap_or(0), Ordering::Relaxed);
+}
+
+#[derive(Debug, Default)]
+struct Failing {
+    in_a_row: u32,
+    /// Failures since the last one logged
+    unlogged: u32,
+    logged_at: Option<Instant>,
+}
+
+/// What to do about a failure
+#[derive(Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Log it, with the number of failures that weren't since the last time
+    pub log: Option<u32>,
+    /// Failures in a row when this one reached `notify-failures`
+    pub notify: Option<u32>,
+}
+
+/// Failing commands by their template in the config, so a command with variables counts as one
+#[derive(Debug, Default)]
+pub struct FailureLog {
+    failing: HashMap<String, Failing>,
+}
+
+impl FailureLog {
+    /// A run of `template` succeeded, it starts over
+    pub fn succeeded(&mut self, template: &str) {
+        self.failing.remove(template);
+    }
+
+    /// A run of `template` failed at `now`, notifying once when it failed `notify_after` times
+    /// in a row
+    pub fn failed(&mut self, template: &str, now: Instant, notify_after: u32) -> Report {
+        let failing = self.failing.entry(template.to_string()).or_default();
+        failing.in_a_row += 1;
+        let log = if failing
+            .logged_at
+            .is_none_or(|at| now.saturating_duration_since(at) >= FAILURE_LOG_INTERVAL)
+        {
+            failing.logged_at = Some(now);
+            Some(std::mem::take(&mut failing.unlogged))
+        } else {
+            failing.unlogged += 1;
+            None
+        };
+        Report {
+            log,
+            notify: (notify_after > 0 && failing.in_a_row == notify_after)
+                .then_some(failing.in_a_row),
+        }
+    }
+}
+
+/// Record the outcome of `command`, run for `template`
+pub fn record(template: &str, command: &str, status: &CommandStatus) {
+    let report = match status {
+        CommandStatus::Exited(0) | CommandStatus::Running | CommandStatus::Skipped(_) => {
+            FAILURES.lock().succeeded(template);
+            return;
+        }
+        // Logged where they happen, they only count towards the notification
+        CommandStatus::Failed(_) => Report {
+            log: None,
+            ..FAILURES.lock().failed(
+                template,
+                Instant::now(),
+                NOTIFY_AFTER.load(Ordering::Relaxed),
+            )
+        },
+        CommandStatus::Exited(_) | CommandStatus::Signaled(_) => FAILURES.lock().failed(
+            template,
+            Instant::now(),
+            NOTIFY_AFTER.load(Ordering::Relaxed),
+        ),
+    };
+    match report.log {
+        Some(0) => log::warn!("Command '{}' failed: {}", command, status),
+        Some(unlogged) => log::warn!(
+            "Command '{}' failed: {} ({} more failures in the last {}s not logged)",
+            command,
+            status,
+            unlogged,
+            FAILURE_LOG_INTERVAL.as_secs()
+        ),
+        None => log::debug!("Command '{}' failed: {}", command, status),
+    }
+    if let Some(in_a_row) = report.notify {
+        notify(&format!(
+            "'{}' failed {} times in a row ({})",
+            command, in_a_row, status
+        ));
+    }
+}
+
+fn notify(body: &str) {
+    let sent = Command::new("notify-send")
+        .args(["-a", "gestures", "-u", "critical", "--"])
+        .arg("A gesture command keeps failing")
+        .arg(body)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if let Err(e) = sent {
+        log::warn!("Could not send a notification with notify-send: {}", e);
+    }
+}
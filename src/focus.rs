@@ -0,0 +1,527 @@
+//! Focused window tracking for `app=`, `window-class=` and `window-title=` conditions: events of
+//! the Hyprland or Sway/i3 IPC, wlr-foreign-toplevel-management (which every wlroots compositor
+//! offers whether or not it has an IPC of its own) or EWMH properties on X11
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use crate::compositor::{connect_registry, dispatch, message, read_string, read_u32, wire_string};
+use crate::compositor::{hyprland_socket, FOREIGN_TOPLEVEL_MANAGER, WL_REGISTRY};
+use crate::json::Json;
+use crate::sway;
+
+/// Id after wl_display (1), the registry (2) and the probe's sync callback (3)
+const MANAGER: u32 = 4;
+/// Version 3 added `parent`, nothing newer is needed
+const MANAGER_VERSION: u32 = 3;
+
+/// zwlr_foreign_toplevel_manager_v1 events
+const TOPLEVEL: u32 = 0;
+const FINISHED: u32 = 1;
+
+/// zwlr_foreign_toplevel_handle_v1 events
+const TITLE: u32 = 0;
+const APP_ID: u32 = 1;
+const STATE: u32 = 4;
+const DONE: u32 = 5;
+const CLOSED: u32 = 6;
+/// zwlr_foreign_toplevel_handle_v1.destroy request
+const DESTROY: u32 = 1;
+const STATE_ACTIVATED: u32 = 2;
+
+/// i3-ipc `SUBSCRIBE` and `GET_TREE` messages, and the `window` event
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+const WINDOW_EVENT: u32 = 0x8000_0003;
+
+/// Where the focused window comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusProvider {
+    /// `activewindow` events of Hyprland's event socket
+    Hyprland,
+    /// `window` events of the Sway or i3 IPC
+    Sway,
+    /// zwlr_foreign_toplevel_manager_v1, wlroots compositors
+    ForeignToplevel,
+    /// `_NET_ACTIVE_WINDOW` of an EWMH window manager
+    X11,
+    /// Nothing usable, window conditions never match
+    None,
+}
+
+impl FocusProvider {
+    /// Pick a provider: the IPC of the `compositor`, the globals it advertises, or EWMH on X11
+    pub fn select(compositor: Option<&str>, x11: bool, wayland_globals: Option<&[String]>) -> Self {
+        let foreign_toplevel = cfg!(feature = "wlr")
+            && wayland_globals.is_some_and(|g| g.iter().any(|g| g == FOREIGN_TOPLEVEL_MANAGER));
+        match compositor {
+            Some("hyprland") => Self::Hyprland,
+            Some("sway" | "i3") => Self::Sway,
+            _ if foreign_toplevel => Self::ForeignToplevel,
+            _ if x11 && cfg!(feature = "x11") => Self::X11,
+            _ => Self::None,
+        }
+    }
+
+    /// Start following focus changes in the background
+    pub fn start(self) {
+        if self == Self::None {
+            return;
+        }
+        let spawned = std::thread::Builder::new()
+            .name("focus".to_string())
+            .spawn(move || {
+                let result = match self {
+                    Self::Hyprland => follow_hyprland(),
+                    Self::Sway => follow_sway(),
+                    Self::ForeignToplevel => follow_foreign_toplevels(),
+                    Self::X11 => follow_x11(),
+                    Self::None => Ok(()),
+                };
+                if let Err(e) = result {
+                    log::warn!("Stopped tracking the focused window: {}", e);
+                }
+                set_focused(Focused::default());
+            });
+        if let Err(e) = spawned {
+            log::warn!("Could not start tracking the focused window: {}", e);
+        }
+    }
+}
+
+impl fmt::Display for FocusProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Hyprland => "hyprland",
+            Self::Sway => "sway",
+            Self::ForeignToplevel => "wlr-foreign-toplevel",
+            Self::X11 => "ewmh",
+            Self::None => "none",
+        })
+    }
+}
+
+/// Focused window as far as the provider tells
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Focused {
+    /// App id on Wayland, the `WM_CLASS` class on X11
+    pub app: Option<String>,
+    pub title: Option<String>,
+}
+
+static FOCUSED: Lazy<RwLock<Focused>> = Lazy::new(|| RwLock::new(Focused::default()));
+/// Bumped on every focus change so the gesture cache knows to re-evaluate conditions
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// App id of the focused window, `None` when unknown
+pub fn focused_app() -> Option<String> {
+    FOCUSED.read().app.clone()
+}
+
+/// Title of the focused window, `None` when unknown
+pub fn focused_title() -> Option<String> {
+    FOCUSED.read().title.clone()
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+fn set_focused(window: Focused) {
+    let mut focused = FOCUSED.write();
+    if *focused != window {
+        log::debug!(
+            "Focused app: {} ({})",
+            window.app.as_deref().unwrap_or("none"),
+            window.title.as_deref().unwrap_or("")
+        );
+        *focused = window;
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Toplevel {
+    app_id: Option<String>,
+    title: Option<String>,
+    activated: bool,
+    /// Changes sent since the last `done`, applied together
+    pending_app_id: Option<String>,
+    pending_title: Option<String>,
+    pending_activated: Option<bool>,
+}
+
+/// Toplevels announced by the manager, fed its events in order
+#[derive(Debug, Default)]
+pub struct Toplevels {
+    handles: HashMap<u32, Toplevel>,
+}
+
+/// What the connection has to do after an event
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    None,
+    /// The toplevel was closed, its handle should be destroyed
+    Destroy(u32),
+    /// The manager will send nothing more, focus is unknown from here on
+    Finished,
+}
+
+impl Toplevels {
+    pub fn event(&mut self, object: u32, opcode: u32, payload: &[u8]) -> Reply {
+        if object == MANAGER {
+            match opcode {
+                TOPLEVEL => {
+                    if let Some(handle) = read_u32(payload, 0) {
+                        self.handles.insert(handle, Toplevel::default());
+                    }
+                }
+                FINISHED => {
+                    self.handles.clear();
+                    return Reply::Finished;
+                }
+                _ => {}
+            }
+            return Reply::None;
+        }
+        let Some(toplevel) = self.handles.get_mut(&object) else {
+            return Reply::None;
+        };
+        match opcode {
+            TITLE => toplevel.pending_title = read_string(payload, 0),
+            APP_ID => toplevel.pending_app_id = read_string(payload, 0),
+            STATE => {
+                // array of uint states
+                let len = read_u32(payload, 0).unwrap_or(0) as usize;
+                let activated = (0..len / 4)
+                    .filter_map(|i| read_u32(payload, 4 + i * 4))
+                    .any(|state| state == STATE_ACTIVATED);
+                toplevel.pending_activated = Some(activated);
+            }
+            DONE => {
+                if let Some(app_id) = toplevel.pending_app_id.take() {
+                    toplevel.app_id = Some(app_id);
+                }
+                if let Some(title) = toplevel.pending_title.take() {
+                    toplevel.title = Some(title);
+                }
+                if let Some(activated) = toplevel.pending_activated.take() {
+                    toplevel.activated = activated;
+                }
+            }
+            CLOSED => {
+                self.handles.remove(&object);
+                return Reply::Destroy(object);
+            }
+            _ => {}
+        }
+        Reply::None
+    }
+
+    /// App id and title of the activated toplevel
+    pub fn focused_window(&self) -> Focused {
+        self.handles
+            .values()
+            .find(|t| t.activated)
+            .map_or_else(Focused::default, |t| Focused {
+                app: t.app_id.clone(),
+                title: t.title.clone(),
+            })
+    }
+}
+
+fn follow_foreign_toplevels() -> Result<(), String> {
+    let (mut stream, globals) = connect_registry()?;
+    let manager = globals
+        .iter()
+        .find(|g| g.interface == FOREIGN_TOPLEVEL_MANAGER)
+        .ok_or_else(|| format!("compositor does not offer {}", FOREIGN_TOPLEVEL_MANAGER))?;
+
+    // wl_registry.bind(name, interface, version, id)
+    let mut bind = manager.name.to_ne_bytes().to_vec();
+    bind.extend(wire_string(FOREIGN_TOPLEVEL_MANAGER));
+    bind.extend_from_slice(&manager.version.min(MANAGER_VERSION).to_ne_bytes());
+    bind.extend_from_slice(&MANAGER.to_ne_bytes());
+    stream
+        .write_all(&message(WL_REGISTRY, 0, &bind))
+        .map_err(|e| e.to_string())?;
+    log::info!(
+        "Tracking the focused window through {}",
+        FOREIGN_TOPLEVEL_MANAGER
+    );
+
+    let mut requests = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut toplevels = Toplevels::default();
+    dispatch(&mut stream, |object, opcode, payload| {
+        match toplevels.event(object, opcode, payload) {
+            Reply::None => {}
+            Reply::Destroy(handle) => {
+                let _ = requests.write_all(&message(handle, DESTROY, &[]));
+            }
+            Reply::Finished => log::warn!("{} finished", FOREIGN_TOPLEVEL_MANAGER),
+        }
+        set_focused(toplevels.focused_window());
+    })
+}
+
+/// Focus change in a line of Hyprland's event socket, `activewindow>>class,title`
+pub fn hyprland_event(line: &str) -> Option<Focused> {
+    let (class, title) = line.strip_prefix("activewindow>>")?.split_once(',')?;
+    Some(Focused {
+        app: (!class.is_empty()).then(|| class.to_string()),
+        title: (!title.is_empty()).then(|| title.to_string()),
+    })
+}
+
+/// Window in a Hyprland `j/activewindow` reply or a Sway container, an empty object when nothing
+/// has focus
+pub fn window_of(window: &Json) -> Focused {
+    let text = |value: Option<&Json>| {
+        value
+            .and_then(Json::as_str)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+    };
+    Focused {
+        // `class` on Hyprland, `app_id` on Sway, `window_properties.class` for X11 windows on Sway
+        // and i3
+        app: text(window.get("class"))
+            .or_else(|| text(window.get("app_id")))
+            .or_else(|| text(window.pointer(&["window_properties", "class"]))),
+        title: text(window.get("title")).or_else(|| text(window.get("name"))),
+    }
+}
+
+fn follow_hyprland() -> Result<(), String> {
+    let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|e| e.to_string())?;
+    let requests = hyprland_socket(&signature)
+        .ok_or_else(|| format!("no socket for Hyprland instance {}", signature))?;
+    let events =
+        UnixStream::connect(requests.with_file_name(".socket2.sock")).map_err(|e| e.to_string())?;
+    log::info!("Tracking the focused window through Hyprland's event socket");
+
+    if let Ok(reply) = crate::hyprland::request(&requests, "j/activewindow") {
+        set_focused(Json::parse(&reply).map_or_else(|_| Focused::default(), |w| window_of(&w)));
+    }
+    for line in BufReader::new(events).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Some(window) = hyprland_event(&line) {
+            set_focused(window);
+        }
+    }
+    Err("Hyprland closed its event socket".to_string())
+}
+
+/// Focused container of a Sway/i3 `GET_TREE` reply, floating windows included
+pub fn focused_in_tree(node: &Json) -> Option<&Json> {
+    if node.get("focused") == Some(&Json::Bool(true)) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node.get(key).and_then(Json::as_array))
+        .flatten()
+        .find_map(focused_in_tree)
+}
+
+/// Focus change in a Sway/i3 `window` event, `None` for events about other windows
+pub fn sway_event(event: &Json) -> Option<Focused> {
+    let change = event.get("change").and_then(Json::as_str)?;
+    let container = event.get("container")?;
+    let focused = container.get("focused") == Some(&Json::Bool(true));
+    match change {
+        "focus" => Some(window_of(container)),
+        "title" if focused => Some(window_of(container)),
+        "close" if focused => Some(Focused::default()),
+        _ => None,
+    }
+}
+
+fn follow_sway() -> Result<(), String> {
+    let mut stream = UnixStream::connect(sway::socket_path().map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(&sway::encode(GET_TREE, ""))
+        .map_err(|e| e.to_string())?;
+    let (_, tree) = sway::read_message(&mut stream).map_err(|e| e.to_string())?;
+    if let Ok(tree) = Json::parse(&tree) {
+        set_focused(focused_in_tree(&tree).map_or_else(Focused::default, window_of));
+    }
+    stream
+        .write_all(&sway::encode(SUBSCRIBE, r#"["window"]"#))
+        .map_err(|e| e.to_string())?;
+    log::info!("Tracking the focused window through the Sway/i3 IPC");
+
+    loop {
+        let (kind, payload) = sway::read_message(&mut stream).map_err(|e| e.to_string())?;
+        if kind != WINDOW_EVENT {
+            continue;
+        }
+        if let Some(window) = Json::parse(&payload).ok().as_ref().and_then(sway_event) {
+            set_focused(window);
+        }
+    }
+}
+
+#[cfg(feature = "x11")]
+fn follow_x11() -> Result<(), String> {
+    let watcher = xlib::ActiveWindow::open()?;
+    log::info!("Tracking the focused window through _NET_ACTIVE_WINDOW");
+    watcher.follow(set_focused)
+}
+
+#[cfg(not(feature = "x11"))]
+fn follow_x11() -> Result<(), String> {
+    Err("built without X11 support".to_string())
+}
+
+#[cfg(feature = "x11")]
+mod xlib {
+    use std::ffi::CString;
+    use std::os::raw::{c_int, c_uchar, c_ulong};
+
+    use x11::xlib::{
+        AnyPropertyType, Display, False, PropertyChangeMask, PropertyNotify, Window, XCloseDisplay,
+        XDefaultRootWindow, XEvent, XFree, XGetWindowProperty, XInternAtom, XNextEvent,
+        XOpenDisplay, XSelectInput,
+    };
+
+    use super::Focused;
+
+    /// Own display connection following `_NET_ACTIVE_WINDOW` on the root window and the title of
+    /// the window it names
+    pub struct ActiveWindow {
+        display: *mut Display,
+        root: Window,
+    }
+
+    impl ActiveWindow {
+        pub fn open() -> Result<Self, String> {
+            // SAFETY: a null name opens $DISPLAY, the result is checked before use
+            let display = unsafe { XOpenDisplay(std::ptr::null()) };
+            if display.is_null() {
+                return Err("cannot open the X display".to_string());
+            }
+            // SAFETY: `display` is a live connection
+            let root = unsafe { XDefaultRootWindow(display) };
+            Ok(Self { display, root })
+        }
+
+        fn atom(&self, name: &str) -> c_ulong {
+            let name = CString::new(name).unwrap();
+            // SAFETY: `display` is live and `name` NUL terminated
+            unsafe { XInternAtom(self.display, name.as_ptr(), False) }
+        }
+
+        /// Raw items of a window property and their format (8 or 32 bits)
+        fn property(&self, window: Window, name: &str) -> Option<(c_int, Vec<u8>)> {
+            let (mut actual_type, mut format) = (0, 0 as c_int);
+            let (mut items, mut remaining) = (0 as c_ulong, 0 as c_ulong);
+            let mut data: *mut c_uchar = std::ptr::null_mut();
+            // SAFETY: out pointers reference live locals, `data` is freed with XFree below
+            let status = unsafe {
+                XGetWindowProperty(
+                    self.display,
+                    window,
+                    self.atom(name),
+                    0,
+                    1024,
+                    False,
+                    AnyPropertyType as c_ulong,
+                    &mut actual_type,
+                    &mut format,
+                    &mut items,
+                    &mut remaining,
+                    &mut data,
+                )
+            };
+            if status != 0 || data.is_null() {
+                return None;
+            }
+            // Xlib hands format 32 items out as longs
+            let size = match format {
+                8 => 1,
+                32 => std::mem::size_of::<std::os::raw::c_long>(),
+                _ => 0,
+            };
+            // SAFETY: the property holds `items` items of `size` bytes at `data`
+            let bytes = unsafe { std::slice::from_raw_parts(data, items as usize * size).to_vec() };
+            // SAFETY: `data` was allocated by Xlib
+            unsafe { XFree(data.cast()) };
+            (!bytes.is_empty()).then_some((format, bytes))
+        }
+
+        fn active(&self) -> Option<Window> {
+            let (format, bytes) = self.property(self.root, "_NET_ACTIVE_WINDOW")?;
+            let window = bytes.get(..std::mem::size_of::<Window>())?;
+            let window = Window::from_ne_bytes(window.try_into().ok()?);
+            (format == 32 && window != 0).then_some(window)
+        }
+
+        fn text(&self, window: Window, name: &str) -> Option<String> {
+            let (format, bytes) = self.property(window, name)?;
+            (format == 8).then(|| String::from_utf8_lossy(&bytes).into_owned())
+        }
+
+        fn window(&self, window: Window) -> Focused {
+            // WM_CLASS holds the instance and the class, each NUL terminated
+            let class = self
+                .text(window, "WM_CLASS")
+                .and_then(|c| c.split('\0').rfind(|s| !s.is_empty()).map(str::to_string));
+            Focused {
+                app: class,
+                title: self
+                    .text(window, "_NET_WM_NAME")
+                    .or_else(|| self.text(window, "WM_NAME")),
+            }
+        }
+
+        /// Report the active window to `report` whenever it or its title changes
+        pub fn follow(&self, report: impl Fn(Focused)) -> Result<(), String> {
+            let active_window = self.atom("_NET_ACTIVE_WINDOW");
+            let titles = [self.atom("_NET_WM_NAME"), self.atom("WM_NAME")];
+            // SAFETY: `display` and `root` are live
+            unsafe { XSelectInput(self.display, self.root, PropertyChangeMask) };
+            let mut active = self.active();
+            let watch = |window: Option<Window>| {
+                if let Some(window) = window {
+                    // SAFETY: `display` is live, a window that is already gone only raises an
+                    // X error that Xlib logs
+                    unsafe { XSelectInput(self.display, window, PropertyChangeMask) };
+                }
+            };
+            watch(active);
+            report(active.map_or_else(Focused::default, |w| self.window(w)));
+            loop {
+                let mut event = XEvent { pad: [0; 24] };
+                // SAFETY: `display` is live and `event` large enough for any event
+                unsafe { XNextEvent(self.display, &mut event) };
+                if event.get_type() != PropertyNotify {
+                    continue;
+                }
+                // SAFETY: the type says this is a property event
+                let property = unsafe { event.property };
+                if property.window == self.root && property.atom == active_window {
+                    active = self.active();
+                    watch(active);
+                } else if Some(property.window) != active || !titles.contains(&property.atom) {
+                    continue;
+                }
+                report(active.map_or_else(Focused::default, |w| self.window(w)));
+            }
+        }
+    }
+
+    impl Drop for ActiveWindow {
+        fn drop(&mut self) {
+            // SAFETY: `display` was opened by XOpenDisplay and is not used afterwards
+            unsafe { XCloseDisplay(self.display) };
+        }
+    }
+}
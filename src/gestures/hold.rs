@@ -1,4 +1,10 @@
-use knuffel::Decode;
+use knuffel::{Decode, DecodeScalar};
+
+use crate::conditions::Conditions;
+use crate::keys::KeyCombo;
+use crate::logs::RuleLog;
+use crate::script::Script;
+use crate::utils::SpawnOptions;
 
 #[derive(Decode, Debug, Clone, PartialEq, Eq)]
 pub struct Hold {
@@ -6,4 +12,66 @@ pub struct Hold {
     pub fingers: i32,
     #[knuffel(property)]
     pub action: Option<String>,
+    /// Runs as soon as the fingers come down, before the hold's length is known
+    #[knuffel(property)]
+    pub start: Option<String>,
+    /// Runs when the fingers are lifted, for either trigger
+    #[knuffel(property)]
+    pub end: Option<String>,
+    /// Key combination pressed in-process when `action` would run
+    #[knuffel(property, str)]
+    pub key: Option<KeyCombo>,
+    /// Script run in-process when `action` would run, e.g. `if delta_x > 100 { key("super+Right") }`
+    #[knuffel(property, str)]
+    pub script: Option<Script>,
+    /// Text typed in-process when `action` would run, e.g. a signature or emoji
+    #[knuffel(property)]
+    pub type_text: Option<String>,
+    /// Paste `type-text` from the clipboard instead of typing it key by key
+    #[knuffel(property)]
+    pub paste: Option<bool>,
+    #[knuffel(property)]
+    pub trigger: Option<HoldTrigger>,
+    /// Milliseconds the fingers must stay down before a `timeout` trigger fires
+    #[knuffel(property)]
+    pub duration: Option<u64>,
+    /// Milliseconds between repeated firings of a `timeout` trigger while still held
+    #[knuffel(property)]
+    pub repeat: Option<u64>,
+    /// Milliseconds the fingers must stay down for the rule to fire on release
+    #[knuffel(property)]
+    pub min_duration: Option<u64>,
+    /// Milliseconds after which lifting the fingers no longer fires the rule
+    #[knuffel(property)]
+    pub max_duration: Option<u64>,
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    #[knuffel(flatten(property))]
+    pub spawn: SpawnOptions,
+    #[knuffel(flatten(property))]
+    pub log: RuleLog,
+}
+
+/// When a hold action fires
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HoldTrigger {
+    /// On GestureHoldEvent::End, after the fingers are lifted
+    #[default]
+    Release,
+    /// As soon as `duration` elapses while the fingers are still down
+    Timeout,
+}
+
+pub const DEFAULT_HOLD_DURATION_MS: u64 = 500;
+
+impl Hold {
+    pub fn trigger(&self) -> HoldTrigger {
+        self.trigger.unwrap_or_default()
+    }
+
+    /// Whether fingers lifted after `held_ms` are within `min-duration` and `max-duration`
+    pub fn held_for(&self, held_ms: u64) -> bool {
+        self.min_duration.is_none_or(|min| held_ms >= min)
+            && self.max_duration.is_none_or(|max| held_ms <= max)
+    }
 }
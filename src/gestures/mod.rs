@@ -1,11 +1,24 @@
 pub mod hold;
 pub mod pinch;
+pub mod rotate;
 pub mod swipe;
 
-use knuffel::Decode;
+use knuffel::{
+    ast::{Literal, TypeName},
+    decode::Context,
+    errors::DecodeError,
+    span::Spanned,
+    traits::ErrorSpan,
+    Decode, DecodeScalar,
+};
 
+use std::collections::HashMap;
+
+use crate::conditions::{Conditions, Modifiers};
+use crate::utils::expand_vars;
 use hold::Hold;
 use pinch::Pinch;
+use rotate::Rotate;
 use swipe::Swipe;
 
 #[derive(Decode, Debug, Clone, PartialEq)]
@@ -13,5 +26,174 @@ pub enum Gesture {
     Swipe(Swipe),
     Pinch(Pinch),
     Hold(Hold),
+    Rotate(Rotate),
     None,
 }
+
+impl Gesture {
+    pub fn conditions(&self) -> Option<&Conditions> {
+        match self {
+            Gesture::Swipe(s) => Some(&s.conditions),
+            Gesture::Pinch(p) => Some(&p.conditions),
+            Gesture::Hold(h) => Some(&h.conditions),
+            Gesture::Rotate(r) => Some(&r.conditions),
+            Gesture::None => None,
+        }
+    }
+
+    pub fn conditions_mut(&mut self) -> Option<&mut Conditions> {
+        match self {
+            Gesture::Swipe(s) => Some(&mut s.conditions),
+            Gesture::Pinch(p) => Some(&mut p.conditions),
+            Gesture::Hold(h) => Some(&mut h.conditions),
+            Gesture::Rotate(r) => Some(&mut r.conditions),
+            Gesture::None => None,
+        }
+    }
+
+    /// Copy with `$name` references to user variables replaced in all commands
+    pub fn with_vars(&self, vars: &HashMap<String, String>) -> Self {
+        let mut gesture = self.clone();
+        if vars.is_empty() {
+            return gesture;
+        }
+        let expand = |cmd: &mut Option<String>| {
+            if let Some(cmd) = cmd {
+                *cmd = expand_vars(cmd, vars);
+            }
+        };
+        match &mut gesture {
+            Gesture::Swipe(s) => {
+                expand(&mut s.spawn.cwd);
+                expand(&mut s.update);
+                expand(&mut s.start);
+                expand(&mut s.end);
+                expand(&mut s.cancel);
+                expand(&mut s.repeating.repeat);
+                for zone in &mut s.drop_zones {
+                    zone.action = expand_vars(&zone.action, vars);
+                }
+            }
+            Gesture::Pinch(p) => {
+                expand(&mut p.spawn.cwd);
+                expand(&mut p.update);
+                expand(&mut p.start);
+                expand(&mut p.end);
+                expand(&mut p.cancel);
+            }
+            Gesture::Hold(h) => {
+                expand(&mut h.spawn.cwd);
+                expand(&mut h.action);
+                expand(&mut h.start);
+                expand(&mut h.end);
+            }
+            Gesture::Rotate(r) => {
+                expand(&mut r.spawn.cwd);
+                expand(&mut r.start);
+                expand(&mut r.update);
+                expand(&mut r.end);
+            }
+            Gesture::None => {}
+        }
+        gesture
+    }
+
+    /// Commands and compositor requests of the rule, as written in the config
+    pub fn commands(&self) -> Vec<&str> {
+        let commands: Vec<&Option<String>> = match self {
+            Gesture::Swipe(s) => vec![
+                &s.start,
+                &s.update,
+                &s.end,
+                &s.cancel,
+                &s.repeating.repeat,
+                &s.hyprland,
+                &s.hyprland_update,
+                &s.sway,
+                &s.sway_update,
+            ],
+            Gesture::Pinch(p) => vec![
+                &p.start,
+                &p.update,
+                &p.end,
+                &p.cancel,
+                &p.hyprland,
+                &p.hyprland_update,
+                &p.sway,
+                &p.sway_update,
+            ],
+            Gesture::Hold(h) => vec![&h.start, &h.action, &h.end],
+            Gesture::Rotate(r) => vec![&r.start, &r.update, &r.end],
+            Gesture::None => Vec::new(),
+        };
+        let mut commands: Vec<&str> = commands.into_iter().flatten().map(String::as_str).collect();
+        if let Gesture::Swipe(s) = self {
+            commands.extend(s.drop_zones.iter().map(|z| z.action.as_str()));
+        }
+        commands
+    }
+
+    /// True when both rules bind the same gesture with the same modifiers, so `self` replaces
+    /// `other` when layering configs
+    pub fn same_trigger(&self, other: &Gesture) -> bool {
+        let after_hold = |g: &Gesture| match g {
+            Gesture::Swipe(s) => s.after_hold,
+            _ => None,
+        };
+        self.modifiers() == other.modifiers()
+            && after_hold(self) == after_hold(other)
+            && self.same_gesture(other)
+    }
+
+    /// `modifiers` of the rule's conditions
+    pub fn modifiers(&self) -> Option<Modifiers> {
+        self.conditions().and_then(|c| c.modifiers)
+    }
+
+    /// Whether both rules are bound to the same motion, whatever keyboard modifiers or rest before
+    /// a swipe they need
+    pub fn same_gesture(&self, other: &Gesture) -> bool {
+        match (self, other) {
+            (Gesture::Swipe(a), Gesture::Swipe(b)) => {
+                a.fingers == b.fingers
+                    && a.direction == b.direction
+                    && a.edge == b.edge
+                    && a.corner == b.corner
+                    && a.min_velocity == b.min_velocity
+                    && a.max_velocity == b.max_velocity
+            }
+            (Gesture::Pinch(a), Gesture::Pinch(b)) => {
+                a.fingers == b.fingers && a.direction == b.direction
+            }
+            (Gesture::Hold(a), Gesture::Hold(b)) => {
+                a.fingers == b.fingers
+                    && a.min_duration == b.min_duration
+                    && a.max_duration == b.max_duration
+            }
+            (Gesture::Rotate(a), Gesture::Rotate(b)) => {
+                a.fingers == b.fingers && a.direction == b.direction
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Floating point property that also accepts integer literals, e.g. `acceleration=20`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct Float(pub f64);
+
+impl<S: ErrorSpan> DecodeScalar<S> for Float {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <f64 as DecodeScalar<S>>::type_check(type_name, ctx);
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        match &**value {
+            Literal::Int(_) => i64::raw_decode(value, ctx).map(|v| Float(v as f64)),
+            _ => f64::raw_decode(value, ctx).map(Float),
+        }
+    }
+}
@@ -1,6 +1,14 @@
 use knuffel::{Decode, DecodeScalar};
 
-#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+use super::Float;
+use crate::conditions::Conditions;
+use crate::keys::KeyCombo;
+use crate::logs::RuleLog;
+use crate::pipe::PipeAction;
+use crate::script::Script;
+use crate::utils::SpawnOptions;
+
+#[derive(Decode, Debug, Clone, PartialEq)]
 pub struct Pinch {
     #[knuffel(property)]
     pub fingers: i32,
@@ -12,6 +20,69 @@ pub struct Pinch {
     pub start: Option<String>,
     #[knuffel(property)]
     pub end: Option<String>,
+    /// Command run instead of `end` when libinput cancels the pinch
+    #[knuffel(property)]
+    pub cancel: Option<String>,
+    /// Key combination pressed in-process when `end` would run
+    #[knuffel(property, str)]
+    pub key: Option<KeyCombo>,
+    /// Script run in-process when `end` would run, e.g. `if delta_x > 100 { key("super+Right") }`
+    #[knuffel(property, str)]
+    pub script: Option<Script>,
+    /// Text typed in-process when `end` would run, e.g. a signature or emoji
+    #[knuffel(property)]
+    pub type_text: Option<String>,
+    /// Paste `type-text` from the clipboard instead of typing it key by key
+    #[knuffel(property)]
+    pub paste: Option<bool>,
+    /// Hyprland request sent over its socket when `end` would run, e.g. `dispatch workspace e+1`
+    #[knuffel(property)]
+    pub hyprland: Option<String>,
+    /// Hyprland request sent on every update, next to the `update` command
+    #[knuffel(property)]
+    pub hyprland_update: Option<String>,
+    /// i3/Sway command run over its IPC socket when `end` would run, e.g. `workspace next`
+    #[knuffel(property)]
+    pub sway: Option<String>,
+    /// i3/Sway command run on every update, next to the `update` command
+    #[knuffel(property)]
+    pub sway_update: Option<String>,
+    /// Knob mode for `clockwise`/`counter-clockwise` rules: run `update` once per this many
+    /// degrees turned
+    #[knuffel(property)]
+    pub rotate_step_deg: Option<Float>,
+    /// Total scale change an `in`/`out` pinch must reach for `end` to run, e.g. `0.2`; the
+    /// direction then follows the scale since the pinch began instead of the last update
+    #[knuffel(property)]
+    pub min_scale_delta: Option<Float>,
+    /// Stepped mode for `in`/`out`/`any` rules: run `update` once per this much scale change,
+    /// e.g. `0.1`, instead of on every pinch update
+    #[knuffel(property)]
+    pub step: Option<Float>,
+    /// Most `update` commands per second, every pinch update runs them without it
+    #[knuffel(property)]
+    pub update_rate: Option<u32>,
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    #[knuffel(flatten(property))]
+    pub spawn: SpawnOptions,
+    #[knuffel(flatten(property))]
+    pub log: RuleLog,
+    /// Command fed every update on its stdin, started once per pinch
+    #[knuffel(flatten(property))]
+    pub pipe: Box<PipeAction>,
+}
+
+impl Pinch {
+    /// Whether `end` runs for a pinch that ended in `dir` at `scale` since it began
+    pub fn ends_on(&self, dir: &PinchDir, scale: f64) -> bool {
+        match self.min_scale_delta {
+            Some(delta) if matches!(self.direction, PinchDir::In | PinchDir::Out) => {
+                (scale - 1.0).abs() >= delta.0 && self.direction == PinchDir::dir(scale, 0.0)
+            }
+            _ => self.direction == *dir || self.direction == PinchDir::Any,
+        }
+    }
 }
 
 /// Direction of pinch gestures
@@ -40,4 +111,15 @@ impl PinchDir {
             Self::In
         }
     }
+
+    /// Name as written in the config, empty for `any`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::In => "in",
+            Self::Out => "out",
+            Self::Clockwise => "clockwise",
+            Self::CounterClockwise => "counter-clockwise",
+            Self::Any => "",
+        }
+    }
 }
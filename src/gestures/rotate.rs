@@ -0,0 +1,94 @@
+use knuffel::{Decode, DecodeScalar};
+
+use super::Float;
+use crate::conditions::Conditions;
+use crate::logs::RuleLog;
+use crate::utils::SpawnOptions;
+
+/// Two or more fingers turning on the pad, fed by libinput's pinch angle deltas
+#[derive(Decode, Debug, Clone, PartialEq)]
+pub struct Rotate {
+    #[knuffel(property)]
+    pub fingers: i32,
+    #[knuffel(property)]
+    pub direction: RotateDir,
+    #[knuffel(property)]
+    pub start: Option<String>,
+    #[knuffel(property)]
+    pub update: Option<String>,
+    #[knuffel(property)]
+    pub end: Option<String>,
+    /// Degrees the fingers must turn before the rule starts, small turns of a pinch are ignored
+    #[knuffel(property)]
+    pub threshold: Option<Float>,
+    /// Knob mode: run `update` once per this many degrees turned instead of on every event
+    #[knuffel(property)]
+    pub rotate_step_deg: Option<Float>,
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    #[knuffel(flatten(property))]
+    pub spawn: SpawnOptions,
+    #[knuffel(flatten(property))]
+    pub log: RuleLog,
+}
+
+/// Direction of rotate gestures
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotateDir {
+    /// Clockwise
+    Cw,
+    /// Counter-clockwise
+    Ccw,
+    Any,
+}
+
+pub const DEFAULT_ROTATE_THRESHOLD: f64 = 15.0;
+
+impl RotateDir {
+    /// Direction of a total rotation in degrees, libinput reports clockwise as positive
+    pub fn of(angle: f64) -> Self {
+        if angle >= 0.0 {
+            Self::Cw
+        } else {
+            Self::Ccw
+        }
+    }
+
+    /// `$direction` of a rotation
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cw => "cw",
+            Self::Ccw => "ccw",
+            Self::Any => "any",
+        }
+    }
+}
+
+/// Whole steps of a knob-style rule turned since the last call, negative counter-clockwise.
+/// `fired` is the step count reached so far, turning back and forth past the same step fires it
+/// again like a detent.
+pub fn knob_steps(angle: f64, step: f64, fired: &mut i64) -> i64 {
+    if step <= 0.0 {
+        return 0;
+    }
+    let reached = (angle / step).trunc() as i64;
+    let steps = reached - *fired;
+    *fired = reached;
+    steps
+}
+
+impl Rotate {
+    pub fn threshold(&self) -> f64 {
+        self.threshold.map_or(DEFAULT_ROTATE_THRESHOLD, |t| t.0)
+    }
+
+    pub fn step(&self) -> Option<f64> {
+        self.rotate_step_deg.map(|s| s.0)
+    }
+
+    /// Whether the fingers turned far enough in this rule's direction
+    pub fn triggered_by(&self, angle: f64) -> bool {
+        angle.abs() >= self.threshold()
+            && (self.direction == RotateDir::Any || self.direction == RotateDir::of(angle))
+    }
+}
@@ -1,6 +1,20 @@
+use std::fmt;
+use std::str::FromStr;
+
 use knuffel::{Decode, DecodeScalar};
 
-#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+use super::Float;
+use crate::conditions::Conditions;
+use crate::contacts::PadEdge;
+use crate::dbus::DbusCall;
+use crate::keys::KeyCombo;
+use crate::logs::RuleLog;
+use crate::pipe::PipeAction;
+use crate::script::Script;
+use crate::units::{Acceleration, DeviceUnits, Distance};
+use crate::utils::SpawnOptions;
+
+#[derive(Decode, Debug, Clone, PartialEq)]
 pub struct Swipe {
     #[knuffel(property)]
     pub direction: SwipeDir,
@@ -12,10 +26,229 @@ pub struct Swipe {
     pub start: Option<String>,
     #[knuffel(property)]
     pub end: Option<String>,
+    /// Command run instead of `end` when libinput cancels the swipe
+    #[knuffel(property)]
+    pub cancel: Option<String>,
+    /// Firing again while the swipe is held, boxed as it is rarely set
+    #[knuffel(flatten(property))]
+    pub repeating: Box<Repeat>,
+    /// Key combination pressed in-process when `end` would run, e.g. `ctrl+plus`
+    #[knuffel(property, str)]
+    pub key: Option<KeyCombo>,
+    /// Script run in-process when `end` would run, e.g. `if delta_x > 100 { key("super+Right") }`
+    #[knuffel(property, str)]
+    pub script: Option<Script>,
+    /// Text typed in-process when `end` would run, e.g. a signature or emoji
+    #[knuffel(property)]
+    pub type_text: Option<String>,
+    /// Paste `type-text` from the clipboard instead of typing it key by key
+    #[knuffel(property)]
+    pub paste: Option<bool>,
+    /// Hyprland request sent over its socket when `end` would run, e.g. `dispatch workspace e+1`
+    #[knuffel(property)]
+    pub hyprland: Option<String>,
+    /// Hyprland request sent on every update, next to the `update` command
+    #[knuffel(property)]
+    pub hyprland_update: Option<String>,
+    /// i3/Sway command run over its IPC socket when `end` would run, e.g. `workspace next`
+    #[knuffel(property)]
+    pub sway: Option<String>,
+    /// i3/Sway command run on every update, next to the `update` command
+    #[knuffel(property)]
+    pub sway_update: Option<String>,
+    /// Pointer speed of a drag, unitless or in `px/mm`
     #[knuffel(property)]
-    pub acceleration: Option<i8>,
+    pub acceleration: Option<Acceleration>,
+    #[knuffel(property, str)]
+    pub acceleration_curve: Option<AccelCurve>,
+    /// Minimum cutoff frequency (Hz) of the 1€ filter applied to drag deltas
+    #[knuffel(property)]
+    pub smoothing: Option<Float>,
+    /// Speed coefficient of the 1€ filter, higher values reduce lag on fast movements
+    #[knuffel(property)]
+    pub smoothing_beta: Option<Float>,
     #[knuffel(property)]
     pub mouse_up_delay: Option<i64>,
+    /// Updates per second of this rule instead of the global `update-rate`, `0` for every event
+    #[knuffel(property)]
+    pub update_rate: Option<u32>,
+    /// Glide of a drag after the fingers lift, boxed as it is rarely set
+    #[knuffel(flatten(property))]
+    pub glide: Box<Glide>,
+    /// Keep the screen from dimming or locking while the drag is held
+    #[knuffel(property)]
+    pub idle_inhibit: Option<bool>,
+    /// Only run when the swipe starts at this border of the touchpad
+    #[knuffel(property)]
+    pub edge: Option<PadEdge>,
+    /// Only run when the fingers rested this many milliseconds before moving, replacing the rule
+    /// for the same swipe without it
+    #[knuffel(property)]
+    pub after_hold: Option<u64>,
+    /// Only run `end` when the drag of the same swipe is released in this screen corner
+    #[knuffel(property)]
+    pub corner: Option<ScreenCorner>,
+    /// Only run `end` when the swipe averaged at least this speed per second (libinput units,
+    /// `mm` or `px`)
+    #[knuffel(property)]
+    pub min_velocity: Option<Distance>,
+    /// Only run `end` when the swipe averaged at most this speed
+    #[knuffel(property)]
+    pub max_velocity: Option<Distance>,
+    #[knuffel(children(name = "drop-zone"))]
+    pub drop_zones: Vec<DropZone>,
+    #[knuffel(flatten(property))]
+    pub conditions: Conditions,
+    #[knuffel(flatten(property))]
+    pub spawn: SpawnOptions,
+    #[knuffel(flatten(property))]
+    pub log: RuleLog,
+    /// Method call made on every update, next to the `update` command. Boxed as it is rarely set
+    /// and would make every rule larger
+    #[knuffel(flatten(property))]
+    pub dbus: Box<DbusCall>,
+    /// Command fed every update on its stdin, started once per swipe. Boxed like `dbus`
+    #[knuffel(flatten(property))]
+    pub pipe: Box<PipeAction>,
+}
+
+impl Swipe {
+    /// Whether a swipe of mean `speed` (libinput units per second) is within `min-velocity` and
+    /// `max-velocity`
+    pub fn matches_velocity(&self, speed: f64, units: &DeviceUnits) -> bool {
+        self.min_velocity
+            .is_none_or(|min| speed >= units.units(min))
+            && self
+                .max_velocity
+                .is_none_or(|max| speed <= units.units(max))
+    }
+
+    /// Whether a swipe that moved `offset` since it began is held far enough in this rule's
+    /// direction to repeat
+    pub fn repeats_at(&self, offset: (f64, f64), units: &DeviceUnits) -> bool {
+        let distance = self
+            .repeating
+            .repeat_distance
+            .unwrap_or(DEFAULT_REPEAT_DISTANCE);
+        self.repeating.repeat_interval.is_some()
+            && self.direction != SwipeDir::Any
+            && SwipeDir::dir(offset.0, offset.1) == self.direction
+            && offset.0.hypot(offset.1) >= units.units(distance)
+    }
+}
+
+/// Distance a `repeat-interval` swipe travels before repeating when `repeat-distance` is unset
+pub const DEFAULT_REPEAT_DISTANCE: Distance = Distance::Mm(15.0);
+
+/// Repeats of a directional swipe held past a distance, e.g. to keep switching workspaces
+#[derive(Decode, Debug, Clone, Default, PartialEq)]
+pub struct Repeat {
+    /// Milliseconds between repeats while the swipe is held past `repeat-distance`, each repeat
+    /// runs `repeat` or the rule's `end` actions, and `end` is then skipped on release
+    #[knuffel(property)]
+    pub repeat_interval: Option<u64>,
+    /// Command run on every repeat instead of the `end` actions, `end` still runs on release
+    #[knuffel(property)]
+    pub repeat: Option<String>,
+    /// Distance travelled in the rule's direction before repeating starts (libinput units, `mm`,
+    /// `px` or `%` of the pad width)
+    #[knuffel(property)]
+    pub repeat_distance: Option<Distance>,
+}
+
+/// Pointer of a drag kept moving after the fingers lift
+#[derive(Decode, Debug, Clone, Default, PartialEq)]
+pub struct Glide {
+    /// Keep the pointer moving at the drag's last speed, slowing down until it stops
+    #[knuffel(property)]
+    pub inertia: Option<bool>,
+    /// How quickly the glide slows down, the share of its speed lost per second is
+    /// `1 - e^-friction`
+    #[knuffel(property)]
+    pub friction: Option<Float>,
+}
+
+/// Command fired when a drag is released with the pointer at a screen edge or corner
+#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+pub struct DropZone {
+    /// Edge (`n`, `s`, `e`, `w`) or corner (`ne`, `nw`, `se`, `sw`) of the screen
+    #[knuffel(property)]
+    pub edge: SwipeDir,
+    #[knuffel(property)]
+    pub action: String,
+    /// Distance from the screen border in pixels that still counts as the edge
+    #[knuffel(property, default = 5)]
+    pub size: i32,
+}
+
+impl DropZone {
+    fn is_corner(&self) -> bool {
+        matches!(
+            self.edge,
+            SwipeDir::NE | SwipeDir::NW | SwipeDir::SE | SwipeDir::SW
+        )
+    }
+
+    pub fn contains(&self, x: i32, y: i32, width: i32, height: i32) -> bool {
+        let north = y < self.size;
+        let south = y >= height - self.size;
+        let west = x < self.size;
+        let east = x >= width - self.size;
+
+        match self.edge {
+            SwipeDir::N => north,
+            SwipeDir::S => south,
+            SwipeDir::E => east,
+            SwipeDir::W => west,
+            SwipeDir::NE => north && east,
+            SwipeDir::NW => north && west,
+            SwipeDir::SE => south && east,
+            SwipeDir::SW => south && west,
+            SwipeDir::Any => false,
+        }
+    }
+
+    /// Find the zone under the pointer, corners take precedence over edges
+    pub fn find(zones: &[DropZone], x: i32, y: i32, width: i32, height: i32) -> Option<&DropZone> {
+        let mut hits = zones.iter().filter(|z| z.contains(x, y, width, height));
+        let first = hits.next()?;
+        if first.is_corner() {
+            return Some(first);
+        }
+        hits.find(|z| z.is_corner()).or(Some(first))
+    }
+}
+
+/// Screen corner for `corner=` hot corner rules
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Distance from the screen border in pixels that counts as a hot corner
+pub const HOT_CORNER_SIZE: i32 = 10;
+
+impl ScreenCorner {
+    /// Corner a drag ended in, `bounds` is the screen as `(x, y, width, height)`. The end point
+    /// is the start plus the pixels moved, so a drag that kept going past the border (where the
+    /// real pointer stopped) still counts.
+    pub fn at(x: i32, y: i32, bounds: (i32, i32, i32, i32)) -> Option<Self> {
+        let (left, top, width, height) = bounds;
+        let west = x < left + HOT_CORNER_SIZE;
+        let east = x >= left + width - HOT_CORNER_SIZE;
+        let north = y < top + HOT_CORNER_SIZE;
+        let south = y >= top + height - HOT_CORNER_SIZE;
+        match (north, south, west, east) {
+            (true, _, true, _) => Some(Self::TopLeft),
+            (true, _, _, true) => Some(Self::TopRight),
+            (_, true, true, _) => Some(Self::BottomLeft),
+            (_, true, _, true) => Some(Self::BottomRight),
+            _ => None,
+        }
+    }
 }
 
 /// Direction of swipe gestures
@@ -58,4 +291,143 @@ impl SwipeDir {
             _ => SwipeDir::W,                          // 7π/8 to π
         }
     }
+
+    /// Name as written in the config, empty for `any`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Any => "",
+            Self::N => "n",
+            Self::S => "s",
+            Self::E => "e",
+            Self::W => "w",
+            Self::NE => "ne",
+            Self::NW => "nw",
+            Self::SE => "se",
+            Self::SW => "sw",
+        }
+    }
+}
+
+/// Speed at which the quadratic curve doubles the base acceleration
+const QUADRATIC_PIVOT: f64 = 10.0;
+/// Speed below which the adaptive curve slows the pointer down for precision
+const ADAPTIVE_THRESHOLD: f64 = 2.0;
+/// Factor of the adaptive curve for a finger barely moving
+const ADAPTIVE_MIN: f64 = 0.5;
+/// Factor the adaptive curve levels off at for fast flicks
+const ADAPTIVE_MAX: f64 = 3.0;
+
+/// Non-linear scaling applied on top of `acceleration` for direct mouse drags
+///
+/// The factor depends on the per-event movement speed (length of the delta vector),
+/// so slow movements stay precise while fast swings travel further.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AccelCurve {
+    #[default]
+    Linear,
+    Quadratic,
+    /// Like libinput's adaptive profile: slower than `acceleration` for the slowest movements,
+    /// then growing with speed up to a cap
+    Adaptive,
+    /// Sorted `(speed, factor)` points, linearly interpolated and clamped at both ends
+    Points(Vec<(f64, f64)>),
+}
+
+impl AccelCurve {
+    pub fn factor(&self, speed: f64) -> f64 {
+        match self {
+            Self::Linear => 1.0,
+            Self::Quadratic => 1.0 + speed / QUADRATIC_PIVOT,
+            Self::Adaptive if speed < ADAPTIVE_THRESHOLD => {
+                ADAPTIVE_MIN + (1.0 - ADAPTIVE_MIN) * speed / ADAPTIVE_THRESHOLD
+            }
+            Self::Adaptive => {
+                (1.0 + (speed - ADAPTIVE_THRESHOLD) / QUADRATIC_PIVOT).min(ADAPTIVE_MAX)
+            }
+            Self::Points(points) => {
+                let (first, last) = match (points.first(), points.last()) {
+                    (Some(first), Some(last)) => (first, last),
+                    _ => return 1.0,
+                };
+                if speed <= first.0 {
+                    return first.1;
+                }
+                if speed >= last.0 {
+                    return last.1;
+                }
+                for w in points.windows(2) {
+                    let ((s0, f0), (s1, f1)) = (w[0], w[1]);
+                    if speed <= s1 {
+                        let t = (speed - s0) / (s1 - s0);
+                        return f0 + (f1 - f0) * t;
+                    }
+                }
+                last.1
+            }
+        }
+    }
+}
+
+impl FromStr for AccelCurve {
+    type Err = String;
+
+    /// Accepts `linear` (or `flat`), `quadratic`, `adaptive` or a list of `speed:factor` pairs,
+    /// e.g. `"0:1 5:1.5 20:3"`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "linear" | "flat" => return Ok(Self::Linear),
+            "quadratic" => return Ok(Self::Quadratic),
+            "adaptive" => return Ok(Self::Adaptive),
+            _ => {}
+        }
+
+        let mut points = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (speed, factor) = p
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected `speed:factor`, got `{p}`"))?;
+                let speed: f64 = speed
+                    .parse()
+                    .map_err(|_| format!("invalid speed `{speed}`"))?;
+                let factor: f64 = factor
+                    .parse()
+                    .map_err(|_| format!("invalid factor `{factor}`"))?;
+                if speed < 0.0 || factor <= 0.0 {
+                    return Err(format!(
+                        "curve point `{p}` must have speed >= 0 and factor > 0"
+                    ));
+                }
+                Ok((speed, factor))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if points.is_empty() {
+            return Err(
+                "acceleration-curve must be `linear`, `quadratic` or `speed:factor` points"
+                    .to_string(),
+            );
+        }
+
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self::Points(points))
+    }
+}
+
+impl fmt::Display for AccelCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Linear => f.write_str("linear"),
+            Self::Quadratic => f.write_str("quadratic"),
+            Self::Adaptive => f.write_str("adaptive"),
+            Self::Points(points) => {
+                let points: Vec<String> = points
+                    .iter()
+                    .map(|(speed, factor)| format!("{}:{}", speed, factor))
+                    .collect();
+                f.write_str(&points.join(" "))
+            }
+        }
+    }
 }
@@ -0,0 +1,166 @@
+//! Recent gestures and what became of them, served over IPC by `gestures history`
+//!
+//! Unlike `gestures trace` it keeps one entry per gesture: the rule that fired, or that none did,
+//! and the exit status of the command the rule ran.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Number of gestures kept for `gestures history`
+const HISTORY_CAPACITY: usize = 100;
+
+static HISTORY: Lazy<Mutex<VecDeque<Entry>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(HISTORY_CAPACITY)));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Entry a command reports its outcome to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryId(u64);
+
+/// What became of the command of a rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandStatus {
+    Running,
+    Exited(i32),
+    Signaled(i32),
+    /// Not run, e.g. by a dry run or `max-concurrent`
+    Skipped(&'static str),
+    /// Couldn't be started
+    Failed(String),
+}
+
+impl From<ExitStatus> for CommandStatus {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Self::Exited(code),
+            (None, Some(signal)) => Self::Signaled(signal),
+            (None, None) => Self::Failed(status.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running => f.write_str("running"),
+            Self::Exited(code) => write!(f, "exit {}", code),
+            Self::Signaled(signal) => write!(f, "killed by signal {}", signal),
+            Self::Skipped(reason) => write!(f, "skipped, {}", reason),
+            Self::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+/// A gesture that ended, or a rule that fired while it went on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    id: u64,
+    time: String,
+    /// e.g. `3-finger swipe e`
+    pub gesture: String,
+    /// Whether a rule fired, `None` when no rule matched the gesture
+    pub rule: Option<RuleFired>,
+}
+
+/// The rule that fired for a gesture and its command
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleFired {
+    /// `log-label` of the rule
+    pub label: Option<String>,
+    /// The command as run, with its variables filled in
+    pub command: Option<String>,
+    pub status: Option<CommandStatus>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: ", self.time, self.gesture)?;
+        let Some(rule) = &self.rule else {
+            return f.write_str("no rule matched");
+        };
+        match &rule.label {
+            Some(label) => write!(f, "rule [{}] fired", label)?,
+            None => f.write_str("rule fired")?,
+        }
+        match (&rule.command, &rule.status) {
+            (Some(command), Some(status)) => write!(f, ", ran '{}': {}", command, status),
+            _ => f.write_str(", no command"),
+        }
+    }
+}
+
+fn push(gesture: &str, rule: Option<RuleFired>) -> EntryId {
+    // Taken under the lock so entries stay in id order, one event handler per seat records
+    let mut history = HISTORY.lock();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if history.len() == HISTORY_CAPACITY {
+        history.pop_front();
+    }
+    history.push_back(Entry {
+        id,
+        time: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+        gesture: gesture.to_string(),
+        rule,
+    });
+    EntryId(id)
+}
+
+/// Record a rule that fired for `gesture`, its command reports to the returned entry
+pub fn fired(gesture: &str, label: Option<&str>) -> EntryId {
+    push(
+        gesture,
+        Some(RuleFired {
+            label: label.map(str::to_string),
+            command: None,
+            status: None,
+        }),
+    )
+}
+
+/// Record a gesture that ended without any rule firing
+pub fn unmatched(gesture: &str) {
+    push(gesture, None);
+}
+
+/// Number of entries recorded so far, to tell whether a rule fired since
+pub fn recorded() -> u64 {
+    NEXT_ID.load(Ordering::Relaxed)
+}
+
+fn update(id: EntryId, f: impl FnOnce(&mut RuleFired)) {
+    let mut history = HISTORY.lock();
+    // Entries are in id order, one that was pushed out is gone
+    let first = history.front().map_or(0, |e| e.id);
+    let rule =
+        id.0.checked_sub(first)
+            .and_then(|i| history.get_mut(i as usize))
+            .and_then(|e| e.rule.as_mut());
+    if let Some(rule) = rule {
+        f(rule);
+    }
+}
+
+/// The rule's command was queued as `command`
+pub fn command_queued(id: EntryId, command: &str) {
+    update(id, |rule| {
+        rule.command = Some(command.to_string());
+        rule.status = Some(CommandStatus::Running);
+    });
+}
+
+/// The rule's command finished, or wasn't run after all
+pub fn command_finished(id: EntryId, status: CommandStatus) {
+    update(id, |rule| rule.status = Some(status));
+}
+
+/// Oldest first copy of the recorded gestures
+pub fn snapshot() -> Vec<Entry> {
+    HISTORY.lock().iter().cloned().collect()
+}
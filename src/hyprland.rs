@@ -0,0 +1,118 @@
+//! `hyprland=` actions: requests written straight to Hyprland's socket instead of spawning
+//! `hyprctl` for every swipe
+//!
+//! Hyprland answers one request per connection, so every dispatch connects again. Dispatches that
+//! queue up while Hyprland is busy, e.g. continuous updates, go out together as one `[[BATCH]]`.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::compositor::hyprland_socket;
+use crate::trace::trace_event;
+use crate::utils::{render_command, skipped_by_dry_run, CommandVars};
+
+/// Dispatches queued while Hyprland is slow, further updates are dropped
+const DISPATCH_QUEUE_SIZE: usize = 64;
+/// A compositor that stopped answering must not stall later dispatches for long
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Render `request` (e.g. `dispatch workspace e+1`) with `vars` and queue it, a no-op for `None`
+pub fn dispatch(request: Option<&str>, vars: &CommandVars) {
+    let Some(request) = request.and_then(|r| render_command(r, vars)) else {
+        return;
+    };
+    trace_event!("hyprland {}", request);
+    if skipped_by_dry_run(format_args!("hyprland {}", request)) {
+        return;
+    }
+    let Some(tx) = WORKER.get_or_init(start_worker) else {
+        return;
+    };
+    if let Err(mpsc::TrySendError::Full(request)) = tx.try_send(request) {
+        log::debug!("Hyprland worker busy, dropping `{}`", request);
+    }
+}
+
+/// Requests to send for the queued `dispatches`: one batch, or each on its own when an argument
+/// contains the `;` batches are split on
+pub fn batch(dispatches: Vec<String>) -> Vec<String> {
+    if dispatches.len() < 2 || dispatches.iter().any(|d| d.contains(';')) {
+        return dispatches;
+    }
+    vec![format!("[[BATCH]]{}", dispatches.join(";"))]
+}
+
+/// Hyprland replies `ok` to every dispatch that worked, anything else is an error message
+pub fn reply_ok(reply: &str) -> bool {
+    reply
+        .split("\n\n")
+        .map(str::trim)
+        .all(|r| r.is_empty() || r == "ok")
+}
+
+/// Send one request to the socket at `path` and read the reply
+pub fn request(path: &Path, body: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    stream.write_all(body.as_bytes())?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// The socket is looked up for every request, so a restarted Hyprland is found again
+fn send(body: &str) -> io::Result<String> {
+    let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "HYPRLAND_INSTANCE_SIGNATURE is not set",
+        )
+    })?;
+    let path = hyprland_socket(&signature).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no socket for Hyprland instance {}", signature),
+        )
+    })?;
+    request(&path, body)
+}
+
+static WORKER: OnceLock<Option<mpsc::SyncSender<String>>> = OnceLock::new();
+
+fn start_worker() -> Option<mpsc::SyncSender<String>> {
+    let (tx, rx) = mpsc::sync_channel::<String>(DISPATCH_QUEUE_SIZE);
+    let spawned = thread::Builder::new()
+        .name("hyprland".to_string())
+        .spawn(move || {
+            // Warn once per outage instead of on every update
+            let mut failing = false;
+            while let Ok(first) = rx.recv() {
+                let mut dispatches = vec![first];
+                dispatches.extend(rx.try_iter());
+                for body in batch(dispatches) {
+                    match send(&body) {
+                        Ok(reply) if reply_ok(&reply) => failing = false,
+                        Ok(reply) => log::warn!("Hyprland refused `{}`: {}", body, reply.trim()),
+                        Err(e) if failing => log::debug!("Hyprland request failed: {}", e),
+                        Err(e) => {
+                            log::warn!("Cannot reach Hyprland: {}", e);
+                            failing = true;
+                        }
+                    }
+                }
+            }
+        });
+    match spawned {
+        Ok(_) => Some(tx),
+        Err(e) => {
+            log::warn!("Could not start the Hyprland worker: {}", e);
+            None
+        }
+    }
+}
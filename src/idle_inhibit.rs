@@ -0,0 +1,80 @@
+use std::process::{Child, Command, Stdio};
+
+/// Blocks idle actions (screen dim/lock) through a logind inhibitor lock while held
+///
+/// The lock is owned by a `systemd-inhibit` child process, which works the same under
+/// X11 and Wayland sessions as long as the desktop honours logind idle inhibitors.
+#[derive(Debug)]
+pub struct IdleInhibitor {
+    /// Program and arguments holding the lock while they run
+    command: &'static [&'static str],
+    child: Option<Child>,
+}
+
+const INHIBIT_COMMAND: &[&str] = &[
+    "systemd-inhibit",
+    "--what=idle",
+    "--who=gestures",
+    "--why=Drag in progress",
+    "--mode=block",
+    "sleep",
+    "infinity",
+];
+
+impl Default for IdleInhibitor {
+    fn default() -> Self {
+        Self {
+            command: INHIBIT_COMMAND,
+            child: None,
+        }
+    }
+}
+
+impl IdleInhibitor {
+    /// Inhibitor running `command` instead of `systemd-inhibit`
+    #[cfg(test)]
+    pub fn with_command(command: &'static [&'static str]) -> Self {
+        Self {
+            command,
+            child: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_held(&self) -> bool {
+        self.child.is_some()
+    }
+
+    pub fn acquire(&mut self) {
+        if self.child.is_some() {
+            return;
+        }
+        match Command::new(self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => {
+                log::debug!("Idle inhibitor acquired");
+                self.child = Some(child);
+            }
+            Err(e) => log::warn!("Failed to take idle inhibitor lock: {}", e),
+        }
+    }
+
+    pub fn release(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+            log::debug!("Idle inhibitor released");
+        }
+    }
+}
+
+impl Drop for IdleInhibitor {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
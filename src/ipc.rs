@@ -1,43 +1,217 @@
 use miette::Result;
 use parking_lot::Mutex;
 use parking_lot::RwLock;
-use std::env;
 use std::fs;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use once_cell::sync::Lazy;
+
+use crate::compositor::Stack;
+use crate::conditions::{
+    clear_profile_overrides, profile_override, set_profile_override, switch_profile,
+};
 use crate::config::Config;
+use crate::gestures::Gesture;
+use crate::json::Json;
+use crate::platform::{current_uid, socket_path};
 
 const IPC_WORKERS: usize = 4;
 const IPC_QUEUE_CAPACITY: usize = 128;
 
-fn current_uid() -> Option<u32> {
-    fs::metadata("/proc/self").ok().map(|m| m.uid())
+/// Remote clients must authenticate and send their commands within this time
+const REMOTE_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Whole time a remote client has for its `auth` line, however slowly it sends it
+const REMOTE_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest `auth` line accepted, a token is far shorter
+const MAX_AUTH_LINE: usize = 1024;
+/// Remote connections served at once, further ones are turned away until one ends
+const MAX_REMOTE_CONNECTIONS: usize = 8;
+/// Commands of remote clients. Others change the config or stream without end, and stay local
+const REMOTE_COMMANDS: &[&str] = &["status", "status json", "reload", "validate", "history"];
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// How long `takeover` waits for in-flight gestures before cancelling them
+pub const TAKEOVER_GRACE: Duration = Duration::from_secs(3);
+/// Upper bound for each handover step, so a wedged thread can't block the new instance forever
+const TAKEOVER_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handover states, advanced by the IPC worker, the event handler and the accept loop in turn
+const TAKEOVER_NONE: u8 = 0;
+const TAKEOVER_REQUESTED: u8 = 1;
+const TAKEOVER_DEVICES_RELEASED: u8 = 2;
+const TAKEOVER_LISTENER_IDLE: u8 = 3;
+const TAKEOVER_HANDED_OVER: u8 = 4;
+
+static TAKEOVER: AtomicU8 = AtomicU8::new(TAKEOVER_NONE);
+static LISTENER_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// A newer instance asked for the devices, the event loop should wind down once idle
+pub fn takeover_requested() -> bool {
+    TAKEOVER.load(Ordering::Relaxed) == TAKEOVER_REQUESTED
 }
 
-fn resolve_socket_path() -> Result<PathBuf> {
-    if let Ok(socket_dir) = env::var("XDG_RUNTIME_DIR") {
-        return Ok(PathBuf::from(socket_dir).join("gestures.sock"));
+/// The socket went to a newer instance, which keeps using what this one set up
+pub fn handed_over() -> bool {
+    TAKEOVER.load(Ordering::Relaxed) == TAKEOVER_HANDED_OVER
+}
+
+/// Event handler threads holding a libinput context, one per seat
+static HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Called by an event handler thread before it sets up its libinput context
+pub fn devices_acquired() {
+    HANDLERS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Called by an event handler thread after dropping its libinput context, the devices count as
+/// released once the handlers of all seats did
+pub fn devices_released() {
+    if HANDLERS.fetch_sub(1, Ordering::Relaxed) > 1 {
+        return;
     }
+    let _ = TAKEOVER.compare_exchange(
+        TAKEOVER_REQUESTED,
+        TAKEOVER_DEVICES_RELEASED,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+}
 
-    let uid = current_uid()
-        .ok_or_else(|| miette::miette!("Cannot determine current uid from /proc/self"))?;
-    let fallback = PathBuf::from(format!("/run/user/{uid}"));
-    if fallback.is_dir() {
-        Ok(fallback.join("gestures.sock"))
-    } else {
-        Err(miette::miette!(
-            "XDG_RUNTIME_DIR is unset and fallback runtime dir {} is unavailable",
-            fallback.display()
-        ))
+fn wait_for_takeover(state: u8) -> bool {
+    let deadline = Instant::now() + TAKEOVER_STEP_TIMEOUT;
+    while TAKEOVER.load(Ordering::Relaxed) < state {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    true
+}
+
+/// Streams the line based IPC protocol is spoken over
+pub trait IpcStream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown_write(&self) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Local connections can carry file descriptors, used by `takeover`
+    fn as_unix(&self) -> Option<&UnixStream> {
+        None
+    }
+}
+
+impl IpcStream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn as_unix(&self) -> Option<&UnixStream> {
+        Some(self)
+    }
+}
+
+impl IpcStream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// One of the `MAX_REMOTE_CONNECTIONS` of a remote listener, given back when dropped
+struct RemoteSlot(Arc<AtomicUsize>);
+
+impl RemoteSlot {
+    fn acquire(open: &Arc<AtomicUsize>) -> Option<Self> {
+        let slot = Self(open.clone());
+        // Over the limit the slot is given back right away by dropping it
+        (open.fetch_add(1, Ordering::AcqRel) < MAX_REMOTE_CONNECTIONS).then_some(slot)
+    }
+}
+
+impl Drop for RemoteSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Whether a remote client may send `line`, one of `REMOTE_COMMANDS` with its arguments
+fn remote_command(line: &str) -> bool {
+    let line = line.trim();
+    REMOTE_COMMANDS.iter().any(|command| {
+        line.strip_prefix(command).is_some_and(|args| {
+            args.is_empty() || (*command == "validate" && args.starts_with(' '))
+        })
+    })
+}
+
+/// Read the `auth` line of a remote client within `REMOTE_AUTH_TIMEOUT` in total, so a client
+/// trickling bytes can't hold its connection open before it authenticated
+fn read_auth_line<S: IpcStream>(reader: &mut BufReader<S>) -> Option<String> {
+    let deadline = Instant::now() + REMOTE_AUTH_TIMEOUT;
+    let mut line = Vec::new();
+    loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())?;
+        reader.get_ref().set_read_timeout(Some(remaining)).ok()?;
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        };
+        if available.is_empty() {
+            return None;
+        }
+        let (used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (available.len(), false),
+        };
+        line.extend_from_slice(&available[..used]);
+        reader.consume(used);
+        if line.len() > MAX_AUTH_LINE {
+            return None;
+        }
+        if done {
+            return String::from_utf8(line).ok();
+        }
     }
 }
 
+/// Compare without an early exit, so response timing doesn't leak the token
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 fn remove_stale_socket(socket_path: &Path) -> Result<()> {
     let metadata = match fs::symlink_metadata(socket_path) {
         Ok(metadata) => metadata,
@@ -57,13 +231,11 @@ fn remove_stale_socket(socket_path: &Path) -> Result<()> {
         ));
     }
 
-    if let Some(uid) = current_uid() {
-        if metadata.uid() != uid {
-            return Err(miette::miette!(
-                "Refusing to remove socket {} not owned by current user",
-                socket_path.display()
-            ));
-        }
+    if metadata.uid() != current_uid() {
+        return Err(miette::miette!(
+            "Refusing to remove socket {} not owned by current user",
+            socket_path.display()
+        ));
     }
 
     if let Err(e) = fs::remove_file(socket_path) {
@@ -77,26 +249,131 @@ fn remove_stale_socket(socket_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Send `data` with `fd` attached as SCM_RIGHTS ancillary data
+pub fn send_fd(stream: &UnixStream, fd: RawFd, data: &[u8]) -> io::Result<()> {
+    let fd_len = std::mem::size_of::<RawFd>() as u32;
+    // SAFETY: CMSG_SPACE only computes a size
+    let space = unsafe { libc::CMSG_SPACE(fd_len) } as usize;
+    let mut control = vec![0u8; space];
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: msghdr is plain old data, every field used is set below
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+    // SAFETY: `control` is CMSG_SPACE(fd_len) bytes, enough for one header carrying one fd
+    let sent = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+        libc::sendmsg(stream.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive data into `buf` along with at most one file descriptor sent by [`send_fd`]
+pub fn recv_fd(stream: &UnixStream, buf: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let fd_len = std::mem::size_of::<RawFd>() as u32;
+    // SAFETY: CMSG_SPACE only computes a size
+    let space = unsafe { libc::CMSG_SPACE(fd_len) } as usize;
+    let mut control = vec![0u8; space];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // SAFETY: msghdr is plain old data, every field used is set below
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+    // SAFETY: buffers outlive the call and their lengths are passed along
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fd = None;
+    // SAFETY: the kernel filled `control` up to msg_controllen, CMSG_* walk within it
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let raw = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+                fd = Some(OwnedFd::from_raw_fd(raw));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((received as usize, fd))
+}
+
+/// Handle `takeover` from a newer instance: let the event handler finish in-flight gestures and
+/// release the devices, stop accepting, then pass the listening socket over and exit
+fn hand_over(writer: &UnixStream) {
+    if TAKEOVER
+        .compare_exchange(
+            TAKEOVER_NONE,
+            TAKEOVER_REQUESTED,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+        .is_err()
+    {
+        let _ = (&*writer).write_all(b"error: takeover already in progress\n");
+        return;
+    }
+    log::info!("Takeover requested, finishing in-flight gestures");
+
+    if !wait_for_takeover(TAKEOVER_DEVICES_RELEASED) {
+        log::warn!("Event handler did not release the devices in time, handing over anyway");
+        TAKEOVER.store(TAKEOVER_DEVICES_RELEASED, Ordering::Relaxed);
+    }
+    if !wait_for_takeover(TAKEOVER_LISTENER_IDLE) {
+        log::warn!("IPC listener did not stop in time, handing over anyway");
+    }
+
+    let fd = LISTENER_FD.load(Ordering::Relaxed);
+    let response = format!("ok {}\n", std::process::id());
+    if let Err(e) = send_fd(writer, fd, response.as_bytes()) {
+        log::error!("Failed to hand over the IPC socket: {}", e);
+    } else {
+        log::info!("Handed over to the new instance, exiting");
+    }
+    TAKEOVER.store(TAKEOVER_HANDED_OVER, Ordering::Relaxed);
+    crate::SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+/// `inherited` is the listener received from the instance replaced by `start --replace`
 pub fn create_socket(
     config: Arc<RwLock<Config>>,
     config_path: Option<std::path::PathBuf>,
+    inherited: Option<UnixListener>,
 ) -> Result<()> {
-    let socket_path = resolve_socket_path()?;
+    Lazy::force(&STARTED);
+    let socket_path = socket_path()?;
 
-    if socket_path.exists() {
-        remove_stale_socket(&socket_path)?;
-    }
-
-    let listener = match UnixListener::bind(&socket_path) {
-        Ok(listener) => listener,
-        Err(e) => {
-            return Err(miette::miette!(
-                "Failed to bind IPC socket {}: {}",
-                socket_path.display(),
-                e
-            ));
+    let listener = match inherited {
+        Some(listener) => listener,
+        None => {
+            if socket_path.exists() {
+                remove_stale_socket(&socket_path)?;
+            }
+            UnixListener::bind(&socket_path).map_err(|e| {
+                miette::miette!("Failed to bind IPC socket {}: {}", socket_path.display(), e)
+            })?
         }
     };
+    LISTENER_FD.store(listener.as_raw_fd(), Ordering::Relaxed);
 
     // Set non-blocking mode
     if let Err(e) = listener.set_nonblocking(true) {
@@ -104,10 +381,12 @@ pub fn create_socket(
         return Err(miette::miette!("Cannot set non-blocking IPC socket: {}", e));
     }
 
-    // Cleanup socket on shutdown
+    // Cleanup socket on shutdown, a socket of systemd stays to start the next instance
     let socket_path_clone = socket_path.clone();
     let cleanup = move || {
-        let _ = fs::remove_file(&socket_path_clone);
+        if !crate::systemd::socket_activated() {
+            let _ = fs::remove_file(&socket_path_clone);
+        }
     };
 
     let (tx, rx) = mpsc::sync_channel::<UnixStream>(IPC_QUEUE_CAPACITY);
@@ -127,12 +406,24 @@ pub fn create_socket(
                 }
             };
 
-            handle_connection(stream, config.clone(), config_path.clone());
+            handle_connection(stream, config.clone(), config_path.clone(), None);
             log::trace!("IPC worker {} handled one connection", worker_id);
         });
     }
 
+    crate::systemd::notify("READY=1");
     loop {
+        // Pending connections stay queued on the socket for the new instance
+        match TAKEOVER.load(Ordering::Relaxed) {
+            TAKEOVER_HANDED_OVER => break,
+            TAKEOVER_DEVICES_RELEASED | TAKEOVER_LISTENER_IDLE => {
+                TAKEOVER.store(TAKEOVER_LISTENER_IDLE, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(20));
+                continue;
+            }
+            _ => {}
+        }
+
         // Check shutdown flag
         if crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
             log::info!("IPC listener shutting down");
@@ -159,14 +450,421 @@ pub fn create_socket(
     Ok(())
 }
 
-fn handle_connection(
-    stream: UnixStream,
+/// Accept IPC commands over TCP, every connection must start with `auth <token>`.
+/// Returns the bound address.
+pub fn create_remote_listener(
+    addr: SocketAddr,
+    token: String,
     config: Arc<RwLock<Config>>,
     config_path: Option<std::path::PathBuf>,
+) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| miette::miette!("Failed to bind remote IPC listener {}: {}", addr, e))?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| miette::miette!("Failed to bind remote IPC listener: {}", e))?;
+    log::info!("Remote IPC listening on {}", addr);
+
+    let token: Arc<str> = token.into();
+    let open = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Remote IPC accept failed: {}", e);
+                    continue;
+                }
+            };
+            let Some(slot) = RemoteSlot::acquire(&open) else {
+                log::warn!(
+                    "Rejected remote IPC connection from {:?}: {} connections already open",
+                    stream.peer_addr().ok(),
+                    MAX_REMOTE_CONNECTIONS
+                );
+                let _ = stream.write_all(b"error: too many connections\n");
+                continue;
+            };
+            if let Err(e) = stream.set_read_timeout(Some(REMOTE_READ_TIMEOUT)) {
+                log::warn!("Cannot set remote IPC read timeout: {}", e);
+                continue;
+            }
+            let peer = stream.peer_addr().ok();
+            log::debug!("Remote IPC connection from {:?}", peer);
+
+            let config = config.clone();
+            let config_path = config_path.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                handle_connection(stream, config, config_path, Some(&token));
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/// Re-read the configuration and swap it in, the gesture cache picks it up within a second
+pub fn reload_config(config: &RwLock<Config>, config_path: Option<&Path>) -> Result<()> {
+    let new = Config::read_from_optional_path(config_path)?;
+    let mut trial = TRIAL.lock();
+    if trial.take().is_some() {
+        log::info!("Reload ended the temporary config trial");
+    }
+    *config.write() = new;
+    Ok(())
+}
+
+/// A config applied with `apply-temporary`, reverted when its time is up unless a reload or
+/// `apply` replaced it first
+struct Trial {
+    id: u64,
+    /// Config in effect before the first of overlapping trials
+    previous: Config,
+}
+
+static TRIAL: Lazy<Mutex<Option<Trial>>> = Lazy::new(|| Mutex::new(None));
+static TRIAL_IDS: AtomicU64 = AtomicU64::new(0);
+
+/// Swap in `new` without saving it, bringing the previous config back after `revert_after`
+fn apply_config(config: &Arc<RwLock<Config>>, new: Config, revert_after: Option<Duration>) {
+    let mut trial = TRIAL.lock();
+    let previous = std::mem::replace(&mut *config.write(), new);
+    let Some(revert_after) = revert_after else {
+        *trial = None;
+        return;
+    };
+    let id = TRIAL_IDS.fetch_add(1, Ordering::Relaxed) + 1;
+    let previous = trial.take().map_or(previous, |t| t.previous);
+    *trial = Some(Trial { id, previous });
+    drop(trial);
+
+    let config = config.clone();
+    thread::spawn(move || {
+        thread::sleep(revert_after);
+        let mut trial = TRIAL.lock();
+        if trial.as_ref().is_some_and(|t| t.id == id) {
+            if let Some(t) = trial.take() {
+                *config.write() = t.previous;
+                log::info!(
+                    "Reverted the temporary config after {}s",
+                    revert_after.as_secs()
+                );
+            }
+        }
+    });
+}
+
+/// Handle `validate`, `apply` and `apply-temporary <secs>` with the config text as a JSON string
+fn candidate_config(
+    config: &Arc<RwLock<Config>>,
+    args: &str,
+    apply: bool,
+    revert_after: Option<Duration>,
+) -> String {
+    let text = match Json::parse(args.trim()) {
+        Ok(Json::String(text)) => text,
+        _ => return "error: expected the config as a JSON string\n".to_string(),
+    };
+    let validation = crate::lsp::validate(&text);
+    if !apply || !matches!(validation.get("ok"), Some(Json::Bool(true))) {
+        return format!("{}\n", validation);
+    }
+    let new = match Config::read_unsaved(&text) {
+        Ok(new) => new,
+        Err(e) => return format!("error: {}\n", e),
+    };
+    apply_config(config, new, revert_after);
+    match revert_after {
+        Some(after) => log::info!(
+            "Applied a temporary config via IPC, reverting in {}s",
+            after.as_secs()
+        ),
+        None => log::info!("Applied an unsaved config via IPC"),
+    }
+    format!(
+        "{}\n",
+        Json::object([
+            ("ok", true.into()),
+            ("errors", Json::from(Vec::new())),
+            (
+                "reverts_in_secs",
+                revert_after.map_or(Json::Null, |d| Json::from(d.as_secs() as usize)),
+            ),
+        ])
+    )
+}
+
+/// Rules of each type across top-level, profile and device blocks
+fn rule_counts(config: &Config) -> [usize; 4] {
+    let mut counts = [0; 4];
+    let rules = config
+        .gestures
+        .iter()
+        .chain(config.profiles.iter().flat_map(|p| &p.gestures))
+        .chain(config.devices.iter().flat_map(|d| &d.gestures));
+    for rule in rules {
+        match rule {
+            Gesture::Swipe(_) => counts[0] += 1,
+            Gesture::Pinch(_) => counts[1] += 1,
+            Gesture::Hold(_) => counts[2] += 1,
+            Gesture::Rotate(_) => counts[3] += 1,
+            Gesture::None => {}
+        }
+    }
+    counts
+}
+
+fn status(config: &Config, config_path: Option<&Path>) -> String {
+    let files: Vec<_> = crate::watch::config_files(config_path)
+        .into_iter()
+        .filter(|f| f.exists())
+        .map(|f| f.display().to_string())
+        .collect();
+    let [swipe, pinch, hold, rotate] = rule_counts(config);
+    format!(
+        "version {}\npid {}\nuptime {}s\nsession {}\npointer {}\nevent-thread {}\nconfig {}\nrules {}\nrule-types swipe={} pinch={} hold={} rotate={}\nprofiles {}\nlast-gesture {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id(),
+        STARTED.elapsed().as_secs(),
+        Stack::current().map_or_else(|| "unknown".to_string(), |s| s.to_string()),
+        crate::status::output_check().map_or_else(
+            || "unchecked".to_string(),
+            |(backend, result)| crate::status::describe_output_check(backend, &result)
+        ),
+        crate::status::describe_event_thread(crate::status::event_thread_failure().as_ref()),
+        if files.is_empty() {
+            "none".to_string()
+        } else {
+            files.join(", ")
+        },
+        swipe + pinch + hold + rotate,
+        swipe,
+        pinch,
+        hold,
+        rotate,
+        config
+            .profiles
+            .iter()
+            .map(|p| match profile_override(&p.name) {
+                Some(true) => format!("{} (forced on)", p.name),
+                Some(false) => format!("{} (forced off)", p.name),
+                None => p.name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        crate::status::last_gesture().map_or_else(
+            || "none".to_string(),
+            |(gesture, ago)| format!("{} ({}s ago)", gesture, ago.as_secs())
+        )
+    )
+}
+
+/// `status json`: the fields of `status` as one JSON object, for `gestures --json status`
+fn status_json(config: &Config, config_path: Option<&Path>) -> Json {
+    let files: Vec<_> = crate::watch::config_files(config_path)
+        .into_iter()
+        .filter(|f| f.exists())
+        .map(|f| Json::from(f.display().to_string()))
+        .collect();
+    let [swipe, pinch, hold, rotate] = rule_counts(config);
+    let profiles: Vec<_> = config
+        .profiles
+        .iter()
+        .map(|p| {
+            Json::object([
+                ("name", Json::from(p.name.as_str())),
+                (
+                    "forced",
+                    match profile_override(&p.name) {
+                        Some(true) => Json::from("on"),
+                        Some(false) => Json::from("off"),
+                        None => Json::Null,
+                    },
+                ),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+        ("pid", Json::from(std::process::id() as usize)),
+        (
+            "uptime_secs",
+            Json::from(STARTED.elapsed().as_secs() as usize),
+        ),
+        (
+            "session",
+            Stack::current().map_or(Json::Null, Stack::to_json),
+        ),
+        (
+            "pointer",
+            crate::status::output_check().map_or(Json::Null, |(backend, result)| {
+                crate::status::output_check_json(backend, &result)
+            }),
+        ),
+        (
+            "event_thread",
+            crate::status::event_thread_failure().map_or(Json::Null, |f| {
+                Json::object([
+                    ("error", Json::from(f.error)),
+                    ("secs_ago", Json::from(f.at.elapsed().as_secs() as usize)),
+                    ("restarts", Json::from(f.restarts as usize)),
+                ])
+            }),
+        ),
+        ("config", Json::from(files)),
+        (
+            "rules",
+            Json::object([
+                ("total", Json::from(swipe + pinch + hold + rotate)),
+                ("swipe", Json::from(swipe)),
+                ("pinch", Json::from(pinch)),
+                ("hold", Json::from(hold)),
+                ("rotate", Json::from(rotate)),
+            ]),
+        ),
+        ("profiles", Json::from(profiles)),
+        (
+            "last_gesture",
+            crate::status::last_gesture().map_or(Json::Null, |(gesture, ago)| {
+                Json::object([
+                    ("name", Json::from(gesture)),
+                    ("secs_ago", Json::from(ago.as_secs() as usize)),
+                ])
+            }),
+        ),
+    ])
+}
+
+/// Handle `profile <name> on|off|auto`, `profile <name>` switching to that profile alone and
+/// `profile auto` undoing every override, the gesture cache picks the change up within a second
+pub(crate) fn override_profile(config: &Config, args: &str) -> String {
+    let args = args.trim();
+    let (name, state) = match args.rsplit_once(' ') {
+        Some((name, state @ ("on" | "off" | "auto"))) => (name.trim(), state),
+        _ => (args, "only"),
+    };
+    let name = name.trim_matches('"');
+    let known = config.profiles.iter().any(|p| p.name == name);
+    if name == "auto" && !known {
+        clear_profile_overrides();
+        log::info!("Profile overrides cleared via IPC");
+        return "profile auto\n".to_string();
+    }
+    if name.is_empty() {
+        return "error: usage: profile <name> [on|off|auto]\n".to_string();
+    }
+    if !known {
+        return format!("error: unknown profile `{}`\n", name);
+    }
+    let active = match state {
+        "on" => Some(true),
+        "off" => Some(false),
+        "auto" => None,
+        _ => {
+            switch_profile(name, config.profiles.iter().map(|p| p.name.as_str()));
+            log::info!("Switched to profile {} via IPC", name);
+            return format!("profile {}\n", name);
+        }
+    };
+    set_profile_override(name, active);
+    log::info!("Profile override via IPC: {} {}", name, state);
+    format!("profile {} {}\n", name, state)
+}
+
+fn stream_logs(writer: &mut impl Write) {
+    let (records, rx) = crate::logs::follow();
+    for record in records {
+        if writeln!(writer, "{}", record).is_err() {
+            return;
+        }
+    }
+    while !crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(record) => {
+                if writeln!(writer, "{}", record).is_err() {
+                    return;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Stream handler decisions and gesture updates as they happen, for `gestures monitor`
+fn stream_monitor(writer: &mut impl Write) {
+    let rx = crate::trace::follow();
+    while !crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => {
+                if writeln!(writer, "{}", event).is_err() {
+                    return;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Stream the daemon state as one JSON line per change, for `gestures waybar`
+fn stream_state(writer: &mut impl Write) {
+    let (state, rx) = crate::status::subscribe();
+    if writeln!(writer, "{}", state.to_json()).is_err() {
+        return;
+    }
+    while !crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(state) => {
+                if writeln!(writer, "{}", state.to_json()).is_err() {
+                    return;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Answer the commands on `stream` like a connection to the local socket
+#[cfg(test)]
+pub fn serve_local(stream: UnixStream, config: Arc<RwLock<Config>>) {
+    handle_connection(stream, config, None, None);
+}
+
+fn handle_connection<S: IpcStream>(
+    stream: S,
+    config: Arc<RwLock<Config>>,
+    config_path: Option<std::path::PathBuf>,
+    token: Option<&str>,
 ) {
-    let stream = BufReader::new(stream);
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            log::error!("Failed to clone IPC stream: {}", e);
+            return;
+        }
+    };
+    let mut reader = BufReader::new(stream);
+
+    if let Some(token) = token {
+        let authenticated = read_auth_line(&mut reader)
+            .and_then(|line| line.trim().strip_prefix("auth ").map(str::to_string))
+            .is_some_and(|given| token_matches(given.trim(), token));
+        if !authenticated {
+            log::warn!("Rejected remote IPC connection: authentication failed");
+            let _ = writer.write_all(b"error: authentication failed\n");
+            return;
+        }
+        if let Err(e) = reader.get_ref().set_read_timeout(Some(REMOTE_READ_TIMEOUT)) {
+            log::warn!("Cannot set remote IPC read timeout: {}", e);
+            return;
+        }
+    }
 
-    for line in stream.lines() {
+    for line in reader.lines() {
         let line = match line {
             Ok(line) => line,
             Err(e) => {
@@ -175,15 +873,123 @@ fn handle_connection(
             }
         };
 
-        if line.contains("reload") {
-            let mut c = config.write();
-            *c = Config::read_from_optional_path(config_path.as_deref()).unwrap_or_else(|e| {
+        if token.is_some() && !remote_command(&line) {
+            let response = format!(
+                "error: `{}` is not available remotely, only {}\n",
+                line.trim(),
+                REMOTE_COMMANDS.join(", ")
+            );
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write remote IPC response: {}", e);
+            }
+            continue;
+        }
+
+        if let Some(args) = line.trim().strip_prefix("logs") {
+            let follow = args.trim() == "follow";
+            let Ok(mut writer) = writer.try_clone() else {
+                break;
+            };
+            if follow {
+                // Streams indefinitely, keep the IPC workers free for other commands
+                thread::spawn(move || stream_logs(&mut writer));
+            } else {
+                let mut response = crate::logs::snapshot().join("\n");
+                response.push('\n');
+                if let Err(e) = writer.write_all(response.as_bytes()) {
+                    log::error!("Failed to write logs response: {}", e);
+                }
+            }
+        } else if line.trim() == "subscribe" {
+            let Ok(mut writer) = writer.try_clone() else {
+                break;
+            };
+            // Streams indefinitely like `logs follow`
+            thread::spawn(move || stream_state(&mut writer));
+        } else if line.trim() == "monitor" {
+            let Ok(mut writer) = writer.try_clone() else {
+                break;
+            };
+            // Streams indefinitely like `logs follow`
+            thread::spawn(move || stream_monitor(&mut writer));
+        } else if line.trim() == "status" {
+            let response = status(&config.read(), config_path.as_deref());
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write status response: {}", e);
+            }
+        } else if line.trim() == "status json" {
+            let response = format!("{}\n", status_json(&config.read(), config_path.as_deref()));
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write status response: {}", e);
+            }
+        } else if line.trim() == "schema" {
+            let response = format!("{}\n", crate::lsp::schema());
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write schema response: {}", e);
+            }
+        } else if let Some(args) = line.trim().strip_prefix("validate ") {
+            let response = candidate_config(&config, args, false, None);
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write validate response: {}", e);
+            }
+        } else if let Some(args) = line.trim().strip_prefix("apply ") {
+            let response = candidate_config(&config, args, true, None);
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write apply response: {}", e);
+            }
+        } else if let Some(args) = line.trim().strip_prefix("apply-temporary ") {
+            let response = match args.trim_start().split_once(' ') {
+                Some((secs, text)) => match secs.parse::<u64>() {
+                    Ok(secs) if secs > 0 => {
+                        candidate_config(&config, text, true, Some(Duration::from_secs(secs)))
+                    }
+                    _ => format!("error: invalid number of seconds `{}`\n", secs),
+                },
+                None => {
+                    "error: usage: apply-temporary <secs> <config as JSON string>\n".to_string()
+                }
+            };
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write apply-temporary response: {}", e);
+            }
+        } else if let Some(args) = line.trim().strip_prefix("profile ") {
+            let response = override_profile(&config.read(), args);
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write profile response: {}", e);
+            }
+        } else if line.trim() == "takeover" {
+            match writer.as_unix() {
+                Some(unix) => hand_over(unix),
+                None => {
+                    let _ = writer.write_all(b"error: takeover is only possible locally\n");
+                }
+            }
+            break;
+        } else if line.trim() == "history" {
+            let mut response: String = crate::history::snapshot()
+                .iter()
+                .map(|e| format!("{}\n", e))
+                .collect();
+            if response.is_empty() {
+                response.push_str("no gestures yet\n");
+            }
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write history response: {}", e);
+            }
+        } else if line.trim() == "trace" {
+            let mut response = crate::trace::snapshot().join("\n");
+            response.push('\n');
+            if let Err(e) = writer.write_all(response.as_bytes()) {
+                log::error!("Failed to write trace response: {}", e);
+            }
+        } else if line.contains("reload") {
+            if let Err(e) = reload_config(&config, config_path.as_deref()) {
                 log::error!(
                     "Could not read configuration file, using empty config: {}",
                     e
                 );
-                Config::default()
-            });
+                *config.write() = Config::default();
+            }
         }
     }
 }
@@ -1,55 +1,253 @@
-use std::env;
-use std::io::Write;
-use std::os::unix::fs::MetadataExt;
-use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
 
 use miette::Result;
 
-use crate::Commands;
+use crate::ipc::{recv_fd, IpcStream, TAKEOVER_GRACE};
+use crate::json::Json;
+use crate::platform::socket_path;
+use crate::status::{self, DaemonState};
+use crate::{Commands, SHUTDOWN};
 
-fn current_uid() -> Option<u32> {
-    std::fs::metadata("/proc/self").ok().map(|m| m.uid())
+/// How long `gestures waybar` waits before reconnecting to a daemon that went away
+const WAYBAR_RETRY: Duration = Duration::from_secs(2);
+/// Reads time out this often so `gestures waybar` notices SIGTERM from the bar
+const WAYBAR_POLL: Duration = Duration::from_millis(500);
+
+/// Read the shared secret for remote IPC, warning when others can read it
+pub fn read_token(path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path)
+        .map_err(|e| miette::miette!("Failed to read token file {}: {}", path.display(), e))?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        log::warn!(
+            "Token file {} is readable by other users, consider chmod 600",
+            path.display()
+        );
+    }
+
+    let token = fs::read_to_string(path)
+        .map_err(|e| miette::miette!("Failed to read token file {}: {}", path.display(), e))?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        return Err(miette::miette!("Token file {} is empty", path.display()));
+    }
+    Ok(token)
+}
+
+/// A candidate config file as the JSON string `validate` and `apply-temporary` expect
+fn candidate(file: &Path) -> Result<String> {
+    let text = fs::read_to_string(file)
+        .map_err(|e| miette::miette!("Failed to read {}: {}", file.display(), e))?;
+    Ok(Json::from(text).to_string())
+}
+
+fn command_line(cmd: &Commands, json: bool) -> Result<String> {
+    Ok(match cmd {
+        Commands::Reload => "reload\n".to_string(),
+        Commands::Trace => "trace\n".to_string(),
+        Commands::History => "history\n".to_string(),
+        Commands::Status if json => "status json\n".to_string(),
+        Commands::Status => "status\n".to_string(),
+        Commands::Logs { follow: true } => "logs follow\n".to_string(),
+        Commands::Logs { follow: false } => "logs\n".to_string(),
+        Commands::Profile {
+            name,
+            state: Some(state),
+        } => format!("profile {} {}\n", name, state),
+        Commands::Profile { name, state: None } => format!("profile {}\n", name),
+        Commands::Schema => "schema\n".to_string(),
+        Commands::Monitor => "monitor\n".to_string(),
+        Commands::Validate { file } => format!("validate {}\n", candidate(file)?),
+        Commands::ApplyTemporary { file, seconds } => {
+            format!("apply-temporary {} {}\n", seconds, candidate(file)?)
+        }
+        _ => String::new(),
+    })
 }
 
-fn socket_path() -> Result<PathBuf> {
-    if let Ok(socket_dir) = env::var("XDG_RUNTIME_DIR") {
-        return Ok(PathBuf::from(socket_dir).join("gestures.sock"));
+fn send<S: IpcStream>(
+    mut stream: S,
+    cmd: &Commands,
+    json: bool,
+    token: Option<&str>,
+) -> Result<()> {
+    if let Some(token) = token {
+        writeln!(stream, "auth {}", token)
+            .map_err(|e| miette::miette!("Failed to authenticate: {}", e))?;
     }
+    let line = command_line(cmd, json)?;
+    stream
+        .write_all(line.as_bytes())
+        .and_then(|_| stream.shutdown_write())
+        .map_err(|e| miette::miette!("Failed to write command: {}", e))?;
 
-    let uid = current_uid()
-        .ok_or_else(|| miette::miette!("Cannot determine current uid from /proc/self"))?;
-    let fallback = PathBuf::from(format!("/run/user/{uid}"));
-    if fallback.is_dir() {
-        Ok(fallback.join("gestures.sock"))
-    } else {
-        Err(miette::miette!(
-            "Could not determine IPC socket path: XDG_RUNTIME_DIR is unset and fallback runtime dir {} is unavailable",
-            fallback.display()
-        ))
+    // Print the response as it arrives, `logs -f` streams until the daemon goes away
+    let mut reader = BufReader::new(stream);
+    let mut stdout = io::stdout().lock();
+    let mut first = true;
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return Err(miette::miette!("Failed to read response: {}", e)),
+        }
+        if first {
+            if let Some(error) = line.strip_prefix("error: ") {
+                return Err(miette::miette!("{}", error.trim()));
+            }
+            first = false;
+        }
+        stdout
+            .write_all(line.as_bytes())
+            .map_err(|e| miette::miette!("Failed to print response: {}", e))?;
     }
+
+    Ok(())
 }
 
-pub fn handle_command(cmd: Commands) -> Result<()> {
-    let socket_path = socket_path()?;
+/// Send `cmd` to the local daemon, or to `remote` authenticating with the token in `token_file`,
+/// asking for the JSON form of the response with `json`
+pub fn handle_command(
+    cmd: Commands,
+    json: bool,
+    remote: Option<SocketAddr>,
+    token_file: Option<&Path>,
+) -> Result<()> {
+    if let Some(addr) = remote {
+        let token_file =
+            token_file.ok_or_else(|| miette::miette!("--remote requires --remote-token-file"))?;
+        let token = read_token(token_file)?;
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| miette::miette!("Failed to connect to {}: {}", addr, e))?;
+        return send(stream, &cmd, json, Some(&token));
+    }
+
+    let socket_path =
+        socket_path().map_err(|e| miette::miette!("Could not determine IPC socket path: {}", e))?;
 
-    let mut stream = UnixStream::connect(&socket_path).map_err(|e| {
+    let stream = UnixStream::connect(&socket_path).map_err(|e| {
         miette::miette!(
             "Failed to connect to IPC socket {}: {}. Is gestures running?",
             socket_path.display(),
             e
         )
     })?;
+    send(stream, &cmd, json, None)
+}
+
+/// Ask the running daemon to hand over its IPC socket for `start --replace`, `None` when there is
+/// no daemon to replace
+pub fn take_over() -> Result<Option<UnixListener>> {
+    let socket_path =
+        socket_path().map_err(|e| miette::miette!("Could not determine IPC socket path: {}", e))?;
+    let mut stream = match UnixStream::connect(&socket_path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::info!("No running instance to replace ({}), starting fresh", e);
+            return Ok(None);
+        }
+    };
+    // The old instance may wait out the grace period and then several handover steps
+    stream
+        .set_read_timeout(Some(TAKEOVER_GRACE * 5))
+        .and_then(|_| stream.write_all(b"takeover\n"))
+        .map_err(|e| miette::miette!("Failed to request takeover: {}", e))?;
+
+    let mut buf = [0u8; 256];
+    let (len, fd) = recv_fd(&stream, &mut buf)
+        .map_err(|e| miette::miette!("Failed to receive takeover response: {}", e))?;
+    let response = String::from_utf8_lossy(&buf[..len]);
+    if let Some(error) = response.trim().strip_prefix("error: ") {
+        return Err(miette::miette!("Takeover refused: {}", error));
+    }
+    let fd = fd.ok_or_else(|| {
+        miette::miette!(
+            "Running instance did not hand over its socket: {}",
+            response.trim()
+        )
+    })?;
+    log::info!(
+        "Took over from instance {}",
+        response.trim().strip_prefix("ok ").unwrap_or("?")
+    );
+    Ok(Some(UnixListener::from(fd)))
+}
+
+/// Print one waybar line, false once the bar stopped reading
+fn emit(line: &Json) -> bool {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", line)
+        .and_then(|_| stdout.flush())
+        .is_ok()
+}
 
-    #[allow(clippy::single_match)]
-    match cmd {
-        Commands::Reload => {
-            stream
-                .write_all(b"reload")
-                .map_err(|e| miette::miette!("Failed to write reload command: {}", e))?;
+/// Print the daemon state for every change until the connection ends, false when the bar is gone
+fn follow_state(mut stream: UnixStream) -> io::Result<bool> {
+    stream.write_all(b"subscribe\n")?;
+    stream.set_read_timeout(Some(WAYBAR_POLL))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Ok(true),
+            Ok(_) => {}
+            // A partial line stays in `line` and is completed by the next read
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
         }
-        _ => (),
+        if let Some(error) = line.strip_prefix("error: ") {
+            return Err(io::Error::other(error.trim().to_string()));
+        }
+        match Json::parse(line.trim()) {
+            Ok(json) => {
+                if !emit(&status::waybar(Some(&DaemonState::from_json(&json)))) {
+                    return Ok(false);
+                }
+            }
+            Err(e) => log::debug!("Ignoring malformed state line: {}", e),
+        }
+        line.clear();
     }
+    Ok(false)
+}
+
+/// `gestures waybar`: follow the daemon state as a waybar custom module, showing it as stopped
+/// and reconnecting while the daemon is not running. Remote daemons don't stream their state.
+pub fn waybar() -> Result<()> {
+    let socket_path =
+        socket_path().map_err(|e| miette::miette!("Could not determine IPC socket path: {}", e))?;
 
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        let followed = UnixStream::connect(&socket_path).and_then(follow_state);
+        match followed {
+            Ok(false) => break,
+            Ok(true) => log::debug!("Daemon closed the state stream"),
+            Err(e) => log::debug!("Daemon state unavailable: {}", e),
+        }
+        if !emit(&status::waybar(None)) {
+            break;
+        }
+        let mut waited = Duration::ZERO;
+        while waited < WAYBAR_RETRY && !SHUTDOWN.load(Ordering::Relaxed) {
+            thread::sleep(WAYBAR_POLL);
+            waited += WAYBAR_POLL;
+        }
+    }
     Ok(())
 }
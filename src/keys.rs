@@ -0,0 +1,389 @@
+//! `key="ctrl+plus"` on swipe, pinch and hold rules: key presses synthesized in-process through
+//! libxdo on X11 or a virtual /dev/uinput keyboard elsewhere, without spawning xdotool or wtype
+//!
+//! `type-text="..."` types a snippet the same way. Text a US layout can't type on the uinput
+//! keyboard (e.g. emoji), or any text with `paste=true`, goes through the clipboard and ctrl+v.
+
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::compositor::{DisplayServer, Stack};
+use crate::uinput::UinputKeyboard;
+
+/// Key names with their evdev code and X keysym, modifiers first
+const KEYS: &[(&str, u16, &str)] = &[
+    ("ctrl", 29, "ctrl"),
+    ("control", 29, "ctrl"),
+    ("shift", 42, "shift"),
+    ("alt", 56, "alt"),
+    ("super", 125, "super"),
+    ("meta", 125, "super"),
+    ("logo", 125, "super"),
+    ("altgr", 100, "ISO_Level3_Shift"),
+    ("esc", 1, "Escape"),
+    ("escape", 1, "Escape"),
+    ("minus", 12, "minus"),
+    ("equal", 13, "equal"),
+    ("plus", 78, "KP_Add"),
+    ("backspace", 14, "BackSpace"),
+    ("tab", 15, "Tab"),
+    ("bracketleft", 26, "bracketleft"),
+    ("bracketright", 27, "bracketright"),
+    ("enter", 28, "Return"),
+    ("return", 28, "Return"),
+    ("semicolon", 39, "semicolon"),
+    ("apostrophe", 40, "apostrophe"),
+    ("grave", 41, "grave"),
+    ("backslash", 43, "backslash"),
+    ("comma", 51, "comma"),
+    ("period", 52, "period"),
+    ("slash", 53, "slash"),
+    ("space", 57, "space"),
+    ("print", 99, "Print"),
+    ("home", 102, "Home"),
+    ("up", 103, "Up"),
+    ("pageup", 104, "Prior"),
+    ("left", 105, "Left"),
+    ("right", 106, "Right"),
+    ("end", 107, "End"),
+    ("down", 108, "Down"),
+    ("pagedown", 109, "Next"),
+    ("insert", 110, "Insert"),
+    ("delete", 111, "Delete"),
+    ("mute", 113, "XF86AudioMute"),
+    ("volumedown", 114, "XF86AudioLowerVolume"),
+    ("volumeup", 115, "XF86AudioRaiseVolume"),
+    ("nextsong", 163, "XF86AudioNext"),
+    ("playpause", 164, "XF86AudioPlay"),
+    ("previoussong", 165, "XF86AudioPrev"),
+    ("brightnessdown", 224, "XF86MonBrightnessDown"),
+    ("brightnessup", 225, "XF86MonBrightnessUp"),
+];
+
+/// Letters in alphabetical order as evdev codes (QWERTY positions)
+const LETTERS: [u16; 26] = [
+    30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45,
+    21, 44,
+];
+/// evdev KEY_F1..KEY_F10, KEY_F11 and KEY_F12 are not contiguous with them
+const F1: u16 = 59;
+const F11: u16 = 87;
+
+/// Evdev code and X keysym of a key name, case-insensitive
+fn lookup(name: &str) -> Option<(u16, String)> {
+    let lower = name.to_ascii_lowercase();
+    if let Some(&(_, code, keysym)) = KEYS.iter().find(|(n, _, _)| *n == lower) {
+        return Some((code, keysym.to_string()));
+    }
+    let mut chars = lower.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return match c {
+            'a'..='z' => Some((LETTERS[(c as u8 - b'a') as usize], c.to_string())),
+            // KEY_1 is 2, KEY_0 follows KEY_9
+            '1'..='9' => Some(((c as u8 - b'0') as u16 + 1, c.to_string())),
+            '0' => Some((11, c.to_string())),
+            _ => None,
+        };
+    }
+    let n: u16 = lower.strip_prefix('f')?.parse().ok()?;
+    let code = match n {
+        1..=10 => F1 + n - 1,
+        11 | 12 => F11 + n - 11,
+        _ => return None,
+    };
+    Some((code, format!("F{n}")))
+}
+
+/// evdev KEY_LEFTSHIFT, held for upper case letters and shifted symbols
+const SHIFT: u16 = 42;
+
+/// Characters typed with shift held on a US layout, with the character of the same key
+const SHIFTED: &[(char, char)] = &[
+    ('!', '1'),
+    ('@', '2'),
+    ('#', '3'),
+    ('$', '4'),
+    ('%', '5'),
+    ('^', '6'),
+    ('&', '7'),
+    ('*', '8'),
+    ('(', '9'),
+    (')', '0'),
+    ('_', '-'),
+    ('+', '='),
+    ('{', '['),
+    ('}', ']'),
+    (':', ';'),
+    ('"', '\''),
+    ('~', '`'),
+    ('|', '\\'),
+    ('<', ','),
+    ('>', '.'),
+    ('?', '/'),
+];
+
+/// Key codes typing `c` on a US layout, shift first where needed
+fn char_codes(c: char) -> Option<Vec<u16>> {
+    if let Some(&(_, base)) = SHIFTED.iter().find(|(shifted, _)| *shifted == c) {
+        return Some(vec![SHIFT, char_codes(base)?[0]]);
+    }
+    if c.is_ascii_uppercase() {
+        return Some(vec![SHIFT, char_codes(c.to_ascii_lowercase())?[0]]);
+    }
+    let name = match c {
+        '-' => "minus",
+        '=' => "equal",
+        '[' => "bracketleft",
+        ']' => "bracketright",
+        ';' => "semicolon",
+        '\'' => "apostrophe",
+        '`' => "grave",
+        '\\' => "backslash",
+        ',' => "comma",
+        '.' => "period",
+        '/' => "slash",
+        ' ' => "space",
+        '\n' => "enter",
+        '\t' => "tab",
+        'a'..='z' | '0'..='9' => {
+            return lookup(c.encode_utf8(&mut [0; 4])).map(|(code, _)| vec![code])
+        }
+        _ => return None,
+    };
+    lookup(name).map(|(code, _)| vec![code])
+}
+
+/// Key presses for each character of `text`, `None` if one can't be typed
+pub fn text_codes(text: &str) -> Option<Vec<Vec<u16>>> {
+    text.chars().map(char_codes).collect()
+}
+
+/// Every evdev code a combination can contain, registered on the uinput keyboard
+pub fn all_codes() -> Vec<u16> {
+    let mut codes: Vec<u16> = KEYS.iter().map(|&(_, code, _)| code).collect();
+    codes.extend(LETTERS);
+    codes.extend(2..=11);
+    codes.extend(F1..F1 + 10);
+    codes.extend([F11, F11 + 1]);
+    codes.sort_unstable();
+    codes.dedup();
+    codes
+}
+
+/// Keys pressed together, e.g. `ctrl+shift+t`, validated when the config is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCombo {
+    text: String,
+    /// In the order given, pressed in that order and released in reverse
+    codes: Vec<u16>,
+    keysyms: Vec<String>,
+}
+
+impl KeyCombo {
+    pub fn codes(&self) -> &[u16] {
+        &self.codes
+    }
+
+    /// libxdo key sequence, e.g. `ctrl+KP_Add`
+    #[cfg(feature = "x11")]
+    pub fn keysyms(&self) -> String {
+        self.keysyms.join("+")
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut codes = Vec::new();
+        let mut keysyms = Vec::new();
+        for name in s.split('+').map(str::trim) {
+            let (code, keysym) =
+                lookup(name).ok_or_else(|| format!("unknown key `{name}` in `{s}`"))?;
+            codes.push(code);
+            keysyms.push(keysym);
+        }
+        Ok(Self {
+            text: s.trim().to_string(),
+            codes,
+            keysyms,
+        })
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Key presses queued while the worker is busy, further ones are dropped
+const KEY_QUEUE_SIZE: usize = 32;
+/// Delay between characters typed through libxdo
+#[cfg(feature = "x11")]
+const TYPE_DELAY_US: u32 = 12_000;
+
+/// Keys pressed by the key worker
+#[derive(Debug, Clone)]
+enum KeyAction {
+    Press(KeyCombo),
+    Type(String),
+    Paste(String),
+}
+
+impl fmt::Display for KeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Press(key) => key.fmt(f),
+            Self::Type(text) | Self::Paste(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+static WORKER: OnceLock<Option<mpsc::SyncSender<KeyAction>>> = OnceLock::new();
+
+fn send(action: KeyAction) {
+    let Some(tx) = WORKER.get_or_init(start_worker) else {
+        log::warn!("No way to synthesize keys, dropping `{}`", action);
+        return;
+    };
+    if let Err(TrySendError::Full(action) | TrySendError::Disconnected(action)) =
+        tx.try_send(action)
+    {
+        log::warn!("Key worker busy, dropping `{}`", action);
+    }
+}
+
+/// Press `key` on the key worker, started on first use
+pub fn press(key: &KeyCombo) {
+    send(KeyAction::Press(key.clone()));
+}
+
+/// Type `text` on the key worker, or paste it from the clipboard with `paste`
+pub fn type_text(text: &str, paste: bool) {
+    if paste {
+        send(KeyAction::Paste(text.to_string()));
+    } else {
+        send(KeyAction::Type(text.to_string()));
+    }
+}
+
+/// Put `text` on the clipboard with `wl-copy` under Wayland or `xclip` under X11
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut command = if env::var_os("WAYLAND_DISPLAY").is_some() {
+        Command::new("wl-copy")
+    } else {
+        let mut xclip = Command::new("xclip");
+        xclip.args(["-selection", "clipboard"]);
+        xclip
+    };
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    // Both fork to serve the selection, the parent exits once it took ownership
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "clipboard tool failed: {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+/// Copy `text` and press ctrl+v with `press`
+fn paste(text: &str, press: impl FnOnce(&KeyCombo)) {
+    match copy_to_clipboard(text) {
+        Ok(()) => press(&"ctrl+v".parse().expect("valid key combination")),
+        Err(e) => log::error!("Failed to paste {:?}: {}", text, e),
+    }
+}
+
+/// libxdo under X11, otherwise a uinput keyboard, whichever initializes first
+fn start_worker() -> Option<mpsc::SyncSender<KeyAction>> {
+    let (tx, rx) = mpsc::sync_channel::<KeyAction>(KEY_QUEUE_SIZE);
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let x11 = Stack::current().is_some_and(|s| s.display_server == DisplayServer::X11);
+
+    thread::Builder::new()
+        .name("keys".to_string())
+        .spawn(move || {
+            #[cfg(feature = "x11")]
+            if x11 {
+                match libxdo::XDo::new(None) {
+                    Ok(xdo) => {
+                        let _ = ready_tx.send(true);
+                        log::info!("Synthesizing keys through libxdo");
+                        let press = |key: &KeyCombo| {
+                            if let Err(e) = xdo.send_keysequence(&key.keysyms(), 0) {
+                                log::error!("Failed to press `{}`: {}", key, e);
+                            }
+                        };
+                        for action in rx {
+                            match action {
+                                KeyAction::Press(key) => press(&key),
+                                KeyAction::Type(text) => {
+                                    if let Err(e) = xdo.enter_text(&text, TYPE_DELAY_US) {
+                                        log::error!("Failed to type {:?}: {}", text, e);
+                                    }
+                                }
+                                KeyAction::Paste(text) => paste(&text, press),
+                            }
+                        }
+                        return;
+                    }
+                    Err(e) => log::warn!("libxdo unavailable for keys ({}), trying uinput", e),
+                }
+            }
+            #[cfg(not(feature = "x11"))]
+            let _ = x11;
+
+            if !cfg!(feature = "uinput") {
+                log::warn!("Built without the uinput feature, cannot synthesize keys");
+                let _ = ready_tx.send(false);
+                return;
+            }
+            match UinputKeyboard::create(&all_codes()) {
+                Ok(mut keyboard) => {
+                    let _ = ready_tx.send(true);
+                    log::info!("Synthesizing keys through a uinput keyboard");
+                    let mut press = |codes: &[u16]| {
+                        if let Err(e) = keyboard.press(codes) {
+                            log::error!("Failed to press keys {:?}: {}", codes, e);
+                        }
+                    };
+                    for action in rx {
+                        match action {
+                            KeyAction::Press(key) => press(key.codes()),
+                            KeyAction::Type(text) => match text_codes(&text) {
+                                Some(keys) => {
+                                    for codes in &keys {
+                                        press(codes);
+                                    }
+                                }
+                                None => {
+                                    log::debug!("{:?} can't be typed on uinput, pasting", text);
+                                    paste(&text, |key| press(key.codes()));
+                                }
+                            },
+                            KeyAction::Paste(text) => paste(&text, |key| press(key.codes())),
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Cannot create a uinput keyboard: {}", e);
+                    let _ = ready_tx.send(false);
+                }
+            }
+        })
+        .ok()?;
+
+    ready_rx.recv().unwrap_or(false).then_some(tx)
+}
@@ -0,0 +1,166 @@
+//! In-memory buffer of recent log records, served over IPC by `gestures logs`
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use knuffel::{Decode, DecodeScalar};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Number of log records kept for `gestures logs`
+const LOG_CAPACITY: usize = 1000;
+
+/// Records a slow `gestures logs -f` client may lag behind before it is disconnected
+const FOLLOW_QUEUE_CAPACITY: usize = 256;
+
+/// Target of the records of rules with `log=`, which pass whatever the log level is
+pub const RULE_TARGET: &str = "gestures::rule";
+
+struct LogBuffer {
+    records: VecDeque<String>,
+    followers: Vec<SyncSender<String>>,
+}
+
+static LOGS: Lazy<Mutex<LogBuffer>> = Lazy::new(|| {
+    Mutex::new(LogBuffer {
+        records: VecDeque::with_capacity(LOG_CAPACITY),
+        followers: Vec::new(),
+    })
+});
+
+/// Append a formatted record, followers that went away or fell too far behind are dropped
+pub fn push(line: String) {
+    let mut logs = LOGS.lock();
+    logs.followers
+        .retain(|tx| tx.try_send(line.clone()).is_ok());
+    if logs.records.len() == LOG_CAPACITY {
+        logs.records.pop_front();
+    }
+    logs.records.push_back(line);
+}
+
+/// Oldest first copy of the buffered records
+pub fn snapshot() -> Vec<String> {
+    LOGS.lock().records.iter().cloned().collect()
+}
+
+/// Buffered records plus a receiver for every record logged from now on
+pub fn follow() -> (Vec<String>, Receiver<String>) {
+    let (tx, rx) = sync_channel(FOLLOW_QUEUE_CAPACITY);
+    let mut logs = LOGS.lock();
+    logs.followers.push(tx);
+    (logs.records.iter().cloned().collect(), rx)
+}
+
+/// Level of a rule's `log=` records
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<RuleLevel> for Level {
+    fn from(level: RuleLevel) -> Self {
+        match level {
+            RuleLevel::Error => Level::Error,
+            RuleLevel::Warn => Level::Warn,
+            RuleLevel::Info => Level::Info,
+            RuleLevel::Debug => Level::Debug,
+            RuleLevel::Trace => Level::Trace,
+        }
+    }
+}
+
+/// `log=` and `log-label=` of a rule: what it does is logged at that level even when the daemon
+/// logs nothing else
+#[derive(Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleLog {
+    #[knuffel(property)]
+    pub log: Option<RuleLevel>,
+    /// Prefix of the rule's records, e.g. `workspace-switch`
+    #[knuffel(property)]
+    pub log_label: Option<String>,
+}
+
+impl RuleLog {
+    /// Log `message` about this rule, a no-op without `log=`
+    pub fn record(&self, message: fmt::Arguments) {
+        let Some(level) = self.log else {
+            return;
+        };
+        match &self.log_label {
+            Some(label) => log_rule(level.into(), format_args!("[{}] {}", label, message)),
+            None => log_rule(level.into(), message),
+        }
+    }
+}
+
+/// Built by hand as the `log!` macros stop at the global max level
+pub fn log_rule(level: Level, args: fmt::Arguments) {
+    log::logger().log(
+        &Record::builder()
+            .level(level)
+            .target(RULE_TARGET)
+            .args(args)
+            .build(),
+    );
+}
+
+/// Wraps the env_logger logger, keeping a copy of every record it lets through
+pub struct BufferedLogger {
+    inner: env_logger::Logger,
+    /// Lets every rule record through, formatted like the others
+    rules: env_logger::Logger,
+}
+
+impl BufferedLogger {
+    /// Install as the global logger
+    pub fn init(inner: env_logger::Logger) {
+        log::set_max_level(inner.filter());
+        let rules = env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .build();
+        if log::set_boxed_logger(Box::new(Self { inner, rules })).is_err() {
+            eprintln!("Logger was already initialized");
+        }
+    }
+
+    fn logger_for(&self, target: &str) -> &env_logger::Logger {
+        if target == RULE_TARGET {
+            &self.rules
+        } else {
+            &self.inner
+        }
+    }
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger_for(metadata.target()).enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let inner = self.logger_for(record.target());
+        if !inner.matches(record) {
+            return;
+        }
+        push(format!(
+            "{} {:<5} {}: {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+        inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        self.rules.flush();
+    }
+}
@@ -0,0 +1,573 @@
+//! Minimal language server for gestures.kdl: completions and diagnostics from the real parser
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use knuffel_miette::Diagnostic;
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::config::Config;
+use crate::json::Json;
+
+const CONDITION_PROPERTIES: [&str; 10] = [
+    "power-profile",
+    "not-power-profile",
+    "min-displays",
+    "max-displays",
+    "time",
+    "days",
+    "app",
+    "window-class",
+    "window-title",
+    "modifiers",
+];
+
+/// `SpawnOptions` and `RuleLog`, accepted by every rule that runs commands
+const SPAWN_PROPERTIES: [&str; 7] = [
+    "cwd",
+    "stdin",
+    "stdout",
+    "stderr",
+    "max-concurrent",
+    "log",
+    "log-label",
+];
+
+const COMPLETION_KIND_PROPERTY: usize = 10;
+const COMPLETION_KIND_VALUE: usize = 12;
+const COMPLETION_KIND_KEYWORD: usize = 14;
+
+/// Properties accepted by each node, kept in sync with the `Decode` structs
+fn node_properties(node: &str) -> &'static [&'static str] {
+    match node {
+        "swipe" => &[
+            "direction",
+            "fingers",
+            "update",
+            "start",
+            "end",
+            "cancel",
+            "repeat-interval",
+            "repeat",
+            "repeat-distance",
+            "acceleration",
+            "acceleration-curve",
+            "smoothing",
+            "smoothing-beta",
+            "mouse-up-delay",
+            "update-rate",
+            "inertia",
+            "friction",
+            "idle-inhibit",
+            "edge",
+            "after-hold",
+            "corner",
+            "min-velocity",
+            "max-velocity",
+            "key",
+            "script",
+            "type-text",
+            "paste",
+            "hyprland",
+            "hyprland-update",
+            "sway",
+            "sway-update",
+            "dbus-dest",
+            "dbus-path",
+            "dbus-method",
+            "dbus-args",
+            "pipe",
+            "pipe-format",
+        ],
+        "pinch" => &[
+            "direction",
+            "fingers",
+            "update",
+            "start",
+            "end",
+            "cancel",
+            "rotate-step-deg",
+            "min-scale-delta",
+            "step",
+            "update-rate",
+            "key",
+            "script",
+            "type-text",
+            "paste",
+            "hyprland",
+            "hyprland-update",
+            "sway",
+            "sway-update",
+            "pipe",
+            "pipe-format",
+        ],
+        "hold" => &[
+            "fingers",
+            "action",
+            "start",
+            "end",
+            "key",
+            "script",
+            "type-text",
+            "paste",
+            "trigger",
+            "duration",
+            "repeat",
+            "min-duration",
+            "max-duration",
+        ],
+        "rotate" => &[
+            "direction",
+            "fingers",
+            "threshold",
+            "rotate-step-deg",
+            "start",
+            "update",
+            "end",
+        ],
+        "drop-zone" => &["edge", "action", "size"],
+        "palm-rejection" => &["side-margin", "bottom-margin", "max-touch-size"],
+        "touchscreen" => &["min-fingers", "threshold"],
+        "device" => &["name", "finger-offset", "finger-map"],
+        _ => &[],
+    }
+}
+
+fn has_conditions(node: &str) -> bool {
+    matches!(
+        node,
+        "swipe" | "pinch" | "hold" | "rotate" | "profile" | "group"
+    )
+}
+
+/// Child nodes allowed inside a block opened by `parent` (`None` at top level)
+fn child_nodes(parent: Option<&str>) -> &'static [&'static str] {
+    match parent {
+        None => &[
+            "swipe",
+            "pinch",
+            "hold",
+            "rotate",
+            "profile",
+            "group",
+            "device",
+            "ignore-device",
+            "only-device",
+            "palm-rejection",
+            "touchscreen",
+            "finger-change",
+            "watch",
+            "output-scale",
+            "idle-pause",
+            "update-rate",
+            "notify-failures",
+            "seat",
+            "include",
+            "vars",
+        ],
+        Some("profile") => &["swipe", "pinch", "hold", "rotate", "group", "vars"],
+        Some("group" | "device") => &["swipe", "pinch", "hold", "rotate", "group"],
+        Some("swipe") => &["drop-zone"],
+        Some(_) => &[],
+    }
+}
+
+fn property_values(node: &str, property: &str) -> &'static [&'static str] {
+    match (node, property) {
+        ("swipe", "direction") => &["any", "n", "s", "e", "w", "ne", "nw", "se", "sw"],
+        ("pinch", "direction") => &["any", "in", "out", "clockwise", "counter-clockwise"],
+        ("drop-zone", "edge") => &["n", "s", "e", "w", "ne", "nw", "se", "sw"],
+        ("hold", "trigger") => &["release", "timeout"],
+        ("rotate", "direction") => &["cw", "ccw", "any"],
+        ("swipe", "edge") => &["left", "right", "top", "bottom"],
+        ("swipe", "corner") => &["top-left", "top-right", "bottom-left", "bottom-right"],
+        ("swipe", "acceleration-curve") => &["linear", "flat", "quadratic", "adaptive"],
+        (_, "power-profile" | "not-power-profile") => &["power-saver", "balanced", "performance"],
+        (_, "stdin") => &["inherit", "null"],
+        (_, "stdout" | "stderr") => &["inherit", "null"],
+        (_, "log") => &["error", "warn", "info", "debug", "trace"],
+        _ => &[],
+    }
+}
+
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// Byte offset of an LSP position (line, UTF-16 column) in `text`
+fn offset_at(text: &str, line: usize, character: usize) -> usize {
+    let mut offset = 0;
+    for (i, l) in text.split_inclusive('\n').enumerate() {
+        if i == line {
+            let mut units = 0;
+            for (byte, c) in l.char_indices() {
+                if units >= character || c == '\n' {
+                    return offset + byte;
+                }
+                units += c.len_utf16();
+            }
+            return offset + l.len();
+        }
+        offset += l.len();
+    }
+    text.len()
+}
+
+/// LSP position (line, UTF-16 column) of a byte offset in `text`
+fn position_at(text: &str, offset: usize) -> Json {
+    let before = &text[..floor_char_boundary(text, offset)];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object([("line", line.into()), ("character", character.into())])
+}
+
+/// Diagnostics for `text` as produced by the same parser the daemon uses
+pub fn diagnostics(text: &str) -> Vec<(usize, usize, String)> {
+    let Err(err) = knuffel::parse::<Config>("gestures.kdl", text) else {
+        return Vec::new();
+    };
+
+    let Some(related) = err.related() else {
+        return vec![(0, 0, err.to_string())];
+    };
+    related
+        .map(|diag| {
+            let (start, len) = diag
+                .labels()
+                .and_then(|mut labels| labels.next())
+                .map_or((0, 0), |l| (l.offset(), l.len()));
+            (start, start + len, diag.to_string())
+        })
+        .collect()
+}
+
+/// First word of a line, i.e. the node name
+fn node_name(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+/// Node names of the blocks enclosing the end of `text`, innermost last
+fn enclosing_nodes(text: &str) -> Vec<&str> {
+    let mut stack = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("//") {
+            continue;
+        }
+        for c in trimmed.chars() {
+            match c {
+                '{' => stack.push(node_name(trimmed)),
+                '}' => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    stack
+}
+
+/// Completion labels and their LSP kind for the cursor at byte `offset`
+pub fn completions(text: &str, offset: usize) -> Vec<(&'static str, usize)> {
+    let before = &text[..floor_char_boundary(text, offset)];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = &before[line_start..];
+    let node = node_name(line);
+
+    // Inside a quoted property value
+    if line.matches('"').count() % 2 == 1 {
+        let Some(property) = line
+            .rfind("=\"")
+            .map(|i| &line[..i])
+            .and_then(|s| s.split_whitespace().next_back())
+        else {
+            return Vec::new();
+        };
+        return property_values(node, property)
+            .iter()
+            .map(|v| (*v, COMPLETION_KIND_VALUE))
+            .collect();
+    }
+
+    // Still typing the node name
+    if !line.trim_start().contains(char::is_whitespace) {
+        let parents = enclosing_nodes(&before[..line_start]);
+        return child_nodes(parents.last().copied())
+            .iter()
+            .map(|n| (*n, COMPLETION_KIND_KEYWORD))
+            .collect();
+    }
+
+    all_properties(node)
+        .filter(|p| !line.contains(&format!("{p}=")))
+        .map(|p| (p, COMPLETION_KIND_PROPERTY))
+        .collect()
+}
+
+/// Own, condition and spawn properties of `node`
+fn all_properties(node: &str) -> impl Iterator<Item = &'static str> {
+    let conditions: &[&str] = if has_conditions(node) {
+        &CONDITION_PROPERTIES
+    } else {
+        &[]
+    };
+    let spawn: &[&str] = if matches!(node, "swipe" | "pinch" | "hold" | "rotate") {
+        &SPAWN_PROPERTIES
+    } else {
+        &[]
+    };
+    node_properties(node)
+        .iter()
+        .chain(conditions)
+        .chain(spawn)
+        .copied()
+}
+
+/// Every node with its properties, their fixed values and the child nodes it accepts, for GUI
+/// configurators (`schema` over IPC)
+pub fn schema() -> Json {
+    let mut names: Vec<&str> = child_nodes(None).to_vec();
+    let mut i = 0;
+    while i < names.len() {
+        for child in child_nodes(Some(names[i])) {
+            if !names.contains(child) {
+                names.push(child);
+            }
+        }
+        i += 1;
+    }
+    let nodes = names
+        .iter()
+        .map(|&node| {
+            let properties = all_properties(node)
+                .map(|property| {
+                    let values = property_values(node, property)
+                        .iter()
+                        .map(|v| Json::from(*v))
+                        .collect::<Vec<_>>();
+                    Json::object([("name", property.into()), ("values", values.into())])
+                })
+                .collect::<Vec<_>>();
+            let children = child_nodes(Some(node))
+                .iter()
+                .map(|c| Json::from(*c))
+                .collect::<Vec<_>>();
+            Json::object([
+                ("name", node.into()),
+                ("properties", properties.into()),
+                ("children", children.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let top_level = child_nodes(None)
+        .iter()
+        .map(|n| Json::from(*n))
+        .collect::<Vec<_>>();
+    Json::object([
+        ("version", env!("CARGO_PKG_VERSION").into()),
+        ("top_level", top_level.into()),
+        ("nodes", nodes.into()),
+    ])
+}
+
+/// `{"ok":true,"errors":[]}`, or the parse errors of `text` with their LSP ranges (`validate` over
+/// IPC)
+pub fn validate(text: &str) -> Json {
+    let errors = diagnostics(text)
+        .into_iter()
+        .map(|(start, end, message)| {
+            Json::object([
+                ("start", position_at(text, start)),
+                ("end", position_at(text, end)),
+                ("message", message.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    Json::object([("ok", errors.is_empty().into()), ("errors", errors.into())])
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).into_diagnostic()? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| miette!("Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).into_diagnostic()?;
+    let body = String::from_utf8(body).into_diagnostic()?;
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|e| miette!("Invalid JSON-RPC message: {}", e))
+}
+
+fn send(out: &mut impl Write, message: Json) -> Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body).into_diagnostic()?;
+    out.flush().into_diagnostic()
+}
+
+fn respond(out: &mut impl Write, id: Json, result: Json) -> Result<()> {
+    send(
+        out,
+        Json::object([("jsonrpc", "2.0".into()), ("id", id), ("result", result)]),
+    )
+}
+
+fn publish_diagnostics(out: &mut impl Write, uri: &str, text: &str) -> Result<()> {
+    let diagnostics = diagnostics(text)
+        .into_iter()
+        .map(|(start, end, message)| {
+            Json::object([
+                (
+                    "range",
+                    Json::object([
+                        ("start", position_at(text, start)),
+                        ("end", position_at(text, end)),
+                    ]),
+                ),
+                ("severity", 1usize.into()),
+                ("source", "gestures".into()),
+                ("message", message.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    send(
+        out,
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+            ),
+        ]),
+    )
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Full document sync
+                ("textDocumentSync", 1usize.into()),
+                (
+                    "completionProvider",
+                    Json::object([("triggerCharacters", vec!["\"".into(), " ".into()].into())]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "gestures".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+/// Serve LSP over stdin/stdout until the client sends `exit`
+pub fn run() -> Result<()> {
+    let mut reader = io::stdin().lock();
+    let mut out = io::stdout().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    while let Some(message) = read_message(&mut reader)? {
+        let id = message.get("id").cloned();
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let uri = message
+            .pointer(&["params", "textDocument", "uri"])
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+
+        match method {
+            "initialize" => respond(&mut out, id.unwrap_or(Json::Null), capabilities())?,
+            "shutdown" => respond(&mut out, id.unwrap_or(Json::Null), Json::Null)?,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" => {
+                let text = message
+                    .pointer(&["params", "textDocument", "text"])
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                publish_diagnostics(&mut out, &uri, &text)?;
+                documents.insert(uri, text);
+            }
+            "textDocument/didChange" => {
+                // Full sync, the last change holds the whole document
+                let text = message
+                    .pointer(&["params", "contentChanges"])
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                if let Some(text) = text {
+                    publish_diagnostics(&mut out, &uri, text)?;
+                    documents.insert(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                publish_diagnostics(&mut out, &uri, "")?;
+            }
+            "textDocument/completion" => {
+                let text = documents.get(&uri).map_or("", String::as_str);
+                let position = |key| {
+                    message
+                        .pointer(&["params", "position", key])
+                        .and_then(Json::as_u64)
+                        .unwrap_or(0) as usize
+                };
+                let offset = offset_at(text, position("line"), position("character"));
+                let items = completions(text, offset)
+                    .into_iter()
+                    .map(|(label, kind)| {
+                        Json::object([("label", label.into()), ("kind", kind.into())])
+                    })
+                    .collect::<Vec<_>>();
+                respond(&mut out, id.unwrap_or(Json::Null), items.into())?;
+            }
+            _ => {
+                // Unknown requests need an answer, unknown notifications are ignored
+                if let Some(id) = id {
+                    send(
+                        &mut out,
+                        Json::object([
+                            ("jsonrpc", "2.0".into()),
+                            ("id", id),
+                            (
+                                "error",
+                                Json::object([
+                                    ("code", Json::Number(-32601.0)),
+                                    ("message", format!("Unsupported method {method}").into()),
+                                ]),
+                            ),
+                        ]),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
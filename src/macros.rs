@@ -0,0 +1,222 @@
+//! Recording of key/button sequences and their replay as the `@macro <name>` action
+
+use std::fmt;
+use std::fs;
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use input::event::keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait};
+use input::event::pointer::{ButtonState, PointerEvent, PointerEventTrait};
+use input::event::Event;
+use miette::{miette, Result};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use crate::config::Config;
+use crate::event_handler::Interface;
+use crate::platform::DEFAULT_SEAT;
+
+/// evdev KEY_ESC, stops a recording and is not stored
+const KEY_ESC: u32 = 1;
+/// evdev BTN_LEFT, first mouse button code
+const BTN_LEFT: u32 = 0x110;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepKind {
+    Key,
+    Button,
+}
+
+/// One recorded input change, `delay_ms` is the time since the previous step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacroStep {
+    pub delay_ms: u64,
+    pub kind: StepKind,
+    pub code: u32,
+    pub pressed: bool,
+}
+
+impl fmt::Display for MacroStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            StepKind::Key => "key",
+            StepKind::Button => "button",
+        };
+        write!(
+            f,
+            "{} {} {} {}",
+            self.delay_ms,
+            kind,
+            self.code,
+            if self.pressed { "down" } else { "up" }
+        )
+    }
+}
+
+impl FromStr for MacroStep {
+    type Err = String;
+
+    /// Parses `<delay_ms> <key|button> <evdev code> <down|up>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let [delay, kind, code, state] = parts[..] else {
+            return Err(format!(
+                "expected `<delay> <key|button> <code> <down|up>`, got `{s}`"
+            ));
+        };
+        Ok(Self {
+            delay_ms: delay
+                .parse()
+                .map_err(|_| format!("invalid delay `{delay}`"))?,
+            kind: match kind {
+                "key" => StepKind::Key,
+                "button" => StepKind::Button,
+                _ => return Err(format!("invalid step kind `{kind}`")),
+            },
+            code: code.parse().map_err(|_| format!("invalid code `{code}`"))?,
+            pressed: match state {
+                "down" => true,
+                "up" => false,
+                _ => return Err(format!("invalid state `{state}`")),
+            },
+        })
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<MacroStep>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.parse().map_err(|e: String| miette!("{}", e)))
+        .collect()
+}
+
+fn macro_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(miette!("Invalid macro name `{}`", name));
+    }
+    let config_home = Config::get_config_home()?;
+    Ok(PathBuf::from(config_home)
+        .join("gestures/macros")
+        .join(format!("{name}.macro")))
+}
+
+pub fn load(name: &str) -> Result<Vec<MacroStep>> {
+    let path = macro_path(name)?;
+    let text = fs::read_to_string(&path)
+        .map_err(|e| miette!("Failed to read macro {}: {}", path.display(), e))?;
+    parse(&text)
+}
+
+fn save(name: &str, steps: &[MacroStep]) -> Result<PathBuf> {
+    let path = macro_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| miette!("Failed to create directory {}: {}", dir.display(), e))?;
+    }
+    let mut text = String::from("# <delay ms> <key|button> <evdev code> <down|up>\n");
+    for step in steps {
+        text.push_str(&step.to_string());
+        text.push('\n');
+    }
+    fs::write(&path, text)
+        .map_err(|e| miette!("Failed to write macro {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Replay a stored macro through ydotool, keeping the recorded timing
+pub fn play(name: &str) -> Result<()> {
+    if !cfg!(feature = "ydotool") {
+        return Err(miette!("Macro replay needs the ydotool feature"));
+    }
+
+    for step in load(name)? {
+        thread::sleep(Duration::from_millis(step.delay_ms));
+        let arg = match step.kind {
+            StepKind::Key => format!("{}:{}", step.code, u8::from(step.pressed)),
+            StepKind::Button => {
+                let button = step.code.saturating_sub(BTN_LEFT);
+                let state = if step.pressed { 0x40 } else { 0x80 };
+                format!("{:#x}", button | state)
+            }
+        };
+        let subcommand = match step.kind {
+            StepKind::Key => "key",
+            StepKind::Button => "click",
+        };
+        Command::new("ydotool")
+            .args([subcommand, "--", &arg])
+            .status()
+            .map_err(|e| miette!("Failed to run ydotool: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Record keyboard keys and pointer buttons until Esc is pressed or `timeout` expires
+pub fn record(name: &str, timeout: Duration) -> Result<()> {
+    let mut input = input::Libinput::new_with_udev(Interface);
+    input
+        .udev_assign_seat(DEFAULT_SEAT)
+        .map_err(|_| miette!("Could not initialize libinput"))?;
+
+    println!("Recording macro `{}`, press Esc to stop", name);
+
+    let deadline = Instant::now() + timeout;
+    let mut steps = Vec::new();
+    let mut last_usec: Option<u64> = None;
+    let mut push = |kind, code, pressed, time_usec: u64| {
+        let delay_ms = last_usec.map_or(0, |last| time_usec.saturating_sub(last) / 1000);
+        last_usec = Some(time_usec);
+        steps.push(MacroStep {
+            delay_ms,
+            kind,
+            code,
+            pressed,
+        });
+    };
+
+    'outer: while Instant::now() < deadline && !crate::SHUTDOWN.load(Ordering::Relaxed) {
+        let mut fds = [PollFd::new(input.as_fd(), PollFlags::POLLIN)];
+        if let Err(e) = poll(&mut fds, PollTimeout::from(100u16)) {
+            if e != nix::errno::Errno::EINTR {
+                return Err(miette!("Poll error: {}", e));
+            }
+        }
+        input
+            .dispatch()
+            .map_err(|e| miette!("Failed to dispatch input events: {}", e))?;
+
+        for event in &mut input {
+            match event {
+                Event::Keyboard(KeyboardEvent::Key(k)) => {
+                    if k.key() == KEY_ESC {
+                        break 'outer;
+                    }
+                    push(
+                        StepKind::Key,
+                        k.key(),
+                        k.key_state() == KeyState::Pressed,
+                        k.time_usec(),
+                    );
+                }
+                Event::Pointer(PointerEvent::Button(b)) => push(
+                    StepKind::Button,
+                    b.button(),
+                    b.button_state() == ButtonState::Pressed,
+                    b.time_usec(),
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    let path = save(name, &steps)?;
+    println!("✓ Recorded {} steps to {}", steps.len(), path.display());
+    println!("\nReplay it from a gesture with:");
+    println!("  end=\"@macro {}\"", name);
+    Ok(())
+}
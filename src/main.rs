@@ -1,10 +1,50 @@
+mod builtin;
+mod calibration;
+mod check;
+mod compositor;
+mod conditions;
 mod config;
+mod conflicts;
+mod contacts;
+mod dbus;
 mod event_handler;
+mod failures;
+mod focus;
 mod gestures;
+mod history;
+mod hyprland;
+mod idle_inhibit;
 mod ipc;
 mod ipc_client;
+mod json;
+mod keys;
+mod logs;
+mod lsp;
+mod macros;
 mod mouse_handler;
+mod mpx;
+mod pipe;
+mod platform;
+mod realtime;
+mod script;
+mod session_env;
+mod session_lock;
+mod smoothing;
+mod snap;
+mod status;
+mod stress;
+mod sway;
+mod systemd;
+mod table;
+mod touch;
+mod trace;
+mod uinput;
+mod units;
 mod utils;
+mod virtual_pointer;
+mod wakeups;
+mod watch;
+mod ydotoold;
 
 #[cfg(test)]
 mod tests;
@@ -13,6 +53,8 @@ use parking_lot::RwLock;
 use std::{
     env, fs,
     io::Write,
+    net::SocketAddr,
+    os::unix::net::UnixListener,
     path::PathBuf,
     sync::{atomic::AtomicBool, Arc, LazyLock},
     thread::{self, JoinHandle},
@@ -23,27 +65,15 @@ use env_logger::Builder;
 use log::LevelFilter;
 use miette::Result;
 
+use crate::compositor::{Backend, DisplayServer, Stack};
 use crate::config::*;
+use crate::event_handler::InputSource;
+use crate::json::Json;
 use crate::mouse_handler::start_handler;
+use crate::realtime::{Realtime, RealtimePolicy};
 
 pub static SHUTDOWN: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
 
-/// Detect if running under Wayland by checking environment variables
-fn detect_wayland() -> bool {
-    // Check WAYLAND_DISPLAY first (most reliable indicator)
-    if std::env::var("WAYLAND_DISPLAY").is_ok() {
-        return true;
-    }
-
-    // Check XDG_SESSION_TYPE as fallback
-    if let Ok(session_type) = std::env::var("XDG_SESSION_TYPE") {
-        return session_type.to_lowercase() == "wayland";
-    }
-
-    // Default to X11 if unable to detect
-    false
-}
-
 /// Generate systemd user service file content
 fn generate_service_file() -> Result<String> {
     let exe_path = env::current_exe()
@@ -60,10 +90,12 @@ Documentation=https://github.com/ferstar/gestures
 
 [Service]
 Environment=PATH=/usr/local/bin:/usr/local/sbin:/usr/bin:/bin
-Type=simple
+Type=notify
+NotifyAccess=main
 ExecStart={} start
 ExecReload={} reload
-Restart=no
+Restart=on-failure
+WatchdogSec=30
 
 [Install]
 WantedBy=default.target
@@ -74,9 +106,29 @@ WantedBy=default.target
     Ok(service_content)
 }
 
-/// Install or print systemd user service file
-fn install_service(print_only: bool) -> Result<()> {
-    let service_content = generate_service_file()?;
+/// systemd user socket unit for the IPC socket, starting the daemon on the first command
+fn generate_socket_file() -> String {
+    r#"[Unit]
+Description=Touchpad Gestures IPC socket
+Documentation=https://github.com/ferstar/gestures
+
+[Socket]
+ListenStream=%t/gestures.sock
+SocketMode=0600
+
+[Install]
+WantedBy=sockets.target
+"#
+    .to_string()
+}
+
+/// Install or print systemd user service file, or the socket unit with `socket`
+fn install_service(print_only: bool, socket: bool) -> Result<()> {
+    let (service_content, unit) = if socket {
+        (generate_socket_file(), "gestures.socket")
+    } else {
+        (generate_service_file()?, "gestures.service")
+    };
 
     if print_only {
         print!("{}", service_content);
@@ -88,7 +140,7 @@ fn install_service(print_only: bool) -> Result<()> {
         env::var("HOME").map_err(|_| miette::miette!("HOME environment variable not set"))?;
 
     let systemd_dir = PathBuf::from(home).join(".config/systemd/user");
-    let service_path = systemd_dir.join("gestures.service");
+    let service_path = systemd_dir.join(unit);
 
     // Create directory if it doesn't exist
     fs::create_dir_all(&systemd_dir).map_err(|e| {
@@ -112,10 +164,10 @@ fn install_service(print_only: bool) -> Result<()> {
         .map_err(|e| miette::miette!("Failed to write service file: {}", e))?;
 
     println!("✓ Service file installed to: {}", service_path.display());
-    println!("\nTo enable and start the service, run:");
-    println!("  systemctl --user enable --now gestures.service");
-    println!("\nTo view service status:");
-    println!("  systemctl --user status gestures.service");
+    println!("\nTo enable and start the {}, run:", &unit[9..]);
+    println!("  systemctl --user enable --now {}", unit);
+    println!("\nTo view its status:");
+    println!("  systemctl --user status {}", unit);
 
     Ok(())
 }
@@ -139,14 +191,14 @@ swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20
 // Uncomment and adjust for your desktop environment:
 
 // Hyprland:
-// swipe direction="w" fingers=4 end="hyprctl dispatch workspace e-1"
-// swipe direction="e" fingers=4 end="hyprctl dispatch workspace e+1"
-// swipe direction="n" fingers=4 end="hyprctl dispatch fullscreen"
-// swipe direction="s" fingers=4 end="hyprctl dispatch killactive"
+// swipe direction="w" fingers=4 hyprland="dispatch workspace e-1"
+// swipe direction="e" fingers=4 hyprland="dispatch workspace e+1"
+// swipe direction="n" fingers=4 hyprland="dispatch fullscreen"
+// swipe direction="s" fingers=4 hyprland="dispatch killactive"
 
 // i3/Sway:
-// swipe direction="w" fingers=4 end="i3-msg workspace prev"
-// swipe direction="e" fingers=4 end="i3-msg workspace next"
+// swipe direction="w" fingers=4 sway="workspace prev"
+// swipe direction="e" fingers=4 sway="workspace next"
 
 // GNOME (requires gdbus):
 // swipe direction="n" fingers=4 end="gdbus call --session --dest org.gnome.Shell --object-path /org/gnome/Shell --method org.gnome.Shell.Eval global.workspace_manager.get_active_workspace().get_neighbor(Meta.MotionDirection.UP).activate(global.get_current_time())"
@@ -215,7 +267,10 @@ fn generate_config(print_only: bool, force: bool) -> Result<()> {
 }
 
 fn main() -> Result<()> {
-    let app = App::parse();
+    let mut app = App::parse();
+    app.conf = app
+        .conf
+        .map(|path| utils::expand_path(&path.to_string_lossy()));
 
     // Setup signal handlers for graceful shutdown
     signal_hook::flag::register(signal_hook::consts::SIGTERM, SHUTDOWN.clone())
@@ -238,14 +293,46 @@ fn main() -> Result<()> {
             l.filter_level(LevelFilter::Debug);
         }
 
-        l.init();
+        logs::BufferedLogger::init(l.build());
+    }
+
+    if app.json && !matches!(app.command, Commands::Status | Commands::Doctor) {
+        return Err(miette::miette!(
+            "--json is only supported by status and doctor"
+        ));
     }
 
     match app.command {
-        c @ Commands::Reload => {
-            ipc_client::handle_command(c)?;
+        c @ (Commands::Reload
+        | Commands::Trace
+        | Commands::History
+        | Commands::Monitor
+        | Commands::Status
+        | Commands::Logs { .. }
+        | Commands::Profile { .. }
+        | Commands::Schema
+        | Commands::Validate { .. }
+        | Commands::ApplyTemporary { .. }) => {
+            ipc_client::handle_command(c, app.json, app.remote, app.remote_token_file.as_deref())?;
+        }
+        Commands::Waybar => {
+            if app.remote.is_some() {
+                miette::bail!("gestures waybar only follows the local daemon");
+            }
+            ipc_client::waybar()?;
         }
-        Commands::Start => {
+        Commands::Start {
+            replace,
+            watch_config,
+            dry_run,
+            on_failure,
+            ref seat,
+            ref device,
+        } => {
+            if dry_run {
+                utils::set_dry_run(true, true);
+                log::warn!("Dry run: commands of matching rules are logged, not run");
+            }
             let config_path = app.conf.clone();
             let c = Config::read_from_optional_path(config_path.as_deref()).unwrap_or_else(|e| {
                 log::error!(
@@ -255,65 +342,357 @@ fn main() -> Result<()> {
                 Config::default()
             });
             log::debug!("{:#?}", &c);
+            conflicts::warn(&c);
+            let watch_config = watch_config || c.watch == Some(true);
 
-            let is_wayland = if app.wayland {
-                log::info!("Forced Wayland mode via command line");
-                true
-            } else if app.x11 {
-                log::info!("Forced X11 mode via command line");
-                false
+            session_env::discover(if session_env::is_service() {
+                session_env::SERVICE_WAIT_TIMEOUT
             } else {
-                let detected = detect_wayland();
-                log::info!(
-                    "Auto-detected display server: {}",
-                    if detected { "Wayland" } else { "X11" }
-                );
-                detected
+                std::time::Duration::ZERO
+            });
+
+            let stack = detect_stack(&app)?;
+            log::info!("Detected session: {}", stack);
+            if let Some(master) = &app.master_pointer {
+                mpx::configure(master.clone());
+            }
+            stack.focus.start();
+            session_lock::start();
+            if app.profile_wakeups {
+                wakeups::enable();
+            }
+            let realtime = app.realtime_priority.map(|priority| Realtime {
+                policy: app.realtime_policy,
+                priority,
+            });
+            let remote = match app.remote_listen {
+                Some(addr) => {
+                    let token_file = app.remote_token_file.as_deref().ok_or_else(|| {
+                        miette::miette!("--remote-listen requires --remote-token-file")
+                    })?;
+                    Some((addr, ipc_client::read_token(token_file)?))
+                }
+                None => None,
             };
-            run_eh(Arc::new(RwLock::new(c)), config_path, is_wayland)?;
+            let inherited = if replace {
+                ipc_client::take_over()?
+            } else {
+                systemd::activated_listener()
+            };
+            // Seats are only read at startup, a reload doesn't start or stop event handlers
+            let sources = input_sources(device, seat, &c.seats);
+            let config = Arc::new(RwLock::new(c));
+            if watch_config {
+                if let Err(e) = watch::spawn(config.clone(), config_path.clone()) {
+                    log::warn!("{}", e);
+                }
+            }
+            let eh_threads = sources
+                .into_iter()
+                .map(|source| {
+                    supervise_event_handler(
+                        config.clone(),
+                        source,
+                        stack.backend,
+                        realtime,
+                        on_failure,
+                    )
+                })
+                .collect();
+            let result = run_eh(config, config_path, eh_threads, remote, inherited);
+            if !ipc::handed_over() {
+                mpx::cleanup();
+            }
+            if let Err(e) = &result {
+                if let Some(stopped) = e.downcast_ref::<event_handler::EventLoopError>() {
+                    eprintln!("{:?}", e);
+                    std::process::exit(stopped.exit_code().into());
+                }
+            }
+            result?;
         }
-        Commands::InstallService { print } => {
-            install_service(print)?;
+        Commands::Doctor => doctor(&app)?,
+        Commands::Check { table } => check::run(app.conf.as_deref(), table)?,
+        Commands::InstallService { print, socket } => {
+            install_service(print, socket)?;
         }
         Commands::GenerateConfig { print, force } => {
             generate_config(print, force)?;
         }
+        Commands::Lsp => lsp::run()?,
+        Commands::Stress {
+            events_per_sec,
+            duration,
+            dry_run,
+        } => {
+            let c = Config::read_from_optional_path(app.conf.as_deref())?;
+            stress::run(c, events_per_sec, duration, dry_run)?;
+        }
+        Commands::Calibrate { samples, timeout } => {
+            calibration::run(samples, std::time::Duration::from_secs(timeout))?;
+        }
+        Commands::RecordMacro { name, timeout } => {
+            macros::record(&name, std::time::Duration::from_secs(timeout))?;
+        }
     }
 
     Ok(())
 }
 
+/// What `start` does when the event thread stops with an error
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Stop the daemon, the exit code tells why (3: libinput, 5: input lost)
+    Exit,
+    /// Start the event thread again, up to `MAX_RESTARTS` times with growing delays
+    Restart,
+}
+
+/// Restarts of a failing event thread with `--on-failure restart` before the daemon gives up
+const MAX_RESTARTS: u32 = 5;
+/// Delay before the first restart, doubled for every further one
+const RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Event handlers to run: one for the `--device` nodes, or one per seat of `--seat`, else of
+/// `seat` in the config, else `seat0`
+fn input_sources(
+    devices: &[PathBuf],
+    seats: &[String],
+    config_seats: &[String],
+) -> Vec<InputSource> {
+    if !devices.is_empty() {
+        return vec![InputSource::Paths(devices.to_vec())];
+    }
+    let mut seats = if seats.is_empty() {
+        config_seats.to_vec()
+    } else {
+        seats.to_vec()
+    };
+    if seats.is_empty() {
+        seats.push(platform::DEFAULT_SEAT.to_string());
+    }
+    seats.sort();
+    seats.dedup();
+    seats.into_iter().map(InputSource::Seat).collect()
+}
+
 fn run_eh(
     config: Arc<RwLock<Config>>,
     config_path: Option<PathBuf>,
-    is_wayland: bool,
+    eh_threads: Vec<JoinHandle<Result<()>>>,
+    remote: Option<(SocketAddr, String)>,
+    inherited: Option<UnixListener>,
 ) -> Result<()> {
-    let eh_thread = spawn_event_handler(config.clone(), is_wayland);
-    if let Err(e) = ipc::create_socket(config, config_path) {
+    systemd::start_watchdog();
+    let join_all = |threads: Vec<JoinHandle<Result<()>>>| {
+        let mut result = Ok(());
+        for thread in threads {
+            let joined = thread
+                .join()
+                .map_err(|_| miette::miette!("Event handler supervisor panicked"))
+                .and_then(|r| r);
+            if result.is_ok() {
+                result = joined;
+            }
+        }
+        result
+    };
+    if let Some((addr, token)) = remote {
+        if let Err(e) =
+            ipc::create_remote_listener(addr, token, config.clone(), config_path.clone())
+        {
+            SHUTDOWN.store(true, std::sync::atomic::Ordering::Relaxed);
+            let _ = join_all(eh_threads);
+            return Err(e);
+        }
+    }
+    let listened = ipc::create_socket(config, config_path, inherited);
+    systemd::notify("STOPPING=1");
+    if let Err(e) = listened {
         SHUTDOWN.store(true, std::sync::atomic::Ordering::Relaxed);
-        let _ = eh_thread.join();
+        let _ = join_all(eh_threads);
         return Err(e);
     }
 
-    let thread_result = eh_thread
-        .join()
-        .map_err(|_| miette::miette!("Event handler thread panicked"))?;
-    thread_result?;
+    join_all(eh_threads)
+}
 
-    Ok(())
+/// Run the event thread of `source`, restarting it after an error with `--on-failure restart`.
+/// Once it gives up the daemon shuts down, so the IPC listener stops and `run_eh` returns the
+/// error
+fn supervise_event_handler(
+    config: Arc<RwLock<Config>>,
+    source: InputSource,
+    backend: Backend,
+    realtime: Option<Realtime>,
+    on_failure: OnFailure,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let mut restarts = 0;
+        loop {
+            let result = spawn_event_handler(config.clone(), source.clone(), backend, realtime)
+                .join()
+                .unwrap_or_else(|panic| {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    Err(miette::miette!(
+                        "Event handler thread panicked: {}",
+                        message
+                    ))
+                });
+            let Err(e) = result else {
+                return Ok(());
+            };
+            status::event_thread_failed(e.to_string(), restarts);
+            let shutting_down = SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed);
+            if on_failure == OnFailure::Exit || restarts >= MAX_RESTARTS || shutting_down {
+                log::error!("Event handler stopped: {}", e);
+                SHUTDOWN.store(true, std::sync::atomic::Ordering::Relaxed);
+                return Err(e);
+            }
+            let delay = RESTART_DELAY * 2u32.pow(restarts);
+            restarts += 1;
+            log::error!(
+                "Event handler stopped: {}, restarting in {:?} ({}/{})",
+                e,
+                delay,
+                restarts,
+                MAX_RESTARTS
+            );
+            let deadline = std::time::Instant::now() + delay;
+            while std::time::Instant::now() < deadline {
+                if SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed) {
+                    return Err(e);
+                }
+                thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+    })
+}
+
+fn forced_display_server(app: &App) -> Option<DisplayServer> {
+    if app.wayland {
+        log::info!("Forced Wayland mode via command line");
+        Some(DisplayServer::Wayland)
+    } else if app.x11 {
+        log::info!("Forced X11 mode via command line");
+        Some(DisplayServer::X11)
+    } else {
+        None
+    }
+}
+
+/// Detect and publish the session, `--master-pointer` picks the uinput backend
+fn detect_stack(app: &App) -> Result<&'static Stack> {
+    let backend = match (&app.master_pointer, app.backend) {
+        (None, backend) => backend,
+        (Some(_), None | Some(Backend::Uinput)) => Some(Backend::Uinput),
+        (Some(_), Some(backend)) => {
+            return Err(miette::miette!(
+                "--master-pointer needs the uinput backend, not {}",
+                backend
+            ))
+        }
+    };
+    let stack = Stack::detect(forced_display_server(app), backend).publish();
+    if app.master_pointer.is_some() && stack.display_server != DisplayServer::X11 {
+        return Err(miette::miette!("--master-pointer is only supported on X11"));
+    }
+    Ok(stack)
+}
+
+/// `gestures doctor`: detect the session like `start` and probe the pointer backend it picks
+fn doctor(app: &App) -> Result<()> {
+    session_env::discover(std::time::Duration::ZERO);
+    let stack = detect_stack(app)?;
+    if !app.json {
+        println!("session {}", stack);
+    }
+    let mut mh = start_handler(stack.backend, None);
+    let check = mh.probe();
+    let config = Config::read_from_optional_path(app.conf.as_deref()).unwrap_or_default();
+    let conflicts = conflicts::find(&config);
+    if app.json {
+        let conflicts = conflicts.iter().map(|c| {
+            Json::object([
+                ("handler", Json::from(c.handler.name)),
+                (
+                    "fingers",
+                    Json::from(
+                        c.fingers
+                            .iter()
+                            .map(|&f| Json::Number(f64::from(f)))
+                            .collect::<Vec<_>>(),
+                    ),
+                ),
+                ("hint", Json::from(c.handler.hint)),
+            ])
+        });
+        println!(
+            "{}",
+            Json::object([
+                ("session", stack.to_json()),
+                ("pointer", status::output_check_json(mh.backend(), &check)),
+                ("conflicts", Json::from(conflicts.collect::<Vec<_>>())),
+            ])
+        );
+    } else {
+        println!(
+            "pointer {}",
+            status::describe_output_check(mh.backend(), &check)
+        );
+        for conflict in &conflicts {
+            println!("conflict {}", conflict.describe());
+        }
+    }
+    check.map_err(|e| miette::miette!("Pointer backend {} does not work: {}", mh.backend(), e))
 }
 
-fn spawn_event_handler(config: Arc<RwLock<Config>>, is_wayland: bool) -> JoinHandle<Result<()>> {
+fn spawn_event_handler(
+    config: Arc<RwLock<Config>>,
+    source: InputSource,
+    backend: Backend,
+    realtime: Option<Realtime>,
+) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        log::debug!("Starting event handler in new thread");
-        let mut eh = event_handler::EventHandler::new(config);
-        let mut interface = input::Libinput::new_with_udev(event_handler::Interface);
-        eh.init(&mut interface)?;
-        eh.main_loop(&mut interface, &mut start_handler(!is_wayland))?;
-        Ok(())
+        log::debug!("Starting event handler for {} in new thread", source);
+        if let Some(rt) = realtime {
+            rt.apply("Event handler");
+        }
+        ipc::devices_acquired();
+        let result = run_event_handler(config, &source, backend, realtime);
+        ipc::devices_released();
+        result
     })
 }
 
+/// Event loop of one seat or device list, its libinput context is dropped when this returns
+fn run_event_handler(
+    config: Arc<RwLock<Config>>,
+    source: &InputSource,
+    backend: Backend,
+    realtime: Option<Realtime>,
+) -> Result<()> {
+    let mut eh = event_handler::EventHandler::new(config);
+    let mut interface = source.libinput();
+    eh.init(&mut interface, source)?;
+    let mut mh = start_handler(backend, realtime);
+    let check = mh.probe();
+    match &check {
+        Ok(()) => log::info!("Pointer backend {} passed its self-test", mh.backend()),
+        Err(e) => log::error!(
+            "Pointer backend {} failed its self-test, drag and zoom won't work: {}",
+            mh.backend(),
+            e
+        ),
+    }
+    status::output_checked(mh.backend(), &check);
+    eh.main_loop(&mut interface, &mut mh)
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct App {
@@ -329,9 +708,37 @@ struct App {
     /// Force X11 mode (default: auto-detect)
     #[arg(short = 'x', long, conflicts_with = "wayland")]
     x11: bool,
+    /// Pointer backend for drag and zoom (default: auto-detect, falling back to uinput)
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    /// Drag with a master pointer of this name (X11 MPX), created when missing, instead of the
+    /// cursor a mouse moves; uses the uinput backend
+    #[arg(long, value_name = "NAME")]
+    master_pointer: Option<String>,
     /// Path to config file
     #[arg(short, long, value_name = "FILE")]
     conf: Option<PathBuf>,
+    /// Run the event and mouse threads with realtime priority (1-99, needs CAP_SYS_NICE)
+    #[arg(long, value_name = "PRIORITY", value_parser = clap::value_parser!(i32).range(1..=99))]
+    realtime_priority: Option<i32>,
+    /// Realtime scheduling policy used with --realtime-priority
+    #[arg(long, value_enum, default_value_t = RealtimePolicy::Fifo, requires = "realtime_priority")]
+    realtime_policy: RealtimePolicy,
+    /// Log event loop wakeups, dispatch batches and time per subsystem every minute and on exit
+    #[arg(long)]
+    profile_wakeups: bool,
+    /// Also accept IPC commands over TCP on this address (needs --remote-token-file)
+    #[arg(long, value_name = "ADDR")]
+    remote_listen: Option<SocketAddr>,
+    /// Send reload/status/trace/history/logs/profile/waybar and the config commands to the daemon at this address instead of the local one
+    #[arg(long, value_name = "ADDR", conflicts_with = "remote_listen")]
+    remote: Option<SocketAddr>,
+    /// File holding the shared token for --remote-listen and --remote
+    #[arg(long, value_name = "FILE")]
+    remote_token_file: Option<PathBuf>,
+    /// Print the output of status and doctor as a single JSON object
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -340,13 +747,91 @@ struct App {
 pub enum Commands {
     /// Reload the configuration
     Reload,
+    /// Print recent gesture handling decisions of the running daemon
+    Trace,
+    /// Print the running daemon's last gestures, the rule each fired and its command's exit status
+    History,
+    /// Stream the running daemon's gestures, matched rules and commands as they happen
+    Monitor,
+    /// Print version, uptime, session, config files, loaded rules and last gesture of the running daemon
+    Status,
+    /// Print the daemon's recent log records
+    Logs {
+        /// Keep streaming new records as they are logged
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Force a profile on or off, or switch to it alone, in the running daemon regardless of its
+    /// conditions
+    Profile {
+        /// Profile name as in the config, `auto` alone undoes every override
+        name: String,
+        /// `auto` lets the profile's conditions decide again, without a state the profile is
+        /// switched on and all others off
+        #[arg(value_parser = ["on", "off", "auto"])]
+        state: Option<String>,
+    },
+    /// Print the gesture types and properties the running daemon accepts as JSON, for GUI configurators
+    Schema,
+    /// Check a config file with the running daemon's parser, printing the errors as JSON
+    Validate {
+        /// Candidate config file
+        file: PathBuf,
+    },
+    /// Try a config file in the running daemon without saving it, the previous config comes back
+    /// afterwards unless the daemon reloads first
+    ApplyTemporary {
+        /// Candidate config file
+        file: PathBuf,
+        /// Seconds until the previous config is restored
+        #[arg(short = 't', long, default_value_t = 30)]
+        seconds: u64,
+    },
+    /// Follow the daemon's active profiles and last gesture as a waybar custom module (JSON lines)
+    Waybar,
+    /// Detect the session and check that the pointer backend can inject events, without starting
+    Doctor,
+    /// Check the config files for parse errors, rules defined twice and unknown variables
+    Check {
+        /// Print the parsed rules of each file as a normalized table instead, to compare how a
+        /// config parses across versions
+        #[arg(long)]
+        table: bool,
+    },
     /// Start the program
-    Start,
+    Start {
+        /// Take over from a running instance: it finishes in-flight gestures, releases the
+        /// devices and hands over its IPC socket before exiting
+        #[arg(long)]
+        replace: bool,
+        /// Reload the configuration whenever its file changes (also enabled by `watch true`)
+        #[arg(long)]
+        watch_config: bool,
+        /// Log the commands, keys and compositor requests of matching rules instead of running
+        /// them, to try a config safely
+        #[arg(long)]
+        dry_run: bool,
+        /// What to do when the event thread stops with an error, e.g. the touchpad went away
+        #[arg(long, value_enum, default_value_t = OnFailure::Exit)]
+        on_failure: OnFailure,
+        /// libinput seat to read gestures from, repeat it to run one event handler per seat;
+        /// overrides `seat` of the config, `seat0` without either
+        #[arg(long)]
+        seat: Vec<String>,
+        /// Read only this device node (e.g. /dev/input/event5) without udev, repeat it for more
+        /// devices; replaces the seats, hotplugged devices aren't picked up
+        #[arg(long)]
+        device: Vec<PathBuf>,
+    },
     /// Install systemd user service
     InstallService {
         /// Print service file to stdout instead of installing
         #[arg(short = 'p', long)]
         print: bool,
+        /// Install (or print) the gestures.socket unit instead, so systemd owns the IPC socket
+        /// and starts the daemon on the first command
+        #[arg(long)]
+        socket: bool,
     },
     /// Generate default configuration file
     GenerateConfig {
@@ -357,4 +842,35 @@ pub enum Commands {
         #[arg(short = 'f', long)]
         force: bool,
     },
+    /// Run a language server for gestures.kdl on stdin/stdout (completions and diagnostics)
+    Lsp,
+    /// Measure swipes and pinches per touchpad and store scaling factors the daemon applies
+    Calibrate {
+        /// Gestures to measure of each kind
+        #[arg(short = 'n', long, default_value_t = 5)]
+        samples: usize,
+        /// Give up on a step after this many seconds
+        #[arg(short = 't', long, default_value_t = 60)]
+        timeout: u64,
+    },
+    /// Feed synthetic gestures through the handler and report dropped updates, queue depth and memory
+    Stress {
+        /// Synthetic gesture events generated per second
+        #[arg(long, default_value_t = 1000)]
+        events_per_sec: u32,
+        /// How long to run, e.g. `30s` or `2m`
+        #[arg(long, default_value = "30s", value_parser = stress::parse_duration)]
+        duration: std::time::Duration,
+        /// Queue commands without executing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Record key and mouse button presses as a macro, replayable with `@macro <name>`
+    RecordMacro {
+        /// Macro name, stored as $XDG_CONFIG_HOME/gestures/macros/<name>.macro
+        name: String,
+        /// Stop recording after this many seconds (Esc stops earlier)
+        #[arg(short = 't', long, default_value_t = 30)]
+        timeout: u64,
+    },
 }
@@ -1,41 +1,356 @@
 use chrono::Duration;
+#[cfg(feature = "x11")]
 use libxdo::XDo;
+use parking_lot::Mutex;
 use std::env;
+use std::io;
+#[cfg(feature = "x11")]
 use std::os::unix::fs::MetadataExt;
+#[cfg(feature = "x11")]
 use std::path::Path;
 use std::process::Command;
 use std::sync::mpsc;
 use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, OnceLock};
 use std::thread;
 use std::time::Duration as StdDuration;
 use std::time::Instant;
 use timer::Timer;
 
-fn current_uid() -> Option<u32> {
-    std::fs::metadata("/proc/self").ok().map(|m| m.uid())
-}
+use crate::compositor::Backend;
+use crate::event_handler::MouseActions;
+use crate::gestures::swipe::{DropZone, Swipe};
+use crate::idle_inhibit::IdleInhibitor;
+use crate::json::Json;
+#[cfg(feature = "x11")]
+use crate::platform::current_uid;
+use crate::realtime::Realtime;
+use crate::snap::SnapTarget;
+#[cfg(feature = "x11")]
+use crate::snap::Snapper;
+use crate::uinput::UinputPointer;
+use crate::utils::{exec_command_from_string, CommandVars, SpawnOptions};
+use crate::virtual_pointer::{VirtualPointer, SUBPIXEL_STEPS};
+use crate::ydotoold::YdotooldPointer;
 
 #[derive(Copy, Clone)]
 pub enum MouseCommand {
     MouseUp,
     MouseDown,
     MoveMouseRelative,
+    /// Relative move in `1 / SUBPIXEL_STEPS` of a pixel, only sent to a virtual pointer worker
+    MoveMouseFine,
+    /// Ctrl + wheel, param1 is the signed number of notches (positive zooms in)
+    Zoom,
+    /// Tile the active window, param1 is a `SnapTarget`, only handled by the xdo worker
+    Snap,
+    /// Zero-distance move whose outcome is sent back to `MouseHandler::probe`
+    Probe,
+}
+
+/// How long `MouseHandler::probe` waits for the worker
+const PROBE_TIMEOUT: StdDuration = StdDuration::from_secs(2);
+
+/// Where the worker reports the outcome of a `MouseCommand::Probe`
+static PROBE_REPLY: Mutex<Option<mpsc::Sender<Result<(), String>>>> = Mutex::new(None);
+
+fn reply_probe(result: Result<(), String>) {
+    if let Some(tx) = PROBE_REPLY.lock().take() {
+        let _ = tx.send(result);
+    }
 }
 
+/// High-resolution wheel units per wheel notch, as used by REL_WHEEL_HI_RES
+pub const HI_RES_UNITS_PER_NOTCH: i32 = 120;
+
 pub struct MouseHandler {
-    tx: Option<mpsc::SyncSender<(MouseCommand, i32, i32)>>,
+    /// Backend actually in use after fallbacks
+    pointer: Box<dyn PointerBackend>,
+    /// Scheduling of the worker threads, also for the backends failed over to
+    realtime: Option<Realtime>,
     timer: Timer,
     guard: Option<timer::Guard>,
     dropped_move_events: u64,
     last_drop_report: Instant,
+    drop_zones: Vec<DropZone>,
+    idle_inhibit: bool,
+    inhibitor: Arc<Mutex<IdleInhibitor>>,
+    /// High-resolution zoom units not yet emitted as a whole notch
+    zoom_remainder: i32,
+}
+
+/// Query pointer position and screen size from the X server
+#[cfg(feature = "x11")]
+fn pointer_location() -> Option<(i32, i32, i32, i32)> {
+    // SAFETY: the xdo handle is created, used and freed locally, out pointers
+    // reference live stack variables
+    unsafe {
+        let xdo = libxdo_sys::xdo_new(std::ptr::null());
+        if xdo.is_null() {
+            return None;
+        }
+        let (mut x, mut y, mut screen) = (0, 0, 0);
+        let (mut width, mut height) = (0, 0);
+        let ok = libxdo_sys::xdo_get_mouse_location(xdo, &mut x, &mut y, &mut screen) == 0
+            && libxdo_sys::xdo_get_viewport_dimensions(xdo, &mut width, &mut height, screen) == 0;
+        libxdo_sys::xdo_free(xdo);
+        ok.then_some((x, y, width as i32, height as i32))
+    }
+}
+
+#[cfg(not(feature = "x11"))]
+fn pointer_location() -> Option<(i32, i32, i32, i32)> {
+    None
+}
+
+/// Parse `hyprctl cursorpos` output, e.g. `1280, 720`
+pub fn parse_cursor_pos(output: &str) -> Option<(i32, i32)> {
+    let (x, y) = output.trim().split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// Absolute pointer position from the X server, or from Hyprland where Wayland offers no query
+pub fn pointer_position() -> Option<(i32, i32)> {
+    if let Some((x, y, _, _)) = pointer_location() {
+        return Some((x, y));
+    }
+    env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let output = Command::new("hyprctl").arg("cursorpos").output().ok()?;
+    parse_cursor_pos(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Screen as `(x, y, width, height)` from `hyprctl -j monitors`: the focused monitor in the
+/// logical coordinates `hyprctl cursorpos` uses
+pub fn parse_monitors(output: &str) -> Option<(i32, i32, i32, i32)> {
+    let monitors = Json::parse(output).ok()?;
+    let monitor = monitors
+        .as_array()?
+        .iter()
+        .find(|m| m.get("focused") == Some(&Json::Bool(true)))?;
+    let field = |name| monitor.get(name).and_then(Json::as_f64);
+    let scale = field("scale").filter(|s| *s > 0.0).unwrap_or(1.0);
+    Some((
+        field("x")? as i32,
+        field("y")? as i32,
+        (field("width")? / scale) as i32,
+        (field("height")? / scale) as i32,
+    ))
+}
+
+/// Bounds of the screen under the pointer from the X server, or of the focused Hyprland monitor
+pub fn screen_bounds() -> Option<(i32, i32, i32, i32)> {
+    if let Some((_, _, width, height)) = pointer_location() {
+        return Some((0, 0, width, height));
+    }
+    env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let output = Command::new("hyprctl")
+        .args(["-j", "monitors"])
+        .output()
+        .ok()?;
+    parse_monitors(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Monitor in logical coordinates with its scale, for `output-scale "auto"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    /// Screen pixels per logical pixel
+    pub scale: f64,
+    pub focused: bool,
+}
+
+impl Output {
+    pub fn contains(&self, (x, y): (f64, f64)) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// Outputs from `hyprctl -j monitors`, whose sizes are in screen pixels
+pub fn parse_hyprland_outputs(output: &str) -> Vec<Output> {
+    let Ok(Json::Array(monitors)) = Json::parse(output) else {
+        return Vec::new();
+    };
+    monitors
+        .iter()
+        .filter_map(|m| {
+            let field = |name| m.get(name).and_then(Json::as_f64);
+            let scale = field("scale").filter(|s| *s > 0.0).unwrap_or(1.0);
+            Some(Output {
+                name: m.get("name").and_then(Json::as_str)?.to_string(),
+                x: field("x")?,
+                y: field("y")?,
+                width: field("width")? / scale,
+                height: field("height")? / scale,
+                scale,
+                focused: m.get("focused") == Some(&Json::Bool(true)),
+            })
+        })
+        .collect()
+}
+
+/// Active outputs from the Sway `GET_OUTPUTS` reply, whose `rect` is already logical
+pub fn parse_sway_outputs(output: &str) -> Vec<Output> {
+    let Ok(Json::Array(outputs)) = Json::parse(output) else {
+        return Vec::new();
+    };
+    outputs
+        .iter()
+        .filter(|o| o.get("active") != Some(&Json::Bool(false)))
+        .filter_map(|o| {
+            let rect = o.get("rect")?;
+            let field = |name| rect.get(name).and_then(Json::as_f64);
+            Some(Output {
+                name: o.get("name").and_then(Json::as_str)?.to_string(),
+                x: field("x")?,
+                y: field("y")?,
+                width: field("width")?,
+                height: field("height")?,
+                scale: o
+                    .get("scale")
+                    .and_then(Json::as_f64)
+                    .filter(|s| *s > 0.0)
+                    .unwrap_or(1.0),
+                focused: o.get("focused") == Some(&Json::Bool(true)),
+            })
+        })
+        .collect()
+}
+
+/// Outputs of the running Hyprland or Sway session, empty elsewhere (X11 has no per-output scale)
+pub fn outputs() -> Vec<Output> {
+    if env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        return Command::new("hyprctl")
+            .args(["-j", "monitors"])
+            .output()
+            .map(|o| parse_hyprland_outputs(&String::from_utf8_lossy(&o.stdout)))
+            .unwrap_or_default();
+    }
+    match crate::sway::get_outputs() {
+        Ok(reply) => parse_sway_outputs(&reply),
+        Err(e) => {
+            log::debug!("Output scales unavailable: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(feature = "ydotool")]
+fn ydotool(args: &[&str]) {
+    let _ = Command::new("ydotool").args(args).spawn();
+}
+
+#[cfg(not(feature = "ydotool"))]
+fn ydotool(_args: &[&str]) {
+    log::debug!("Built without the ydotool feature, dropping mouse event");
+}
+
+/// Queue ydotool invocations for the one thread running them, so sequences queued one after
+/// another don't interleave. False when the queue is full.
+#[cfg(feature = "ydotool")]
+fn ydotool_sequence(commands: Vec<Vec<String>>) -> bool {
+    const YDOTOOL_QUEUE_SIZE: usize = 16;
+    static QUEUE: OnceLock<mpsc::SyncSender<Vec<Vec<String>>>> = OnceLock::new();
+    let queue = QUEUE.get_or_init(|| {
+        let (tx, rx) = mpsc::sync_channel::<Vec<Vec<String>>>(YDOTOOL_QUEUE_SIZE);
+        thread::spawn(move || {
+            for commands in rx {
+                for args in commands {
+                    let _ = Command::new("ydotool").args(&args).status();
+                }
+            }
+        });
+        tx
+    });
+    queue.try_send(commands).is_ok()
+}
+
+#[cfg(not(feature = "ydotool"))]
+fn ydotool_sequence(_commands: Vec<Vec<String>>) -> bool {
+    log::debug!("Built without the ydotool feature, dropping mouse event");
+    true
+}
+
+/// Ctrl + wheel through ydotool, KEY_LEFTCTRL is evdev code 29. False when it couldn't be queued.
+fn ydotool_zoom(notches: i32) -> bool {
+    ydotool_sequence(vec![
+        vec!["key".into(), "29:1".into()],
+        vec![
+            "mousemove".into(),
+            "--wheel".into(),
+            "-x".into(),
+            "0".into(),
+            "-y".into(),
+            notches.to_string(),
+        ],
+        vec!["key".into(), "29:0".into()],
+    ])
+}
+
+/// `ydotool mousemove` by zero, fails without a reachable ydotoold
+#[cfg(feature = "ydotool")]
+fn probe_ydotool() -> Result<(), String> {
+    let status = Command::new("ydotool")
+        .args(["mousemove", "-x", "0", "-y", "0"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_err(|e| format!("cannot run ydotool: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("ydotool {}, is ydotoold running?", status))
+    }
+}
+
+#[cfg(not(feature = "ydotool"))]
+fn probe_ydotool() -> Result<(), String> {
+    Err("built without the ydotool feature".to_string())
+}
+
+/// Check that the ydotool binary can be found in PATH
+pub fn ydotool_available() -> bool {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join("ydotool").is_file()))
+        .unwrap_or(false)
+}
+
+/// Run the action of the drop zone under the pointer, if any
+fn fire_drop_zone(zones: &[DropZone]) {
+    if zones.is_empty() {
+        return;
+    }
+    let Some((x, y, width, height)) = pointer_location() else {
+        log::debug!("Pointer location unavailable, skipping drop zones");
+        return;
+    };
+    if let Some(zone) = DropZone::find(zones, x, y, width, height) {
+        log::debug!(
+            "Drag released in drop zone {:?} at ({}, {})",
+            zone.edge,
+            x,
+            y
+        );
+        if let Err(e) = exec_command_from_string(
+            &zone.action,
+            &CommandVars::default(),
+            &SpawnOptions::default(),
+        ) {
+            log::error!("Failed to run drop zone action: {}", e);
+        }
+    }
 }
 
 /// Try to setup X11 environment variables by detecting XAUTHORITY file
+#[cfg(feature = "x11")]
 fn setup_x11_env() {
-    // Ensure DISPLAY is set
+    // Ensure DISPLAY is set, session discovery should normally have filled it in
     if env::var("DISPLAY").is_err() {
         env::set_var("DISPLAY", ":0");
-        log::info!("DISPLAY not set, using default :0");
+        log::warn!("DISPLAY not found in session environment, falling back to :0");
     }
 
     // Check if XAUTHORITY is already set and valid
@@ -62,10 +377,7 @@ fn setup_x11_env() {
                     let Ok(metadata) = entry.metadata() else {
                         continue;
                     };
-                    let Some(uid) = current_uid() else {
-                        continue;
-                    };
-                    if metadata.uid() != uid || !path.is_file() {
+                    if metadata.uid() != current_uid() || !path.is_file() {
                         continue;
                     }
                     if let Some(path_str) = path.to_str() {
@@ -90,130 +402,684 @@ fn setup_x11_env() {
     log::warn!("Could not find XAUTHORITY file, X11 initialization may fail");
 }
 
-pub fn start_handler(is_xorg: bool) -> MouseHandler {
-    let tx = if is_xorg {
-        // Setup X11 environment before initializing XDo
-        setup_x11_env();
-
-        const MOUSE_EVENT_QUEUE_SIZE: usize = 64;
-        let (tx, rx) = mpsc::sync_channel(MOUSE_EVENT_QUEUE_SIZE);
-        let (ready_tx, ready_rx) = mpsc::channel();
-
-        thread::spawn(move || match XDo::new(None) {
-            Ok(xdo) => {
-                let _ = ready_tx.send(Ok(()));
-                log::info!("Successfully initialized libxdo for X11");
-                let mut pending: Option<(MouseCommand, i32, i32)> = None;
-                let mut coalesced_move_events: u64 = 0;
-                let mut last_coalesce_report = Instant::now();
-                loop {
-                    let (command, mut param1, mut param2) = if let Some(cmd) = pending.take() {
-                        cmd
-                    } else {
-                        match rx.recv() {
-                            Ok(cmd) => cmd,
-                            Err(_) => break,
+type MouseSender = mpsc::SyncSender<(MouseCommand, i32, i32)>;
+
+/// Queue of the running xdo worker, which `@snap` requests are sent to
+static X11_WORKER: OnceLock<MouseSender> = OnceLock::new();
+
+/// Ask the xdo worker to tile the active window
+pub fn snap(target: SnapTarget) -> Result<(), String> {
+    let tx = X11_WORKER
+        .get()
+        .ok_or("`@snap` needs the X11 (xdo) backend")?;
+    tx.try_send((MouseCommand::Snap, target.to_param(), 0))
+        .map_err(|e| format!("xdo worker unavailable: {e}"))
+}
+
+/// Spawn the libxdo worker thread, returns None if X11 is unavailable
+#[cfg(feature = "x11")]
+fn start_x11_worker(realtime: Option<Realtime>) -> Option<Worker> {
+    // Setup X11 environment before initializing XDo
+    setup_x11_env();
+
+    const MOUSE_EVENT_QUEUE_SIZE: usize = 64;
+    let (tx, rx) = mpsc::sync_channel(MOUSE_EVENT_QUEUE_SIZE);
+    let (ready_tx, ready_rx) = mpsc::channel();
+
+    thread::spawn(move || match XDo::new(None) {
+        Ok(xdo) => {
+            if let Some(rt) = realtime {
+                rt.apply("Mouse worker");
+            }
+            let _ = ready_tx.send(Ok(()));
+            log::info!("Successfully initialized libxdo for X11");
+            let mut snapper: Option<Snapper> = None;
+            let mut pending: Option<(MouseCommand, i32, i32)> = None;
+            let mut coalesced_move_events: u64 = 0;
+            let mut last_coalesce_report = Instant::now();
+            loop {
+                let (command, mut param1, mut param2) = if let Some(cmd) = pending.take() {
+                    cmd
+                } else {
+                    match rx.recv() {
+                        Ok(cmd) => cmd,
+                        Err(_) => break,
+                    }
+                };
+
+                if matches!(command, MouseCommand::MoveMouseRelative) {
+                    while let Ok((next_cmd, next_p1, next_p2)) = rx.try_recv() {
+                        match next_cmd {
+                            MouseCommand::MoveMouseRelative => {
+                                param1 = param1.saturating_add(next_p1);
+                                param2 = param2.saturating_add(next_p2);
+                                coalesced_move_events = coalesced_move_events.saturating_add(1);
+                            }
+                            _ => {
+                                pending = Some((next_cmd, next_p1, next_p2));
+                                break;
+                            }
                         }
-                    };
+                    }
+                }
 
-                    if matches!(command, MouseCommand::MoveMouseRelative) {
-                        while let Ok((next_cmd, next_p1, next_p2)) = rx.try_recv() {
-                            match next_cmd {
-                                MouseCommand::MoveMouseRelative => {
-                                    param1 = param1.saturating_add(next_p1);
-                                    param2 = param2.saturating_add(next_p2);
-                                    coalesced_move_events = coalesced_move_events.saturating_add(1);
-                                }
-                                _ => {
-                                    pending = Some((next_cmd, next_p1, next_p2));
-                                    break;
-                                }
+                let result = match command {
+                    MouseCommand::MouseDown => xdo.mouse_down(param1),
+                    MouseCommand::MouseUp => xdo.mouse_up(param1),
+                    MouseCommand::MoveMouseRelative => xdo.move_mouse_relative(param1, param2),
+                    MouseCommand::MoveMouseFine => {
+                        xdo.move_mouse_relative(param1 / SUBPIXEL_STEPS, param2 / SUBPIXEL_STEPS)
+                    }
+                    MouseCommand::Zoom => {
+                        // Buttons 4/5 are wheel up/down
+                        let button = if param1 > 0 { 4 } else { 5 };
+                        let _ = xdo.send_keysequence_down("ctrl", 0);
+                        for _ in 0..param1.unsigned_abs() {
+                            let _ = xdo.click(button);
+                        }
+                        xdo.send_keysequence_up("ctrl", 0)
+                    }
+                    MouseCommand::Probe => {
+                        reply_probe(xdo.move_mouse_relative(0, 0).map_err(|e| e.to_string()));
+                        Ok(())
+                    }
+                    MouseCommand::Snap => {
+                        if snapper.is_none() {
+                            snapper = Snapper::open()
+                                .map_err(|e| log::error!("@snap unavailable: {}", e))
+                                .ok();
+                        }
+                        if let Some(snapper) = &snapper {
+                            let target = SnapTarget::from_param(param1);
+                            if let Err(e) = snapper.snap(target) {
+                                log::error!("@snap {:?} failed: {}", target, e);
                             }
                         }
+                        Ok(())
                     }
+                };
+                // Only pointer events say the X server is gone, zoom clicks may fail alone
+                if let Err(e) = result {
+                    if !matches!(command, MouseCommand::Zoom) {
+                        log::error!("libxdo stopped working: {}", e);
+                        break;
+                    }
+                }
 
-                    let _ = match command {
-                        MouseCommand::MouseDown => xdo.mouse_down(param1),
-                        MouseCommand::MouseUp => xdo.mouse_up(param1),
-                        MouseCommand::MoveMouseRelative => xdo.move_mouse_relative(param1, param2),
-                    };
+                if log::log_enabled!(log::Level::Debug)
+                    && coalesced_move_events > 0
+                    && last_coalesce_report.elapsed() >= StdDuration::from_secs(10)
+                {
+                    log::debug!(
+                        "x11 move queue stats: coalesced_move_events={}",
+                        coalesced_move_events
+                    );
+                    coalesced_move_events = 0;
+                    last_coalesce_report = Instant::now();
+                }
+            }
+        }
+        Err(e) => {
+            let _ = ready_tx.send(Err(e));
+        }
+    });
+
+    match ready_rx.recv_timeout(StdDuration::from_secs(2)) {
+        Ok(Ok(())) => {
+            let _ = X11_WORKER.set(tx.clone());
+            Some(Worker::new(tx, Backend::Xdo))
+        }
+        Ok(Err(e)) => {
+            log::error!("Failed to initialize libxdo: {:?}", e);
+            log::warn!("Check DISPLAY/XAUTHORITY if X11 mode was intended");
+            None
+        }
+        Err(e) => {
+            log::error!("Timed out waiting for libxdo initialization: {:?}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "x11"))]
+fn start_x11_worker(_realtime: Option<Realtime>) -> Option<Worker> {
+    log::warn!("Built without the x11 feature, falling back to ydotool mouse control mode");
+    None
+}
+
+/// Pointer driven from a worker thread, implemented by the in-process injection backends
+trait PointerDevice: Send + 'static {
+    fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()>;
+    /// Move by `1 / SUBPIXEL_STEPS` of a pixel, devices without sub-pixel motion round down
+    fn fine_motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        self.motion(dx / SUBPIXEL_STEPS, dy / SUBPIXEL_STEPS)
+    }
+    fn button(&mut self, button: i32, pressed: bool) -> io::Result<()>;
+    fn zoom(&mut self, notches: i32) -> io::Result<()>;
+}
+
+impl PointerDevice for VirtualPointer {
+    fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        VirtualPointer::motion(self, dx, dy)
+    }
+
+    fn fine_motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        VirtualPointer::fine_motion(self, dx, dy)
+    }
 
-                    if log::log_enabled!(log::Level::Debug)
-                        && coalesced_move_events > 0
-                        && last_coalesce_report.elapsed() >= StdDuration::from_secs(10)
-                    {
-                        log::debug!(
-                            "x11 move queue stats: coalesced_move_events={}",
-                            coalesced_move_events
-                        );
-                        coalesced_move_events = 0;
-                        last_coalesce_report = Instant::now();
+    fn button(&mut self, button: i32, pressed: bool) -> io::Result<()> {
+        VirtualPointer::button(self, button, pressed)
+    }
+
+    /// The virtual pointer has no keyboard to hold ctrl with
+    fn zoom(&mut self, notches: i32) -> io::Result<()> {
+        if !ydotool_zoom(notches) {
+            log::debug!("ydotool busy, dropping zoom event");
+        }
+        Ok(())
+    }
+}
+
+impl PointerDevice for UinputPointer {
+    fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        UinputPointer::motion(self, dx, dy)
+    }
+
+    fn button(&mut self, button: i32, pressed: bool) -> io::Result<()> {
+        UinputPointer::button(self, button, pressed)
+    }
+
+    fn zoom(&mut self, notches: i32) -> io::Result<()> {
+        UinputPointer::zoom(self, notches)
+    }
+}
+
+impl PointerDevice for YdotooldPointer {
+    fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        YdotooldPointer::motion(self, dx, dy)
+    }
+
+    fn button(&mut self, button: i32, pressed: bool) -> io::Result<()> {
+        YdotooldPointer::button(self, button, pressed)
+    }
+
+    fn zoom(&mut self, notches: i32) -> io::Result<()> {
+        YdotooldPointer::zoom(self, notches)
+    }
+}
+
+/// Feed `device` from a worker thread, merging moves that queued up while it was busy
+fn start_device_worker(
+    mut device: impl PointerDevice,
+    name: &'static str,
+    realtime: Option<Realtime>,
+) -> MouseSender {
+    const MOUSE_EVENT_QUEUE_SIZE: usize = 64;
+    let (tx, rx) = mpsc::sync_channel::<(MouseCommand, i32, i32)>(MOUSE_EVENT_QUEUE_SIZE);
+
+    thread::spawn(move || {
+        if let Some(rt) = realtime {
+            rt.apply("Mouse worker");
+        }
+        let mut pending: Option<(MouseCommand, i32, i32)> = None;
+        loop {
+            let (command, mut param1, mut param2) = if let Some(cmd) = pending.take() {
+                cmd
+            } else {
+                match rx.recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => break,
+                }
+            };
+
+            if matches!(
+                command,
+                MouseCommand::MoveMouseRelative | MouseCommand::MoveMouseFine
+            ) {
+                while let Ok((next_cmd, next_p1, next_p2)) = rx.try_recv() {
+                    if std::mem::discriminant(&next_cmd) == std::mem::discriminant(&command) {
+                        param1 = param1.saturating_add(next_p1);
+                        param2 = param2.saturating_add(next_p2);
+                    } else {
+                        pending = Some((next_cmd, next_p1, next_p2));
+                        break;
                     }
                 }
             }
-            Err(e) => {
-                let _ = ready_tx.send(Err(e));
-            }
-        });
 
-        match ready_rx.recv_timeout(StdDuration::from_secs(2)) {
-            Ok(Ok(())) => Some(tx),
-            Ok(Err(e)) => {
-                log::error!("Failed to initialize libxdo: {:?}", e);
-                log::warn!("Falling back to ydotool mouse control mode");
-                log::warn!("Check DISPLAY/XAUTHORITY if X11 mode was intended");
-                None
-            }
-            Err(e) => {
-                log::error!("Timed out waiting for libxdo initialization: {:?}", e);
-                log::warn!("Falling back to ydotool mouse control mode");
-                None
+            let result = match command {
+                MouseCommand::MouseDown => device.button(param1, true),
+                MouseCommand::MouseUp => device.button(param1, false),
+                MouseCommand::MoveMouseRelative => device.motion(param1, param2),
+                MouseCommand::MoveMouseFine => device.fine_motion(param1, param2),
+                MouseCommand::Zoom => device.zoom(param1),
+                MouseCommand::Snap => Ok(()),
+                MouseCommand::Probe => device
+                    .motion(0, 0)
+                    .inspect(|()| reply_probe(Ok(())))
+                    .inspect_err(|e| reply_probe(Err(e.to_string()))),
+            };
+            if let Err(e) = result {
+                log::error!("{} stopped working: {}", name, e);
+                break;
             }
         }
+    });
+    tx
+}
+
+/// Spawn the virtual pointer worker thread, returns None if the compositor refuses the pointer
+fn start_virtual_pointer_worker(realtime: Option<Realtime>) -> Option<Worker> {
+    match VirtualPointer::connect() {
+        Ok(pointer) => {
+            log::info!("Using native Wayland virtual pointer");
+            let tx = start_device_worker(pointer, "Wayland virtual pointer", realtime);
+            Some(Worker {
+                steps: SUBPIXEL_STEPS,
+                ..Worker::new(tx, Backend::VirtualPointer)
+            })
+        }
+        Err(e) => {
+            log::error!("Failed to create Wayland virtual pointer: {}", e);
+            None
+        }
+    }
+}
+
+/// Spawn the uinput worker thread, returns None without write access to /dev/uinput
+fn start_uinput_worker(realtime: Option<Realtime>) -> Option<Worker> {
+    if !cfg!(feature = "uinput") {
+        return None;
+    }
+    match UinputPointer::create() {
+        Ok(pointer) => {
+            log::info!("Using uinput virtual pointer");
+            crate::mpx::attach_uinput_pointer();
+            let tx = start_device_worker(pointer, "uinput pointer", realtime);
+            Some(Worker::new(tx, Backend::Uinput))
+        }
+        Err(e) => {
+            log::error!("Failed to create uinput pointer: {}", e);
+            None
+        }
+    }
+}
+
+/// Outcome of handing a command to a pointer backend
+#[derive(Debug, PartialEq, Eq)]
+enum Sent {
+    Queued,
+    /// The worker is busy, only for commands that may be dropped
+    Dropped,
+    /// The worker stopped, the next backend has to take over
+    Stopped,
+}
+
+/// Injection backend `MouseHandler` drives, the in-process ones through their worker thread
+trait PointerBackend: Send {
+    /// Backend reported by `gestures status`
+    fn kind(&self) -> Backend;
+    /// Press or release `button`, waiting while the backend is busy
+    fn button(&mut self, button: i32, pressed: bool) -> Sent;
+    fn motion(&mut self, dx: i32, dy: i32) -> Sent;
+    /// Steps of a pixel the backend moves the pointer by
+    fn motion_steps(&self) -> i32 {
+        1
+    }
+    /// Move by `1 / SUBPIXEL_STEPS` of a pixel, backends without sub-pixel motion round down
+    fn fine_motion(&mut self, dx: i32, dy: i32) -> Sent {
+        self.motion(dx / SUBPIXEL_STEPS, dy / SUBPIXEL_STEPS)
+    }
+    /// Ctrl + wheel by `notches`, positive zooms in
+    fn zoom(&mut self, notches: i32) -> Sent;
+    /// Inject a zero-distance move, an error when the backend can't deliver events
+    fn probe(&mut self) -> Result<(), String>;
+    /// Handle to the same backend, for a mouse up sent from the timer thread
+    fn clone_box(&self) -> Box<dyn PointerBackend>;
+}
+
+/// Queue of one of the xdo, virtual pointer, uinput or ydotoold worker threads
+#[derive(Clone)]
+struct Worker {
+    tx: MouseSender,
+    kind: Backend,
+    /// Steps of a pixel its device moves by
+    steps: i32,
+}
+
+impl Worker {
+    fn new(tx: MouseSender, kind: Backend) -> Self {
+        Self { tx, kind, steps: 1 }
+    }
+
+    /// `wait` blocks while the queue is full instead of dropping the command
+    fn send(&self, command: MouseCommand, param1: i32, param2: i32, wait: bool) -> Sent {
+        if wait {
+            return match self.tx.send((command, param1, param2)) {
+                Ok(()) => Sent::Queued,
+                Err(_) => Sent::Stopped,
+            };
+        }
+        match self.tx.try_send((command, param1, param2)) {
+            Ok(()) => Sent::Queued,
+            Err(TrySendError::Full(_)) => Sent::Dropped,
+            Err(TrySendError::Disconnected(_)) => Sent::Stopped,
+        }
+    }
+}
+
+impl PointerBackend for Worker {
+    fn kind(&self) -> Backend {
+        self.kind
+    }
+
+    fn button(&mut self, button: i32, pressed: bool) -> Sent {
+        let command = if pressed {
+            MouseCommand::MouseDown
+        } else {
+            MouseCommand::MouseUp
+        };
+        self.send(command, button, 255, true)
+    }
+
+    fn motion(&mut self, dx: i32, dy: i32) -> Sent {
+        self.send(MouseCommand::MoveMouseRelative, dx, dy, false)
+    }
+
+    fn motion_steps(&self) -> i32 {
+        self.steps
+    }
+
+    fn fine_motion(&mut self, dx: i32, dy: i32) -> Sent {
+        self.send(MouseCommand::MoveMouseFine, dx, dy, false)
+    }
+
+    fn zoom(&mut self, notches: i32) -> Sent {
+        self.send(MouseCommand::Zoom, notches, 0, false)
+    }
+
+    fn probe(&mut self) -> Result<(), String> {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        *PROBE_REPLY.lock() = Some(reply_tx);
+        if self.send(MouseCommand::Probe, 0, 0, true) == Sent::Stopped {
+            return Err("pointer worker stopped".to_string());
+        }
+        reply_rx
+            .recv_timeout(PROBE_TIMEOUT)
+            .map_err(|_| "pointer worker did not answer".to_string())?
+    }
+
+    fn clone_box(&self) -> Box<dyn PointerBackend> {
+        Box::new(self.clone())
+    }
+}
+
+/// The `ydotool` CLI, spawned per event when ydotoold's socket can't be reached
+#[derive(Clone, Copy)]
+struct YdotoolCli;
+
+impl PointerBackend for YdotoolCli {
+    fn kind(&self) -> Backend {
+        Backend::Ydotool
+    }
+
+    /// ydotool's click codes are for the left button, 0x40 presses and 0x80 releases it
+    fn button(&mut self, _button: i32, pressed: bool) -> Sent {
+        ydotool(&["click", "--", if pressed { "0x40" } else { "0x80" }]);
+        Sent::Queued
+    }
+
+    fn motion(&mut self, dx: i32, dy: i32) -> Sent {
+        ydotool(&["mousemove", "-x", &dx.to_string(), "-y", &dy.to_string()]);
+        Sent::Queued
+    }
+
+    fn zoom(&mut self, notches: i32) -> Sent {
+        if ydotool_zoom(notches) {
+            Sent::Queued
+        } else {
+            Sent::Dropped
+        }
+    }
+
+    fn probe(&mut self) -> Result<(), String> {
+        probe_ydotool()
+    }
+
+    fn clone_box(&self) -> Box<dyn PointerBackend> {
+        Box::new(*self)
+    }
+}
+
+/// No backend left, pointer events are discarded
+#[derive(Clone, Copy)]
+struct NoPointer;
+
+impl PointerBackend for NoPointer {
+    fn kind(&self) -> Backend {
+        Backend::None
+    }
+
+    fn button(&mut self, _button: i32, _pressed: bool) -> Sent {
+        Sent::Queued
+    }
+
+    fn motion(&mut self, _dx: i32, _dy: i32) -> Sent {
+        Sent::Queued
+    }
+
+    fn zoom(&mut self, _notches: i32) -> Sent {
+        Sent::Queued
+    }
+
+    fn probe(&mut self) -> Result<(), String> {
+        Err("no pointer backend available".to_string())
+    }
+
+    fn clone_box(&self) -> Box<dyn PointerBackend> {
+        Box::new(*self)
+    }
+}
+
+/// Backend after `failed`, which didn't initialize or whose worker stopped: uinput for the
+/// in-process backends, then ydotool through ydotoold's socket or its CLI
+fn fall_back(failed: Backend, realtime: Option<Realtime>) -> Box<dyn PointerBackend> {
+    if matches!(failed, Backend::Xdo | Backend::VirtualPointer) {
+        log::warn!("Falling back to the uinput backend");
+        if let Some(worker) = start_uinput_worker(realtime) {
+            return Box::new(worker);
+        }
+    }
+    if let Some(worker) = start_ydotoold_worker(realtime) {
+        return Box::new(worker);
+    }
+    if !cfg!(feature = "ydotool") {
+        log::warn!("Built without the ydotool feature, 3-finger drag is unavailable");
+        Box::new(NoPointer)
+    } else if !ydotool_available() {
+        log::warn!("ydotool not found in PATH, 3-finger drag will not work");
+        Box::new(NoPointer)
     } else {
-        None
+        Box::new(YdotoolCli)
+    }
+}
+
+/// Spawn a worker writing to ydotoold's socket, returns None when the daemon can't be reached
+/// and the `ydotool` CLI is left
+fn start_ydotoold_worker(realtime: Option<Realtime>) -> Option<Worker> {
+    if !cfg!(feature = "ydotool") {
+        return None;
+    }
+    match YdotooldPointer::connect() {
+        Ok(pointer) => {
+            log::info!("Using ydotoold at {}", pointer.path().display());
+            let tx = start_device_worker(pointer, "ydotoold socket", realtime);
+            Some(Worker::new(tx, Backend::Ydotool))
+        }
+        Err(e) => {
+            log::warn!("Cannot reach ydotoold, spawning ydotool instead: {}", e);
+            None
+        }
+    }
+}
+
+pub fn start_handler(backend: Backend, realtime: Option<Realtime>) -> MouseHandler {
+    let worker = match backend {
+        Backend::Xdo => start_x11_worker(realtime),
+        Backend::VirtualPointer => start_virtual_pointer_worker(realtime),
+        Backend::Uinput | Backend::None => start_uinput_worker(realtime),
+        Backend::Ydotool => None,
     };
+    let pointer = match worker {
+        Some(worker) => Box::new(worker) as Box<dyn PointerBackend>,
+        None => fall_back(backend, realtime),
+    };
+    MouseHandler::new(pointer, realtime, IdleInhibitor::default())
+}
 
-    MouseHandler {
-        tx,
-        timer: Timer::new(),
-        guard: None,
-        dropped_move_events: 0,
-        last_drop_report: Instant::now(),
+impl MouseActions for MouseHandler {
+    fn configure_drag(&mut self, drag: Option<&Swipe>) {
+        MouseHandler::configure_drag(self, drag);
+    }
+
+    fn mouse_down(&mut self, button: i32) {
+        MouseHandler::mouse_down(self, button);
+    }
+
+    fn mouse_up_delay(&mut self, button: i32, delay_ms: i64) {
+        MouseHandler::mouse_up_delay(self, button, delay_ms);
+    }
+
+    fn move_mouse_relative(&mut self, x_val: i32, y_val: i32) {
+        MouseHandler::move_mouse_relative(self, x_val, y_val);
+    }
+
+    fn motion_steps(&mut self) -> i32 {
+        MouseHandler::motion_steps(self)
+    }
+
+    fn move_mouse_steps(&mut self, x_val: i32, y_val: i32) {
+        MouseHandler::move_mouse_steps(self, x_val, y_val);
+    }
+
+    fn zoom(&mut self, units: i32) {
+        MouseHandler::zoom(self, units);
+    }
+
+    fn pointer_position(&mut self) -> Option<(i32, i32)> {
+        pointer_position()
+    }
+
+    fn screen_bounds(&mut self) -> Option<(i32, i32, i32, i32)> {
+        screen_bounds()
+    }
+
+    fn outputs(&mut self) -> Vec<Output> {
+        outputs()
     }
 }
 
 impl MouseHandler {
+    fn new(
+        pointer: Box<dyn PointerBackend>,
+        realtime: Option<Realtime>,
+        inhibitor: IdleInhibitor,
+    ) -> Self {
+        MouseHandler {
+            pointer,
+            realtime,
+            timer: Timer::new(),
+            guard: None,
+            dropped_move_events: 0,
+            last_drop_report: Instant::now(),
+            drop_zones: Vec::new(),
+            idle_inhibit: false,
+            inhibitor: Arc::new(Mutex::new(inhibitor)),
+            zoom_remainder: 0,
+        }
+    }
+
+    /// Handler without a pointer backend whose drags hold `inhibitor`
+    #[cfg(test)]
+    pub fn detached(inhibitor: IdleInhibitor) -> Self {
+        Self::new(Box::new(NoPointer), None, inhibitor)
+    }
+
+    #[cfg(test)]
+    pub fn inhibitor_held(&self) -> bool {
+        self.inhibitor.lock().is_held()
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.pointer.kind()
+    }
+
+    /// Hand a command to the backend. A backend whose worker stopped is replaced by the next
+    /// one, which gets the command
+    fn send(&mut self, command: impl Fn(&mut dyn PointerBackend) -> Sent) -> Sent {
+        loop {
+            match command(self.pointer.as_mut()) {
+                Sent::Stopped => self.fail_over(),
+                sent => return sent,
+            }
+        }
+    }
+
+    /// Switch to the next backend after the worker of the current one stopped, `gestures status`
+    /// then reports the new one
+    fn fail_over(&mut self) {
+        let failed = self.pointer.kind();
+        log::error!("Pointer backend {} stopped working", failed);
+        self.pointer = fall_back(failed, self.realtime);
+        let backend = self.pointer.kind();
+        let check = self.probe();
+        match &check {
+            Ok(()) => log::warn!("Switched to the {} pointer backend", backend),
+            Err(e) => log::error!("Switched to the {} pointer backend: {}", backend, e),
+        }
+        crate::status::output_checked(backend, &check);
+    }
+
+    /// Inject a zero-distance move through the backend in use, so a backend that initialized but
+    /// can't deliver events shows up at startup rather than on the first drag
+    pub fn probe(&mut self) -> Result<(), String> {
+        self.pointer.probe()
+    }
+
     pub fn mouse_down(&mut self, button: i32) {
         self.cancel_timer_if_present();
-        if let Some(ref tx) = self.tx {
-            let _ = tx.send((MouseCommand::MouseDown, button, 255));
-        } else {
-            let _ = Command::new("ydotool")
-                .args(["click", "--", "0x40"])
-                .spawn();
+        if self.idle_inhibit {
+            self.inhibitor.lock().acquire();
         }
+        self.send(|pointer| pointer.button(button, true));
+    }
+
+    /// Apply per-drag options of the matched rule, `None` clears them before a cancel
+    pub fn configure_drag(&mut self, drag: Option<&Swipe>) {
+        let Some(drag) = drag else {
+            self.drop_zones.clear();
+            self.inhibitor.lock().release();
+            return;
+        };
+
+        self.idle_inhibit = drag.idle_inhibit.unwrap_or(false);
+        // Drop zones are checked when the drag is released (X11 only)
+        self.drop_zones = drag.drop_zones.clone();
     }
 
+    /// Release `button` after `delay_ms`, and with it the idle inhibitor of the drag
     pub fn mouse_up_delay(&mut self, button: i32, delay_ms: i64) {
-        if let Some(ref tx) = self.tx {
-            let tx_clone = tx.clone();
-            self.guard = Some(self.timer.schedule_with_delay(
-                Duration::milliseconds(delay_ms),
-                move || {
-                    let _ = tx_clone.send((MouseCommand::MouseUp, button, 255));
-                },
-            ));
-        } else {
-            self.guard = Some(self.timer.schedule_with_delay(
-                Duration::milliseconds(delay_ms),
-                move || {
-                    let _ = Command::new("ydotool")
-                        .args(["click", "--", "0x80"])
-                        .spawn();
-                },
-            ));
-        }
+        let inhibitor = self.inhibitor.clone();
+        let mut pointer = self.pointer.clone_box();
+        let zones = std::mem::take(&mut self.drop_zones);
+        self.guard = Some(self.timer.schedule_with_delay(
+            Duration::milliseconds(delay_ms),
+            move || {
+                fire_drop_zone(&zones);
+                pointer.button(button, false);
+                inhibitor.lock().release();
+            },
+        ));
     }
 
     pub fn move_mouse_relative(&mut self, x_val: i32, y_val: i32) {
@@ -222,33 +1088,57 @@ impl MouseHandler {
         }
 
         self.cancel_timer_if_present();
-        if let Some(ref tx) = self.tx {
-            match tx.try_send((MouseCommand::MoveMouseRelative, x_val, y_val)) {
-                Ok(()) => {}
-                Err(TrySendError::Full(_)) => {
-                    self.dropped_move_events = self.dropped_move_events.saturating_add(1);
-                    self.maybe_report_drop_stats();
-                }
-                Err(TrySendError::Disconnected(_)) => {
-                    log::warn!("Mouse worker disconnected, dropping move event");
-                }
-            }
+        if self.send(|pointer| pointer.motion(x_val, y_val)) == Sent::Dropped {
+            self.dropped_move_events = self.dropped_move_events.saturating_add(1);
+            self.maybe_report_drop_stats();
+        }
+    }
+
+    /// Steps of a pixel the backend moves the pointer by, only the virtual pointer moves by less
+    /// than a whole pixel
+    pub fn motion_steps(&self) -> i32 {
+        self.pointer.motion_steps()
+    }
+
+    /// Move by `x_val`/`y_val` in `1 / motion_steps()` of a pixel
+    pub fn move_mouse_steps(&mut self, x_val: i32, y_val: i32) {
+        if self.motion_steps() == 1 {
+            return self.move_mouse_relative(x_val, y_val);
+        }
+        if x_val == 0 && y_val == 0 {
+            return;
+        }
+
+        self.cancel_timer_if_present();
+        // A backend failed over to without sub-pixel motion rounds down
+        if self.send(|pointer| pointer.fine_motion(x_val, y_val)) == Sent::Dropped {
+            self.dropped_move_events = self.dropped_move_events.saturating_add(1);
+            self.maybe_report_drop_stats();
+        }
+    }
+
+    /// Zoom by ctrl + wheel, `units` are high-resolution wheel units (positive zooms in).
+    /// Backends emit whole notches, the rest is carried over to the next call.
+    pub fn zoom(&mut self, units: i32) {
+        self.zoom_remainder = self.zoom_remainder.saturating_add(units);
+        let notches = self.zoom_remainder / HI_RES_UNITS_PER_NOTCH;
+        if notches == 0 {
+            return;
+        }
+
+        // Notches that couldn't be queued stay in the remainder for the next call
+        if self.send(|pointer| pointer.zoom(notches)) == Sent::Queued {
+            self.zoom_remainder -= notches * HI_RES_UNITS_PER_NOTCH;
         } else {
-            let _ = Command::new("ydotool")
-                .args([
-                    "mousemove",
-                    "-x",
-                    &x_val.to_string(),
-                    "-y",
-                    &y_val.to_string(),
-                ])
-                .spawn();
+            log::debug!("Mouse worker busy, deferring {} zoom notches", notches);
         }
     }
 
+    /// Drop a pending mouse up, which would have released the idle inhibitor. A drag that
+    /// resumes takes it again in `mouse_down`
     fn cancel_timer_if_present(&mut self) {
-        if self.guard.is_some() {
-            self.guard = None;
+        if self.guard.take().is_some() {
+            self.inhibitor.lock().release();
         }
     }
 
@@ -0,0 +1,94 @@
+//! X11 multi-pointer (MPX): the uinput pointer moved to a master pointer of its own, so gesture
+//! drags get their own cursor instead of fighting a mouse moving the core pointer
+//!
+//! Uses the `xinput` tool, the X server attaches new devices to the core pointer first.
+
+use std::process::Command;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::uinput::POINTER_NAME;
+
+/// The X server lists a new uinput device only once udev announced it
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(3);
+const ATTACH_POLL: Duration = Duration::from_millis(100);
+
+/// `--master-pointer`, set once at startup
+static MASTER: OnceLock<String> = OnceLock::new();
+/// Master pointer created by this process, removed again on exit
+static CREATED: OnceLock<String> = OnceLock::new();
+
+pub fn configure(master: String) {
+    let _ = MASTER.set(master);
+}
+
+/// Name given to `xinput create-master` and the master pointer it creates: `Drag` and
+/// `Drag pointer` for either form
+pub fn master_names(name: &str) -> (&str, String) {
+    let base = name.strip_suffix(" pointer").unwrap_or(name);
+    (base, format!("{} pointer", base))
+}
+
+fn xinput(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("xinput")
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run xinput: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Move the uinput pointer to the configured master, creating it when missing. Runs in the
+/// background as the device shows up in X a moment after it was created; a no-op without
+/// `--master-pointer`
+pub fn attach_uinput_pointer() {
+    let Some(master) = MASTER.get() else {
+        return;
+    };
+    let spawned = thread::Builder::new()
+        .name("mpx".to_string())
+        .spawn(move || {
+            if let Err(e) = attach(master) {
+                log::warn!("Could not attach the virtual pointer to {}: {}", master, e);
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn!("Could not start attaching the virtual pointer: {}", e);
+    }
+}
+
+fn attach(master: &str) -> Result<(), String> {
+    let (base, pointer) = master_names(master);
+    let devices = xinput(&["list", "--name-only"])?;
+    if !devices.lines().any(|d| d.trim() == pointer) {
+        xinput(&["create-master", base])?;
+        let _ = CREATED.set(pointer.clone());
+        log::info!("Created master pointer {}", pointer);
+    }
+    let slave = format!("pointer:{}", POINTER_NAME);
+    let deadline = Instant::now() + ATTACH_TIMEOUT;
+    loop {
+        match xinput(&["reattach", &slave, &pointer]) {
+            Ok(_) => {
+                log::info!("Gesture drags move {}", pointer);
+                return Ok(());
+            }
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => thread::sleep(ATTACH_POLL),
+        }
+    }
+}
+
+/// Remove the master pointer this process created, its cursor would stay on screen otherwise
+pub fn cleanup() {
+    let Some(pointer) = CREATED.get() else {
+        return;
+    };
+    match xinput(&["remove-master", pointer]) {
+        Ok(_) => log::info!("Removed master pointer {}", pointer),
+        Err(e) => log::warn!("Could not remove master pointer {}: {}", pointer, e),
+    }
+}
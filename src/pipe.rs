@@ -0,0 +1,149 @@
+//! `pipe` actions: one process per gesture that reads a line per update on its stdin, instead
+//! of a shell spawned for every update
+//!
+//! The process starts when the gesture begins and its stdin is closed when the gesture ends or
+//! is cancelled, so a consumer reads until end of file.
+
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread;
+
+use knuffel::{Decode, DecodeScalar};
+
+use crate::trace::trace_event;
+use crate::utils::{skipped_by_dry_run, CommandVars, SpawnOptions};
+
+/// How update lines are written
+#[derive(DecodeScalar, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PipeFormat {
+    /// `gesture_type=swipe fingers=3 direction=e delta_x=1.50 ...`
+    #[default]
+    KeyValue,
+    /// One JSON object per line
+    Json,
+}
+
+/// Command of a swipe or pinch rule fed the gesture's updates on its stdin
+#[derive(Decode, Debug, Clone, Default, PartialEq)]
+pub struct PipeAction {
+    #[knuffel(property)]
+    pub pipe: Option<String>,
+    #[knuffel(property)]
+    pub pipe_format: Option<PipeFormat>,
+}
+
+impl PipeAction {
+    /// Start the command for a gesture that just began, `None` without `pipe` or when it
+    /// couldn't be started
+    pub fn open(&self, spawn: &SpawnOptions) -> Option<ActionPipe> {
+        let command = self.pipe.as_deref()?;
+        if skipped_by_dry_run(format_args!("pipe {}", command)) {
+            return None;
+        }
+        match spawn_piped(command, spawn) {
+            Ok(mut child) => {
+                trace_event!("pipe started: {}", command);
+                Some(ActionPipe {
+                    command: command.to_string(),
+                    format: self.pipe_format.unwrap_or_default(),
+                    stdin: child.stdin.take(),
+                    child: Some(child),
+                })
+            }
+            Err(e) => {
+                log::error!("Failed to start pipe '{}': {}", command, e);
+                None
+            }
+        }
+    }
+}
+
+fn spawn_piped(command: &str, spawn: &SpawnOptions) -> io::Result<Child> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    spawn.apply(&mut cmd)?;
+    cmd.stdin(Stdio::piped()).spawn()
+}
+
+/// Running `pipe` command of the current gesture, its stdin is closed when dropped
+#[derive(Debug)]
+pub struct ActionPipe {
+    command: String,
+    format: PipeFormat,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+}
+
+impl ActionPipe {
+    /// Write one update, a command that stopped reading is left alone for the rest of the
+    /// gesture
+    pub fn send(&mut self, vars: &CommandVars) {
+        let Some(stdin) = &mut self.stdin else {
+            return;
+        };
+        if let Err(e) = writeln!(stdin, "{}", update_line(self.format, vars)) {
+            log::debug!("Pipe '{}' stopped reading: {}", self.command, e);
+            self.stdin = None;
+        }
+    }
+}
+
+impl Drop for ActionPipe {
+    fn drop(&mut self) {
+        // End of file tells the command the gesture is over, it is reaped once it exits
+        self.stdin = None;
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        let command = std::mem::take(&mut self.command);
+        thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                log::warn!(
+                    "Pipe exited with non-zero status '{}': {:?}",
+                    command,
+                    status
+                )
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to wait for pipe '{}': {}", command, e),
+        });
+    }
+}
+
+/// Line written for an update, names like the `$variables` of commands
+pub fn update_line(format: PipeFormat, vars: &CommandVars) -> String {
+    let fields = [
+        ("gesture_type", vars.gesture_type.to_string()),
+        ("fingers", vars.fingers.to_string()),
+        ("direction", vars.direction.to_string()),
+        ("delta_x", format!("{:.2}", vars.delta_x)),
+        ("delta_y", format!("{:.2}", vars.delta_y)),
+        ("scale", format!("{:.2}", vars.scale)),
+        ("delta_angle", format!("{:.2}", vars.delta_angle)),
+        ("velocity_x", format!("{:.2}", vars.velocity_x)),
+        ("velocity_y", format!("{:.2}", vars.velocity_y)),
+        ("scale_rate", format!("{:.2}", vars.scale_rate)),
+        ("elapsed_ms", vars.elapsed_ms.to_string()),
+    ];
+    let quoted = ["gesture_type", "direction"];
+    match format {
+        PipeFormat::KeyValue => fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(" "),
+        PipeFormat::Json => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| {
+                    if quoted.contains(name) {
+                        format!("\"{}\":\"{}\"", name, value)
+                    } else {
+                        format!("\"{}\":{}", name, value)
+                    }
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! OS specific bits: user ids, runtime directories and the libinput seat
+
+use std::env;
+use std::path::PathBuf;
+
+use miette::Result;
+
+/// Seat passed to libinput's udev backend (libudev-devd provides the same API on the BSDs)
+pub const DEFAULT_SEAT: &str = "seat0";
+
+pub fn current_uid() -> u32 {
+    nix::unistd::getuid().as_raw()
+}
+
+/// Per-user runtime directory used when XDG_RUNTIME_DIR is unset
+fn fallback_runtime_dir(uid: u32) -> PathBuf {
+    if cfg!(target_os = "linux") {
+        PathBuf::from(format!("/run/user/{uid}"))
+    } else {
+        // FreeBSD/DragonFly/OpenBSD with pam_xdg or similar
+        PathBuf::from(format!("/var/run/user/{uid}"))
+    }
+}
+
+pub fn runtime_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let fallback = fallback_runtime_dir(current_uid());
+    if fallback.is_dir() {
+        Ok(fallback)
+    } else {
+        Err(miette::miette!(
+            "XDG_RUNTIME_DIR is unset and fallback runtime dir {} is unavailable",
+            fallback.display()
+        ))
+    }
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(runtime_dir()?.join("gestures.sock"))
+}
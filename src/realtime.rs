@@ -0,0 +1,56 @@
+use clap::ValueEnum;
+
+/// Realtime scheduling policy for latency sensitive threads
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RealtimePolicy {
+    #[default]
+    Fifo,
+    Rr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Realtime {
+    pub policy: RealtimePolicy,
+    pub priority: i32,
+}
+
+impl Realtime {
+    /// Switch the calling thread to realtime scheduling
+    ///
+    /// Failures (usually missing CAP_SYS_NICE or RLIMIT_RTPRIO) are logged and the
+    /// thread keeps running with normal priority.
+    pub fn apply(self, thread: &str) {
+        let policy = match self.policy {
+            RealtimePolicy::Fifo => libc::SCHED_FIFO,
+            RealtimePolicy::Rr => libc::SCHED_RR,
+        };
+        // SAFETY: querying the priority range has no preconditions
+        let (min, max) = unsafe {
+            (
+                libc::sched_get_priority_min(policy),
+                libc::sched_get_priority_max(policy),
+            )
+        };
+        let priority = self.priority.clamp(min, max);
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+
+        // SAFETY: pthread_self() is always a valid handle for the calling thread
+        let ret = unsafe { libc::pthread_setschedparam(libc::pthread_self(), policy, &param) };
+        if ret == 0 {
+            log::info!(
+                "{} thread uses {:?} scheduling with priority {}",
+                thread,
+                self.policy,
+                priority
+            );
+        } else {
+            log::warn!(
+                "Could not enable realtime scheduling for {} thread: {} (needs CAP_SYS_NICE or RLIMIT_RTPRIO)",
+                thread,
+                std::io::Error::from_raw_os_error(ret)
+            );
+        }
+    }
+}
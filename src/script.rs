@@ -0,0 +1,142 @@
+//! `script=` snippets run in-process when a rule fires, for branching on the gesture's
+//! variables without a shell script per gesture
+//!
+//! ```text
+//! if delta_x > 100 { exec("hyprctl dispatch workspace e+1") } else { key("super+Right") }
+//! ```
+//!
+//! Scripts are [Rhai](https://rhai.rs), compiled with the config so syntax errors and unknown
+//! variables are reported there. A run is capped at `MAX_OPERATIONS`, so a loop that doesn't end
+//! stops with an error instead of holding up the gesture.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use rhai::{Engine, EvalAltResult, Scope, AST};
+
+use crate::keys::KeyCombo;
+use crate::utils::CommandVars;
+
+/// Operations a run may take, far more than any branching on the variables needs
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// What a script can do outside itself
+pub trait ScriptHost {
+    /// Run a command like `end`, `$variables` in it are substituted
+    fn exec(&mut self, command: &str);
+    fn key(&mut self, key: &KeyCombo);
+    fn notify(&mut self, message: &str);
+}
+
+/// Call of a script to its host, made once the script stopped
+enum Call {
+    Exec(String),
+    Key(KeyCombo),
+    Notify(String),
+}
+
+thread_local! {
+    /// Calls of the script running on this thread
+    static CALLS: RefCell<Vec<Call>> = const { RefCell::new(Vec::new()) };
+}
+
+fn call(call: Call) {
+    CALLS.with(|calls| calls.borrow_mut().push(call));
+}
+
+static ENGINE: Lazy<Engine> = Lazy::new(|| {
+    let mut engine = Engine::new();
+    engine
+        .set_strict_variables(true)
+        .set_max_operations(MAX_OPERATIONS)
+        .register_fn("exec", |command: &str| {
+            call(Call::Exec(command.to_string()))
+        })
+        .register_fn("notify", |message: &str| {
+            call(Call::Notify(message.to_string()))
+        })
+        .register_fn("key", |key: &str| -> Result<(), Box<EvalAltResult>> {
+            call(Call::Key(key.parse::<KeyCombo>()?));
+            Ok(())
+        });
+    engine
+});
+
+/// Variables set from the gesture, named like the `$variables` of commands
+fn scope(vars: &CommandVars) -> Scope<'static> {
+    let mut scope = Scope::new();
+    scope
+        .push("delta_x", vars.delta_x)
+        .push("delta_y", vars.delta_y)
+        .push("scale", vars.scale)
+        .push("delta_angle", vars.delta_angle)
+        .push("angle", vars.angle)
+        .push("velocity_x", vars.velocity_x)
+        .push("velocity_y", vars.velocity_y)
+        .push("scale_rate", vars.scale_rate)
+        .push("direction", vars.direction.to_string())
+        .push("fingers", i64::from(vars.fingers))
+        .push("gesture_type", vars.gesture_type.to_string())
+        .push("elapsed_ms", vars.elapsed_ms as i64);
+    scope
+}
+
+/// A compiled `script=`
+#[derive(Clone)]
+pub struct Script {
+    source: String,
+    ast: AST,
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ast = ENGINE
+            .compile_with_scope(&scope(&CommandVars::default()), s)
+            .map_err(|e| format!("{} in script `{}`", e, s.trim()))?;
+        Ok(Self {
+            source: s.to_string(),
+            ast,
+        })
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.source.trim())
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Script {}
+
+impl Script {
+    /// Run the script with the gesture's `vars`, stopping at the first error. What it called
+    /// before an error is still done.
+    pub fn run(&self, vars: &CommandVars, host: &mut impl ScriptHost) -> Result<(), String> {
+        CALLS.with(|calls| calls.borrow_mut().clear());
+        let result = ENGINE.run_ast_with_scope(&mut scope(vars), &self.ast);
+        for call in CALLS.with(|calls| calls.take()) {
+            match call {
+                Call::Exec(command) => host.exec(&command),
+                Call::Key(key) => host.key(&key),
+                Call::Notify(message) => host.notify(&message),
+            }
+        }
+        result.map_err(|e| e.to_string())
+    }
+}
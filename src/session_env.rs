@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Variables imported from the graphical session when missing from our own environment
+const SESSION_VARS: [&str; 8] = [
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_SESSION_TYPE",
+    "XDG_CURRENT_DESKTOP",
+    "HYPRLAND_INSTANCE_SIGNATURE",
+    "SWAYSOCK",
+    "NIRI_SOCKET",
+];
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for a graphical session when started by systemd
+pub const SERVICE_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn has_display() -> bool {
+    env::var_os("DISPLAY").is_some() || env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// Returns true when the process was started as a systemd unit
+pub fn is_service() -> bool {
+    env::var_os("INVOCATION_ID").is_some()
+}
+
+/// Parse `KEY=VALUE` lines as printed by `systemctl --user show-environment`
+pub fn parse_environment(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(k, _)| SESSION_VARS.contains(k))
+        .map(|(k, v)| (k.to_string(), v.trim_matches('\'').to_string()))
+        .collect()
+}
+
+/// Parse `Key=Value` lines as printed by `loginctl show-session -p ...`
+fn parse_properties(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+fn from_systemd_user_env() -> HashMap<String, String> {
+    run("systemctl", &["--user", "show-environment"])
+        .map(|s| parse_environment(&s))
+        .unwrap_or_default()
+}
+
+fn find_wayland_socket() -> Option<String> {
+    let runtime_dir = crate::platform::runtime_dir().ok()?;
+    let mut sockets: Vec<String> = std::fs::read_dir(runtime_dir)
+        .ok()?
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| name.starts_with("wayland-") && !name.ends_with(".lock"))
+        .collect();
+    sockets.sort();
+    sockets.into_iter().next()
+}
+
+fn from_logind() -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+
+    let Some(session) = run("loginctl", &["show-user", "--property=Display", "--value"])
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+    else {
+        return vars;
+    };
+
+    let Some(props) = run(
+        "loginctl",
+        &[
+            "show-session",
+            &session,
+            "-p",
+            "Type",
+            "-p",
+            "Display",
+            "-p",
+            "State",
+        ],
+    )
+    .map(|s| parse_properties(&s)) else {
+        return vars;
+    };
+
+    if props.get("State").map(String::as_str) == Some("closing") {
+        return vars;
+    }
+
+    match props.get("Type").map(String::as_str) {
+        Some("x11") => {
+            if let Some(display) = props.get("Display").filter(|d| !d.is_empty()) {
+                vars.insert("DISPLAY".to_string(), display.clone());
+            }
+            vars.insert("XDG_SESSION_TYPE".to_string(), "x11".to_string());
+        }
+        Some("wayland") => {
+            if let Some(socket) = find_wayland_socket() {
+                vars.insert("WAYLAND_DISPLAY".to_string(), socket);
+            }
+            vars.insert("XDG_SESSION_TYPE".to_string(), "wayland".to_string());
+        }
+        _ => {}
+    }
+
+    vars
+}
+
+fn import(vars: HashMap<String, String>) {
+    for (key, value) in vars {
+        if env::var_os(&key).is_none() && !value.is_empty() {
+            log::info!("Imported {}={} from session environment", key, value);
+            env::set_var(key, value);
+        }
+    }
+}
+
+/// Resolve DISPLAY/WAYLAND_DISPLAY/XAUTHORITY from the systemd user manager or the
+/// active logind session, retrying until a graphical session shows up or `timeout` expires.
+pub fn discover(timeout: Duration) {
+    if has_display() {
+        return;
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        import(from_systemd_user_env());
+        if !has_display() {
+            import(from_logind());
+        }
+        if has_display() {
+            return;
+        }
+
+        if Instant::now() >= deadline || crate::SHUTDOWN.load(Ordering::Relaxed) {
+            log::warn!("No graphical session found, display environment is incomplete");
+            return;
+        }
+
+        log::debug!("Waiting for graphical session to come up");
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
@@ -0,0 +1,69 @@
+//! logind session lock notifications, so a drag held while the screen locks is released
+//!
+//! `gdbus monitor` only subscribes to the broadcast signals of login1, which needs no privileges
+//! on the system bus unlike `busctl monitor`.
+
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Locks seen since startup, the event loop compares it with the count it last handled
+static LOCKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn locks() -> u64 {
+    LOCKS.load(Ordering::Relaxed)
+}
+
+/// Record a session lock
+pub fn notify_locked() {
+    LOCKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether a `gdbus monitor` line reports a session being locked: the `Lock` request sent by
+/// `loginctl lock-session` and idle daemons, or a locker setting `LockedHint`
+pub fn is_lock_line(line: &str) -> bool {
+    let Some((path, signal)) = line.split_once(": ") else {
+        return false;
+    };
+    if !path.starts_with("/org/freedesktop/login1/session/") {
+        return false;
+    }
+    signal.starts_with("org.freedesktop.login1.Session.Lock ")
+        || (signal.starts_with("org.freedesktop.DBus.Properties.PropertiesChanged ")
+            && signal.contains("'LockedHint': <true>"))
+}
+
+/// Follow session locks in the background, a no-op without `gdbus`
+pub fn start() {
+    let spawned = std::thread::Builder::new()
+        .name("session-lock".to_string())
+        .spawn(|| {
+            let child = Command::new("gdbus")
+                .args(["monitor", "--system", "--dest", "org.freedesktop.login1"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = match child {
+                Ok(child) => child,
+                Err(e) => {
+                    log::info!("Not following session locks, gdbus unavailable: {}", e);
+                    return;
+                }
+            };
+            let Some(stdout) = child.stdout.take() else {
+                return;
+            };
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if is_lock_line(&line) {
+                    log::debug!("Session locked");
+                    notify_locked();
+                }
+            }
+            let _ = child.wait();
+            log::warn!("Stopped following session locks");
+        });
+    if let Err(e) = spawned {
+        log::warn!("Could not start following session locks: {}", e);
+    }
+}
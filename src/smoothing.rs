@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+use std::time::Instant;
+
+/// Cutoff used when low-pass filtering the derivative
+const DERIVATIVE_CUTOFF: f64 = 1.0;
+
+/// Fallback sample period when two updates share a timestamp
+const MIN_DT: f64 = 1.0 / 1000.0;
+
+fn alpha(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+/// Single axis 1€ filter (Casiez et al.)
+///
+/// Filters strongly when the signal changes slowly and lets fast changes through,
+/// which removes jitter at low speed without adding lag to quick movements.
+#[derive(Debug, Clone, Copy)]
+struct OneEuro {
+    min_cutoff: f64,
+    beta: f64,
+    value: Option<f64>,
+    derivative: f64,
+}
+
+impl OneEuro {
+    fn new(min_cutoff: f64, beta: f64) -> Self {
+        Self {
+            min_cutoff,
+            beta,
+            value: None,
+            derivative: 0.0,
+        }
+    }
+
+    fn filter(&mut self, x: f64, dt: f64) -> f64 {
+        let Some(prev) = self.value else {
+            self.value = Some(x);
+            return x;
+        };
+
+        let d = (x - prev) / dt;
+        let a_d = alpha(DERIVATIVE_CUTOFF, dt);
+        self.derivative = a_d * d + (1.0 - a_d) * self.derivative;
+
+        let cutoff = self.min_cutoff + self.beta * self.derivative.abs();
+        let a = alpha(cutoff, dt);
+        let filtered = a * x + (1.0 - a) * prev;
+        self.value = Some(filtered);
+        filtered
+    }
+}
+
+/// 1€ filter over a stream of 2D drag deltas
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaFilter {
+    x: OneEuro,
+    y: OneEuro,
+    last: Option<Instant>,
+}
+
+impl DeltaFilter {
+    pub fn new(min_cutoff: f64, beta: f64) -> Self {
+        Self {
+            x: OneEuro::new(min_cutoff, beta),
+            y: OneEuro::new(min_cutoff, beta),
+            last: None,
+        }
+    }
+
+    pub fn filter(&mut self, dx: f64, dy: f64) -> (f64, f64) {
+        self.filter_at(dx, dy, Instant::now())
+    }
+
+    pub fn filter_at(&mut self, dx: f64, dy: f64, now: Instant) -> (f64, f64) {
+        let dt = self
+            .last
+            .map_or(MIN_DT, |last| now.duration_since(last).as_secs_f64())
+            .max(MIN_DT);
+        self.last = Some(now);
+        (self.x.filter(dx, dt), self.y.filter(dy, dt))
+    }
+}
@@ -0,0 +1,237 @@
+//! `@snap` built-in action: tile the active X11 window through EWMH client messages, so minimal
+//! window managers get swipe tiling without wmctrl pipelines. Runs on the xdo worker thread.
+
+use std::str::FromStr;
+
+/// Where `@snap` puts the active window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapTarget {
+    /// Left half of the work area
+    Left,
+    /// Right half of the work area
+    Right,
+    /// Maximized through _NET_WM_STATE, so the window manager can restore it
+    Maximize,
+}
+
+impl SnapTarget {
+    /// Encoded as a mouse worker command parameter
+    pub fn to_param(self) -> i32 {
+        match self {
+            Self::Left => 0,
+            Self::Right => 1,
+            Self::Maximize => 2,
+        }
+    }
+
+    #[cfg(feature = "x11")]
+    pub fn from_param(param: i32) -> Self {
+        match param {
+            0 => Self::Left,
+            1 => Self::Right,
+            _ => Self::Maximize,
+        }
+    }
+}
+
+impl FromStr for SnapTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            "maximize" => Ok(Self::Maximize),
+            _ => Err(format!(
+                "unknown snap target `{s}`, expected left, right or maximize"
+            )),
+        }
+    }
+}
+
+/// Rectangle as `(x, y, width, height)`
+#[cfg(feature = "x11")]
+pub type Rect = (i64, i64, i64, i64);
+
+/// Client geometry for a half of `workarea`, `frame` is the decoration size (left, right, top,
+/// bottom) from _NET_FRAME_EXTENTS that the window manager adds around the client
+#[cfg(feature = "x11")]
+pub fn half_geometry(target: SnapTarget, workarea: Rect, frame: (i64, i64, i64, i64)) -> Rect {
+    let (x, y, width, height) = workarea;
+    let (left, right, top, bottom) = frame;
+    let half = width / 2;
+    let x = match target {
+        SnapTarget::Right => x + width - half,
+        SnapTarget::Left | SnapTarget::Maximize => x,
+    };
+    (
+        x,
+        y,
+        (half - left - right).max(1),
+        (height - top - bottom).max(1),
+    )
+}
+
+#[cfg(feature = "x11")]
+pub use self::xlib::Snapper;
+
+#[cfg(feature = "x11")]
+mod xlib {
+    use std::ffi::CString;
+    use std::os::raw::{c_int, c_long, c_uchar, c_ulong};
+
+    use x11::xlib::{
+        AnyPropertyType, ClientMessage, Display, False, SubstructureNotifyMask,
+        SubstructureRedirectMask, True, Window, XClientMessageEvent, XCloseDisplay,
+        XDefaultRootWindow, XEvent, XFlush, XFree, XGetWindowProperty, XInternAtom, XOpenDisplay,
+    };
+
+    use super::{half_geometry, SnapTarget};
+
+    /// NorthWest gravity with x, y, width and height present (_NET_MOVERESIZE_WINDOW flags)
+    const MOVERESIZE_FLAGS: c_long = 1 | (0xf << 8);
+    /// Source indication of requests sent on behalf of the user
+    const SOURCE_USER: c_long = 2 << 12;
+    const NET_WM_STATE_REMOVE: c_long = 0;
+    const NET_WM_STATE_ADD: c_long = 1;
+
+    /// Own display connection of the xdo worker, opened on the first `@snap`
+    pub struct Snapper {
+        display: *mut Display,
+        root: Window,
+    }
+
+    impl Snapper {
+        pub fn open() -> Result<Self, String> {
+            // SAFETY: a null name opens $DISPLAY, the result is checked before use
+            let display = unsafe { XOpenDisplay(std::ptr::null()) };
+            if display.is_null() {
+                return Err("cannot open the X display".to_string());
+            }
+            // SAFETY: `display` is a live connection
+            let root = unsafe { XDefaultRootWindow(display) };
+            Ok(Self { display, root })
+        }
+
+        fn atom(&self, name: &str) -> c_ulong {
+            let name = CString::new(name).unwrap();
+            // SAFETY: `display` is live and `name` NUL terminated
+            unsafe { XInternAtom(self.display, name.as_ptr(), False) }
+        }
+
+        /// 32-bit items of a window property, Xlib hands them out as longs
+        fn property(&self, window: Window, name: &str) -> Option<Vec<c_long>> {
+            let (mut actual_type, mut format) = (0, 0 as c_int);
+            let (mut items, mut remaining) = (0 as c_ulong, 0 as c_ulong);
+            let mut data: *mut c_uchar = std::ptr::null_mut();
+            // SAFETY: out pointers reference live locals, `data` is freed with XFree below
+            let status = unsafe {
+                XGetWindowProperty(
+                    self.display,
+                    window,
+                    self.atom(name),
+                    0,
+                    1024,
+                    False,
+                    AnyPropertyType as c_ulong,
+                    &mut actual_type,
+                    &mut format,
+                    &mut items,
+                    &mut remaining,
+                    &mut data,
+                )
+            };
+            if status != 0 || data.is_null() {
+                return None;
+            }
+            // SAFETY: a format 32 property holds `items` longs at `data`
+            let values = (format == 32).then(|| unsafe {
+                std::slice::from_raw_parts(data as *const c_long, items as usize).to_vec()
+            });
+            // SAFETY: `data` was allocated by Xlib
+            unsafe { XFree(data.cast()) };
+            values.filter(|v| !v.is_empty())
+        }
+
+        /// Send an EWMH client message about `window` to the root window
+        fn send(&self, window: Window, message: &str, data: [c_long; 5]) {
+            let mut event = XClientMessageEvent {
+                type_: ClientMessage,
+                serial: 0,
+                send_event: True,
+                display: self.display,
+                window,
+                message_type: self.atom(message),
+                format: 32,
+                data: Default::default(),
+            };
+            event.data.as_longs_mut().copy_from_slice(&data);
+            let mut event = XEvent {
+                client_message: event,
+            };
+            // SAFETY: `event` is a fully initialized client message
+            unsafe {
+                x11::xlib::XSendEvent(
+                    self.display,
+                    self.root,
+                    False,
+                    SubstructureRedirectMask | SubstructureNotifyMask,
+                    &mut event,
+                );
+            }
+        }
+
+        pub fn snap(&self, target: SnapTarget) -> Result<(), String> {
+            let window = self
+                .property(self.root, "_NET_ACTIVE_WINDOW")
+                .and_then(|w| w.first().copied())
+                .filter(|&w| w != 0)
+                .ok_or("the window manager reports no active window (_NET_ACTIVE_WINDOW)")?
+                as Window;
+            let horz = self.atom("_NET_WM_STATE_MAXIMIZED_HORZ") as c_long;
+            let vert = self.atom("_NET_WM_STATE_MAXIMIZED_VERT") as c_long;
+
+            if target == SnapTarget::Maximize {
+                let state = [NET_WM_STATE_ADD, horz, vert, SOURCE_USER >> 12, 0];
+                self.send(window, "_NET_WM_STATE", state);
+            } else {
+                // _NET_WORKAREA lists x, y, width, height per desktop
+                let desktop = self
+                    .property(self.root, "_NET_CURRENT_DESKTOP")
+                    .map_or(0, |d| d[0].max(0) as usize);
+                let workarea = self
+                    .property(self.root, "_NET_WORKAREA")
+                    .ok_or("the window manager reports no work area (_NET_WORKAREA)")?;
+                let area = workarea
+                    .get(desktop * 4..desktop * 4 + 4)
+                    .or_else(|| workarea.get(..4))
+                    .ok_or("malformed _NET_WORKAREA")?;
+                let frame = self
+                    .property(window, "_NET_FRAME_EXTENTS")
+                    .filter(|f| f.len() == 4)
+                    .map_or((0, 0, 0, 0), |f| (f[0], f[1], f[2], f[3]));
+                let (x, y, width, height) = half_geometry(
+                    target,
+                    (area[0], area[1], area[2], area[3]),
+                    (frame.0, frame.1, frame.2, frame.3),
+                );
+
+                // Window managers ignore geometry requests for maximized windows
+                let state = [NET_WM_STATE_REMOVE, horz, vert, SOURCE_USER >> 12, 0];
+                self.send(window, "_NET_WM_STATE", state);
+                let moveresize = [MOVERESIZE_FLAGS | SOURCE_USER, x, y, width, height];
+                self.send(window, "_NET_MOVERESIZE_WINDOW", moveresize);
+            }
+            // SAFETY: `display` is a live connection
+            unsafe { XFlush(self.display) };
+            Ok(())
+        }
+    }
+
+    impl Drop for Snapper {
+        fn drop(&mut self) {
+            // SAFETY: `display` was opened by XOpenDisplay and is not used afterwards
+            unsafe { XCloseDisplay(self.display) };
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Daemon state published to status bars: `gestures waybar` follows it over the IPC `subscribe`
+//! command
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::compositor::Backend;
+use crate::json::Json;
+
+/// Updates a slow subscriber may lag behind before it is disconnected
+const SUBSCRIBER_QUEUE_CAPACITY: usize = 64;
+
+/// What a bar shows about the running daemon
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DaemonState {
+    /// Active profiles, in config order
+    pub profiles: Vec<String>,
+    /// Human readable description of the last rule that fired, e.g. `4-finger swipe w`
+    pub last_gesture: Option<String>,
+}
+
+impl DaemonState {
+    /// One-line form sent to `subscribe` clients
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            (
+                "profiles",
+                Json::from(
+                    self.profiles
+                        .iter()
+                        .map(|p| Json::from(p.as_str()))
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            (
+                "last_gesture",
+                self.last_gesture.as_deref().map_or(Json::Null, Json::from),
+            ),
+        ])
+    }
+
+    pub fn from_json(json: &Json) -> Self {
+        Self {
+            profiles: json
+                .get("profiles")
+                .and_then(Json::as_array)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|p| p.as_str().map(str::to_string))
+                .collect(),
+            last_gesture: json
+                .get("last_gesture")
+                .and_then(Json::as_str)
+                .map(str::to_string),
+        }
+    }
+}
+
+struct Published {
+    state: DaemonState,
+    /// When `last_gesture` fired, kept out of the state so repeats don't wake subscribers
+    fired_at: Option<Instant>,
+    /// Backend and outcome of the pointer backend self-test
+    output_check: Option<(Backend, Result<(), String>)>,
+    /// Last error the event thread stopped with
+    event_thread: Option<EventThreadFailure>,
+    subscribers: Vec<SyncSender<DaemonState>>,
+}
+
+static STATE: Lazy<Mutex<Published>> = Lazy::new(|| {
+    Mutex::new(Published {
+        state: DaemonState::default(),
+        fired_at: None,
+        output_check: None,
+        event_thread: None,
+        subscribers: Vec::new(),
+    })
+});
+
+fn update(change: impl FnOnce(&mut DaemonState)) {
+    let mut published = STATE.lock();
+    let before = published.state.clone();
+    change(&mut published.state);
+    if published.state == before {
+        return;
+    }
+    let state = published.state.clone();
+    published
+        .subscribers
+        .retain(|tx| tx.try_send(state.clone()).is_ok());
+}
+
+pub fn set_profiles(profiles: &[&str]) {
+    update(|s| s.profiles = profiles.iter().map(|p| p.to_string()).collect());
+}
+
+/// A rule ran its end or action command
+pub fn fired(gesture: String) {
+    STATE.lock().fired_at = Some(Instant::now());
+    update(|s| s.last_gesture = Some(gesture));
+}
+
+/// Last rule that fired and how long ago, for `gestures status`
+pub fn last_gesture() -> Option<(String, Duration)> {
+    let published = STATE.lock();
+    let gesture = published.state.last_gesture.clone()?;
+    Some((gesture, published.fired_at?.elapsed()))
+}
+
+/// `uinput ok` or `uinput failed: <reason>`
+pub fn describe_output_check(backend: Backend, result: &Result<(), String>) -> String {
+    match result {
+        Ok(()) => format!("{} ok", backend),
+        Err(e) => format!("{} failed: {}", backend, e),
+    }
+}
+
+/// `{"backend":"uinput","ok":false,"error":"<reason>"}` for `--json`
+pub fn output_check_json(backend: Backend, result: &Result<(), String>) -> Json {
+    Json::object([
+        ("backend", Json::from(backend.to_string())),
+        ("ok", Json::from(result.is_ok())),
+        (
+            "error",
+            result
+                .as_ref()
+                .err()
+                .map_or(Json::Null, |e| Json::from(e.as_str())),
+        ),
+    ])
+}
+
+/// Record the startup self-test of the pointer backend for `gestures status`
+pub fn output_checked(backend: Backend, result: &Result<(), String>) {
+    STATE.lock().output_check = Some((backend, result.clone()));
+}
+
+pub fn output_check() -> Option<(Backend, Result<(), String>)> {
+    STATE.lock().output_check.clone()
+}
+
+/// Last error the event thread stopped with and how often it was restarted since startup
+#[derive(Debug, Clone)]
+pub struct EventThreadFailure {
+    pub error: String,
+    pub at: Instant,
+    pub restarts: u32,
+}
+
+/// Record that the event thread stopped with `error`, `restarts` counts the restarts so far
+pub fn event_thread_failed(error: String, restarts: u32) {
+    STATE.lock().event_thread = Some(EventThreadFailure {
+        error,
+        at: Instant::now(),
+        restarts,
+    });
+}
+
+pub fn event_thread_failure() -> Option<EventThreadFailure> {
+    STATE.lock().event_thread.clone()
+}
+
+/// `ok`, or `restarted 2 times, last error 12s ago: <error>`
+pub fn describe_event_thread(failure: Option<&EventThreadFailure>) -> String {
+    match failure {
+        None => "ok".to_string(),
+        Some(f) => format!(
+            "restarted {} times, last error {}s ago: {}",
+            f.restarts,
+            f.at.elapsed().as_secs(),
+            f.error
+        ),
+    }
+}
+
+/// Current state plus a receiver for every change from now on
+pub fn subscribe() -> (DaemonState, Receiver<DaemonState>) {
+    let (tx, rx) = sync_channel(SUBSCRIBER_QUEUE_CAPACITY);
+    let mut published = STATE.lock();
+    published.subscribers.push(tx);
+    (published.state.clone(), rx)
+}
+
+/// Waybar custom module line for `state`, `None` when the daemon isn't running
+pub fn waybar(state: Option<&DaemonState>) -> Json {
+    let Some(state) = state else {
+        return Json::object([
+            ("text", Json::from("")),
+            ("alt", Json::from("stopped")),
+            ("class", Json::from("stopped")),
+            ("tooltip", Json::from("gestures is not running")),
+        ]);
+    };
+    let profiles = if state.profiles.is_empty() {
+        "default".to_string()
+    } else {
+        state.profiles.join(", ")
+    };
+    let tooltip = format!(
+        "Profiles: {}\nLast gesture: {}",
+        profiles,
+        state.last_gesture.as_deref().unwrap_or("none")
+    );
+    Json::object([
+        ("text", Json::from(profiles)),
+        ("alt", Json::from("running")),
+        ("class", Json::from("running")),
+        ("tooltip", Json::from(tooltip)),
+    ])
+}
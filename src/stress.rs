@@ -0,0 +1,229 @@
+//! `gestures stress`: drive the event handler with synthetic gestures at a fixed rate and report
+//! dropped updates, command queue depth and memory growth
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use miette::{miette, Result};
+use parking_lot::RwLock;
+
+use crate::config::Config;
+use crate::event_handler::{EventHandler, MouseActions, SyntheticEvent};
+use crate::gestures::swipe::Swipe;
+use crate::mouse_handler::Output;
+use crate::utils::{command_stats, set_dry_run};
+
+/// Updates per generated gesture, roughly a one second swipe at libinput's report rate
+const UPDATES_PER_GESTURE: usize = 60;
+
+/// Counts pointer output instead of moving the real pointer
+#[derive(Debug, Default)]
+struct CountingMouse {
+    moves: usize,
+    clicks: usize,
+    zoom_units: i64,
+}
+
+impl MouseActions for CountingMouse {
+    fn configure_drag(&mut self, _drag: Option<&Swipe>) {}
+
+    fn mouse_down(&mut self, _button: i32) {
+        self.clicks += 1;
+    }
+
+    fn mouse_up_delay(&mut self, _button: i32, _delay_ms: i64) {}
+
+    fn move_mouse_relative(&mut self, _x_val: i32, _y_val: i32) {
+        self.moves += 1;
+    }
+
+    fn zoom(&mut self, units: i32) {
+        self.zoom_units += i64::from(units.abs());
+    }
+
+    fn pointer_position(&mut self) -> Option<(i32, i32)> {
+        None
+    }
+
+    fn screen_bounds(&mut self) -> Option<(i32, i32, i32, i32)> {
+        None
+    }
+
+    fn outputs(&mut self) -> Vec<Output> {
+        Vec::new()
+    }
+}
+
+/// Parse `30s`, `2m`, `500ms` or plain seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .map_or((s, ""), |i| s.split_at(i));
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        _ => return Err(format!("unknown unit `{unit}` in `{s}`, use ms, s or m")),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// The `index`-th event of an endless sequence of swipes and pinches on the configured fingers
+pub fn synthetic_event(
+    index: usize,
+    swipe_fingers: &[i32],
+    pinch_fingers: &[i32],
+) -> SyntheticEvent {
+    let per_gesture = UPDATES_PER_GESTURE + 2;
+    let gesture = index / per_gesture;
+    let step = index % per_gesture;
+    // Alternate swipes and pinches when both are configured
+    let (pinch, nth) = match (swipe_fingers.is_empty(), pinch_fingers.is_empty()) {
+        (false, false) => (gesture % 2 == 1, gesture / 2),
+        (true, false) => (true, gesture),
+        _ => (false, gesture),
+    };
+    let fingers = |list: &[i32], default| {
+        list.get(nth % list.len().max(1))
+            .copied()
+            .unwrap_or(default)
+    };
+    // Progress through the gesture, used for a smooth scale and a direction that rotates per gesture
+    let t = step as f64 / UPDATES_PER_GESTURE as f64;
+    let angle = gesture as f64 * std::f64::consts::FRAC_PI_4;
+
+    match (pinch, step) {
+        (false, 0) => SyntheticEvent::SwipeBegin {
+            fingers: fingers(swipe_fingers, 3),
+        },
+        (false, s) if s == per_gesture - 1 => SyntheticEvent::SwipeEnd,
+        (false, _) => SyntheticEvent::SwipeUpdate {
+            dx: 4.0 * angle.cos(),
+            dy: 4.0 * angle.sin(),
+        },
+        (true, 0) => SyntheticEvent::PinchBegin {
+            fingers: fingers(pinch_fingers, 2),
+        },
+        (true, s) if s == per_gesture - 1 => SyntheticEvent::PinchEnd,
+        (true, _) => SyntheticEvent::PinchUpdate {
+            scale: if gesture % 4 < 2 {
+                1.0 + t
+            } else {
+                1.0 - t / 2.0
+            },
+            angle: 0.0,
+        },
+    }
+}
+
+/// Resident set size of this process in bytes, from /proc/self/statm
+fn rss_bytes() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+fn mib(bytes: Option<u64>) -> String {
+    bytes.map_or_else(
+        || "n/a".to_string(),
+        |b| format!("{:.1}MiB", b as f64 / (1024.0 * 1024.0)),
+    )
+}
+
+pub fn run(config: Config, events_per_sec: u32, duration: Duration, dry_run: bool) -> Result<()> {
+    if events_per_sec == 0 {
+        return Err(miette!("--events-per-sec must be at least 1"));
+    }
+    set_dry_run(dry_run, false);
+    if dry_run {
+        println!("Dry run: commands are queued but not executed");
+    } else {
+        println!("Commands of matching rules run for real, use --dry-run to only queue them");
+    }
+
+    let mut handler = EventHandler::new(Arc::new(RwLock::new(config)));
+    let (swipe_fingers, pinch_fingers) = handler.configured_fingers();
+    if swipe_fingers.is_empty() && pinch_fingers.is_empty() {
+        println!("No swipe or pinch rules are active, only the handler itself is measured");
+    }
+    let mut mouse = CountingMouse::default();
+
+    let interval = Duration::from_secs_f64(1.0 / f64::from(events_per_sec));
+    let rss_start = rss_bytes();
+    let start = Instant::now();
+    let mut next_report = start + Duration::from_secs(1);
+    let mut events = 0usize;
+    let mut behind = Duration::ZERO;
+    let mut slowest = Duration::ZERO;
+
+    while start.elapsed() < duration && !crate::SHUTDOWN.load(std::sync::atomic::Ordering::Relaxed)
+    {
+        let due = start + interval * u32::try_from(events).unwrap_or(u32::MAX);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        } else {
+            behind = behind.max(now - due);
+        }
+
+        let event = synthetic_event(events, &swipe_fingers, &pinch_fingers);
+        let handled = Instant::now();
+        let time_usec = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        handler.handle_synthetic(event, time_usec, &mut mouse)?;
+        slowest = slowest.max(handled.elapsed());
+        events += 1;
+
+        if Instant::now() >= next_report {
+            let stats = command_stats();
+            println!(
+                "{:>4}s events={} queue={} peak={} dropped={} rss={}",
+                start.elapsed().as_secs(),
+                events,
+                stats.pending,
+                stats.peak_pending,
+                stats.dropped_updates,
+                mib(rss_bytes())
+            );
+            next_report += Duration::from_secs(1);
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let stats = command_stats();
+    let rss_end = rss_bytes();
+    println!(
+        "\nEvents:           {} ({:.0}/s achieved)",
+        events,
+        events as f64 / elapsed
+    );
+    println!("Slowest event:    {:.2}ms", slowest.as_secs_f64() * 1000.0);
+    println!("Max lag:          {:.2}ms", behind.as_secs_f64() * 1000.0);
+    println!(
+        "Pointer output:   {} moves, {} drags, {} zoom units",
+        mouse.moves, mouse.clicks, mouse.zoom_units
+    );
+    println!(
+        "Command queue:    peak {}, {} still pending",
+        stats.peak_pending, stats.pending
+    );
+    println!("Dropped updates:  {}", stats.dropped_updates);
+    println!(
+        "Memory (RSS):     {} -> {}{}",
+        mib(rss_start),
+        mib(rss_end),
+        match (rss_start, rss_end) {
+            (Some(a), Some(b)) =>
+                format!(" ({:+.1}MiB)", (b as f64 - a as f64) / (1024.0 * 1024.0)),
+            _ => String::new(),
+        }
+    );
+    Ok(())
+}
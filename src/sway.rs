@@ -0,0 +1,196 @@
+//! `sway=` actions: commands sent over the i3/Sway IPC socket instead of forking `swaymsg` or
+//! `i3-msg` for every swipe
+//!
+//! The connection stays open between commands. Commands that queue up while the compositor is
+//! busy, e.g. continuous updates, are joined with `;` into one `RUN_COMMAND`.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::json::Json;
+use crate::trace::trace_event;
+use crate::utils::{render_command, skipped_by_dry_run, CommandVars};
+
+/// Commands queued while the compositor is slow, further updates are dropped
+const COMMAND_QUEUE_SIZE: usize = 64;
+/// A compositor that stopped answering must not stall later commands for long
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const RUN_COMMAND: u32 = 0;
+const GET_OUTPUTS: u32 = 3;
+
+/// Render `command` (e.g. `workspace next`) with `vars` and queue it, a no-op for `None`
+pub fn run(command: Option<&str>, vars: &CommandVars) {
+    let Some(command) = command.and_then(|c| render_command(c, vars)) else {
+        return;
+    };
+    trace_event!("sway {}", command);
+    if skipped_by_dry_run(format_args!("sway {}", command)) {
+        return;
+    }
+    let Some(tx) = WORKER.get_or_init(start_worker) else {
+        return;
+    };
+    if let Err(mpsc::TrySendError::Full(command)) = tx.try_send(command) {
+        log::debug!("Sway worker busy, dropping `{}`", command);
+    }
+}
+
+/// Magic, payload length and type in native byte order, then the payload
+pub fn encode(kind: u32, payload: &str) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(14 + payload.len());
+    msg.extend_from_slice(MAGIC);
+    msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(payload.as_bytes());
+    msg
+}
+
+/// Read one message, returning its type and payload
+pub fn read_message(stream: &mut impl Read) -> io::Result<(u32, String)> {
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reply without i3-ipc magic",
+        ));
+    }
+    let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    let kind = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, String::from_utf8_lossy(&payload).into_owned()))
+}
+
+/// Errors in a `RUN_COMMAND` reply, one object with `success` per command that ran
+pub fn command_errors(reply: &str) -> Vec<String> {
+    let Ok(json) = Json::parse(reply) else {
+        return vec![format!("malformed reply `{}`", reply)];
+    };
+    json.as_array()
+        .unwrap_or_default()
+        .iter()
+        .filter(|r| r.get("success") != Some(&Json::Bool(true)))
+        .map(|r| {
+            r.get("error")
+                .and_then(Json::as_str)
+                .unwrap_or("command failed")
+                .to_string()
+        })
+        .collect()
+}
+
+/// `$SWAYSOCK`, or `$I3SOCK` under i3
+pub fn socket_path() -> io::Result<PathBuf> {
+    env::var_os("SWAYSOCK")
+        .or_else(|| env::var_os("I3SOCK"))
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SWAYSOCK and I3SOCK are not set"))
+}
+
+/// Run `commands` over `stream` and check the reply
+pub fn run_command(stream: &mut UnixStream, commands: &str) -> io::Result<Vec<String>> {
+    stream.write_all(&encode(RUN_COMMAND, commands))?;
+    let (kind, reply) = read_message(stream)?;
+    if kind != RUN_COMMAND {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected reply type {}", kind),
+        ));
+    }
+    Ok(command_errors(&reply))
+}
+
+/// JSON reply to `GET_OUTPUTS` on a connection of its own
+pub fn get_outputs() -> io::Result<String> {
+    let mut stream = connect()?;
+    stream.write_all(&encode(GET_OUTPUTS, ""))?;
+    let (kind, reply) = read_message(&mut stream)?;
+    if kind != GET_OUTPUTS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected reply type {}", kind),
+        ));
+    }
+    Ok(reply)
+}
+
+fn connect() -> io::Result<UnixStream> {
+    let stream = UnixStream::connect(socket_path()?)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Run `commands` on the open connection, connecting again when the compositor closed it (e.g.
+/// after a restart) before the commands were written
+fn send(connection: &mut Option<UnixStream>, commands: &str) -> io::Result<Vec<String>> {
+    if let Some(stream) = connection {
+        match run_command(stream, commands) {
+            Ok(errors) => return Ok(errors),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                log::debug!("Sway connection lost: {}", e)
+            }
+            Err(e) => {
+                *connection = None;
+                return Err(e);
+            }
+        }
+    }
+    *connection = None;
+    let mut stream = connect()?;
+    let errors = run_command(&mut stream, commands)?;
+    *connection = Some(stream);
+    Ok(errors)
+}
+
+static WORKER: OnceLock<Option<mpsc::SyncSender<String>>> = OnceLock::new();
+
+/// Connects on the first command and again after the compositor went away
+fn start_worker() -> Option<mpsc::SyncSender<String>> {
+    let (tx, rx) = mpsc::sync_channel::<String>(COMMAND_QUEUE_SIZE);
+    let spawned = thread::Builder::new()
+        .name("sway".to_string())
+        .spawn(move || {
+            let mut connection: Option<UnixStream> = None;
+            // Warn once per outage instead of on every update
+            let mut failing = false;
+            while let Ok(first) = rx.recv() {
+                let mut commands = vec![first];
+                commands.extend(rx.try_iter());
+                let commands = commands.join("; ");
+                match send(&mut connection, &commands) {
+                    Ok(errors) => {
+                        failing = false;
+                        for error in errors {
+                            log::warn!("Sway refused `{}`: {}", commands, error);
+                        }
+                    }
+                    Err(e) if failing => log::debug!("Sway command failed: {}", e),
+                    Err(e) => {
+                        log::warn!("Cannot reach Sway/i3: {}", e);
+                        failing = true;
+                    }
+                }
+            }
+        });
+    match spawned {
+        Ok(_) => Some(tx),
+        Err(e) => {
+            log::warn!("Could not start the Sway worker: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! systemd integration: the IPC listener passed by socket activation and `sd_notify` state
+//! updates for units with `Type=notify` and a `WatchdogSec=`
+//!
+//! Both only look at the environment systemd sets up (`LISTEN_FDS`, `NOTIFY_SOCKET`,
+//! `WATCHDOG_USEC`), outside systemd every function here does nothing.
+
+use std::env;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// First descriptor passed by socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+static ACTIVATED: AtomicBool = AtomicBool::new(false);
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+/// Milliseconds after `STARTED` the event loop last went round
+static HEARTBEAT_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of descriptors passed to this process, from `LISTEN_PID` and `LISTEN_FDS`
+pub fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    match (listen_pid.and_then(|p| p.parse::<u32>().ok()), listen_fds) {
+        (Some(listen_pid), Some(fds)) if listen_pid == pid => fds.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// The IPC listener when systemd started the daemon for `gestures.socket`. The variables are
+/// removed so commands the daemon spawns don't take the socket for theirs
+pub fn activated_listener() -> Option<UnixListener> {
+    let fds = passed_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if fds == 0 {
+        return None;
+    }
+    if fds > 1 {
+        log::warn!("systemd passed {} sockets, using the first", fds);
+    }
+    // SAFETY: systemd passes open descriptors starting at 3, owned by this process from now on
+    unsafe {
+        libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    log::info!("Using the IPC socket passed by systemd");
+    ACTIVATED.store(true, Ordering::Relaxed);
+    Some(unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// Whether the IPC socket belongs to systemd, which keeps it after the daemon exits
+pub fn socket_activated() -> bool {
+    ACTIVATED.load(Ordering::Relaxed)
+}
+
+/// Send `state` (e.g. `READY=1`) to the service manager
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    // A leading `@` names a socket in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = addr.and_then(|addr| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    if let Err(e) = sent {
+        log::debug!("sd_notify {} failed: {}", state, e);
+    }
+}
+
+/// Record that the event loop is running, the watchdog only pings while it does
+pub fn heartbeat() {
+    HEARTBEAT_MS.store(STARTED.elapsed().as_millis() as u64, Ordering::Relaxed);
+}
+
+/// `WatchdogSec=` of the unit, when it is meant for this process
+fn watchdog_interval() -> Option<Duration> {
+    let pid = env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Ping the watchdog at half its interval while the event loop keeps going round. An event
+/// loop stuck longer than the interval stops the pings and systemd restarts the daemon
+pub fn start_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    log::info!("systemd watchdog every {:?}", interval);
+    Lazy::force(&STARTED);
+    heartbeat();
+    thread::spawn(move || loop {
+        thread::sleep(interval / 2);
+        if crate::SHUTDOWN.load(Ordering::Relaxed) {
+            break;
+        }
+        let since = STARTED
+            .elapsed()
+            .saturating_sub(Duration::from_millis(HEARTBEAT_MS.load(Ordering::Relaxed)));
+        if since < interval {
+            notify("WATCHDOG=1");
+        } else {
+            log::warn!(
+                "Event loop stalled for {:?}, not pinging the watchdog",
+                since
+            );
+        }
+    });
+}
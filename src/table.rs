@@ -0,0 +1,448 @@
+//! Normalized gesture table of a config, one line per rule, for comparing how a config parses
+//! across versions (`gestures check --table` and the snapshot tests)
+//!
+//! Every rule is written back as a config node with its properties in a fixed order and their
+//! values as a config would give them, unset properties are left out so a property added in a
+//! later version doesn't change the table of a config that doesn't use it.
+
+use std::fmt::{self, Write};
+
+use miette::{miette, Result};
+
+use crate::conditions::Conditions;
+use crate::config::{Config, FingerRemap};
+use crate::contacts::PadEdge;
+use crate::dbus::DbusCall;
+use crate::gestures::hold::{Hold, HoldTrigger};
+use crate::gestures::pinch::{Pinch, PinchDir};
+use crate::gestures::rotate::Rotate;
+use crate::gestures::swipe::{DropZone, Glide, Repeat, ScreenCorner, Swipe, SwipeDir};
+use crate::gestures::Gesture;
+use crate::logs::{RuleLevel, RuleLog};
+use crate::pipe::{PipeAction, PipeFormat};
+use crate::units::{Acceleration, Distance};
+use crate::utils::{Input, SpawnOptions};
+
+/// A config node with the properties that are set
+struct Line(Vec<String>);
+
+impl Line {
+    fn new(node: &str) -> Self {
+        Self(vec![node.to_string()])
+    }
+
+    /// A number or boolean
+    fn value(&mut self, name: &str, value: Option<impl fmt::Display>) {
+        if let Some(value) = value {
+            self.0.push(format!("{}={}", name, value));
+        }
+    }
+
+    /// A string, quoted as in the config
+    fn text(&mut self, name: &str, value: Option<impl fmt::Display>) {
+        if let Some(value) = value {
+            self.0.push(format!("{}={:?}", name, value.to_string()));
+        }
+    }
+
+    /// A distance, a bare number is in libinput units
+    fn distance(&mut self, name: &str, value: Option<&Distance>) {
+        match value {
+            Some(Distance::Units(units)) => self.value(name, Some(units)),
+            distance => self.text(name, distance),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join(" "))
+    }
+}
+
+fn swipe_dir(dir: &SwipeDir) -> &'static str {
+    match dir {
+        SwipeDir::Any => "any",
+        dir => dir.name(),
+    }
+}
+
+fn pinch_dir(dir: &PinchDir) -> &'static str {
+    match dir {
+        PinchDir::Any => "any",
+        dir => dir.name(),
+    }
+}
+
+fn hold_trigger(trigger: HoldTrigger) -> &'static str {
+    match trigger {
+        HoldTrigger::Release => "release",
+        HoldTrigger::Timeout => "timeout",
+    }
+}
+
+fn pad_edge(edge: PadEdge) -> &'static str {
+    match edge {
+        PadEdge::Left => "left",
+        PadEdge::Right => "right",
+        PadEdge::Top => "top",
+        PadEdge::Bottom => "bottom",
+    }
+}
+
+fn screen_corner(corner: ScreenCorner) -> &'static str {
+    match corner {
+        ScreenCorner::TopLeft => "top-left",
+        ScreenCorner::TopRight => "top-right",
+        ScreenCorner::BottomLeft => "bottom-left",
+        ScreenCorner::BottomRight => "bottom-right",
+    }
+}
+
+fn input(input: Input) -> &'static str {
+    match input {
+        Input::Inherit => "inherit",
+        Input::Null => "null",
+    }
+}
+
+fn pipe_format(format: PipeFormat) -> &'static str {
+    match format {
+        PipeFormat::KeyValue => "key-value",
+        PipeFormat::Json => "json",
+    }
+}
+
+fn rule_level(level: RuleLevel) -> &'static str {
+    match level {
+        RuleLevel::Error => "error",
+        RuleLevel::Warn => "warn",
+        RuleLevel::Info => "info",
+        RuleLevel::Debug => "debug",
+        RuleLevel::Trace => "trace",
+    }
+}
+
+fn conditions(line: &mut Line, conditions: &Conditions) {
+    let Conditions {
+        power_profile,
+        not_power_profile,
+        min_displays,
+        max_displays,
+        time,
+        days,
+        app,
+        window_class,
+        window_title,
+        modifiers,
+    } = conditions;
+    line.text("power-profile", power_profile.as_ref());
+    line.text("not-power-profile", not_power_profile.as_ref());
+    line.value("min-displays", min_displays.as_ref());
+    line.value("max-displays", max_displays.as_ref());
+    line.text("time", time.as_ref());
+    line.text("days", days.as_ref());
+    line.text("app", app.as_ref());
+    line.text("window-class", window_class.as_ref());
+    line.text("window-title", window_title.as_ref());
+    line.text("modifiers", modifiers.as_ref());
+}
+
+fn spawn(line: &mut Line, spawn: &SpawnOptions) {
+    let SpawnOptions {
+        cwd,
+        stdin,
+        stdout,
+        stderr,
+        max_concurrent,
+    } = spawn;
+    line.text("cwd", cwd.as_ref());
+    line.text("stdin", stdin.map(input));
+    line.text("stdout", stdout.as_ref());
+    line.text("stderr", stderr.as_ref());
+    line.value("max-concurrent", max_concurrent.as_ref());
+}
+
+fn log(line: &mut Line, log: &RuleLog) {
+    let RuleLog { log, log_label } = log;
+    line.text("log", log.map(rule_level));
+    line.text("log-label", log_label.as_ref());
+}
+
+fn dbus(line: &mut Line, dbus: &DbusCall) {
+    let DbusCall {
+        dbus_dest,
+        dbus_path,
+        dbus_method,
+        dbus_args,
+    } = dbus;
+    line.text("dbus-dest", dbus_dest.as_ref());
+    line.text("dbus-path", dbus_path.as_ref());
+    line.text("dbus-method", dbus_method.as_ref());
+    line.text("dbus-args", dbus_args.as_ref());
+}
+
+fn pipe(line: &mut Line, pipe: &PipeAction) {
+    let PipeAction { pipe, pipe_format } = pipe;
+    line.text("pipe", pipe.as_ref());
+    line.text("pipe-format", pipe_format.map(self::pipe_format));
+}
+
+fn swipe(swipe: &Swipe) -> Vec<Line> {
+    let Swipe {
+        direction,
+        fingers,
+        update,
+        start,
+        end,
+        cancel,
+        repeating,
+        key,
+        script,
+        type_text,
+        paste,
+        hyprland,
+        hyprland_update,
+        sway,
+        sway_update,
+        acceleration,
+        acceleration_curve,
+        smoothing,
+        smoothing_beta,
+        mouse_up_delay,
+        update_rate,
+        glide,
+        idle_inhibit,
+        edge,
+        after_hold,
+        corner,
+        min_velocity,
+        max_velocity,
+        drop_zones,
+        conditions: rule_conditions,
+        spawn: rule_spawn,
+        log: rule_log,
+        dbus: rule_dbus,
+        pipe: rule_pipe,
+    } = swipe;
+    let Repeat {
+        repeat_interval,
+        repeat,
+        repeat_distance,
+    } = &**repeating;
+    let Glide { inertia, friction } = &**glide;
+
+    let mut line = Line::new("swipe");
+    line.text("direction", Some(swipe_dir(direction)));
+    line.value("fingers", Some(fingers));
+    line.text("update", update.as_ref());
+    line.text("start", start.as_ref());
+    line.text("end", end.as_ref());
+    line.text("cancel", cancel.as_ref());
+    line.value("repeat-interval", repeat_interval.as_ref());
+    line.text("repeat", repeat.as_ref());
+    line.distance("repeat-distance", repeat_distance.as_ref());
+    line.text("key", key.as_ref());
+    line.text("script", script.as_ref());
+    line.text("type-text", type_text.as_ref());
+    line.value("paste", paste.as_ref());
+    line.text("hyprland", hyprland.as_ref());
+    line.text("hyprland-update", hyprland_update.as_ref());
+    line.text("sway", sway.as_ref());
+    line.text("sway-update", sway_update.as_ref());
+    match acceleration {
+        Some(Acceleration::Factor(factor)) => line.value("acceleration", Some(factor)),
+        acceleration => line.text("acceleration", acceleration.as_ref()),
+    }
+    line.text("acceleration-curve", acceleration_curve.as_ref());
+    line.value("smoothing", smoothing.map(|s| s.0));
+    line.value("smoothing-beta", smoothing_beta.map(|s| s.0));
+    line.value("mouse-up-delay", mouse_up_delay.as_ref());
+    line.value("update-rate", update_rate.as_ref());
+    line.value("inertia", inertia.as_ref());
+    line.value("friction", friction.map(|f| f.0));
+    line.value("idle-inhibit", idle_inhibit.as_ref());
+    line.text("edge", edge.map(pad_edge));
+    line.value("after-hold", after_hold.as_ref());
+    line.text("corner", corner.map(screen_corner));
+    line.distance("min-velocity", min_velocity.as_ref());
+    line.distance("max-velocity", max_velocity.as_ref());
+    conditions(&mut line, rule_conditions);
+    spawn(&mut line, rule_spawn);
+    log(&mut line, rule_log);
+    dbus(&mut line, rule_dbus);
+    pipe(&mut line, rule_pipe);
+
+    let mut lines = vec![line];
+    for DropZone { edge, action, size } in drop_zones {
+        let mut zone = Line::new("  drop-zone");
+        zone.text("edge", Some(swipe_dir(edge)));
+        zone.text("action", Some(action));
+        zone.value("size", Some(size));
+        lines.push(zone);
+    }
+    lines
+}
+
+fn pinch(pinch: &Pinch) -> Line {
+    let Pinch {
+        fingers,
+        direction,
+        update,
+        start,
+        end,
+        cancel,
+        key,
+        script,
+        type_text,
+        paste,
+        hyprland,
+        hyprland_update,
+        sway,
+        sway_update,
+        rotate_step_deg,
+        min_scale_delta,
+        step,
+        update_rate,
+        conditions: rule_conditions,
+        spawn: rule_spawn,
+        log: rule_log,
+        pipe: rule_pipe,
+    } = pinch;
+    let mut line = Line::new("pinch");
+    line.text("direction", Some(pinch_dir(direction)));
+    line.value("fingers", Some(fingers));
+    line.text("update", update.as_ref());
+    line.text("start", start.as_ref());
+    line.text("end", end.as_ref());
+    line.text("cancel", cancel.as_ref());
+    line.value("rotate-step-deg", rotate_step_deg.map(|d| d.0));
+    line.value("min-scale-delta", min_scale_delta.map(|d| d.0));
+    line.value("step", step.map(|s| s.0));
+    line.value("update-rate", update_rate.as_ref());
+    line.text("key", key.as_ref());
+    line.text("script", script.as_ref());
+    line.text("type-text", type_text.as_ref());
+    line.value("paste", paste.as_ref());
+    line.text("hyprland", hyprland.as_ref());
+    line.text("hyprland-update", hyprland_update.as_ref());
+    line.text("sway", sway.as_ref());
+    line.text("sway-update", sway_update.as_ref());
+    conditions(&mut line, rule_conditions);
+    spawn(&mut line, rule_spawn);
+    log(&mut line, rule_log);
+    pipe(&mut line, rule_pipe);
+    line
+}
+
+fn hold(hold: &Hold) -> Line {
+    let Hold {
+        fingers,
+        action,
+        start,
+        end,
+        key,
+        script,
+        type_text,
+        paste,
+        trigger,
+        duration,
+        repeat,
+        min_duration,
+        max_duration,
+        conditions: rule_conditions,
+        spawn: rule_spawn,
+        log: rule_log,
+    } = hold;
+    let mut line = Line::new("hold");
+    line.value("fingers", Some(fingers));
+    line.text("action", action.as_ref());
+    line.text("start", start.as_ref());
+    line.text("end", end.as_ref());
+    line.text("key", key.as_ref());
+    line.text("script", script.as_ref());
+    line.text("type-text", type_text.as_ref());
+    line.value("paste", paste.as_ref());
+    line.text("trigger", trigger.map(hold_trigger));
+    line.value("duration", duration.as_ref());
+    line.value("repeat", repeat.as_ref());
+    line.value("min-duration", min_duration.as_ref());
+    line.value("max-duration", max_duration.as_ref());
+    conditions(&mut line, rule_conditions);
+    spawn(&mut line, rule_spawn);
+    log(&mut line, rule_log);
+    line
+}
+
+fn rotate(rotate: &Rotate) -> Line {
+    let Rotate {
+        fingers,
+        direction,
+        start,
+        update,
+        end,
+        threshold,
+        rotate_step_deg,
+        conditions: rule_conditions,
+        spawn: rule_spawn,
+        log: rule_log,
+    } = rotate;
+    let mut line = Line::new("rotate");
+    line.text("direction", Some(direction.name()));
+    line.value("fingers", Some(fingers));
+    line.value("threshold", threshold.map(|t| t.0));
+    line.value("rotate-step-deg", rotate_step_deg.map(|d| d.0));
+    line.text("start", start.as_ref());
+    line.text("update", update.as_ref());
+    line.text("end", end.as_ref());
+    conditions(&mut line, rule_conditions);
+    spawn(&mut line, rule_spawn);
+    log(&mut line, rule_log);
+    line
+}
+
+/// Lines of `gesture`, e.g. `swipe direction="e" fingers=3 end="echo hi"`, followed by its
+/// drop zones
+fn rule_lines(gesture: &Gesture) -> Vec<Line> {
+    match gesture {
+        Gesture::Swipe(s) => swipe(s),
+        Gesture::Pinch(p) => vec![pinch(p)],
+        Gesture::Hold(h) => vec![hold(h)],
+        Gesture::Rotate(r) => vec![rotate(r)],
+        Gesture::None => vec![Line::new("none")],
+    }
+}
+
+/// Rules of `text` by block (top level, then each profile and device), groups already merged
+/// into their block
+pub fn gesture_table(name: &str, text: &str) -> Result<String> {
+    let mut config: Config = knuffel::parse(name, text).map_err(|e| miette!("{}", e))?;
+    config.flatten_groups();
+
+    let mut table = String::new();
+    let mut block = |header: Line, gestures: &[Gesture]| {
+        let _ = writeln!(table, "{}", header);
+        for line in gestures.iter().flat_map(rule_lines) {
+            let _ = writeln!(table, "  {}", line);
+        }
+    };
+    block(Line::new("top level"), &config.gestures);
+    for profile in &config.profiles {
+        let mut header = Line::new("profile");
+        header.0.push(format!("{:?}", profile.name));
+        conditions(&mut header, &profile.conditions);
+        block(header, &profile.gestures);
+    }
+    for device in &config.devices {
+        let FingerRemap {
+            finger_offset,
+            finger_map,
+        } = &device.fingers;
+        let mut header = Line::new("device");
+        header.text("name", Some(&device.name));
+        header.value("finger-offset", finger_offset.as_ref());
+        header.text("finger-map", finger_map.as_ref());
+        block(header, &device.gestures);
+    }
+    Ok(table)
+}
@@ -1,11 +1,31 @@
-use crate::config::Config;
-use crate::gestures::swipe::SwipeDir;
-use crate::utils::exec_command_from_string;
+use crate::builtin::zoom_sensitivity;
+use crate::conditions::{count_connected_displays, parse_busctl_string, PowerProfile, SystemState};
+use crate::config::{Config, Vars};
+use crate::conflicts::conflicts_with;
+use crate::contacts::PadState;
+use crate::gestures::rotate::knob_steps;
+use crate::gestures::swipe::{AccelCurve, DropZone, ScreenCorner, SwipeDir};
+use crate::gestures::{Float, Gesture};
+use crate::json::Json;
+use crate::keys::{text_codes, KeyCombo};
+use crate::lsp::{completions, diagnostics};
+use crate::macros::{parse as parse_macro, MacroStep, StepKind};
+use crate::mouse_handler::{parse_hyprland_outputs, parse_monitors, parse_sway_outputs};
+use crate::session_env::parse_environment;
+use crate::session_lock::is_lock_line;
+use crate::smoothing::DeltaFilter;
+use crate::status::{waybar, DaemonState};
+use crate::table::gesture_table;
+use crate::units::{Acceleration, DeviceUnits, Distance, OutputScale, UNITS_PER_MM};
+use crate::utils::{
+    exec_command_from_string, expand_path_with, render_command, CommandVars, SpawnOptions,
+};
+use std::time::{Duration, Instant};
 
 #[test]
 fn test_zombie_process() {
     for _ in 0..100 {
-        let _ = exec_command_from_string("echo", 0.0, 0.0, 0.0, 0.0);
+        let _ = exec_command_from_string("echo", &CommandVars::default(), &SpawnOptions::default());
     }
 }
 
@@ -15,7 +35,22 @@ fn test_config_default() {
     assert_eq!(
         c,
         Config {
-            // // device: None,
+            vars: Vars::default(),
+            palm_rejection: None,
+            finger_change: None,
+            touchscreen: None,
+            watch: None,
+            output_scale: None,
+            idle_pause: None,
+            update_rate: None,
+            notify_failures: None,
+            ignore_devices: Vec::new(),
+            only_devices: Vec::new(),
+            seats: Vec::new(),
+            includes: vec![],
+            profiles: vec![],
+            devices: vec![],
+            groups: vec![],
             gestures: vec![],
         }
     );
@@ -43,3 +78,2746 @@ fn test_dir() {
         assert_eq!(SwipeDir::dir(x, y), expected);
     }
 }
+
+#[test]
+fn test_parse_session_environment() {
+    let vars = parse_environment(
+        "HOME=/home/user\nDISPLAY=:1\nXAUTHORITY=/run/user/1000/xauth_abc\nWAYLAND_DISPLAY=\n",
+    );
+    assert_eq!(vars.get("DISPLAY").map(String::as_str), Some(":1"));
+    assert_eq!(
+        vars.get("XAUTHORITY").map(String::as_str),
+        Some("/run/user/1000/xauth_abc")
+    );
+    assert!(!vars.contains_key("HOME"));
+}
+
+#[test]
+fn test_accel_curve() {
+    assert_eq!("linear".parse::<AccelCurve>().unwrap(), AccelCurve::Linear);
+    assert_eq!(AccelCurve::Quadratic.factor(0.0), 1.0);
+    assert_eq!(AccelCurve::Quadratic.factor(10.0), 2.0);
+    assert_eq!("flat".parse::<AccelCurve>().unwrap(), AccelCurve::Linear);
+
+    let adaptive: AccelCurve = "adaptive".parse().unwrap();
+    assert_eq!(adaptive.factor(0.0), 0.5);
+    assert_eq!(adaptive.factor(1.0), 0.75);
+    assert_eq!(adaptive.factor(12.0), 2.0);
+    assert_eq!(adaptive.factor(100.0), 3.0);
+
+    let curve: AccelCurve = "20:3, 0:1 10:2".parse().unwrap();
+    assert_eq!(curve.factor(0.0), 1.0);
+    assert_eq!(curve.factor(5.0), 1.5);
+    assert_eq!(curve.factor(15.0), 2.5);
+    assert_eq!(curve.factor(100.0), 3.0);
+
+    assert!("fast".parse::<AccelCurve>().is_err());
+    assert!("0:-1".parse::<AccelCurve>().is_err());
+}
+
+#[test]
+fn test_float_acceleration_accepts_integers() {
+    let c: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20
+swipe direction="any" fingers=4 mouse-up-delay=500 acceleration=12.5
+"#,
+    )
+    .unwrap();
+    let accel: Vec<_> = c
+        .gestures
+        .iter()
+        .filter_map(|g| match g {
+            Gesture::Swipe(s) => s.acceleration,
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        accel,
+        vec![Acceleration::Factor(20.0), Acceleration::Factor(12.5)]
+    );
+}
+
+#[test]
+fn test_units_in_thresholds_and_acceleration() {
+    let c: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+output-scale 2
+swipe direction="any" fingers=3 mouse-up-delay=500 acceleration="4px/mm"
+swipe direction="e" fingers=4 min-velocity="50mm/s" max-velocity=3000 end="true"
+swipe direction="w" fingers=4 min-velocity="10%" max-velocity="400px" end="true"
+"#,
+    )
+    .unwrap();
+    assert_eq!(c.output_scale, Some(OutputScale::Fixed(2.0)));
+    let swipes: Vec<_> = c
+        .gestures
+        .iter()
+        .filter_map(|g| match g {
+            Gesture::Swipe(s) => Some(s),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(swipes[0].acceleration, Some(Acceleration::PxPerMm(4.0)));
+    assert_eq!(swipes[1].min_velocity, Some(Distance::Mm(50.0)));
+    assert_eq!(swipes[1].max_velocity, Some(Distance::Units(3000.0)));
+
+    let units = DeviceUnits {
+        size_mm: Some((80.0, 50.0)),
+        output_scale: 2.0,
+        auto_scale: false,
+    };
+    assert!((units.units(Distance::Mm(25.4)) - 1000.0).abs() < 1e-9);
+    assert!((units.units(Distance::PadPercent(10.0)) - 8.0 * UNITS_PER_MM).abs() < 1e-9);
+    assert_eq!(units.units(Distance::Px(400.0)), 200.0);
+    assert!((units.mm(1000.0) - 25.4).abs() < 1e-9);
+    assert_eq!(units.px(10.0), 20.0);
+    // 4 screen pixels per mm at scale 2: 2 logical pixels per 39.37 units
+    let factor = units.pointer_factor(Acceleration::PxPerMm(4.0));
+    assert!((factor * UNITS_PER_MM - 2.0).abs() < 1e-9);
+    assert_eq!(units.pointer_factor(Acceleration::Factor(20.0)), 2.0);
+    // 50mm/s is about 1969 units/s
+    assert!(swipes[1].matches_velocity(2000.0, &units));
+    assert!(!swipes[1].matches_velocity(1900.0, &units));
+
+    assert!("12in".parse::<Distance>().is_err());
+    assert!("-3mm".parse::<Distance>().is_err());
+    assert!("fast".parse::<Acceleration>().is_err());
+    assert!(knuffel::parse::<Config>(
+        "test.kdl",
+        r#"swipe direction="e" fingers=3 min-velocity="5cm""#
+    )
+    .is_err());
+}
+
+#[test]
+fn test_delta_filter_reduces_jitter() {
+    let mut filter = DeltaFilter::new(1.0, 0.0);
+    let start = Instant::now();
+    let mut out = Vec::new();
+    for i in 0..20u64 {
+        let jitter = if i % 2 == 0 { 1.0 } else { -1.0 };
+        let now = start + Duration::from_millis(i * 10);
+        out.push(filter.filter_at(jitter, 0.0, now).0);
+    }
+    assert_eq!(out[0], 1.0);
+    assert!(out[1..].iter().all(|x| x.abs() < 1.0));
+}
+
+#[test]
+fn test_drop_zone_lookup() {
+    let c: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20 {
+    drop-zone edge="w" action="snap-left"
+    drop-zone edge="nw" action="maximize" size=20
+}
+"#,
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &c.gestures[0] else {
+        panic!("expected swipe");
+    };
+    let zones = &swipe.drop_zones;
+
+    let find = |x, y| DropZone::find(zones, x, y, 1920, 1080).map(|z| z.action.as_str());
+    assert_eq!(find(0, 500), Some("snap-left"));
+    assert_eq!(find(2, 10), Some("maximize"));
+    assert_eq!(find(960, 500), None);
+}
+
+#[test]
+fn test_hot_corner_lookup() {
+    let screen = (0, 0, 1920, 1080);
+    assert_eq!(ScreenCorner::at(3, 5, screen), Some(ScreenCorner::TopLeft));
+    assert_eq!(
+        ScreenCorner::at(-250, -40, screen),
+        Some(ScreenCorner::TopLeft)
+    );
+    assert_eq!(
+        ScreenCorner::at(1915, 2, screen),
+        Some(ScreenCorner::TopRight)
+    );
+    assert_eq!(
+        ScreenCorner::at(0, 1200, screen),
+        Some(ScreenCorner::BottomLeft)
+    );
+    assert_eq!(
+        ScreenCorner::at(1919, 1079, screen),
+        Some(ScreenCorner::BottomRight)
+    );
+    assert_eq!(ScreenCorner::at(960, 0, screen), None);
+    assert_eq!(ScreenCorner::at(0, 540, screen), None);
+    // Second monitor to the right
+    assert_eq!(
+        ScreenCorner::at(1925, 1, (1920, 0, 1280, 720)),
+        Some(ScreenCorner::TopLeft)
+    );
+
+    let monitors = r#"[
+        {"id": 0, "x": 0, "y": 0, "width": 2880, "height": 1800, "scale": 2.0, "focused": false},
+        {"id": 1, "x": 1440, "y": 0, "width": 1920, "height": 1080, "scale": 1.0, "focused": true}
+    ]"#;
+    assert_eq!(parse_monitors(monitors), Some((1440, 0, 1920, 1080)));
+    assert_eq!(parse_monitors("[]"), None);
+}
+
+#[test]
+fn test_output_scales() {
+    let c: Config = knuffel::parse("test.kdl", r#"output-scale "auto""#).unwrap();
+    assert_eq!(c.output_scale, Some(OutputScale::Auto));
+    assert!(knuffel::parse::<Config>("test.kdl", r#"output-scale "big""#).is_err());
+
+    let monitors = r#"[
+        {"id": 0, "name": "eDP-1", "x": 0, "y": 0, "width": 2880, "height": 1800, "scale": 1.5, "focused": false},
+        {"id": 1, "name": "DP-1", "x": 1920, "y": 0, "width": 1920, "height": 1080, "scale": 1.0, "focused": true}
+    ]"#;
+    let outputs = parse_hyprland_outputs(monitors);
+    assert_eq!(outputs.len(), 2);
+    assert_eq!((outputs[0].width, outputs[0].height), (1920.0, 1200.0));
+    assert!(outputs[0].contains((1919.0, 10.0)));
+    assert!(outputs[1].contains((1920.0, 10.0)) && outputs[1].focused);
+
+    let sway = r#"[
+        {"name": "eDP-1", "active": true, "focused": true, "scale": 1.5,
+         "rect": {"x": 0, "y": 0, "width": 1920, "height": 1200}},
+        {"name": "HDMI-A-1", "active": false, "rect": {"x": 0, "y": 0, "width": 0, "height": 0}}
+    ]"#;
+    let outputs = parse_sway_outputs(sway);
+    assert_eq!(outputs.len(), 1);
+    assert_eq!((outputs[0].name.as_str(), outputs[0].scale), ("eDP-1", 1.5));
+
+    let units = DeviceUnits {
+        size_mm: None,
+        output_scale: 2.0,
+        auto_scale: true,
+    };
+    assert_eq!(units.pointer_factor(Acceleration::Factor(20.0)), 1.0);
+    assert_eq!(units.compensation(), 0.5);
+}
+
+#[test]
+fn test_render_velocity_variables() {
+    let vars = CommandVars {
+        scale: 1.5,
+        velocity_x: 120.0,
+        velocity_y: -40.5,
+        scale_rate: 2.25,
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        render_command("cmd $velocity_x $velocity_y $scale $scale_rate", &vars).as_deref(),
+        Some("cmd 120.00 -40.50 1.50 2.25")
+    );
+}
+
+#[test]
+fn test_rotate_knob_steps() {
+    let mut fired = 0;
+    assert_eq!(knob_steps(9.0, 10.0, &mut fired), 0);
+    assert_eq!(knob_steps(25.0, 10.0, &mut fired), 2);
+    assert_eq!(knob_steps(-12.0, 10.0, &mut fired), -3);
+    assert_eq!(fired, -1);
+    assert_eq!(knob_steps(-12.0, 0.0, &mut fired), 0);
+
+    let vars = CommandVars {
+        direction: "ccw",
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        render_command("wpctl set-volume $direction", &vars).as_deref(),
+        Some("wpctl set-volume ccw")
+    );
+    // Outside a gesture `$direction` is left alone
+    assert_eq!(
+        render_command("echo $direction", &CommandVars::default()).as_deref(),
+        Some("echo $direction")
+    );
+}
+
+#[test]
+fn test_render_gesture_variables() {
+    let vars = CommandVars {
+        direction: "w",
+        fingers: 4,
+        gesture_type: "swipe",
+        elapsed_ms: 250,
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        render_command(
+            "dispatch $gesture_type $fingers $direction $elapsed_ms",
+            &vars
+        )
+        .as_deref(),
+        Some("dispatch swipe 4 w 250")
+    );
+    assert_eq!(
+        render_command("echo $fingers $gesture_type", &CommandVars::default()).as_deref(),
+        Some("echo $fingers $gesture_type")
+    );
+}
+
+#[test]
+fn test_script() {
+    use crate::script::{Script, ScriptHost};
+
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+    impl ScriptHost for Recorder {
+        fn exec(&mut self, command: &str) {
+            self.0.push(format!("exec {}", command));
+        }
+        fn key(&mut self, key: &KeyCombo) {
+            self.0.push(format!("key {}", key));
+        }
+        fn notify(&mut self, message: &str) {
+            self.0.push(format!("notify {}", message));
+        }
+    }
+    let run = |script: &str, delta_x: f64| {
+        let script: Script = script.parse().unwrap();
+        let vars = CommandVars {
+            delta_x,
+            fingers: 3,
+            direction: "e",
+            ..CommandVars::default()
+        };
+        let mut host = Recorder::default();
+        let result = script.run(&vars, &mut host);
+        (host.0, result)
+    };
+
+    let script = r#"
+        if delta_x > 100 { exec("hyprctl dispatch workspace e+1") }
+        else if abs(delta_x) < 10 && direction == "e" { key("super+Right") }
+        else { let steps = max(1.0, delta_x / 50); notify("swiped " + steps + " steps with " + fingers) }
+    "#;
+    assert_eq!(
+        run(script, 150.0),
+        (
+            vec!["exec hyprctl dispatch workspace e+1".to_string()],
+            Ok(())
+        )
+    );
+    assert_eq!(run(script, 5.0).0, vec!["key super+Right"]);
+    assert_eq!(run(script, 75.0).0, vec!["notify swiped 1.5 steps with 3"]);
+    assert_eq!(
+        run("for i in 1..fingers { exec(`echo ${i}`) }", 0.0).0,
+        vec!["exec echo 1", "exec echo 2"]
+    );
+
+    // Runtime errors stop the script, what it called before still happens
+    for (script, error) in [
+        ("exec(\"a\"); loop {}", "Too many operations"),
+        ("exec(\"a\"); launch(\"x\")", "launch"),
+        ("exec(\"a\"); key(\"hyper+q\")", "unknown key `hyper`"),
+    ] {
+        let (calls, result) = run(script, 0.0);
+        assert_eq!(calls, vec!["exec a"], "{}", script);
+        let err = result.unwrap_err();
+        assert!(err.contains(error), "{}: {}", script, err);
+    }
+
+    // Syntax errors and unknown variables are reported when the config is parsed
+    for (script, error) in [
+        ("delta_z > 1", "delta_z"),
+        (
+            "if fingers { exec(\"x\")",
+            "script `if fingers { exec(\"x\")`",
+        ),
+    ] {
+        let err = script.parse::<Script>().unwrap_err();
+        assert!(err.contains(error), "{}: {}", script, err);
+    }
+    let config: Result<Config, _> = knuffel::parse(
+        "test.kdl",
+        r#"swipe direction="any" fingers=3 script="if {""#,
+    );
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_systemd_passed_fds() {
+    use crate::systemd::passed_fds;
+
+    assert_eq!(passed_fds(Some("42"), Some("1"), 42), 1);
+    // Variables inherited from a parent systemd started aren't for this process
+    assert_eq!(passed_fds(Some("41"), Some("1"), 42), 0);
+    assert_eq!(passed_fds(None, Some("1"), 42), 0);
+    assert_eq!(passed_fds(Some("42"), None, 42), 0);
+    assert_eq!(passed_fds(Some("42"), Some("x"), 42), 0);
+}
+
+#[test]
+fn test_ydotoold_socket() {
+    use crate::ydotoold::{socket_candidates, YdotooldPointer};
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+
+    assert_eq!(
+        socket_candidates(
+            Some("/run/ydotool.sock".into()),
+            Some("/run/user/1000".into())
+        ),
+        vec![PathBuf::from("/run/ydotool.sock")]
+    );
+    assert_eq!(
+        socket_candidates(None, Some("/run/user/1000".into())),
+        vec![
+            PathBuf::from("/run/user/1000/.ydotool_socket"),
+            PathBuf::from("/tmp/.ydotool_socket")
+        ]
+    );
+
+    let dir = std::env::temp_dir().join(format!("gestures-ydotoold-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".ydotool_socket");
+    let _ = std::fs::remove_file(&path);
+    let daemon = UnixDatagram::bind(&path).unwrap();
+    let mut pointer = YdotooldPointer::connect_to(path.clone()).unwrap();
+    pointer.motion(3, -2).unwrap();
+    // REL_X, REL_Y and a SYN_REPORT, each its own input_event datagram
+    let mut events = Vec::new();
+    for _ in 0..3 {
+        let mut buf = [0u8; 64];
+        assert_eq!(daemon.recv(&mut buf).unwrap(), 24);
+        let type_ = u16::from_ne_bytes([buf[16], buf[17]]);
+        let code = u16::from_ne_bytes([buf[18], buf[19]]);
+        let value = i32::from_ne_bytes([buf[20], buf[21], buf[22], buf[23]]);
+        events.push((type_, code, value));
+    }
+    assert_eq!(events, vec![(2, 0, 3), (2, 1, -2), (0, 0, 0)]);
+    drop(daemon);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(YdotooldPointer::connect_to(path).is_err());
+}
+
+#[test]
+fn test_pipe_update_lines() {
+    use crate::pipe::{update_line, PipeFormat};
+
+    let vars = CommandVars {
+        delta_x: 1.5,
+        direction: "e",
+        fingers: 3,
+        gesture_type: "swipe",
+        elapsed_ms: 40,
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        update_line(PipeFormat::KeyValue, &vars),
+        "gesture_type=swipe fingers=3 direction=e delta_x=1.50 delta_y=0.00 scale=0.00 \
+         delta_angle=0.00 velocity_x=0.00 velocity_y=0.00 scale_rate=0.00 elapsed_ms=40"
+    );
+    let json = update_line(PipeFormat::Json, &vars);
+    assert!(
+        json.starts_with(r#"{"gesture_type":"swipe","fingers":3,"direction":"e","delta_x":1.50,"#)
+    );
+    assert!(json.ends_with(r#""elapsed_ms":40}"#));
+}
+
+#[test]
+fn test_merge_system_config() {
+    let system: Config = knuffel::parse(
+        "system.kdl",
+        r#"
+swipe direction="w" fingers=4 end="system-prev"
+swipe direction="e" fingers=4 end="system-next"
+hold fingers=4 action="system-launcher"
+"#,
+    )
+    .unwrap();
+    let user: Config = knuffel::parse(
+        "user.kdl",
+        r#"
+swipe direction="w" fingers=4 end="user-prev"
+pinch direction="in" fingers=2 end="user-zoom"
+"#,
+    )
+    .unwrap();
+
+    let merged = system.merge(user);
+    let ends: Vec<_> = merged
+        .gestures
+        .iter()
+        .map(|g| match g {
+            Gesture::Swipe(s) => s.end.clone(),
+            Gesture::Pinch(p) => p.end.clone(),
+            Gesture::Hold(h) => h.action.clone(),
+            Gesture::Rotate(r) => r.end.clone(),
+            Gesture::None => None,
+        })
+        .collect();
+    assert_eq!(
+        ends,
+        vec![
+            Some("system-next".to_string()),
+            Some("system-launcher".to_string()),
+            Some("user-prev".to_string()),
+            Some("user-zoom".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_trace_ring_buffer_is_bounded() {
+    for i in 0..600 {
+        crate::trace::record(format_args!("ring-test-{:03}", i));
+    }
+    let trace = crate::trace::snapshot();
+    assert!(trace.len() <= 512);
+    assert!(trace.last().unwrap().ends_with("ring-test-599"));
+    assert!(!trace.iter().any(|e| e.ends_with("ring-test-000")));
+}
+
+#[test]
+fn test_monitor_streams_decisions_and_updates() {
+    crate::trace::monitor(format_args!("monitor-test-unseen"));
+    let rx = crate::trace::follow();
+    crate::trace::record(format_args!("monitor-test-decision"));
+    crate::trace::monitor(format_args!("monitor-test-update"));
+    let events: Vec<String> = rx
+        .try_iter()
+        .filter(|e| e.contains("monitor-test"))
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert!(events[0].ends_with("monitor-test-decision"));
+    assert!(events[1].ends_with("monitor-test-update"));
+    // Updates are only streamed, `gestures trace` keeps decisions
+    let trace = crate::trace::snapshot();
+    assert!(!trace.iter().any(|e| e.contains("monitor-test-u")));
+}
+
+#[test]
+fn test_parse_macro() {
+    let steps =
+        parse_macro("# comment\n0 key 29 down\n15 key 46 down\n\n40 button 272 up\n").unwrap();
+    assert_eq!(steps.len(), 3);
+    assert_eq!(
+        steps[2],
+        MacroStep {
+            delay_ms: 40,
+            kind: StepKind::Button,
+            code: 272,
+            pressed: false,
+        }
+    );
+    assert_eq!(steps[1].to_string(), "15 key 46 down");
+    assert!(parse_macro("10 key 30").is_err());
+    assert!(parse_macro("10 wheel 30 down").is_err());
+}
+
+#[test]
+fn test_power_profile_condition() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        pinch direction="out" fingers=2 update="notify-send $scale" not-power-profile="power-saver"
+        hold fingers=4 action="@power-profile next" power-profile="performance"
+        "#,
+    )
+    .unwrap();
+    let conditions: Vec<_> = config
+        .gestures
+        .iter()
+        .filter_map(|g| g.conditions())
+        .collect();
+    assert_eq!(
+        conditions[0].not_power_profile,
+        Some(PowerProfile::PowerSaver)
+    );
+    assert_eq!(conditions[1].power_profile, Some(PowerProfile::Performance));
+
+    assert_eq!(parse_busctl_string("s \"balanced\"\n"), Some("balanced"));
+    assert_eq!(parse_busctl_string(""), None);
+}
+
+#[test]
+fn test_profile_overrides_top_level_rules() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="n" fingers=4 end="rofi -show drun"
+        swipe direction="s" fingers=4 end="close"
+        profile "always" {
+            swipe direction="n" fingers=4 end="overview"
+        }
+        profile "never" max-displays=0 min-displays=1 {
+            swipe direction="s" fingers=4 end="never"
+        }
+        "#,
+    )
+    .unwrap();
+
+    let (profiles, gestures) = config.active_gestures(&SystemState::default());
+    assert_eq!(profiles, vec!["always"]);
+    let ends: Vec<_> = gestures
+        .iter()
+        .filter_map(|g| match g {
+            Gesture::Swipe(s) => s.end.as_deref(),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ends, vec!["overview", "close"]);
+}
+
+#[test]
+fn test_vars_expand_with_profile_overrides() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        vars {
+            terminal "alacritty"
+            modkey "super"
+        }
+        swipe direction="n" fingers=4 end="$terminal -e htop"
+        swipe direction="s" fingers=4 update="xdotool mousemove_relative -- $delta_x $delta_y"
+        profile "always" {
+            vars {
+                terminal "foot"
+            }
+        }
+        "#,
+    )
+    .unwrap();
+
+    let (profiles, gestures) = config.active_gestures(&SystemState::default());
+    let vars = config.active_vars(&profiles);
+    assert_eq!(vars["terminal"], "foot");
+    assert_eq!(vars["modkey"], "super");
+
+    let commands: Vec<_> = gestures
+        .iter()
+        .filter_map(|g| match g.with_vars(&vars) {
+            Gesture::Swipe(s) => s.end.or(s.update),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            "foot -e htop",
+            "xdotool mousemove_relative -- $delta_x $delta_y"
+        ]
+    );
+}
+
+#[test]
+fn test_time_window_condition() {
+    use crate::conditions::{set_profile_override, Conditions};
+    use chrono::NaiveDate;
+
+    let gesture: Gesture = knuffel::parse::<Vec<Gesture>>(
+        "test.kdl",
+        r#"hold fingers=4 action="media" time="18:00-02:00" days="fri-sun""#,
+    )
+    .unwrap()
+    .remove(0);
+    let conditions: &Conditions = gesture.conditions().unwrap();
+    let at = |day, h, m| {
+        SystemState::at(
+            NaiveDate::from_ymd_opt(2024, 3, day)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap(),
+        )
+    };
+    // 2024-03-01 is a Friday
+    assert!(conditions.matches(&at(1, 18, 0)));
+    assert!(conditions.matches(&at(3, 1, 59)));
+    assert!(!conditions.matches(&at(1, 17, 59)));
+    assert!(!conditions.matches(&at(1, 2, 0)));
+    assert!(!conditions.matches(&at(4, 20, 0)));
+
+    assert!(knuffel::parse::<Vec<Gesture>>("test.kdl", r#"hold fingers=4 time="9-17""#).is_err());
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"profile "evening-test" time="18:00-18:00" days="mon" { }"#,
+    )
+    .unwrap();
+    assert!(config.active_gestures(&at(5, 12, 0)).0.is_empty());
+    set_profile_override("evening-test", Some(true));
+    assert_eq!(
+        config.active_gestures(&at(5, 12, 0)).0,
+        vec!["evening-test"]
+    );
+    set_profile_override("evening-test", None);
+    assert!(config.active_gestures(&at(5, 12, 0)).0.is_empty());
+}
+
+#[test]
+fn test_switch_profile() {
+    use crate::conditions::profile_override;
+    use crate::ipc::override_profile;
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        profile "switch-normal" { }
+        profile "switch media" { }
+        profile "switch-gaming" { }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        override_profile(&config, "\"switch media\""),
+        "profile switch media\n"
+    );
+    assert_eq!(profile_override("switch media"), Some(true));
+    assert_eq!(profile_override("switch-normal"), Some(false));
+    assert_eq!(profile_override("switch-gaming"), Some(false));
+
+    assert_eq!(
+        override_profile(&config, "switch-gaming on"),
+        "profile switch-gaming on\n"
+    );
+    assert_eq!(profile_override("switch media"), Some(true));
+    override_profile(&config, "switch media auto");
+    assert_eq!(profile_override("switch media"), None);
+    assert!(override_profile(&config, "switch-other").starts_with("error: unknown profile"));
+}
+
+#[test]
+fn test_calibration_factors_roundtrip() {
+    use crate::calibration::{factor, Calibration};
+
+    // Median of the samples, clamped so outliers don't skew it
+    assert_eq!(factor(&[100.0, 500.0, 125.0], 250.0), Some(2.0));
+    assert_eq!(factor(&[10.0], 250.0), Some(4.0));
+    assert_eq!(factor(&[0.0], 250.0), None);
+
+    let calibration: Calibration = knuffel::parse(
+        "calibration.kdl",
+        r#"device "SynPS/2 \"Pad\"" swipe-scale=2 pinch-scale=0.5"#,
+    )
+    .unwrap();
+    let pad = calibration.device("SynPS/2 \"Pad\"").unwrap();
+    assert_eq!(pad.scale_delta(1.5, -2.0), (3.0, -4.0));
+    assert!((pad.scale_pinch(4.0) - 2.0).abs() < 1e-9);
+    assert!((pad.scale_pinch(1.0) - 1.0).abs() < 1e-9);
+
+    let reparsed: Calibration = knuffel::parse("calibration.kdl", &calibration.to_kdl()).unwrap();
+    assert_eq!(reparsed, calibration);
+}
+
+#[test]
+fn test_virtual_pointer_wire() {
+    use crate::compositor::{message, wire_string, Global};
+    use crate::virtual_pointer::VirtualPointer;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    fn read(stream: &mut UnixStream, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        stream.read_exact(&mut bytes).unwrap();
+        bytes
+    }
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+    }
+    /// Arguments after the timestamp of a request of `len` bytes, checking the frame after it
+    fn request(stream: &mut UnixStream, opcode: u32, len: usize) -> Vec<u8> {
+        let bytes = read(stream, len + 8);
+        assert_eq!(bytes[..8], message(5, opcode, &vec![0; len - 8])[..8]);
+        assert_eq!(bytes[len..], message(5, 4, &[]));
+        bytes[12..len].to_vec()
+    }
+
+    let (client, mut compositor) = UnixStream::pair().unwrap();
+    let globals = [Global {
+        name: 7,
+        interface: "zwlr_virtual_pointer_manager_v1".to_string(),
+        version: 2,
+    }];
+    let created = std::thread::spawn(move || VirtualPointer::create(client, &globals));
+
+    // wl_registry.bind(7, interface, version 1, id 4)
+    let mut bind = u32s(&[7]);
+    bind.extend(wire_string("zwlr_virtual_pointer_manager_v1"));
+    bind.extend(u32s(&[1, 4]));
+    assert_eq!(read(&mut compositor, 8 + bind.len()), message(2, 0, &bind));
+    // create_virtual_pointer(seat null, id 5), then wl_display.sync(6) for the roundtrip
+    assert_eq!(read(&mut compositor, 16), message(4, 0, &u32s(&[0, 5])));
+    assert_eq!(read(&mut compositor, 12), message(1, 0, &u32s(&[6])));
+    compositor.write_all(&message(6, 0, &u32s(&[0]))).unwrap();
+    let mut pointer = created.join().unwrap().unwrap();
+
+    // Motion is in wl_fixed_t, 24.8 fixed point
+    pointer.motion(2, -3).unwrap();
+    assert_eq!(
+        request(&mut compositor, 0, 20),
+        u32s(&[512, -768i32 as u32])
+    );
+    pointer.fine_motion(384, 1).unwrap();
+    assert_eq!(request(&mut compositor, 0, 20), u32s(&[384, 1]));
+
+    // X11 button numbers 1, 2 and 3 are BTN_LEFT, BTN_MIDDLE and BTN_RIGHT
+    for (button, pressed, code) in [(1, true, 0x110), (3, true, 0x111), (2, false, 0x112)] {
+        pointer.button(button, pressed).unwrap();
+        assert_eq!(
+            request(&mut compositor, 2, 20),
+            u32s(&[code, u32::from(pressed)])
+        );
+    }
+}
+
+#[test]
+fn test_wayland_registry_globals() {
+    use crate::compositor::{
+        registry_globals, select_backend, wire_string, Available, Backend, DisplayServer,
+    };
+
+    fn message(object: u32, opcode: u32, payload: &[u8]) -> Vec<u8> {
+        let mut msg = object.to_ne_bytes().to_vec();
+        msg.extend_from_slice(&(((8 + payload.len() as u32) << 16) | opcode).to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg
+    }
+    fn global(name: u32, interface: &str) -> Vec<u8> {
+        let mut payload = name.to_ne_bytes().to_vec();
+        payload.extend_from_slice(&(interface.len() as u32 + 1).to_ne_bytes());
+        let mut string = interface.as_bytes().to_vec();
+        string.resize((interface.len() + 1).next_multiple_of(4), 0);
+        payload.extend_from_slice(&string);
+        payload.extend_from_slice(&1u32.to_ne_bytes());
+        message(2, 0, &payload)
+    }
+
+    let mut events = global(1, "wl_compositor");
+    events.extend(global(7, "zwlr_virtual_pointer_manager_v1"));
+    assert_eq!(registry_globals(&events), Ok(None));
+    // A partial message waits for more bytes
+    assert_eq!(registry_globals(&events[..events.len() - 3]), Ok(None));
+
+    events.extend(message(3, 0, &0u32.to_ne_bytes()));
+    assert_eq!(
+        registry_globals(&events),
+        Ok(Some(vec![
+            "wl_compositor".to_string(),
+            "zwlr_virtual_pointer_manager_v1".to_string()
+        ]))
+    );
+    assert!(registry_globals(&message(1, 0, &[0; 12])).is_err());
+    assert_eq!(wire_string("abc"), [4, 0, 0, 0, b'a', b'b', b'c', 0]);
+    assert_eq!(wire_string("abcd").len(), 12);
+
+    let ydotool = Available {
+        ydotool: true,
+        uinput: true,
+        ..Available::default()
+    };
+    assert_eq!(
+        select_backend(DisplayServer::Wayland, ydotool),
+        Backend::Ydotool
+    );
+    assert_eq!(
+        select_backend(
+            DisplayServer::Wayland,
+            Available {
+                virtual_pointer: true,
+                ..ydotool
+            }
+        ),
+        Backend::VirtualPointer
+    );
+    assert_eq!(
+        select_backend(
+            DisplayServer::Wayland,
+            Available {
+                uinput: true,
+                ..Available::default()
+            }
+        ),
+        Backend::Uinput
+    );
+    assert_eq!(
+        select_backend(DisplayServer::Wayland, Available::default()),
+        Backend::None
+    );
+}
+
+#[test]
+fn test_focused_app_condition() {
+    use crate::compositor::wire_string;
+    use crate::focus::{FocusProvider, Reply, Toplevels};
+
+    // Manager is object 4, toplevel handles are created by the compositor
+    let mut toplevels = Toplevels::default();
+    let handle = 0xff00_0001u32;
+    let activated = [4u32, 2].map(u32::to_ne_bytes).concat();
+    assert_eq!(toplevels.event(4, 0, &handle.to_ne_bytes()), Reply::None);
+    toplevels.event(handle, 1, &wire_string("firefox"));
+    toplevels.event(handle, 4, &activated);
+    // Nothing applies before `done`
+    assert_eq!(toplevels.focused_window().app.as_deref(), None);
+    toplevels.event(handle, 5, &[]);
+    assert_eq!(toplevels.focused_window().app.as_deref(), Some("firefox"));
+    toplevels.event(handle, 4, &0u32.to_ne_bytes());
+    toplevels.event(handle, 5, &[]);
+    assert_eq!(toplevels.focused_window().app.as_deref(), None);
+    assert_eq!(toplevels.event(handle, 6, &[]), Reply::Destroy(handle));
+    assert_eq!(toplevels.event(4, 1, &[]), Reply::Finished);
+
+    // The foreign toplevel client is only built with the wlr feature
+    let globals = ["zwlr_foreign_toplevel_manager_v1".to_string()];
+    assert_eq!(
+        FocusProvider::select(None, false, Some(&globals)),
+        if cfg!(feature = "wlr") {
+            FocusProvider::ForeignToplevel
+        } else {
+            FocusProvider::None
+        }
+    );
+    assert_eq!(
+        FocusProvider::select(None, false, None),
+        FocusProvider::None
+    );
+
+    let gesture: Gesture = knuffel::parse::<Vec<Gesture>>(
+        "test.kdl",
+        r#"swipe direction="e" fingers=3 end="back" app="Firefox, chromium""#,
+    )
+    .unwrap()
+    .remove(0);
+    let conditions = gesture.conditions().unwrap();
+    assert!(conditions.matches(&SystemState::focused_on(Some("firefox"))));
+    assert!(conditions.matches(&SystemState::focused_on(Some("chromium"))));
+    assert!(!conditions.matches(&SystemState::focused_on(Some("foot"))));
+    assert!(!conditions.matches(&SystemState::focused_on(None)));
+}
+
+#[test]
+fn test_window_class_and_title_conditions() {
+    use crate::focus::{
+        focused_in_tree, hyprland_event, sway_event, window_of, FocusProvider, Focused,
+    };
+    use crate::json::Json;
+
+    assert_eq!(
+        FocusProvider::select(Some("hyprland"), false, None),
+        FocusProvider::Hyprland
+    );
+    assert_eq!(
+        FocusProvider::select(Some("i3"), true, None),
+        FocusProvider::Sway
+    );
+
+    let gesture: Gesture = knuffel::parse::<Vec<Gesture>>(
+        "test.kdl",
+        r#"swipe direction="e" fingers=3 end="skip" window-class="mpv,Firefox" window-title="youtube|netflix""#,
+    )
+    .unwrap()
+    .remove(0);
+    let conditions = gesture.conditions().unwrap();
+    let focused = |class, title| SystemState::focused_window(Some(class), Some(title));
+    assert!(conditions.matches(&focused("firefox", "Video - YouTube — Mozilla Firefox")));
+    assert!(conditions.matches(&focused("mpv", "Netflix")));
+    assert!(!conditions.matches(&focused("firefox", "GitHub — Mozilla Firefox")));
+    assert!(!conditions.matches(&focused("chromium", "YouTube")));
+    assert!(!conditions.matches(&SystemState::focused_window(Some("mpv"), None)));
+    assert!(
+        knuffel::parse::<Vec<Gesture>>("test.kdl", r#"hold fingers=4 window-title="(""#).is_err()
+    );
+
+    // Hyprland titles may contain commas, classes don't
+    assert_eq!(
+        hyprland_event("activewindow>>kitty,vim a, b"),
+        Some(Focused {
+            app: Some("kitty".to_string()),
+            title: Some("vim a, b".to_string()),
+        })
+    );
+    assert_eq!(hyprland_event("activewindow>>,"), Some(Focused::default()));
+    assert_eq!(hyprland_event("workspace>>2"), None);
+    let reply = Json::parse(r#"{"class": "firefox", "title": "Docs"}"#).unwrap();
+    assert_eq!(window_of(&reply).app.as_deref(), Some("firefox"));
+    assert_eq!(window_of(&Json::parse("{}").unwrap()), Focused::default());
+
+    // An X11 window on i3 has no app id, a Wayland one on Sway no window properties
+    let tree = Json::parse(
+        r#"{"focused": false, "nodes": [{"focused": false, "nodes": []}], "floating_nodes": [
+            {"focused": true, "app_id": null, "name": "Inbox",
+             "window_properties": {"class": "Thunderbird"}}]}"#,
+    )
+    .unwrap();
+    let window = window_of(focused_in_tree(&tree).unwrap());
+    assert_eq!(window.app.as_deref(), Some("Thunderbird"));
+    assert_eq!(window.title.as_deref(), Some("Inbox"));
+    let event = |change: &str, focused: bool| {
+        Json::parse(&format!(
+            r#"{{"change": "{}", "container": {{"focused": {}, "app_id": "foot", "name": "htop"}}}}"#,
+            change, focused
+        ))
+        .unwrap()
+    };
+    assert_eq!(
+        sway_event(&event("focus", true)).unwrap().title.as_deref(),
+        Some("htop")
+    );
+    assert!(sway_event(&event("title", true)).is_some());
+    assert_eq!(sway_event(&event("title", false)), None);
+    assert_eq!(sway_event(&event("close", true)), Some(Focused::default()));
+}
+
+#[test]
+fn test_modifier_conditions() {
+    use crate::conditions::{ModifierKeys, Modifiers};
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="e" fingers=3 end="plain"
+        swipe direction="e" fingers=3 end="ctrl" modifiers="ctrl"
+        swipe direction="e" fingers=3 end="super-shift" modifiers="Shift+super"
+        swipe direction="w" fingers=3 end="plain-w"
+        "#,
+    )
+    .unwrap();
+    let ends = |modifiers: &str| -> Vec<String> {
+        let state = SystemState::default().with_modifiers(modifiers.parse().unwrap_or_default());
+        config
+            .active_gestures(&state)
+            .1
+            .iter()
+            .filter_map(|g| match g {
+                Gesture::Swipe(s) => s.end.clone(),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(ends(""), vec!["plain", "plain-w"]);
+    // The modified rule replaces the plain one, other gestures stay
+    assert_eq!(ends("ctrl"), vec!["ctrl", "plain-w"]);
+    assert_eq!(ends("super+shift"), vec!["super-shift", "plain-w"]);
+    // Modifiers must match exactly
+    assert_eq!(ends("ctrl+shift"), vec!["plain", "plain-w"]);
+
+    // Rules for other modifiers are different bindings when layering configs
+    assert!(!config.gestures[0].same_trigger(&config.gestures[1]));
+    assert!(config.gestures[0].same_gesture(&config.gestures[1]));
+    assert!("ctrl+hyper".parse::<Modifiers>().is_err());
+    assert_eq!(
+        "super+ctrl".parse::<Modifiers>().unwrap().to_string(),
+        "ctrl+super"
+    );
+
+    // Left and right keys are held apart, releasing one keeps the modifier
+    let mut keys = ModifierKeys::default();
+    keys.key(29, true);
+    keys.key(97, true);
+    keys.key(30, true);
+    keys.key(29, false);
+    assert_eq!(keys.modifiers(), "ctrl".parse().unwrap());
+    keys.key(97, false);
+    assert_eq!(keys.modifiers(), Modifiers::default());
+}
+
+#[test]
+fn test_device_blocks_replace_global_rules() {
+    let mut config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="e" fingers=3 end="global-e"
+        swipe direction="w" fingers=3 end="global-w"
+        device name="Apple Magic Trackpad" {
+            swipe direction="e" fingers=3 end="trackpad-e"
+            group {
+                hold fingers=4 action="trackpad-hold"
+            }
+        }
+        "#,
+    )
+    .unwrap();
+    config.flatten_groups();
+
+    let commands = |device: Option<&str>| -> Vec<String> {
+        let (_, gestures) = config.active_gestures(&SystemState::on_device(device));
+        gestures
+            .iter()
+            .filter_map(|g| match g {
+                Gesture::Swipe(s) => s.end.clone(),
+                Gesture::Hold(h) => h.action.clone(),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(commands(None), vec!["global-e", "global-w"]);
+    assert_eq!(commands(Some("SynPS/2 Synaptics TouchPad")), commands(None));
+    assert_eq!(
+        commands(Some("Apple Magic Trackpad")),
+        vec!["trackpad-e", "trackpad-hold", "global-w"]
+    );
+}
+
+#[test]
+fn test_device_finger_remap() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        device name="Apple Magic Trackpad" finger-offset=-1 finger-map="2:2, 5:3" {
+        }
+        device name="ELAN Touchpad" {
+        }
+        "#,
+    )
+    .unwrap();
+    let remap = config.finger_remap(Some("Apple Magic Trackpad"));
+    assert_eq!(remap.apply(4), 3);
+    assert_eq!(remap.apply(2), 2);
+    assert_eq!(remap.apply(5), 3);
+    assert_eq!(config.finger_remap(Some("ELAN Touchpad")).apply(4), 4);
+    assert_eq!(config.finger_remap(None).apply(3), 3);
+
+    assert!(
+        knuffel::parse::<Config>("test.kdl", r#"device name="x" finger-map="4=3" {}"#).is_err()
+    );
+}
+
+#[test]
+fn test_device_filter() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        ignore-device "SynPS/2 *"
+        ignore-device "/^Wacom .* Pad$/"
+        "#,
+    )
+    .unwrap();
+    let filter = config.device_filter();
+    assert!(!filter.accepts("SynPS/2 Synaptics TouchPad", "event5"));
+    assert!(!filter.accepts("Wacom Intuos S Pad", "event9"));
+    assert!(filter.accepts("Wacom Intuos S Pen", "event10"));
+    assert!(filter.accepts("ELAN0670:00 04F3:3150 Touchpad", "event7"));
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        only-device "ELAN????:00 *"
+        only-device "event12"
+        "#,
+    )
+    .unwrap();
+    let filter = config.device_filter();
+    assert!(filter.accepts("ELAN0670:00 04F3:3150 Touchpad", "event7"));
+    assert!(filter.accepts("Apple Magic Trackpad", "event12"));
+    assert!(!filter.accepts("Apple Magic Trackpad", "event1"));
+    assert!(!filter.accepts("ELAN Touchpad", "event7"));
+
+    assert!(knuffel::parse::<Config>("test.kdl", r#"only-device "/(/""#).is_err());
+}
+
+#[test]
+fn test_seats() {
+    let system: Config = knuffel::parse("system.kdl", r#"seat "seat0""#).unwrap();
+    let user: Config = knuffel::parse(
+        "user.kdl",
+        r#"
+        seat "seat0"
+        seat "seat1"
+        "#,
+    )
+    .unwrap();
+    let system = system.merge(Config::default());
+    assert_eq!(system.seats, vec!["seat0"]);
+    assert_eq!(system.merge(user).seats, vec!["seat0", "seat1"]);
+}
+
+#[test]
+fn test_input_sources() {
+    use crate::event_handler::InputSource;
+    use crate::input_sources;
+    use std::path::PathBuf;
+
+    let seats = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        input_sources(&[], &[], &[]),
+        vec![InputSource::Seat("seat0".to_string())]
+    );
+    // --seat replaces the seats of the config
+    assert_eq!(
+        input_sources(&[], &seats(&["seat1"]), &seats(&["seat0", "seat2"])),
+        vec![InputSource::Seat("seat1".to_string())]
+    );
+    assert_eq!(
+        input_sources(&[], &[], &seats(&["seat1", "seat0"])).len(),
+        2
+    );
+    // --device replaces both
+    let devices = vec![PathBuf::from("/dev/input/event5")];
+    let sources = input_sources(&devices, &seats(&["seat1"]), &[]);
+    assert_eq!(sources, vec![InputSource::Paths(devices)]);
+    assert_eq!(sources[0].to_string(), "/dev/input/event5");
+}
+
+#[test]
+fn test_group_conditions_apply_to_children() {
+    use crate::conditions::PowerProfile;
+
+    let mut config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        group min-displays=2 {
+            swipe direction="n" fingers=4 end="docked"
+            swipe direction="s" fingers=4 end="laptop" min-displays=1 max-displays=1
+            group power-profile="performance" {
+                hold fingers=4 action="fast"
+            }
+        }
+        profile "p" {
+            group max-displays=1 {
+                pinch direction="in" fingers=2 end="zoom"
+            }
+        }
+        swipe direction="e" fingers=4 end="plain"
+        "#,
+    )
+    .unwrap();
+    config.flatten_groups();
+    assert!(config.groups.is_empty());
+
+    let min_max = |g: &Gesture| {
+        let c = g.conditions().unwrap();
+        (c.min_displays, c.max_displays, c.power_profile)
+    };
+    let flattened: Vec<_> = config.gestures.iter().map(min_max).collect();
+    assert_eq!(
+        flattened,
+        vec![
+            (None, None, None),
+            (Some(2), None, None),
+            (Some(1), Some(1), None),
+            (Some(2), None, Some(PowerProfile::Performance)),
+        ]
+    );
+    assert_eq!(
+        min_max(&config.profiles[0].gestures[0]),
+        (None, Some(1), None)
+    );
+}
+
+#[test]
+fn test_stress_event_sequence() {
+    use crate::event_handler::SyntheticEvent;
+    use crate::stress::{parse_duration, synthetic_event};
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+    assert!(parse_duration("5h").is_err());
+
+    // Swipes and pinches alternate, each begins and ends once
+    let events: Vec<_> = (0..124).map(|i| synthetic_event(i, &[3], &[2])).collect();
+    assert_eq!(events[0], SyntheticEvent::SwipeBegin { fingers: 3 });
+    assert_eq!(events[61], SyntheticEvent::SwipeEnd);
+    assert_eq!(events[62], SyntheticEvent::PinchBegin { fingers: 2 });
+    assert_eq!(events[123], SyntheticEvent::PinchEnd);
+    assert!(events[1..61]
+        .iter()
+        .all(|e| matches!(e, SyntheticEvent::SwipeUpdate { .. })));
+
+    assert_eq!(
+        synthetic_event(0, &[], &[]),
+        SyntheticEvent::SwipeBegin { fingers: 3 }
+    );
+}
+
+#[test]
+fn test_screenshot_command() {
+    use crate::builtin::{is_screenshot, screenshot_command, Region};
+    use crate::mouse_handler::parse_cursor_pos;
+
+    let region = Region::from_drag((100, 200), (-30, 40));
+    assert_eq!(
+        region,
+        Region {
+            x: 70,
+            y: 200,
+            w: 30,
+            h: 40
+        }
+    );
+    assert_eq!(
+        screenshot_command("@screenshot grim -g \"$geometry\" $HOME/shot.png", region).as_deref(),
+        Some("grim -g \"70,200 30x40\" $HOME/shot.png")
+    );
+    assert_eq!(
+        screenshot_command("@screenshot maim -g ${w}x${h}+$x+$y", region).as_deref(),
+        Some("maim -g 30x40+70+200")
+    );
+    assert_eq!(screenshot_command("@screenshot", region), None);
+    assert!(is_screenshot("@screenshot scrot"));
+    assert!(!is_screenshot("@screenshots"));
+
+    assert_eq!(parse_cursor_pos("1280, 720\n"), Some((1280, 720)));
+    assert_eq!(parse_cursor_pos("error"), None);
+}
+
+#[test]
+fn test_count_connected_displays() {
+    let dir = std::env::temp_dir().join(format!("gestures-drm-{}", std::process::id()));
+    for (connector, status) in [
+        ("card0-eDP-1", "connected"),
+        ("card0-HDMI-A-1", "connected\n"),
+        ("card0-DP-1", "disconnected"),
+    ] {
+        std::fs::create_dir_all(dir.join(connector)).unwrap();
+        std::fs::write(dir.join(connector).join("status"), status).unwrap();
+    }
+    std::fs::create_dir_all(dir.join("card0")).unwrap();
+
+    assert_eq!(count_connected_displays(&dir), Some(2));
+    assert_eq!(count_connected_displays(&dir.join("missing")), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_json_roundtrip() {
+    let text = r#"{"id":1,"params":{"text":"a\"b\u00e9\n","list":[true,null,-2.5]}}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(
+        json.pointer(&["params", "text"]).and_then(Json::as_str),
+        Some("a\"bé\n")
+    );
+    assert_eq!(json.get("id").and_then(Json::as_u64), Some(1));
+    assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    assert!(Json::parse("{\"a\":}").is_err());
+
+    // Deep nesting is an error instead of a stack overflow
+    let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(Json::parse(&nested(128)).is_ok());
+    assert!(Json::parse(&nested(129)).is_err());
+    assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+
+    let numbers = Json::from(vec![
+        Json::Number(f64::NAN),
+        Json::Number(f64::INFINITY),
+        Json::Number(-1.5),
+    ]);
+    assert_eq!(numbers.to_string(), "[null,null,-1.5]");
+}
+
+#[test]
+fn test_lsp_completions() {
+    let labels = |text: &str| -> Vec<&str> {
+        completions(text, text.len())
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect()
+    };
+
+    assert_eq!(
+        labels("sw"),
+        vec![
+            "swipe",
+            "pinch",
+            "hold",
+            "rotate",
+            "profile",
+            "group",
+            "device",
+            "ignore-device",
+            "only-device",
+            "palm-rejection",
+            "touchscreen",
+            "finger-change",
+            "watch",
+            "output-scale",
+            "idle-pause",
+            "update-rate",
+            "notify-failures",
+            "seat",
+            "include",
+            "vars"
+        ]
+    );
+    assert_eq!(labels("swipe fingers=3 {\n    "), vec!["drop-zone"]);
+    assert!(labels("group min-displays=2 {\n    ").contains(&"group"));
+    assert!(labels("pinch direction=\"").contains(&"counter-clockwise"));
+    assert!(labels("hold fingers=3 trigger=\"").contains(&"timeout"));
+
+    let props = labels("swipe fingers=3 ");
+    assert!(props.contains(&"acceleration-curve"));
+    assert!(props.contains(&"min-displays"));
+    assert!(!props.contains(&"fingers"));
+}
+
+#[test]
+fn test_lsp_diagnostics() {
+    assert!(diagnostics("swipe direction=\"n\" fingers=4 end=\"true\"").is_empty());
+
+    let text = "swipe direction=\"up\" fingers=4";
+    let errors = diagnostics(text);
+    assert_eq!(errors.len(), 1);
+    let (start, end, _) = &errors[0];
+    assert_eq!(&text[*start..*end], "\"up\"");
+
+    assert!(!diagnostics("swipe direction=\"n\" {").is_empty());
+}
+
+#[test]
+fn test_touchscreen_gestures() {
+    use crate::event_handler::SyntheticEvent;
+    use crate::touch::TouchTracker;
+
+    let config: Config = knuffel::parse("test.kdl", "touchscreen min-fingers=2").unwrap();
+    let settings = config.touchscreen.unwrap();
+    assert_eq!(settings.threshold, Float(5.0));
+
+    // Two fingers held still begin a hold, lifting them ends it
+    let mut touch = TouchTracker::new(settings.clone());
+    touch.down(0, 10.0, 10.0);
+    assert!(touch.frame().is_empty());
+    touch.down(1, 30.0, 10.0);
+    assert_eq!(
+        touch.frame(),
+        vec![SyntheticEvent::HoldBegin { fingers: 2 }]
+    );
+    touch.motion(0, 11.0, 10.0);
+    assert!(touch.frame().is_empty());
+    touch.up(0);
+    touch.up(1);
+    assert_eq!(touch.frame(), vec![SyntheticEvent::HoldEnd]);
+
+    // Moving together is a swipe, the first update covers the movement so far
+    touch.down(0, 10.0, 10.0);
+    touch.down(1, 30.0, 10.0);
+    touch.frame();
+    touch.motion(0, 10.0, 20.0);
+    touch.motion(1, 30.0, 20.0);
+    assert_eq!(
+        touch.frame(),
+        vec![
+            SyntheticEvent::HoldCancel,
+            SyntheticEvent::SwipeBegin { fingers: 2 },
+            SyntheticEvent::SwipeUpdate { dx: 0.0, dy: 10.0 },
+        ]
+    );
+    // Lifting one finger ends the swipe, the other one can't start a new gesture
+    touch.up(1);
+    assert_eq!(touch.frame(), vec![SyntheticEvent::SwipeEnd]);
+    touch.motion(0, 10.0, 40.0);
+    assert!(touch.frame().is_empty());
+    touch.up(0);
+    assert!(touch.frame().is_empty());
+
+    // Spreading apart is a pinch with the scale relative to the start
+    touch.down(0, 20.0, 10.0);
+    touch.down(1, 30.0, 10.0);
+    touch.frame();
+    touch.motion(0, 15.0, 10.0);
+    touch.motion(1, 35.0, 10.0);
+    let events = touch.frame();
+    assert_eq!(events[1], SyntheticEvent::PinchBegin { fingers: 2 });
+    assert_eq!(
+        events[2],
+        SyntheticEvent::PinchUpdate {
+            scale: 2.0,
+            angle: 0.0
+        }
+    );
+    assert_eq!(touch.cancel(), vec![SyntheticEvent::PinchCancel]);
+
+    // Fewer fingers than min-fingers are left alone
+    let mut touch = TouchTracker::new(settings);
+    touch.down(0, 10.0, 10.0);
+    touch.frame();
+    touch.motion(0, 10.0, 50.0);
+    assert!(touch.frame().is_empty());
+}
+
+#[test]
+fn test_zoom_sensitivity() {
+    assert_eq!(zoom_sensitivity("@zoom"), Some(1.0));
+    assert_eq!(zoom_sensitivity(" @zoom 2.5 "), Some(2.5));
+    assert_eq!(zoom_sensitivity("@zoomies"), None);
+    assert_eq!(zoom_sensitivity("xdotool key ctrl+plus"), None);
+}
+
+#[test]
+fn test_pinch_min_scale_delta() {
+    use crate::gestures::pinch::PinchDir;
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        pinch direction="out" fingers=2 end="zoom in" min-scale-delta=0.2
+        pinch direction="in" fingers=2 end="zoom out"
+        "#,
+    )
+    .unwrap();
+    let (Gesture::Pinch(out), Gesture::Pinch(r#in)) = (&config.gestures[0], &config.gestures[1])
+    else {
+        panic!("expected pinch rules");
+    };
+    // The last update decides without a threshold, the total scale with one
+    assert!(r#in.ends_on(&PinchDir::In, 1.01));
+    assert!(!out.ends_on(&PinchDir::Out, 1.1));
+    assert!(out.ends_on(&PinchDir::Out, 1.25));
+    assert!(out.ends_on(&PinchDir::Clockwise, 1.25));
+    assert!(!out.ends_on(&PinchDir::Out, 0.7));
+}
+
+#[test]
+fn test_palm_rejection_resting_thumb() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        "palm-rejection bottom-margin=20\nswipe direction=\"n\" fingers=3 end=\"true\"",
+    )
+    .unwrap();
+    let rejection = config.palm_rejection.unwrap();
+    assert_eq!(rejection.side_margin, Float(5.0));
+    assert_eq!(rejection.bottom_margin, Float(20.0));
+
+    // EV_ABS codes: slot, tracking id, x, y
+    let (abs, slot, id, x, y) = (0x03, 0x2f, 0x39, 0x35, 0x36);
+    let mut pad = PadState::new((0, 1000), (0, 600));
+    for (code, value) in [(slot, 0), (id, 1), (x, 300), (y, 200)] {
+        pad.apply(abs, code, value);
+    }
+    for (code, value) in [(slot, 1), (id, 2), (x, 500), (y, 580)] {
+        pad.apply(abs, code, value);
+    }
+    assert_eq!(pad.resting_contacts(&rejection), 1);
+
+    // The bottom contact moves along with the gesture, so it isn't a resting thumb
+    pad.apply(abs, y, 520);
+    pad.apply(abs, x, 600);
+    assert_eq!(pad.resting_contacts(&rejection), 0);
+
+    pad.apply(abs, id, -1);
+    assert_eq!(pad.resting_contacts(&rejection), 0);
+}
+
+#[test]
+fn test_swipe_start_edge() {
+    use crate::contacts::PadEdge;
+
+    let (abs, slot, id, x, y) = (0x03, 0x2f, 0x39, 0x35, 0x36);
+    let mut pad = PadState::new((0, 1000), (0, 600));
+    assert_eq!(pad.start_edge(), None);
+    for (n, start) in [(0, 40), (1, 180), (2, 320)] {
+        for (code, value) in [(slot, n), (id, n + 1), (x, start), (y, 300)] {
+            pad.apply(abs, code, value);
+        }
+    }
+    // The outermost finger decides, later movement doesn't change where the swipe started
+    assert_eq!(pad.start_edge(), Some(PadEdge::Left));
+    pad.apply(abs, slot, 0);
+    pad.apply(abs, x, 700);
+    assert_eq!(pad.start_edge(), Some(PadEdge::Left));
+
+    // In a corner the closer border wins
+    let mut pad = PadState::new((0, 1000), (0, 600));
+    for (code, value) in [(slot, 0), (id, 1), (x, 950), (y, 590)] {
+        pad.apply(abs, code, value);
+    }
+    assert_eq!(pad.start_edge(), Some(PadEdge::Bottom));
+    pad.apply(abs, id, -1);
+    assert_eq!(pad.start_edge(), None);
+
+    let gesture: Gesture = knuffel::parse::<Vec<Gesture>>(
+        "test.kdl",
+        r#"swipe direction="e" fingers=3 edge="left" end="sidebar""#,
+    )
+    .unwrap()
+    .remove(0);
+    let Gesture::Swipe(swipe) = gesture else {
+        panic!("expected a swipe");
+    };
+    assert_eq!(swipe.edge, Some(PadEdge::Left));
+}
+
+#[test]
+#[cfg(feature = "x11")]
+fn test_snap_geometry() {
+    use crate::snap::{half_geometry, SnapTarget};
+
+    assert_eq!("left".parse(), Ok(SnapTarget::Left));
+    assert!("top".parse::<SnapTarget>().is_err());
+    for target in [SnapTarget::Left, SnapTarget::Right, SnapTarget::Maximize] {
+        assert_eq!(SnapTarget::from_param(target.to_param()), target);
+    }
+
+    // Work area below a 30px panel, windows with 2px borders and a 20px title bar
+    let area = (0, 30, 1921, 1050);
+    let frame = (2, 2, 20, 2);
+    assert_eq!(
+        half_geometry(SnapTarget::Left, area, frame),
+        (0, 30, 956, 1028)
+    );
+    assert_eq!(
+        half_geometry(SnapTarget::Right, area, frame),
+        (961, 30, 956, 1028)
+    );
+}
+
+#[test]
+fn test_wakeup_profile_summary() {
+    use crate::wakeups::{Subsystem, Wakeup, WakeupProfile};
+
+    let start = Instant::now();
+    let mut profile = WakeupProfile::new(start);
+    for _ in 0..9 {
+        profile.wakeup(Wakeup::Timeout);
+    }
+    profile.wakeup(Wakeup::Input);
+    profile.batch(0);
+    profile.batch(6);
+    profile.batch(2);
+    profile.spent(Subsystem::Poll, Duration::from_millis(1990));
+    profile.spent(Subsystem::Dispatch, Duration::from_micros(1500));
+    profile.spent(Subsystem::Gestures, Duration::from_micros(2500));
+
+    assert_eq!(
+        profile.summary(start + Duration::from_secs(2)).to_string(),
+        "Wakeups over 2s: 10 (5.0/s) input=1 timeout=9 interrupted=0; 2 dispatch batches \
+         (4.0 events/batch); busy 4.0ms (dispatch=1.5ms gestures=2.5ms hold-timers=0.0ms), \
+         poll=1990.0ms"
+    );
+}
+
+#[test]
+fn test_log_buffer_follow() {
+    let (_, rx) = crate::logs::follow();
+    for i in 0..1005 {
+        crate::logs::push(format!("record {i}"));
+    }
+    let records = crate::logs::snapshot();
+    assert_eq!(records.len(), 1000);
+    assert_eq!(records.last().map(String::as_str), Some("record 1004"));
+
+    // The follower queue overflowed, so it was disconnected after the records it could hold
+    assert_eq!(rx.recv().unwrap(), "record 0");
+    assert_eq!(rx.iter().count(), 255);
+}
+
+#[test]
+fn test_remote_ipc_requires_token() {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="w" fingers=4 end="true"
+        hold fingers=3 action="true"
+        profile "docked" {
+            swipe direction="w" fingers=4 end="true"
+        }
+        "#,
+    )
+    .unwrap();
+    let config = std::sync::Arc::new(parking_lot::RwLock::new(config));
+    let addr = crate::ipc::create_remote_listener(
+        "127.0.0.1:0".parse().unwrap(),
+        "s3cret".to_string(),
+        config,
+        None,
+    )
+    .unwrap();
+
+    let request = |lines: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(lines.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    assert_eq!(
+        request("auth wrong\nstatus\n"),
+        "error: authentication failed\n"
+    );
+    assert_eq!(request("status\n"), "error: authentication failed\n");
+    let status = request("auth s3cret\nstatus\n");
+    assert!(status.starts_with("version "), "{status}");
+    assert!(status.contains("\nrules 3\n"), "{status}");
+    assert!(
+        status.contains("\nrule-types swipe=2 pinch=0 hold=1 rotate=0\n"),
+        "{status}"
+    );
+    assert!(status.contains("\nconfig "), "{status}");
+    assert!(status.contains("\nlast-gesture "), "{status}");
+
+    let json = Json::parse(&request("auth s3cret\nstatus json\n")).unwrap();
+    assert_eq!(
+        json.get("version").and_then(|v| v.as_str()),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(
+        json.get("pid").and_then(|v| v.as_u64()),
+        Some(std::process::id() as u64)
+    );
+    assert_eq!(
+        json.pointer(&["rules", "total"]).and_then(|v| v.as_u64()),
+        Some(3)
+    );
+    assert_eq!(
+        json.pointer(&["rules", "hold"]).and_then(|v| v.as_u64()),
+        Some(1)
+    );
+    assert!(json.get("config").and_then(|v| v.as_array()).is_some());
+
+    assert!(!request("auth s3cret\nhistory\n").starts_with("error"));
+    let validation = Json::parse(&request("auth s3cret\nvalidate \"\"\n")).unwrap();
+    assert_eq!(validation.get("ok"), Some(&Json::Bool(true)));
+    // Commands that change the config or stream without end stay local
+    for command in [
+        "apply \"\"",
+        "apply-temporary 5 \"\"",
+        "profile docked",
+        "logs follow",
+        "subscribe",
+        "monitor",
+        "statusx",
+    ] {
+        let response = request(&format!("auth s3cret\n{command}\n"));
+        assert!(
+            response.starts_with(&format!("error: `{command}` is not available remotely")),
+            "{response}"
+        );
+    }
+}
+
+#[test]
+fn test_remote_ipc_limits_connections() {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+
+    let config: Config = knuffel::parse("test.kdl", "").unwrap();
+    let config = std::sync::Arc::new(parking_lot::RwLock::new(config));
+    let addr = crate::ipc::create_remote_listener(
+        "127.0.0.1:0".parse().unwrap(),
+        "s3cret".to_string(),
+        config,
+        None,
+    )
+    .unwrap();
+    // Written without closing, so the daemon answers before the client is done sending
+    let request = |lines: &[u8]| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(lines).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // A line longer than any token is rejected without waiting for its end
+    let mut long = b"auth ".to_vec();
+    long.resize(2048, b'x');
+    assert_eq!(request(&long), "error: authentication failed\n");
+
+    // Clients that don't authenticate hold their connection until the auth timeout
+    let mut idle: Vec<_> = (0..8).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(request(b""), "error: too many connections\n");
+
+    idle.pop().unwrap().shutdown(Shutdown::Both).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let authenticated = || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"auth s3cret\nstatus\n").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    };
+    let mut status = authenticated();
+    while !status.starts_with("version ") && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+        status = authenticated();
+    }
+    assert!(status.starts_with("version "), "{status}");
+}
+
+#[test]
+fn test_gui_config_protocol() {
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+
+    let config: Config =
+        knuffel::parse("test.kdl", r#"swipe direction="w" fingers=4 end="true""#).unwrap();
+    let config = std::sync::Arc::new(parking_lot::RwLock::new(config));
+    let request = |line: String| {
+        let (mut stream, daemon) = UnixStream::pair().unwrap();
+        let config = config.clone();
+        std::thread::spawn(move || crate::ipc::serve_local(daemon, config));
+        writeln!(stream, "{line}").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        Json::parse(&response).unwrap_or_else(|e| panic!("{e}: {response}"))
+    };
+
+    let schema = request("schema".to_string());
+    let swipe = schema
+        .get("nodes")
+        .and_then(Json::as_array)
+        .unwrap()
+        .iter()
+        .find(|n| n.get("name").and_then(Json::as_str) == Some("swipe"))
+        .unwrap();
+    let direction = swipe
+        .get("properties")
+        .and_then(Json::as_array)
+        .unwrap()
+        .iter()
+        .find(|p| p.get("name").and_then(Json::as_str) == Some("direction"))
+        .unwrap();
+    assert_eq!(
+        direction
+            .get("values")
+            .and_then(Json::as_array)
+            .map(|v| v.len()),
+        Some(9)
+    );
+    assert_eq!(
+        swipe.pointer(&["children"]).and_then(Json::as_array),
+        Some(&[Json::from("drop-zone")][..])
+    );
+
+    let candidate = |text: &str| Json::from(text).to_string();
+    let invalid = request(format!("validate {}", candidate("swipe fingers=\"x\"")));
+    assert_eq!(invalid.get("ok"), Some(&Json::Bool(false)));
+    assert_eq!(
+        invalid
+            .pointer(&["errors"])
+            .and_then(Json::as_array)
+            .map(|e| e.is_empty()),
+        Some(false)
+    );
+    let trial = r#"hold fingers=3 action="true""#;
+    assert_eq!(
+        request(format!("validate {}", candidate(trial))).get("ok"),
+        Some(&Json::Bool(true))
+    );
+    assert_eq!(config.read().gestures.len(), 1);
+    assert!(matches!(config.read().gestures[0], Gesture::Swipe(_)));
+
+    // A bad candidate leaves the running config alone
+    let refused = request(format!(
+        "apply-temporary 1 {}",
+        candidate("swipe fingers=\"x\"")
+    ));
+    assert_eq!(refused.get("ok"), Some(&Json::Bool(false)));
+    assert!(matches!(config.read().gestures[0], Gesture::Swipe(_)));
+
+    let applied = request(format!("apply-temporary 1 {}", candidate(trial)));
+    assert_eq!(applied.get("ok"), Some(&Json::Bool(true)));
+    assert_eq!(
+        applied.get("reverts_in_secs").and_then(Json::as_u64),
+        Some(1)
+    );
+    assert!(matches!(config.read().gestures[0], Gesture::Hold(_)));
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    assert!(matches!(config.read().gestures[0], Gesture::Swipe(_)));
+}
+
+#[test]
+fn test_takeover_passes_listener_fd() {
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = std::env::temp_dir().join(format!("gestures-takeover-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("gestures.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let (old, new) = UnixStream::pair().unwrap();
+    crate::ipc::send_fd(&old, listener.as_raw_fd(), b"ok 42\n").unwrap();
+    drop(listener);
+
+    let mut buf = [0u8; 64];
+    let (len, fd) = crate::ipc::recv_fd(&new, &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"ok 42\n");
+    let inherited = UnixListener::from(fd.expect("listener fd should be attached"));
+
+    // Clients of the old socket path now reach the inherited listener
+    let mut client = UnixStream::connect(&path).unwrap();
+    client.write_all(b"status\n").unwrap();
+    let (mut accepted, _) = inherited.accept().unwrap();
+    let mut line = [0u8; 7];
+    accepted.read_exact(&mut line).unwrap();
+    assert_eq!(&line, b"status\n");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_spawn_options() {
+    use crate::utils::{Input, Output};
+
+    let dir = std::env::temp_dir().join(format!("gestures-spawn-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("out.log");
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        &format!(
+            r#"hold fingers=4 action="pwd" cwd="{}" stdin="null" stdout="{}" stderr="null""#,
+            dir.display(),
+            log.display()
+        ),
+    )
+    .unwrap();
+    let Gesture::Hold(hold) = &config.gestures[0] else {
+        panic!("expected a hold rule");
+    };
+    assert_eq!(hold.spawn.stdin, Some(Input::Null));
+    assert_eq!(hold.spawn.stdout, Some(Output::File(log.clone())));
+    assert_eq!(hold.spawn.stderr, Some(Output::Null));
+
+    let mut command = std::process::Command::new("sh");
+    command.arg("-c").arg(hold.action.as_deref().unwrap());
+    hold.spawn.apply(&mut command).unwrap();
+    assert!(command.status().unwrap().success());
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap().trim(),
+        dir.canonicalize().unwrap().display().to_string()
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_expand_path() {
+    let lookup = |name: &str| match name {
+        "HOME" => Some("/home/me".to_string()),
+        "XDG_CACHE_HOME" => Some("/cache".to_string()),
+        _ => None,
+    };
+    assert_eq!(expand_path_with("~", lookup), "/home/me");
+    assert_eq!(expand_path_with("~/bin", lookup), "/home/me/bin");
+    assert_eq!(expand_path_with("~other/bin", lookup), "~other/bin");
+    assert_eq!(
+        expand_path_with("$XDG_CACHE_HOME/${HOME}x/$UNSET", lookup),
+        "/cache//home/mex/$UNSET"
+    );
+}
+
+#[test]
+fn test_max_concurrent_skips_overlapping_commands() {
+    let dir = std::env::temp_dir().join(format!("gestures-concurrent-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("runs.log");
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        &format!(
+            r#"swipe direction="n" fingers=3 update="echo $delta_y >> {}; sleep 0.3" max-concurrent=1"#,
+            log.display()
+        ),
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &config.gestures[0] else {
+        panic!("expected a swipe rule");
+    };
+    assert_eq!(swipe.spawn.max_concurrent, Some(1));
+
+    let update = swipe.update.as_deref().unwrap();
+    let runs = || {
+        std::fs::read_to_string(&log)
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    };
+    let wait_for = |n: usize| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while runs() < n && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+    // Other variable values are still the same rule
+    for delta_y in 1..=5 {
+        let vars = CommandVars {
+            delta_y: f64::from(delta_y),
+            ..CommandVars::default()
+        };
+        exec_command_from_string(update, &vars, &swipe.spawn).unwrap();
+    }
+    wait_for(1);
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(runs(), 1);
+
+    // Runs again once the first invocation finished
+    exec_command_from_string(update, &CommandVars::default(), &swipe.spawn).unwrap();
+    wait_for(2);
+    assert_eq!(runs(), 2);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_broken_system_config_keeps_user_config() {
+    let dir = std::env::temp_dir().join(format!("gestures-system-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let system = dir.join("system.kdl");
+    let user = dir.join("user.kdl");
+    std::fs::write(&system, "swipe fingers=\"x\"\n").unwrap();
+    std::fs::write(&user, "hold fingers=4 action=\"user\"\n").unwrap();
+
+    let config = Config::over_system(&system, Config::read_from_file(&user)).unwrap();
+    assert_eq!(config.gestures.len(), 1);
+    assert!(matches!(&config.gestures[0], Gesture::Hold(h) if h.fingers == 4));
+
+    std::fs::write(&system, "hold fingers=3 action=\"system\"\n").unwrap();
+    let config = Config::over_system(&system, Config::read_from_file(&user)).unwrap();
+    assert_eq!(config.gestures.len(), 2);
+    let missing = dir.join("missing.kdl");
+    let config = Config::over_system(&system, Config::read_from_file(&missing)).unwrap();
+    assert_eq!(config.gestures.len(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_watch_config_reloads_on_change() {
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("gestures-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("gestures.kdl");
+    std::fs::write(&path, "watch true\nhold fingers=4 action=\"old\"\n").unwrap();
+
+    let config = Config::read_from_file(&path).unwrap();
+    assert_eq!(config.watch, Some(true));
+    let files = crate::watch::config_files(Some(&path));
+    assert_eq!(files[0], path);
+    assert!(files.contains(&std::path::PathBuf::from(crate::config::SYSTEM_CONFIG)));
+
+    let config = Arc::new(RwLock::new(config));
+    crate::watch::spawn(config.clone(), Some(path.clone())).unwrap();
+    // Written next to the config and renamed over it, as editors do
+    let tmp = dir.join(".gestures.kdl.swp");
+    std::fs::write(&tmp, "hold fingers=4 action=\"new\"\n").unwrap();
+    std::fs::rename(&tmp, &path).unwrap();
+
+    let action = || match &config.read().gestures[..] {
+        [Gesture::Hold(h)] => h.action.clone(),
+        _ => None,
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while action().as_deref() != Some("new") && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(action().as_deref(), Some("new"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_config_include() {
+    let dir = std::env::temp_dir().join(format!("gestures-include-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("de")).unwrap();
+    let path = dir.join("gestures.kdl");
+    std::fs::write(
+        dir.join("base.kdl"),
+        "vars { term \"foot\"; }\nhold fingers=4 action=\"base\"\nhold fingers=3 action=\"base\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("de/hyprland.kdl"),
+        "include \"../base.kdl\"\nhold fingers=3 action=\"hyprland\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &path,
+        "include \"de/hyprland.kdl\"\nhold fingers=4 action=\"user\"\n",
+    )
+    .unwrap();
+
+    // The including file wins over what it includes, at every level
+    let config = Config::read_from_file(&path).unwrap();
+    let actions: Vec<_> = config
+        .gestures
+        .iter()
+        .map(|g| match g {
+            Gesture::Hold(h) => (h.fingers, h.action.clone().unwrap()),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        vec![(3, "hyprland".to_string()), (4, "user".to_string())]
+    );
+    assert_eq!(config.vars.entries[0].value, "foot");
+    assert!(config.includes.is_empty());
+
+    let files = crate::watch::config_files(Some(&path));
+    assert!(files.contains(&dir.join("de/hyprland.kdl")));
+    assert!(files.contains(&dir.join("de/../base.kdl")));
+
+    std::fs::write(dir.join("base.kdl"), "include \"gestures.kdl\"\n").unwrap();
+    let err = Config::read_from_file(&path).unwrap_err().to_string();
+    assert!(err.contains("includes itself"), "{}", err);
+    std::fs::write(&path, "include \"missing.kdl\"\n").unwrap();
+    assert!(Config::read_from_file(&path).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_waybar_state_lines() {
+    let state = DaemonState {
+        profiles: vec!["battery".to_string(), "docked".to_string()],
+        last_gesture: Some("4-finger swipe w".to_string()),
+    };
+    let line = state.to_json().to_string();
+    assert_eq!(
+        line,
+        r#"{"profiles":["battery","docked"],"last_gesture":"4-finger swipe w"}"#
+    );
+    let parsed = DaemonState::from_json(&Json::parse(&line).unwrap());
+    assert_eq!(parsed, state);
+
+    let running = waybar(Some(&parsed));
+    assert_eq!(
+        running.get("text").and_then(Json::as_str),
+        Some("battery, docked")
+    );
+    assert_eq!(running.get("class").and_then(Json::as_str), Some("running"));
+    assert_eq!(
+        running.get("tooltip").and_then(Json::as_str),
+        Some("Profiles: battery, docked\nLast gesture: 4-finger swipe w")
+    );
+
+    let idle = waybar(Some(&DaemonState::default()));
+    assert_eq!(idle.get("text").and_then(Json::as_str), Some("default"));
+    let stopped = waybar(None);
+    assert_eq!(stopped.get("class").and_then(Json::as_str), Some("stopped"));
+}
+
+#[test]
+fn test_key_combos() {
+    let zoom: KeyCombo = "ctrl+plus".parse().unwrap();
+    assert_eq!(zoom.codes(), &[29, 78]);
+    #[cfg(feature = "x11")]
+    assert_eq!(zoom.keysyms(), "ctrl+KP_Add");
+    assert_eq!(zoom.to_string(), "ctrl+plus");
+
+    let combo: KeyCombo = "Super + Shift + F12".parse().unwrap();
+    assert_eq!(combo.codes(), &[125, 42, 88]);
+    #[cfg(feature = "x11")]
+    assert_eq!(combo.keysyms(), "super+shift+F12");
+    let combo: KeyCombo = "alt+t+0".parse().unwrap();
+    assert_eq!(combo.codes(), &[56, 20, 11]);
+
+    assert!("ctrl+nope".parse::<KeyCombo>().is_err());
+    assert!("f13".parse::<KeyCombo>().is_err());
+    assert!("ctrl+".parse::<KeyCombo>().is_err());
+    assert!(knuffel::parse::<Config>("gestures.kdl", r#"hold fingers=4 key="ctrl+nope""#).is_err());
+
+    let c: Config = knuffel::parse(
+        "gestures.kdl",
+        r#"swipe direction="w" fingers=3 key="alt+right"
+        hold fingers=4 key="super""#,
+    )
+    .unwrap();
+    assert_eq!(c.gestures.len(), 2);
+}
+
+#[test]
+fn test_type_text() {
+    assert_eq!(
+        text_codes("Hi!\n"),
+        Some(vec![vec![42, 35], vec![23], vec![42, 2], vec![28]])
+    );
+    assert_eq!(text_codes("a-b"), Some(vec![vec![30], vec![12], vec![48]]));
+    assert_eq!(text_codes("👍"), None);
+    assert_eq!(text_codes("café"), None);
+
+    let c: Config = knuffel::parse(
+        "gestures.kdl",
+        r#"hold fingers=4 type-text="Best regards" paste=true"#,
+    )
+    .unwrap();
+    let Gesture::Hold(hold) = &c.gestures[0] else {
+        panic!("expected a hold rule");
+    };
+    assert_eq!(hold.type_text.as_deref(), Some("Best regards"));
+    assert_eq!(hold.paste, Some(true));
+}
+
+#[test]
+fn test_session_lock_lines() {
+    assert!(is_lock_line(
+        "/org/freedesktop/login1/session/_32: org.freedesktop.login1.Session.Lock ()"
+    ));
+    assert!(is_lock_line(
+        "/org/freedesktop/login1/session/_32: org.freedesktop.DBus.Properties.PropertiesChanged \
+         ('org.freedesktop.login1.Session', {'LockedHint': <true>}, @as [])"
+    ));
+    assert!(!is_lock_line(
+        "/org/freedesktop/login1/session/_32: org.freedesktop.DBus.Properties.PropertiesChanged \
+         ('org.freedesktop.login1.Session', {'LockedHint': <false>}, @as [])"
+    ));
+    assert!(!is_lock_line(
+        "/org/freedesktop/login1/session/_32: org.freedesktop.login1.Session.Unlock ()"
+    ));
+    assert!(!is_lock_line(
+        "/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)"
+    ));
+    assert!(!is_lock_line(
+        "The name org.freedesktop.login1 is owned by :1.3"
+    ));
+}
+
+#[test]
+fn test_output_check_description() {
+    use crate::compositor::Backend;
+    use crate::status::describe_output_check;
+
+    assert_eq!(describe_output_check(Backend::Uinput, &Ok(())), "uinput ok");
+    assert_eq!(
+        describe_output_check(
+            Backend::Ydotool,
+            &Err("ydotool exit status: 2, is ydotoold running?".to_string())
+        ),
+        "ydotool failed: ydotool exit status: 2, is ydotoold running?"
+    );
+    assert_eq!(
+        describe_output_check(
+            Backend::VirtualPointer,
+            &Err("pointer worker did not answer".to_string())
+        ),
+        "wlr-virtual-pointer failed: pointer worker did not answer"
+    );
+}
+
+#[test]
+fn test_dbus_call_encoding() {
+    use crate::dbus::{encode_call, DbusArg, DbusArgs};
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"swipe direction="any" fingers=4 dbus-dest="org.gnome.Shell" dbus-path="/org/gnome/Shell" dbus-method="org.gnome.Shell.Eval" dbus-args="d:$delta_x i:$delta_y s:ws""#,
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &config.gestures[0] else {
+        panic!("expected a swipe rule");
+    };
+    assert_eq!(swipe.dbus.dbus_dest.as_deref(), Some("org.gnome.Shell"));
+    let args = swipe.dbus.dbus_args.as_ref().unwrap();
+    let vars = CommandVars {
+        delta_x: 1.5,
+        delta_y: -3.75,
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        args.render(&vars).unwrap(),
+        vec![
+            DbusArg::Double(1.5),
+            DbusArg::Int32(-3),
+            DbusArg::String("ws".to_string())
+        ]
+    );
+    assert!("d".parse::<DbusArgs>().is_err());
+    assert!("q:1".parse::<DbusArgs>().is_err());
+    assert!("b:$delta_x"
+        .parse::<DbusArgs>()
+        .unwrap()
+        .render(&vars)
+        .is_err());
+    assert!(knuffel::parse::<Config>(
+        "test.kdl",
+        r#"swipe direction="any" fingers=4 dbus-method="a.B" dbus-args="z:1""#
+    )
+    .is_err());
+
+    let msg = encode_call(
+        Some("org.gnome.Shell"),
+        "/org/gnome/Shell",
+        "org.gnome.Shell",
+        "Eval",
+        &[DbusArg::String("hi".to_string()), DbusArg::Double(1.5)],
+    );
+    // Little endian method call, no reply expected, protocol version 1
+    assert_eq!(&msg[..4], &[b'l', 1, 1, 1]);
+    let u32_at = |i: usize| u32::from_le_bytes(msg[i..i + 4].try_into().unwrap()) as usize;
+    let body_len = u32_at(4);
+    let fields_len = u32_at(12);
+    let body_start = (16 + fields_len).next_multiple_of(8);
+    assert_eq!(msg.len(), body_start + body_len);
+    // PATH field: code 1, variant signature `o`, then the string
+    assert_eq!(&msg[16..20], &[1, 1, b'o', 0]);
+    assert_eq!(u32_at(20), "/org/gnome/Shell".len());
+    assert_eq!(&msg[24..41], b"/org/gnome/Shell\0");
+    // SIGNATURE field comes last
+    assert_eq!(
+        &msg[16 + fields_len - 8..16 + fields_len],
+        &[8, 1, b'g', 0, 2, b's', b'd', 0]
+    );
+    let mut body = vec![2, 0, 0, 0, b'h', b'i', 0, 0];
+    body.extend_from_slice(&1.5f64.to_le_bytes());
+    assert_eq!(&msg[body_start..], &body[..]);
+}
+
+#[test]
+fn test_hyprland_dispatch() {
+    use crate::hyprland::{batch, reply_ok, request};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"swipe direction="any" fingers=3 hyprland="dispatch workspace e+1" hyprland-update="dispatch moveactive $delta_x $delta_y""#,
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &config.gestures[0] else {
+        panic!("expected a swipe rule");
+    };
+    assert_eq!(swipe.hyprland.as_deref(), Some("dispatch workspace e+1"));
+    let vars = CommandVars {
+        delta_x: 4.0,
+        delta_y: -2.5,
+        ..CommandVars::default()
+    };
+    assert_eq!(
+        crate::utils::render_command(swipe.hyprland_update.as_deref().unwrap(), &vars).as_deref(),
+        Some("dispatch moveactive 4.00 -2.50")
+    );
+
+    let one = vec!["dispatch workspace 1".to_string()];
+    assert_eq!(batch(one.clone()), one);
+    assert_eq!(
+        batch(vec!["dispatch a".to_string(), "dispatch b".to_string()]),
+        vec!["[[BATCH]]dispatch a;dispatch b".to_string()]
+    );
+    let separate = vec!["dispatch exec a; b".to_string(), "dispatch c".to_string()];
+    assert_eq!(batch(separate.clone()), separate);
+    assert!(reply_ok("ok"));
+    assert!(reply_ok("ok\n\nok"));
+    assert!(!reply_ok("ok\n\nInvalid dispatcher"));
+
+    let dir = std::env::temp_dir().join(format!("gestures-hypr-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(".socket.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 256];
+        let len = stream.read(&mut buf).unwrap();
+        stream.write_all(b"ok").unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    });
+    assert_eq!(request(&path, "dispatch workspace e+1").unwrap(), "ok");
+    assert_eq!(server.join().unwrap(), "dispatch workspace e+1");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_rule_log() {
+    use crate::logs::{RuleLevel, RuleLog};
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="w" fingers=4 end="true" log="info" log-label="workspace-switch"
+        hold fingers=3 action="true" log="debug"
+        pinch direction="in" fingers=2 end="true"
+        "#,
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &config.gestures[0] else {
+        panic!("expected a swipe rule");
+    };
+    assert_eq!(
+        swipe.log,
+        RuleLog {
+            log: Some(RuleLevel::Info),
+            log_label: Some("workspace-switch".to_string()),
+        }
+    );
+    let Gesture::Hold(hold) = &config.gestures[1] else {
+        panic!("expected a hold rule");
+    };
+    assert_eq!(hold.log.log, Some(RuleLevel::Debug));
+    assert_eq!(hold.log.log_label, None);
+    let Gesture::Pinch(pinch) = &config.gestures[2] else {
+        panic!("expected a pinch rule");
+    };
+    assert_eq!(pinch.log, RuleLog::default());
+    assert_eq!(log::Level::from(RuleLevel::Warn), log::Level::Warn);
+
+    assert!(
+        knuffel::parse::<Config>("test.kdl", r#"swipe direction="w" fingers=4 log="loud""#)
+            .is_err()
+    );
+    assert!(completions("rotate fingers=2 ", 17).contains(&("log-label", 10)));
+    assert!(completions("swipe fingers=3 log=\"", 21)
+        .iter()
+        .any(|(label, _)| *label == "debug"));
+}
+
+#[test]
+fn test_sway_ipc() {
+    use crate::sway::{command_errors, encode, read_message, run_command};
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="e" fingers=4 sway="workspace next"
+        pinch direction="out" fingers=3 sway-update="resize grow width $scale px"
+        "#,
+    )
+    .unwrap();
+    let Gesture::Swipe(swipe) = &config.gestures[0] else {
+        panic!("expected a swipe rule");
+    };
+    assert_eq!(swipe.sway.as_deref(), Some("workspace next"));
+    let Gesture::Pinch(pinch) = &config.gestures[1] else {
+        panic!("expected a pinch rule");
+    };
+    assert_eq!(
+        pinch.sway_update.as_deref(),
+        Some("resize grow width $scale px")
+    );
+
+    let msg = encode(0, "workspace next");
+    assert_eq!(&msg[..6], b"i3-ipc");
+    assert_eq!(msg.len(), 14 + "workspace next".len());
+    assert_eq!(
+        read_message(&mut &msg[..]).unwrap(),
+        (0, "workspace next".to_string())
+    );
+    assert!(read_message(&mut &b"i3-ipx\0\0\0\0\0\0\0\0"[..]).is_err());
+
+    assert!(command_errors(r#"[{"success":true},{"success":true}]"#).is_empty());
+    assert_eq!(
+        command_errors(r#"[{"success":true},{"success":false,"error":"Unknown command"}]"#),
+        vec!["Unknown command".to_string()]
+    );
+    assert_eq!(command_errors("nope").len(), 1);
+
+    // One connection serves every command
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    let compositor = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        for reply in [
+            r#"[{"success":true}]"#,
+            r#"[{"success":false,"error":"No such workspace"}]"#,
+        ] {
+            received.push(read_message(&mut server).unwrap());
+            server.write_all(&encode(0, reply)).unwrap();
+        }
+        received
+    });
+    assert!(run_command(&mut client, "workspace next")
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        run_command(&mut client, "workspace 42; focus left").unwrap(),
+        vec!["No such workspace".to_string()]
+    );
+    assert_eq!(
+        compositor.join().unwrap(),
+        vec![
+            (0, "workspace next".to_string()),
+            (0, "workspace 42; focus left".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_mpx_master_names() {
+    use crate::mpx::master_names;
+
+    assert_eq!(master_names("drag"), ("drag", "drag pointer".to_string()));
+    assert_eq!(
+        master_names("drag pointer"),
+        ("drag", "drag pointer".to_string())
+    );
+    assert_eq!(
+        master_names("Virtual core pointer"),
+        ("Virtual core", "Virtual core pointer".to_string())
+    );
+}
+
+#[test]
+fn test_check_reports_duplicates_and_unknown_variables() {
+    use crate::check::check;
+
+    let findings = check(
+        "test.kdl",
+        r#"
+vars {
+    term "alacritty"
+}
+swipe direction="e" fingers=4 end="$term -e htop"
+swipe direction="e" fingers=4 end="echo $delta_x $typo_var"
+swipe direction="e" fingers=4 app="firefox" end="true"
+hold fingers=3 action="a"
+hold fingers=3 action="b" trigger="timeout"
+profile "work" {
+    pinch direction="in" fingers=2 end="true"
+    pinch direction="in" fingers=2 end="false"
+}
+"#,
+    );
+    let errors: Vec<String> = findings.errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors[0].contains("`swipe fingers=4 direction=e` is defined twice in top level"));
+    assert!(
+        errors[1].contains("`pinch fingers=2 direction=in` is defined twice in profile \"work\"")
+    );
+    let warnings: Vec<String> = findings.warnings.iter().map(|e| e.to_string()).collect();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert!(warnings[0].contains("`$typo_var`"));
+
+    let findings = check("test.kdl", "swipe direction=\"up\" fingers=3\n");
+    assert_eq!(findings.errors.len(), 1);
+    assert!(findings.warnings.is_empty());
+}
+
+#[test]
+fn test_hold_repeat_must_be_positive() {
+    use crate::check::check;
+
+    let text = r#"
+profile "work" {
+    hold fingers=3 trigger="timeout" repeat=0 action="true"
+}
+"#;
+    let findings = check("test.kdl", text);
+    assert_eq!(findings.errors.len(), 1);
+    assert!(findings.errors[0]
+        .to_string()
+        .contains("`repeat` of `hold fingers=3` must be at least 1 millisecond"));
+
+    let path = std::env::temp_dir().join(format!("gestures-repeat-{}.kdl", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    assert!(Config::read_from_file(&path).is_err());
+    std::fs::write(&path, text.replace("repeat=0", "repeat=1")).unwrap();
+    assert!(Config::read_from_file(&path).is_ok());
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Tables of example configs, rewritten instead of compared with GESTURES_UPDATE_SNAPSHOTS=1
+#[test]
+fn test_gesture_table_snapshots() {
+    let cases = [
+        (
+            "default",
+            crate::get_default_config(),
+            include_str!("snapshots/default.table"),
+        ),
+        (
+            "profiles",
+            include_str!("snapshots/profiles.kdl"),
+            include_str!("snapshots/profiles.table"),
+        ),
+    ];
+    for (name, config, expected) in cases {
+        let table = gesture_table(name, config).unwrap();
+        if std::env::var_os("GESTURES_UPDATE_SNAPSHOTS").is_some() {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("src/tests/snapshots")
+                .join(format!("{name}.table"));
+            std::fs::write(path, &table).unwrap();
+            continue;
+        }
+        assert_eq!(
+            table, expected,
+            "{name} parses differently, rerun with GESTURES_UPDATE_SNAPSHOTS=1 if that is intended"
+        );
+    }
+}
+
+#[test]
+fn test_conflicting_gesture_handlers() {
+    let config: Config = knuffel::parse(
+        "test.kdl",
+        r#"
+        swipe direction="w" fingers=4 end="true"
+        profile "laptop" {
+            pinch direction="in" fingers=2 end="true"
+        }
+        "#,
+    )
+    .unwrap();
+    let process = |comm: &str, args: &[&str]| {
+        (
+            comm.to_string(),
+            args.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        )
+    };
+
+    let processes = [
+        process(
+            "python3",
+            &["/usr/bin/python3", "/usr/bin/libinput-gestures"],
+        ),
+        process("gnome-shell", &["/usr/bin/gnome-shell"]),
+        process("bash", &["bash", "-c", "touchegg-client"]),
+    ];
+    let conflicts = conflicts_with(&config, &processes);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].handler.name, "libinput-gestures");
+    assert_eq!(conflicts[0].fingers, vec![2, 4]);
+    assert!(conflicts[0].describe().contains("2/4-finger"));
+
+    // GNOME Shell only gets in the way of 3-finger rules
+    let three: Config =
+        knuffel::parse("test.kdl", r#"swipe direction="e" fingers=3 end="true""#).unwrap();
+    let conflicts = conflicts_with(&three, &processes);
+    assert_eq!(conflicts.len(), 2);
+    assert_eq!(conflicts[1].handler.name, "GNOME Shell");
+    assert!(conflicts_with(&Config::default(), &processes).is_empty());
+}
+
+#[test]
+fn test_event_loop_errors() {
+    use crate::event_handler::EventLoopError;
+    use crate::status::{describe_event_thread, EventThreadFailure};
+    use std::time::Instant;
+
+    // The typed error survives the `Report` the event thread returns
+    let report: miette::Report = EventLoopError::Libinput("seat0".to_string()).into();
+    let error = report.downcast_ref::<EventLoopError>().unwrap();
+    assert_eq!(error.exit_code(), 3);
+    assert_eq!(
+        report.to_string(),
+        "Could not initialize libinput on seat seat0"
+    );
+    let missing = EventLoopError::OpenDevice("/dev/input/event5".into());
+    assert_eq!(missing.exit_code(), 3);
+    assert_eq!(
+        missing.to_string(),
+        "Could not open input device /dev/input/event5"
+    );
+    let lost = EventLoopError::Dispatch(std::io::Error::from_raw_os_error(19));
+    assert_eq!(lost.exit_code(), 5);
+    assert!(miette::miette!("other")
+        .downcast_ref::<EventLoopError>()
+        .is_none());
+
+    assert_eq!(describe_event_thread(None), "ok");
+    let failure = EventThreadFailure {
+        error: lost.to_string(),
+        at: Instant::now(),
+        restarts: 2,
+    };
+    let described = describe_event_thread(Some(&failure));
+    assert!(described.starts_with("restarted 2 times, last error 0s ago: Failed to dispatch"));
+}
+
+#[test]
+fn test_update_rate() {
+    let system: Config = knuffel::parse("system.kdl", "update-rate 30").unwrap();
+    let user: Config = knuffel::parse(
+        "user.kdl",
+        r#"
+        update-rate 0
+        swipe direction="any" fingers=3 update="true" update-rate=120
+        pinch direction="any" fingers=2 update="true" update-rate=10
+        "#,
+    )
+    .unwrap();
+    match &user.gestures[..] {
+        [Gesture::Swipe(s), Gesture::Pinch(p)] => {
+            assert_eq!(s.update_rate, Some(120));
+            assert_eq!(p.update_rate, Some(10));
+        }
+        g => panic!("unexpected gestures {:?}", g),
+    }
+    let system = system.merge(Config::default());
+    assert_eq!(system.update_rate, Some(30));
+    assert_eq!(system.merge(user).update_rate, Some(0));
+}
+
+#[test]
+fn test_history() {
+    use crate::history::{self, CommandStatus};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    assert_eq!(
+        CommandStatus::from(ExitStatus::from_raw(1 << 8)),
+        CommandStatus::Exited(1)
+    );
+    assert_eq!(
+        CommandStatus::from(ExitStatus::from_raw(9)),
+        CommandStatus::Signaled(9)
+    );
+
+    let entry = history::fired("9-finger swipe n", Some("history-test"));
+    history::command_queued(entry, "notify-send hi");
+    let running = history::fired("9-finger swipe s", None);
+    history::command_queued(running, "sleep 10");
+    history::fired("9-finger swipe e", None);
+    history::unmatched("9-finger swipe w");
+    history::command_finished(entry, CommandStatus::Exited(1));
+
+    let lines: Vec<String> = history::snapshot().iter().map(|e| e.to_string()).collect();
+    let line = |gesture: &str| {
+        lines
+            .iter()
+            .find(|l| l.contains(gesture))
+            .map(|l| l.split_once(' ').unwrap().1.to_string())
+    };
+    assert_eq!(
+        line("9-finger swipe n").unwrap(),
+        "9-finger swipe n: rule [history-test] fired, ran 'notify-send hi': exit 1"
+    );
+    assert_eq!(
+        line("9-finger swipe s").unwrap(),
+        "9-finger swipe s: rule fired, ran 'sleep 10': running"
+    );
+    assert_eq!(
+        line("9-finger swipe e").unwrap(),
+        "9-finger swipe e: rule fired, no command"
+    );
+    assert_eq!(
+        line("9-finger swipe w").unwrap(),
+        "9-finger swipe w: no rule matched"
+    );
+}
+
+#[test]
+fn test_command_failures() {
+    use crate::failures::{FailureLog, Report};
+    use std::time::{Duration, Instant};
+
+    let config: Config = knuffel::parse("test.kdl", "notify-failures 3").unwrap();
+    assert_eq!(config.notify_failures, Some(3));
+
+    let mut log = FailureLog::default();
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    // The first failure is logged, those within the next minute are only counted
+    assert_eq!(
+        log.failed("hyprcl dispatch", at(0), 3),
+        Report {
+            log: Some(0),
+            notify: None
+        }
+    );
+    assert_eq!(log.failed("hyprcl dispatch", at(10), 3).log, None);
+    assert_eq!(
+        log.failed("hyprcl dispatch", at(20), 3),
+        Report {
+            log: None,
+            notify: Some(3)
+        }
+    );
+    // Notified once per streak, logged again with the failures in between
+    assert_eq!(
+        log.failed("hyprcl dispatch", at(61), 3),
+        Report {
+            log: Some(2),
+            notify: None
+        }
+    );
+    // Other commands count on their own
+    assert_eq!(log.failed("false", at(62), 3).log, Some(0));
+
+    log.succeeded("hyprcl dispatch");
+    assert_eq!(log.failed("hyprcl dispatch", at(63), 3).log, Some(0));
+    assert_eq!(log.failed("hyprcl dispatch", at(64), 0).notify, None);
+}
@@ -0,0 +1,290 @@
+//! Multi-finger gestures synthesized from touchscreen contacts
+//!
+//! libinput only recognizes gestures on touchpads, touchscreens report each finger as raw touch
+//! events. The tracker follows the contacts and turns them into the same swipe, pinch and hold
+//! events a touchpad would produce, so the configured rules apply to both.
+
+use std::collections::BTreeMap;
+
+use knuffel::Decode;
+
+use crate::event_handler::SyntheticEvent;
+use crate::gestures::Float;
+
+/// `touchscreen` config node
+#[derive(Decode, Debug, Clone, PartialEq)]
+pub struct Touchscreen {
+    /// Fewer fingers are left to the compositor (taps, scrolling, on-screen keyboards)
+    #[knuffel(property, default = 3)]
+    pub min_fingers: i32,
+    /// Movement in mm before the fingers are a swipe or a pinch rather than a hold
+    #[knuffel(property, default = Float(5.0))]
+    pub threshold: Float,
+}
+
+impl Default for Touchscreen {
+    fn default() -> Self {
+        Self {
+            min_fingers: 3,
+            threshold: Float(5.0),
+        }
+    }
+}
+
+/// Contact position in mm from the top left corner of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Contact {
+    start: (f64, f64),
+    current: (f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Waiting for enough fingers, or for them to hold still or move
+    Pending,
+    /// The fingers are held still, a hold began
+    Holding,
+    Swipe {
+        centroid: (f64, f64),
+    },
+    Pinch {
+        spread: f64,
+        angle: f64,
+    },
+    /// The gesture ended or was never one, nothing happens until every finger is lifted
+    Done,
+}
+
+/// Contacts of one touchscreen and the gesture they form
+#[derive(Debug)]
+pub struct TouchTracker {
+    pub settings: Touchscreen,
+    contacts: BTreeMap<u32, Contact>,
+    state: State,
+    /// Fingers of the gesture in progress
+    fingers: i32,
+    /// Spread when the pinch began, the scale is relative to it
+    initial_spread: f64,
+    /// Contacts were added or lifted since the last frame
+    changed: bool,
+}
+
+impl TouchTracker {
+    pub fn new(settings: Touchscreen) -> Self {
+        Self {
+            settings,
+            contacts: BTreeMap::new(),
+            state: State::Pending,
+            fingers: 0,
+            initial_spread: 0.0,
+            changed: false,
+        }
+    }
+
+    pub fn down(&mut self, slot: u32, x: f64, y: f64) {
+        let contact = Contact {
+            start: (x, y),
+            current: (x, y),
+        };
+        self.contacts.insert(slot, contact);
+        self.changed = true;
+    }
+
+    pub fn motion(&mut self, slot: u32, x: f64, y: f64) {
+        if let Some(contact) = self.contacts.get_mut(&slot) {
+            contact.current = (x, y);
+        }
+    }
+
+    pub fn up(&mut self, slot: u32) {
+        self.contacts.remove(&slot);
+        self.changed = true;
+    }
+
+    /// The compositor took over the contacts, the gesture in progress is abandoned
+    pub fn cancel(&mut self) -> Vec<SyntheticEvent> {
+        self.contacts.clear();
+        self.changed = false;
+        let events = match self.state {
+            State::Holding => vec![SyntheticEvent::HoldCancel],
+            State::Swipe { .. } => vec![SyntheticEvent::SwipeCancel],
+            State::Pinch { .. } => vec![SyntheticEvent::PinchCancel],
+            State::Pending | State::Done => Vec::new(),
+        };
+        self.state = State::Pending;
+        events
+    }
+
+    /// Gesture events for the contacts of a complete frame
+    pub fn frame(&mut self) -> Vec<SyntheticEvent> {
+        let changed = std::mem::take(&mut self.changed);
+        let count = self.contacts.len() as i32;
+        if count == 0 {
+            let events = match self.state {
+                State::Holding => vec![SyntheticEvent::HoldEnd],
+                State::Swipe { .. } => vec![SyntheticEvent::SwipeEnd],
+                State::Pinch { .. } => vec![SyntheticEvent::PinchEnd],
+                State::Pending | State::Done => Vec::new(),
+            };
+            self.state = State::Pending;
+            return events;
+        }
+
+        match self.state {
+            State::Done => Vec::new(),
+            State::Pending | State::Holding if changed => self.restart(count),
+            State::Pending | State::Holding => self.classify(),
+            // Lifting or adding a finger ends the gesture like it does on a touchpad
+            State::Swipe { .. } if changed => self.finish(SyntheticEvent::SwipeEnd),
+            State::Pinch { .. } if changed => self.finish(SyntheticEvent::PinchEnd),
+            State::Swipe { centroid } => {
+                let now = self.centroid();
+                self.state = State::Swipe { centroid: now };
+                vec![SyntheticEvent::SwipeUpdate {
+                    dx: now.0 - centroid.0,
+                    dy: now.1 - centroid.1,
+                }]
+            }
+            State::Pinch { spread, angle } => {
+                let now_spread = self.spread();
+                let now_angle = self.angle();
+                self.state = State::Pinch {
+                    spread: now_spread,
+                    angle: now_angle,
+                };
+                if now_spread == spread && now_angle == angle {
+                    return Vec::new();
+                }
+                vec![SyntheticEvent::PinchUpdate {
+                    scale: now_spread / self.initial_spread.max(f64::EPSILON),
+                    angle: wrap_degrees(now_angle - angle),
+                }]
+            }
+        }
+    }
+
+    /// The finger count changed before the gesture was recognized, measure from here
+    fn restart(&mut self, count: i32) -> Vec<SyntheticEvent> {
+        let mut events = Vec::new();
+        if self.state == State::Holding {
+            events.push(SyntheticEvent::HoldCancel);
+        }
+        for contact in self.contacts.values_mut() {
+            contact.start = contact.current;
+        }
+        self.fingers = count;
+        if count >= self.settings.min_fingers {
+            self.state = State::Holding;
+            events.push(SyntheticEvent::HoldBegin { fingers: count });
+        } else {
+            self.state = State::Pending;
+        }
+        events
+    }
+
+    /// Turn held fingers into a swipe or a pinch once they moved far enough
+    fn classify(&mut self) -> Vec<SyntheticEvent> {
+        let start = self.start_centroid();
+        let now = self.centroid();
+        let moved = (now.0 - start.0).hypot(now.1 - start.1);
+        let start_spread = self.start_spread();
+        let spread = self.spread();
+        // Rotation as the distance the fingers travelled around the centroid
+        let turned = wrap_degrees(self.angle() - self.start_angle())
+            .to_radians()
+            .abs()
+            * spread;
+        let pinched = (spread - start_spread).abs().max(turned);
+        if moved.max(pinched) < self.settings.threshold.0 {
+            return Vec::new();
+        }
+
+        if self.state == State::Pending {
+            // Too few fingers, the movement belongs to the compositor
+            self.state = State::Done;
+            return Vec::new();
+        }
+        let fingers = self.fingers;
+        let mut events = vec![SyntheticEvent::HoldCancel];
+        if pinched > moved {
+            self.initial_spread = start_spread;
+            self.state = State::Pinch {
+                spread: start_spread,
+                angle: self.start_angle(),
+            };
+            events.push(SyntheticEvent::PinchBegin { fingers });
+        } else {
+            self.state = State::Swipe { centroid: start };
+            events.push(SyntheticEvent::SwipeBegin { fingers });
+        }
+        // Report the movement that recognized the gesture right away
+        events.extend(self.frame());
+        events
+    }
+
+    fn finish(&mut self, end: SyntheticEvent) -> Vec<SyntheticEvent> {
+        self.state = State::Done;
+        vec![end]
+    }
+
+    fn centroid_of(&self, position: impl Fn(&Contact) -> (f64, f64)) -> (f64, f64) {
+        let n = self.contacts.len().max(1) as f64;
+        let (x, y) = self
+            .contacts
+            .values()
+            .map(&position)
+            .fold((0.0, 0.0), |acc, p| (acc.0 + p.0, acc.1 + p.1));
+        (x / n, y / n)
+    }
+
+    fn centroid(&self) -> (f64, f64) {
+        self.centroid_of(|c| c.current)
+    }
+
+    fn start_centroid(&self) -> (f64, f64) {
+        self.centroid_of(|c| c.start)
+    }
+
+    /// Mean distance of the fingers from their centroid
+    fn spread_of(&self, position: impl Fn(&Contact) -> (f64, f64)) -> f64 {
+        let center = self.centroid_of(&position);
+        let n = self.contacts.len().max(1) as f64;
+        self.contacts
+            .values()
+            .map(|c| {
+                let p = position(c);
+                (p.0 - center.0).hypot(p.1 - center.1)
+            })
+            .sum::<f64>()
+            / n
+    }
+
+    fn spread(&self) -> f64 {
+        self.spread_of(|c| c.current)
+    }
+
+    fn start_spread(&self) -> f64 {
+        self.spread_of(|c| c.start)
+    }
+
+    /// Direction of the first finger seen from the centroid in degrees, clockwise on screen
+    fn angle_of(&self, position: impl Fn(&Contact) -> (f64, f64)) -> f64 {
+        let center = self.centroid_of(&position);
+        self.contacts.values().next().map_or(0.0, |c| {
+            let p = position(c);
+            (p.1 - center.1).atan2(p.0 - center.0).to_degrees()
+        })
+    }
+
+    fn angle(&self) -> f64 {
+        self.angle_of(|c| c.current)
+    }
+
+    fn start_angle(&self) -> f64 {
+        self.angle_of(|c| c.start)
+    }
+}
+
+fn wrap_degrees(degrees: f64) -> f64 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
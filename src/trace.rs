@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Number of handler decisions kept for `gestures trace`
+const TRACE_CAPACITY: usize = 512;
+
+/// Events a slow `gestures monitor` client may lag behind before it is disconnected
+const MONITOR_QUEUE_CAPACITY: usize = 1024;
+
+static TRACE: Lazy<Mutex<VecDeque<String>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(TRACE_CAPACITY)));
+
+static MONITORS: Mutex<Vec<SyncSender<String>>> = Mutex::new(Vec::new());
+
+/// Whether any `gestures monitor` client is connected, checked before formatting updates
+static MONITORED: AtomicBool = AtomicBool::new(false);
+
+/// Send `entry` to the monitor clients, those that went away or fell too far behind are dropped
+fn send_to_monitors(entry: &str) {
+    let mut monitors = MONITORS.lock();
+    monitors.retain(|tx| tx.try_send(entry.to_string()).is_ok());
+    MONITORED.store(!monitors.is_empty(), Ordering::Relaxed);
+}
+
+fn timestamped(args: fmt::Arguments) -> String {
+    format!("{} {}", chrono::Local::now().format("%H:%M:%S%.3f"), args)
+}
+
+/// Record a handler decision, independent of the configured log level
+pub fn record(args: fmt::Arguments) {
+    let entry = timestamped(args);
+    if MONITORED.load(Ordering::Relaxed) {
+        send_to_monitors(&entry);
+    }
+    let mut trace = TRACE.lock();
+    if trace.len() == TRACE_CAPACITY {
+        trace.pop_front();
+    }
+    trace.push_back(entry);
+}
+
+/// Send a per-update event, e.g. swipe deltas, to `gestures monitor` clients without keeping it
+/// for `gestures trace`, a no-op while nobody is monitoring
+pub fn monitor(args: fmt::Arguments) {
+    if MONITORED.load(Ordering::Relaxed) {
+        send_to_monitors(&timestamped(args));
+    }
+}
+
+/// Receiver for every decision and update from now on
+pub fn follow() -> Receiver<String> {
+    let (tx, rx) = sync_channel(MONITOR_QUEUE_CAPACITY);
+    MONITORS.lock().push(tx);
+    MONITORED.store(true, Ordering::Relaxed);
+    rx
+}
+
+/// Oldest first copy of the recorded decisions
+pub fn snapshot() -> Vec<String> {
+    TRACE.lock().iter().cloned().collect()
+}
+
+macro_rules! trace_event {
+    ($($arg:tt)+) => {
+        $crate::trace::record(format_args!($($arg)+))
+    };
+}
+
+macro_rules! monitor_event {
+    ($($arg:tt)+) => {
+        $crate::trace::monitor(format_args!($($arg)+))
+    };
+}
+
+pub(crate) use monitor_event;
+pub(crate) use trace_event;
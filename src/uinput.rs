@@ -0,0 +1,195 @@
+//! Pointer injection through /dev/uinput, works the same under X11 and Wayland without libxdo or
+//! ydotool, the user needs write access to /dev/uinput
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::mouse_handler::HI_RES_UNITS_PER_NOTCH;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+/// Device name of the virtual pointer, as X11 and libinput list it
+pub const POINTER_NAME: &str = "gestures virtual pointer";
+
+/// linux/input-event-codes.h
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const SYN_REPORT: u16 = 0;
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_WHEEL: u16 = 0x08;
+const REL_WHEEL_HI_RES: u16 = 0x0b;
+pub const KEY_LEFTCTRL: u16 = 29;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+/// Size of `struct uinput_setup`: input_id (four u16), name[80], ff_effects_max
+const UINPUT_SETUP_SIZE: usize = 92;
+const UINPUT_MAX_NAME_SIZE: usize = 80;
+const BUS_VIRTUAL: u16 = 0x06;
+
+/// `_IOW('U', nr, size)` and `_IO('U', nr)` from linux/uinput.h
+const fn iow(nr: libc::c_ulong, size: libc::c_ulong) -> libc::c_ulong {
+    (1 << 30) | (size << 16) | ((b'U' as libc::c_ulong) << 8) | nr
+}
+const UI_DEV_CREATE: libc::c_ulong = (b'U' as libc::c_ulong) << 8 | 1;
+const UI_DEV_DESTROY: libc::c_ulong = (b'U' as libc::c_ulong) << 8 | 2;
+const UI_DEV_SETUP: libc::c_ulong = iow(3, UINPUT_SETUP_SIZE as libc::c_ulong);
+const UI_SET_EVBIT: libc::c_ulong = iow(100, 4);
+const UI_SET_KEYBIT: libc::c_ulong = iow(101, 4);
+const UI_SET_RELBIT: libc::c_ulong = iow(102, 4);
+
+/// Whether /dev/uinput can be opened for writing by this user
+pub fn available() -> bool {
+    let path = CString::new(UINPUT_PATH).unwrap();
+    // SAFETY: `path` is a valid NUL terminated string
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Evdev code of an X11 style button number (1 left, 2 middle, 3 right)
+pub fn evdev_button(button: i32) -> u16 {
+    match button {
+        2 => BTN_MIDDLE,
+        3 => BTN_RIGHT,
+        _ => BTN_LEFT,
+    }
+}
+
+/// `struct input_event` on 64-bit Linux, the kernel fills in the timestamp
+pub fn input_event(type_: u16, code: u16, value: i32) -> [u8; 24] {
+    let mut event = [0u8; 24];
+    event[16..18].copy_from_slice(&type_.to_ne_bytes());
+    event[18..20].copy_from_slice(&code.to_ne_bytes());
+    event[20..].copy_from_slice(&value.to_ne_bytes());
+    event
+}
+
+fn ioctl(file: &File, request: libc::c_ulong, arg: libc::c_ulong) -> io::Result<()> {
+    // SAFETY: every request used here takes an int argument or none at all
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Open /dev/uinput and create a device offering `keys` and `rels`
+fn create_device(name: &[u8], keys: &[u16], rels: &[u16]) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(UINPUT_PATH)?;
+
+    ioctl(&file, UI_SET_EVBIT, EV_KEY.into())?;
+    for &key in keys {
+        ioctl(&file, UI_SET_KEYBIT, key.into())?;
+    }
+    if !rels.is_empty() {
+        ioctl(&file, UI_SET_EVBIT, EV_REL.into())?;
+        for &rel in rels {
+            ioctl(&file, UI_SET_RELBIT, rel.into())?;
+        }
+    }
+
+    let mut setup = [0u8; UINPUT_SETUP_SIZE];
+    setup[..2].copy_from_slice(&BUS_VIRTUAL.to_ne_bytes());
+    setup[8..8 + name.len().min(UINPUT_MAX_NAME_SIZE - 1)].copy_from_slice(name);
+    // SAFETY: `setup` has the size and layout of struct uinput_setup expected by UI_DEV_SETUP
+    if unsafe { libc::ioctl(file.as_raw_fd(), UI_DEV_SETUP as _, setup.as_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    ioctl(&file, UI_DEV_CREATE, 0)?;
+    Ok(file)
+}
+
+/// Write `events` followed by a SYN_REPORT
+fn emit(mut file: &File, events: &[(u16, u16, i32)]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity((events.len() + 1) * 24);
+    for &(type_, code, value) in events.iter().chain(&[(EV_SYN, SYN_REPORT, 0)]) {
+        buf.extend_from_slice(&input_event(type_, code, value));
+    }
+    file.write_all(&buf)
+}
+
+/// A virtual mouse with the three buttons, motion, a wheel and left ctrl for zooming
+#[derive(Debug)]
+pub struct UinputPointer {
+    file: File,
+}
+
+impl UinputPointer {
+    pub fn create() -> io::Result<Self> {
+        let file = create_device(
+            POINTER_NAME.as_bytes(),
+            &[BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, KEY_LEFTCTRL],
+            &[REL_X, REL_Y, REL_WHEEL, REL_WHEEL_HI_RES],
+        )?;
+        Ok(Self { file })
+    }
+
+    fn emit(&mut self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        emit(&self.file, events)
+    }
+
+    pub fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        self.emit(&[(EV_REL, REL_X, dx), (EV_REL, REL_Y, dy)])
+    }
+
+    pub fn button(&mut self, button: i32, pressed: bool) -> io::Result<()> {
+        self.emit(&[(EV_KEY, evdev_button(button), i32::from(pressed))])
+    }
+
+    /// Ctrl + wheel, positive `notches` zoom in
+    pub fn zoom(&mut self, notches: i32) -> io::Result<()> {
+        self.emit(&[(EV_KEY, KEY_LEFTCTRL, 1)])?;
+        self.emit(&[
+            (EV_REL, REL_WHEEL, notches),
+            (
+                EV_REL,
+                REL_WHEEL_HI_RES,
+                notches.saturating_mul(HI_RES_UNITS_PER_NOTCH),
+            ),
+        ])?;
+        self.emit(&[(EV_KEY, KEY_LEFTCTRL, 0)])
+    }
+}
+
+impl Drop for UinputPointer {
+    fn drop(&mut self) {
+        let _ = ioctl(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
+
+/// A virtual keyboard for `key=` actions, offering the keys they can name
+#[derive(Debug)]
+pub struct UinputKeyboard {
+    file: File,
+}
+
+impl UinputKeyboard {
+    pub fn create(keys: &[u16]) -> io::Result<Self> {
+        let file = create_device(b"gestures virtual keyboard", keys, &[])?;
+        Ok(Self { file })
+    }
+
+    /// Press `codes` in order and release them in reverse, like a shortcut typed by hand
+    pub fn press(&mut self, codes: &[u16]) -> io::Result<()> {
+        for &code in codes {
+            emit(&self.file, &[(EV_KEY, code, 1)])?;
+        }
+        for &code in codes.iter().rev() {
+            emit(&self.file, &[(EV_KEY, code, 0)])?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UinputKeyboard {
+    fn drop(&mut self) {
+        let _ = ioctl(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
@@ -0,0 +1,251 @@
+//! Touchpad deltas in millimeters and screen pixels, for config values given with a unit
+//!
+//! libinput normalizes gesture deltas to a 1000 DPI device, so a unit is a fixed distance on
+//! every touchpad. At the default acceleration it moves the pointer about one logical pixel.
+
+use std::fmt;
+use std::str::FromStr;
+
+use knuffel::{
+    ast::{Literal, TypeName},
+    decode::Context,
+    errors::DecodeError,
+    span::Spanned,
+    traits::ErrorSpan,
+    DecodeScalar,
+};
+
+/// libinput units per millimeter of finger travel
+pub const UNITS_PER_MM: f64 = 1000.0 / 25.4;
+
+/// Touchpad width assumed for `%` values when libinput doesn't know the size of the device
+const DEFAULT_PAD_WIDTH_MM: f64 = 100.0;
+
+/// Legacy unitless `acceleration` values are tenths of a pixel per libinput unit
+const ACCELERATION_DIVISOR: f64 = 10.0;
+
+/// Converts between libinput units, millimeters and screen pixels for the current device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceUnits {
+    /// Touchpad size in mm as reported by libinput
+    pub size_mm: Option<(f64, f64)>,
+    /// Screen pixels per logical pixel, `output-scale` in the config or of the output under the
+    /// pointer with `output-scale "auto"`
+    pub output_scale: f64,
+    /// `output-scale "auto"`, drags move the same screen distance on every output
+    pub auto_scale: bool,
+}
+
+impl Default for DeviceUnits {
+    fn default() -> Self {
+        Self {
+            size_mm: None,
+            output_scale: 1.0,
+            auto_scale: false,
+        }
+    }
+}
+
+impl DeviceUnits {
+    pub fn mm(&self, units: f64) -> f64 {
+        units / UNITS_PER_MM
+    }
+
+    pub fn px(&self, units: f64) -> f64 {
+        units * self.output_scale
+    }
+
+    /// A configured distance in libinput units
+    pub fn units(&self, distance: Distance) -> f64 {
+        match distance {
+            Distance::Units(units) => units,
+            Distance::Mm(mm) => mm * UNITS_PER_MM,
+            Distance::Px(px) => px / self.output_scale,
+            Distance::PadPercent(percent) => {
+                let width = self.size_mm.map_or(DEFAULT_PAD_WIDTH_MM, |(w, _)| w);
+                width * percent / 100.0 * UNITS_PER_MM
+            }
+        }
+    }
+
+    /// Factor for drag deltas with `output-scale "auto"`, so the pointer covers the same screen
+    /// distance on a scaled output
+    pub fn compensation(&self) -> f64 {
+        if self.auto_scale {
+            1.0 / self.output_scale
+        } else {
+            1.0
+        }
+    }
+
+    /// Pointer pixels moved per libinput unit
+    pub fn pointer_factor(&self, acceleration: Acceleration) -> f64 {
+        match acceleration {
+            Acceleration::Factor(factor) => factor / ACCELERATION_DIVISOR * self.compensation(),
+            Acceleration::PxPerMm(px) => px / UNITS_PER_MM / self.output_scale,
+        }
+    }
+}
+
+/// Distance (or distance per second) in a config property: a bare number is in libinput units,
+/// `12mm`, `40px` and `10%` (of the touchpad width) are converted. A trailing `/s` is allowed
+/// for speeds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distance {
+    Units(f64),
+    Mm(f64),
+    Px(f64),
+    PadPercent(f64),
+}
+
+impl FromStr for Distance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_suffix("/s").unwrap_or(s);
+        let (number, unit): (&str, fn(f64) -> Self) = if let Some(n) = s.strip_suffix("mm") {
+            (n, Self::Mm)
+        } else if let Some(n) = s.strip_suffix("px") {
+            (n, Self::Px)
+        } else if let Some(n) = s.strip_suffix('%') {
+            (n, Self::PadPercent)
+        } else {
+            (s, Self::Units)
+        };
+        let value: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected a number with `mm`, `px` or `%`, got `{s}`"))?;
+        if value < 0.0 {
+            return Err(format!("`{s}` must not be negative"));
+        }
+        Ok(unit(value))
+    }
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Units(n) => write!(f, "{}", n),
+            Self::Mm(n) => write!(f, "{}mm", n),
+            Self::Px(n) => write!(f, "{}px", n),
+            Self::PadPercent(n) => write!(f, "{}%", n),
+        }
+    }
+}
+
+/// Speed of the pointer for drags: a bare number as before (tenths of a pixel per libinput
+/// unit) or `3px/mm`, screen pixels per millimeter of finger travel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Acceleration {
+    Factor(f64),
+    PxPerMm(f64),
+}
+
+impl FromStr for Acceleration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit): (&str, fn(f64) -> Self) = match s.strip_suffix("px/mm") {
+            Some(n) => (n, Self::PxPerMm),
+            None => (s, Self::Factor),
+        };
+        let value: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected a number or `<n>px/mm`, got `{s}`"))?;
+        if value <= 0.0 {
+            return Err(format!("acceleration `{s}` must be positive"));
+        }
+        Ok(unit(value))
+    }
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Factor(n) => write!(f, "{}", n),
+            Self::PxPerMm(n) => write!(f, "{}px/mm", n),
+        }
+    }
+}
+
+/// `output-scale`: a fixed number, or `"auto"` to use the scale of the output under the pointer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputScale {
+    Fixed(f64),
+    Auto,
+}
+
+impl FromStr for OutputScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        let value: f64 = s
+            .parse()
+            .map_err(|_| format!("expected a number or `auto`, got `{s}`"))?;
+        if value <= 0.0 {
+            return Err(format!("output scale `{s}` must be positive"));
+        }
+        Ok(Self::Fixed(value))
+    }
+}
+
+/// Numbers are taken as they are, strings are parsed with the unit
+fn decode_with_unit<T: FromStr<Err = String>, S: ErrorSpan>(
+    value: &Spanned<Literal, S>,
+    ctx: &mut Context<S>,
+    number: fn(f64) -> T,
+) -> Result<T, DecodeError<S>> {
+    match &**value {
+        Literal::Int(_) => i64::raw_decode(value, ctx).map(|v| number(v as f64)),
+        Literal::Decimal(_) => f64::raw_decode(value, ctx).map(number),
+        Literal::String(s) => s.parse().map_err(|e| DecodeError::conversion(value, e)),
+        _ => f64::raw_decode(value, ctx).map(number),
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for Distance {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <f64 as DecodeScalar<S>>::type_check(type_name, ctx);
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        decode_with_unit(value, ctx, Self::Units)
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for Acceleration {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <f64 as DecodeScalar<S>>::type_check(type_name, ctx);
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        decode_with_unit(value, ctx, Self::Factor)
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for OutputScale {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <f64 as DecodeScalar<S>>::type_check(type_name, ctx);
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        decode_with_unit(value, ctx, Self::Fixed)
+    }
+}
@@ -1,92 +1,481 @@
+use knuffel::{Decode, DecodeScalar};
 use miette::Result;
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use regex::Regex;
-use std::process::Command;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
 use std::thread;
 use threadpool::ThreadPool;
 
-static REGEX_DELTA_X: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_x").unwrap());
-static REGEX_DELTA_Y: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_y").unwrap());
-static REGEX_SCALE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$scale").unwrap());
-static REGEX_DELTA_ANGLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_angle").unwrap());
+use crate::failures;
+use crate::history::{self, CommandStatus, EntryId};
+use crate::trace::trace_event;
+
+static REGEX_DELTA_X: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_x\b").unwrap());
+static REGEX_DELTA_Y: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_y\b").unwrap());
+static REGEX_SCALE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$scale\b").unwrap());
+static REGEX_DELTA_ANGLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$delta_angle\b").unwrap());
+static REGEX_ANGLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$angle\b").unwrap());
+static REGEX_VELOCITY_X: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$velocity_x\b").unwrap());
+static REGEX_VELOCITY_Y: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$velocity_y\b").unwrap());
+static REGEX_DIRECTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$direction\b").unwrap());
+static REGEX_SCALE_RATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$scale_rate\b").unwrap());
+static REGEX_FINGERS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$fingers\b").unwrap());
+static REGEX_GESTURE_TYPE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$gesture_type\b").unwrap());
+static REGEX_ELAPSED_MS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$elapsed_ms\b").unwrap());
+
+static REGEX_USER_VAR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$([A-Za-z_][A-Za-z0-9_-]*)").unwrap());
+static REGEX_ENV_VAR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))").unwrap()
+});
+
+/// Variables filled in when a command runs, `$x $y $w $h $geometry` only by `@screenshot`
+pub const COMMAND_VARIABLES: [&str; 17] = [
+    "delta_x",
+    "delta_y",
+    "scale",
+    "delta_angle",
+    "angle",
+    "velocity_x",
+    "velocity_y",
+    "direction",
+    "scale_rate",
+    "fingers",
+    "gesture_type",
+    "elapsed_ms",
+    "x",
+    "y",
+    "w",
+    "h",
+    "geometry",
+];
 
 // Thread pool with 4 workers to handle command execution
 static THREAD_POOL: Lazy<ThreadPool> = Lazy::new(|| ThreadPool::new(4));
 const COMMAND_QUEUE_CAPACITY: usize = 256;
 
-static COMMAND_SENDER: Lazy<SyncSender<String>> = Lazy::new(|| {
+/// Commands queued or running, their peak, and update commands dropped because the queue was full
+static PENDING_COMMANDS: AtomicUsize = AtomicUsize::new(0);
+static PEAK_PENDING_COMMANDS: AtomicUsize = AtomicUsize::new(0);
+static DROPPED_UPDATES: AtomicUsize = AtomicUsize::new(0);
+/// Set by `gestures stress --dry-run` and `gestures start --dry-run`: commands go through the
+/// queue but aren't run
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+/// Log every command a dry run skips, for `gestures start --dry-run`
+static LOG_DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommandStats {
+    pub pending: usize,
+    pub peak_pending: usize,
+    pub dropped_updates: usize,
+}
+
+pub fn command_stats() -> CommandStats {
+    CommandStats {
+        pending: PENDING_COMMANDS.load(Ordering::Relaxed),
+        peak_pending: PEAK_PENDING_COMMANDS.load(Ordering::Relaxed),
+        dropped_updates: DROPPED_UPDATES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn set_dry_run(dry_run: bool, log_skipped: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+    LOG_DRY_RUN.store(dry_run && log_skipped, Ordering::Relaxed);
+}
+
+/// Whether `action` is skipped by a dry run, logged whatever the log level when asked to
+pub fn skipped_by_dry_run(action: fmt::Arguments) -> bool {
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        return false;
+    }
+    if LOG_DRY_RUN.load(Ordering::Relaxed) {
+        crate::logs::log_rule(log::Level::Info, format_args!("dry run: {}", action));
+    }
+    true
+}
+
+/// `stdin=` of a rule
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Inherit,
+    Null,
+}
+
+/// `stdout=`/`stderr=` of a rule: `inherit`, `null` or a file the output is appended to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Inherit,
+    Null,
+    File(PathBuf),
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(Self::Inherit),
+            "null" => Ok(Self::Null),
+            "" => Err("expected `inherit`, `null` or a file path".to_string()),
+            path => Ok(Self::File(expand_path(path))),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inherit => f.write_str("inherit"),
+            Self::Null => f.write_str("null"),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Output {
+    fn stdio(&self) -> io::Result<Stdio> {
+        Ok(match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Null => Stdio::null(),
+            Self::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?
+                .into(),
+        })
+    }
+}
+
+/// How a rule's commands are spawned, unset options inherit from the daemon
+#[derive(Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpawnOptions {
+    /// Working directory, `~` and environment variables are expanded
+    #[knuffel(property)]
+    pub cwd: Option<String>,
+    #[knuffel(property)]
+    pub stdin: Option<Input>,
+    #[knuffel(property, str)]
+    pub stdout: Option<Output>,
+    #[knuffel(property, str)]
+    pub stderr: Option<Output>,
+    /// Invocations of the same command queued or running at once, further ones are skipped
+    #[knuffel(property)]
+    pub max_concurrent: Option<u32>,
+}
+
+impl SpawnOptions {
+    pub fn apply(&self, command: &mut Command) -> io::Result<()> {
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(expand_path(cwd));
+        }
+        if self.stdin == Some(Input::Null) {
+            command.stdin(Stdio::null());
+        }
+        if let Some(stdout) = &self.stdout {
+            command.stdout(stdout.stdio()?);
+        }
+        if let Some(stderr) = &self.stderr {
+            command.stderr(stderr.stdio()?);
+        }
+        Ok(())
+    }
+}
+
+/// A path with `~` and `$NAME`/`${NAME}` of the environment expanded, so a config can be shared
+/// between machines with different home directories
+pub fn expand_path(path: &str) -> PathBuf {
+    PathBuf::from(expand_path_with(path, |name| std::env::var(name).ok()))
+}
+
+/// `expand_path` with variables looked up by `lookup`, unset ones are kept as written
+pub fn expand_path_with(path: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let path = match (path.strip_prefix('~'), lookup("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => home + rest,
+        _ => path.to_string(),
+    };
+    REGEX_ENV_VAR
+        .replace_all(&path, |caps: &regex::Captures| {
+            let name = caps
+                .get(1)
+                .or_else(|| caps.get(2))
+                .map_or("", |m| m.as_str());
+            lookup(name).unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Invocations in flight per command with `max-concurrent`, keyed by the unrendered command
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, u32>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// One in-flight invocation of a `max-concurrent` command, given back when dropped
+#[derive(Debug)]
+struct ConcurrencySlot(String);
+
+impl ConcurrencySlot {
+    /// `None` while `max` invocations of `command` are still queued or running
+    fn acquire(command: &str, max: u32) -> Option<Self> {
+        let mut in_flight = IN_FLIGHT.lock();
+        let count = in_flight.entry(command.to_string()).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(Self(command.to_string()))
+    }
+}
+
+impl Drop for ConcurrencySlot {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock();
+        if let Some(count) = in_flight.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// A command waiting for a pool thread
+struct Job {
+    /// The command as written in the config, failures are counted per template
+    template: String,
+    command: String,
+    spawn: SpawnOptions,
+    /// `max-concurrent` slot, released when the command exits
+    slot: Option<ConcurrencySlot>,
+    /// History entry of the rule that fired, told the exit status
+    entry: Option<EntryId>,
+}
+
+static COMMAND_SENDER: Lazy<SyncSender<Job>> = Lazy::new(|| {
     let (tx, rx) = sync_channel(COMMAND_QUEUE_CAPACITY);
     thread::spawn(move || command_dispatch_loop(rx));
     tx
 });
 
-fn command_dispatch_loop(rx: Receiver<String>) {
-    while let Ok(args) = rx.recv() {
+fn command_dispatch_loop(rx: Receiver<Job>) {
+    while let Ok(job) = rx.recv() {
         THREAD_POOL.execute(move || {
-            log::debug!("{:?}", &args);
-            match Command::new("sh").arg("-c").arg(&args).status() {
-                Ok(status) => {
-                    if !status.success() {
-                        log::warn!(
-                            "Command exited with non-zero status '{}': {:?}",
-                            args,
-                            status
-                        );
-                    }
-                }
-                Err(e) => {
-                    log::error!("Failed to execute command '{}': {}", &args, e);
-                }
+            let status = run_command(&job.command, &job.spawn);
+            failures::record(&job.template, &job.command, &status);
+            if let Some(entry) = job.entry {
+                history::command_finished(entry, status);
             }
+            PENDING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
+            drop(job.slot);
         });
     }
 }
 
-fn render_command(args: &str, dx: f64, dy: f64, da: f64, scale: f64) -> Option<String> {
+fn run_command(args: &str, spawn: &SpawnOptions) -> CommandStatus {
+    log::debug!("{:?}", &args);
+    if skipped_by_dry_run(format_args!("{}", args)) {
+        return CommandStatus::Skipped("dry run");
+    }
+    if let Some(action) = args.strip_prefix('@') {
+        return match crate::builtin::run(action) {
+            Ok(()) => CommandStatus::Exited(0),
+            Err(e) => {
+                log::error!("Built-in action '{}' failed: {}", args, e);
+                CommandStatus::Failed(e.to_string())
+            }
+        };
+    }
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(args);
+    if let Err(e) = spawn.apply(&mut command) {
+        log::error!("Failed to set up command '{}': {}", args, e);
+        return CommandStatus::Failed(e.to_string());
+    }
+    match command.status() {
+        // Non-zero exits are logged by `failures::record`
+        Ok(status) => status.into(),
+        Err(e) => {
+            log::error!("Failed to execute command '{}': {}", args, e);
+            CommandStatus::Failed(e.to_string())
+        }
+    }
+}
+
+/// Values substituted into `$variable` placeholders of commands
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CommandVars {
+    pub delta_x: f64,
+    pub delta_y: f64,
+    pub delta_angle: f64,
+    /// Total rotation of a rotate gesture in degrees, clockwise positive
+    pub angle: f64,
+    pub scale: f64,
+    /// Instantaneous swipe velocity in libinput units per second
+    pub velocity_x: f64,
+    pub velocity_y: f64,
+    /// Change of pinch scale per second
+    pub scale_rate: f64,
+    /// Direction of the gesture: `n`, `se`... for swipes, `in` or `out` for pinches, `cw` or
+    /// `ccw` for rotations
+    pub direction: &'static str,
+    pub fingers: i32,
+    /// `swipe`, `pinch`, `rotate` or `hold`
+    pub gesture_type: &'static str,
+    /// Time since the gesture began, up to its last event
+    pub elapsed_ms: u64,
+}
+
+pub fn render_command(args: &str, vars: &CommandVars) -> Option<String> {
     if args.is_empty() {
         return None;
     }
 
-    let args = REGEX_DELTA_Y.replace_all(args, format!("{:.2}", dy));
-    let args = REGEX_DELTA_X.replace_all(&args, format!("{:.2}", dx));
-    let args = REGEX_SCALE.replace_all(&args, format!("{:.2}", scale));
-    let args = REGEX_DELTA_ANGLE.replace_all(&args, format!("{:.2}", da));
-    Some(args.to_string())
+    let args = REGEX_DELTA_Y.replace_all(args, format!("{:.2}", vars.delta_y));
+    let args = REGEX_DELTA_X.replace_all(&args, format!("{:.2}", vars.delta_x));
+    let args = REGEX_SCALE.replace_all(&args, format!("{:.2}", vars.scale));
+    let args = REGEX_DELTA_ANGLE.replace_all(&args, format!("{:.2}", vars.delta_angle));
+    let args = REGEX_ANGLE.replace_all(&args, format!("{:.2}", vars.angle));
+    let args = REGEX_VELOCITY_X.replace_all(&args, format!("{:.2}", vars.velocity_x));
+    let args = REGEX_VELOCITY_Y.replace_all(&args, format!("{:.2}", vars.velocity_y));
+    let args = REGEX_SCALE_RATE.replace_all(&args, format!("{:.2}", vars.scale_rate));
+    let args = REGEX_ELAPSED_MS.replace_all(&args, vars.elapsed_ms.to_string());
+    // Outside a gesture these are left to the shell, as is `$direction` for holds
+    let args = if vars.fingers > 0 {
+        REGEX_FINGERS.replace_all(&args, vars.fingers.to_string())
+    } else {
+        args
+    };
+    let args = if vars.gesture_type.is_empty() {
+        args
+    } else {
+        REGEX_GESTURE_TYPE.replace_all(&args, vars.gesture_type)
+    };
+    if vars.direction.is_empty() {
+        return Some(args.to_string());
+    }
+    Some(
+        REGEX_DIRECTION
+            .replace_all(&args, vars.direction)
+            .to_string(),
+    )
 }
 
-fn enqueue_command(args: String, drop_when_full: bool) -> Result<()> {
-    match COMMAND_SENDER.try_send(args) {
+/// Replace `$name` with user variables from the config, unknown names (e.g. `$HOME`) are kept
+pub fn expand_vars(cmd: &str, vars: &HashMap<String, String>) -> String {
+    REGEX_USER_VAR
+        .replace_all(cmd, |caps: &regex::Captures| {
+            vars.get(&caps[1])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Names of the `$name` references in a command
+pub fn referenced_vars(cmd: &str) -> impl Iterator<Item = &str> {
+    REGEX_USER_VAR
+        .captures_iter(cmd)
+        .filter_map(|caps| caps.get(1))
+        .map(|m| m.as_str())
+}
+
+fn enqueue_command(
+    template: &str,
+    args: String,
+    spawn: &SpawnOptions,
+    drop_when_full: bool,
+    entry: Option<EntryId>,
+) -> Result<()> {
+    if let Some(entry) = entry {
+        history::command_queued(entry, &args);
+    }
+    let slot = match spawn.max_concurrent {
+        Some(max) => match ConcurrencySlot::acquire(template, max) {
+            Some(slot) => Some(slot),
+            None => {
+                log::debug!("{} invocations still running, skipping: {}", max, args);
+                trace_event!("command skipped, max-concurrent={} reached: {}", max, args);
+                if let Some(entry) = entry {
+                    history::command_finished(entry, CommandStatus::Skipped("max-concurrent"));
+                }
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if !drop_when_full {
+        trace_event!("command queued: {}", args);
+    }
+    let pending = PENDING_COMMANDS.fetch_add(1, Ordering::Relaxed) + 1;
+    PEAK_PENDING_COMMANDS.fetch_max(pending, Ordering::Relaxed);
+    let job = Job {
+        template: template.to_string(),
+        command: args,
+        spawn: spawn.clone(),
+        slot,
+        entry,
+    };
+    match COMMAND_SENDER.try_send(job) {
         Ok(()) => Ok(()),
-        Err(TrySendError::Full(cmd)) if drop_when_full => {
-            log::debug!("Command queue is full, dropping update command: {}", cmd);
+        Err(TrySendError::Full(job)) if drop_when_full => {
+            PENDING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
+            DROPPED_UPDATES.fetch_add(1, Ordering::Relaxed);
+            log::debug!(
+                "Command queue is full, dropping update command: {}",
+                job.command
+            );
+            trace_event!("update command dropped, queue full: {}", job.command);
             Ok(())
         }
-        Err(TrySendError::Full(cmd)) => COMMAND_SENDER
-            .send(cmd)
+        Err(TrySendError::Full(job)) => COMMAND_SENDER
+            .send(job)
             .map_err(|e| miette::miette!("Failed to enqueue command: {}", e)),
         Err(TrySendError::Disconnected(_)) => {
+            PENDING_COMMANDS.fetch_sub(1, Ordering::Relaxed);
             Err(miette::miette!("Command queue dispatcher disconnected"))
         }
     }
 }
 
-pub fn exec_command_from_string(args: &str, dx: f64, dy: f64, da: f64, scale: f64) -> Result<()> {
-    if let Some(args) = render_command(args, dx, dy, da, scale) {
-        enqueue_command(args, false)?;
+pub fn exec_command_from_string(
+    args: &str,
+    vars: &CommandVars,
+    spawn: &SpawnOptions,
+) -> Result<()> {
+    if let Some(rendered) = render_command(args, vars) {
+        enqueue_command(args, rendered, spawn, false, None)?;
+    }
+    Ok(())
+}
+
+/// Run the command of a rule that fired, its exit status is kept in the rule's history `entry`
+pub fn exec_fired_command(
+    args: &str,
+    vars: &CommandVars,
+    spawn: &SpawnOptions,
+    entry: EntryId,
+) -> Result<()> {
+    if let Some(rendered) = render_command(args, vars) {
+        enqueue_command(args, rendered, spawn, false, Some(entry))?;
     }
     Ok(())
 }
 
 pub fn exec_update_command_from_string(
     args: &str,
-    dx: f64,
-    dy: f64,
-    da: f64,
-    scale: f64,
+    vars: &CommandVars,
+    spawn: &SpawnOptions,
 ) -> Result<()> {
-    if let Some(args) = render_command(args, dx, dy, da, scale) {
-        enqueue_command(args, true)?;
+    if let Some(rendered) = render_command(args, vars) {
+        enqueue_command(args, rendered, spawn, true, None)?;
     }
     Ok(())
 }
@@ -0,0 +1,112 @@
+//! Native Wayland pointer through wlr-virtual-pointer-unstable-v1, so dragging works on wlroots
+//! based compositors without ydotoold
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+use crate::compositor::{connect_registry, message, roundtrip, wire_string, Global};
+use crate::compositor::{VIRTUAL_POINTER_MANAGER, WL_REGISTRY};
+
+/// Ids after wl_display (1), the registry (2) and the probe's sync callback (3)
+const MANAGER: u32 = 4;
+const POINTER: u32 = 5;
+const CREATE_CALLBACK: u32 = 6;
+
+/// zwlr_virtual_pointer_v1 requests
+const MOTION: u32 = 0;
+const BUTTON: u32 = 2;
+const FRAME: u32 = 4;
+
+/// linux/input-event-codes.h
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+
+/// Evdev code of an X11 style button number (1 left, 2 middle, 3 right)
+fn evdev_button(button: i32) -> u32 {
+    match button {
+        2 => BTN_MIDDLE,
+        3 => BTN_RIGHT,
+        _ => BTN_LEFT,
+    }
+}
+
+/// Steps of a pixel in a wl_fixed_t, 24.8 fixed point
+pub const SUBPIXEL_STEPS: i32 = 256;
+
+#[derive(Debug)]
+pub struct VirtualPointer {
+    stream: UnixStream,
+    started: Instant,
+}
+
+impl VirtualPointer {
+    /// Bind the manager and create a pointer on the default seat
+    pub fn connect() -> Result<Self, String> {
+        let (stream, globals) = connect_registry()?;
+        Self::create(stream, &globals)
+    }
+
+    /// Create the pointer on `stream`, whose registry announced `globals`
+    pub fn create(mut stream: UnixStream, globals: &[Global]) -> Result<Self, String> {
+        let manager = globals
+            .iter()
+            .find(|g| g.interface == VIRTUAL_POINTER_MANAGER)
+            .ok_or_else(|| format!("compositor does not offer {}", VIRTUAL_POINTER_MANAGER))?;
+
+        // wl_registry.bind(name, interface, version, id), version 1 is all that is used
+        let mut bind = manager.name.to_ne_bytes().to_vec();
+        bind.extend(wire_string(VIRTUAL_POINTER_MANAGER));
+        bind.extend_from_slice(&1u32.to_ne_bytes());
+        bind.extend_from_slice(&MANAGER.to_ne_bytes());
+        // create_virtual_pointer(seat: null, id)
+        let mut create = 0u32.to_ne_bytes().to_vec();
+        create.extend_from_slice(&POINTER.to_ne_bytes());
+        stream
+            .write_all(&message(WL_REGISTRY, 0, &bind))
+            .and_then(|_| stream.write_all(&message(MANAGER, 0, &create)))
+            .map_err(|e| e.to_string())?;
+
+        // A refused pointer shows up as a protocol error before the roundtrip completes
+        roundtrip(&mut stream, CREATE_CALLBACK, |_, _, _| {})?;
+        Ok(Self {
+            stream,
+            started: Instant::now(),
+        })
+    }
+
+    /// Event timestamp in milliseconds
+    fn time(&self) -> [u8; 4] {
+        (self.started.elapsed().as_millis() as u32).to_ne_bytes()
+    }
+
+    /// Send `request` followed by a frame so the compositor applies it at once
+    fn send(&mut self, opcode: u32, args: &[u8]) -> std::io::Result<()> {
+        let mut request = message(POINTER, opcode, args);
+        request.extend(message(POINTER, FRAME, &[]));
+        self.stream.write_all(&request)
+    }
+
+    pub fn motion(&mut self, dx: i32, dy: i32) -> std::io::Result<()> {
+        self.fine_motion(
+            dx.saturating_mul(SUBPIXEL_STEPS),
+            dy.saturating_mul(SUBPIXEL_STEPS),
+        )
+    }
+
+    /// Move by `dx`/`dy` in `1 / SUBPIXEL_STEPS` of a pixel, sent as is in wl_fixed_t
+    pub fn fine_motion(&mut self, dx: i32, dy: i32) -> std::io::Result<()> {
+        let mut args = self.time().to_vec();
+        args.extend_from_slice(&dx.to_ne_bytes());
+        args.extend_from_slice(&dy.to_ne_bytes());
+        self.send(MOTION, &args)
+    }
+
+    pub fn button(&mut self, button: i32, pressed: bool) -> std::io::Result<()> {
+        let mut args = self.time().to_vec();
+        args.extend_from_slice(&evdev_button(button).to_ne_bytes());
+        args.extend_from_slice(&u32::from(pressed).to_ne_bytes());
+        self.send(BUTTON, &args)
+    }
+}
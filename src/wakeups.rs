@@ -0,0 +1,171 @@
+//! `--profile-wakeups`: account the event loop's poll wakeups, libinput dispatch batches and the
+//! time spent per subsystem, to find what keeps the daemon busy while idle
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the running totals are logged
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Why `poll` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wakeup {
+    /// The libinput fd became readable
+    Input,
+    /// The poll timeout expired, e.g. the 100ms tick or a hold deadline
+    Timeout,
+    /// A signal interrupted the poll
+    Interrupted,
+}
+
+/// Where the event loop spends its time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// Blocked in `poll`, the loop is idle
+    Poll,
+    /// `libinput_dispatch`, reading the devices
+    Dispatch,
+    /// Matching events against rules and running their actions
+    Gestures,
+    /// Firing `trigger="timeout"` hold actions
+    HoldTimers,
+}
+
+impl Subsystem {
+    const ALL: [Subsystem; 4] = [Self::Poll, Self::Dispatch, Self::Gestures, Self::HoldTimers];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Poll => "poll",
+            Self::Dispatch => "dispatch",
+            Self::Gestures => "gestures",
+            Self::HoldTimers => "hold-timers",
+        }
+    }
+}
+
+/// Totals since the event loop started
+#[derive(Debug, Clone)]
+pub struct WakeupProfile {
+    started: Instant,
+    last_report: Instant,
+    wakeups: [u64; 3],
+    /// Dispatches that yielded at least one event, and the events they yielded
+    batches: u64,
+    events: u64,
+    time: [Duration; 4],
+}
+
+impl WakeupProfile {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            last_report: now,
+            wakeups: [0; 3],
+            batches: 0,
+            events: 0,
+            time: [Duration::ZERO; 4],
+        }
+    }
+
+    pub fn wakeup(&mut self, wakeup: Wakeup) {
+        self.wakeups[wakeup as usize] += 1;
+    }
+
+    pub fn spent(&mut self, subsystem: Subsystem, time: Duration) {
+        self.time[subsystem as usize] += time;
+    }
+
+    /// One libinput dispatch and the number of events read from it
+    pub fn batch(&mut self, events: usize) {
+        if events > 0 {
+            self.batches += 1;
+            self.events += events as u64;
+        }
+    }
+
+    /// Log the totals once per report interval
+    pub fn report_if_due(&mut self, now: Instant) {
+        if now.duration_since(self.last_report) >= REPORT_INTERVAL {
+            self.last_report = now;
+            log::info!("{}", self.summary(now));
+        }
+    }
+
+    pub fn summary(&self, now: Instant) -> Summary<'_> {
+        Summary {
+            profile: self,
+            elapsed: now.duration_since(self.started),
+        }
+    }
+}
+
+/// Human readable totals over `elapsed`
+pub struct Summary<'a> {
+    profile: &'a WakeupProfile,
+    elapsed: Duration,
+}
+
+fn ms(time: Duration) -> String {
+    format!("{:.1}ms", time.as_secs_f64() * 1000.0)
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p = self.profile;
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let [input, timeout, interrupted] = p.wakeups;
+        let total: u64 = p.wakeups.iter().sum();
+        write!(
+            f,
+            "Wakeups over {:.0}s: {} ({:.1}/s) input={} timeout={} interrupted={}; ",
+            self.elapsed.as_secs_f64(),
+            total,
+            total as f64 / seconds,
+            input,
+            timeout,
+            interrupted
+        )?;
+        write!(f, "{} dispatch batches", p.batches)?;
+        if p.batches > 0 {
+            write!(
+                f,
+                " ({:.1} events/batch)",
+                p.events as f64 / p.batches as f64
+            )?;
+        }
+        let busy: Duration = Subsystem::ALL[1..]
+            .iter()
+            .map(|&s| p.time[s as usize])
+            .sum();
+        write!(f, "; busy {} (", ms(busy))?;
+        for (i, subsystem) in Subsystem::ALL[1..].iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(
+                f,
+                "{}={}",
+                subsystem.name(),
+                ms(p.time[*subsystem as usize])
+            )?;
+        }
+        write!(
+            f,
+            "), {}={}",
+            Subsystem::Poll.name(),
+            ms(p.time[Subsystem::Poll as usize])
+        )
+    }
+}
@@ -0,0 +1,136 @@
+//! Reload the configuration when its file changes, enabled with `start --watch-config` or a
+//! top-level `watch true` node
+
+use std::collections::HashMap;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use miette::{miette, Result};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+use parking_lot::RwLock;
+
+use crate::config::{Config, SYSTEM_CONFIG};
+use crate::ipc::reload_config;
+
+/// Editors write a file in several steps, wait for them to settle before reloading
+const SETTLE_DELAY: Duration = Duration::from_millis(200);
+
+/// Files that make up the configuration: the explicit path or the default locations, the system
+/// layer and the files they include
+pub fn config_files(config_path: Option<&Path>) -> Vec<PathBuf> {
+    let mut files = match config_path {
+        Some(path) => vec![path.to_path_buf()],
+        None => Config::get_config_home().map_or_else(
+            |_| Vec::new(),
+            |home| {
+                ["gestures.kdl", "gestures/gestures.kdl"]
+                    .iter()
+                    .map(|f| Path::new(&home).join(f))
+                    .collect()
+            },
+        ),
+    };
+    files.push(PathBuf::from(SYSTEM_CONFIG));
+    let included: Vec<PathBuf> = files
+        .iter()
+        .flat_map(|f| Config::included_files(f))
+        .collect();
+    for file in included {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Directories are watched rather than the files, editors often replace a file by renaming a new
+/// one over it, which a watch on the old inode never sees
+fn watch_directories(inotify: &Inotify, files: &[PathBuf]) -> HashMap<WatchDescriptor, PathBuf> {
+    let flags = AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE;
+    let mut dirs = HashMap::new();
+    for dir in files.iter().filter_map(|f| f.parent()) {
+        if dirs.values().any(|d: &PathBuf| d == dir) {
+            continue;
+        }
+        match inotify.add_watch(dir, flags) {
+            Ok(wd) => {
+                dirs.insert(wd, dir.to_path_buf());
+            }
+            Err(e) => log::debug!("Not watching {}: {}", dir.display(), e),
+        }
+    }
+    dirs
+}
+
+/// Whether `event` touches one of `files`
+fn touches(
+    event: &InotifyEvent,
+    dirs: &HashMap<WatchDescriptor, PathBuf>,
+    files: &[PathBuf],
+) -> bool {
+    let (Some(dir), Some(name)) = (dirs.get(&event.wd), &event.name) else {
+        return false;
+    };
+    let path = dir.join(name);
+    files.contains(&path)
+}
+
+pub fn spawn(config: Arc<RwLock<Config>>, config_path: Option<PathBuf>) -> Result<()> {
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)
+        .map_err(|e| miette!("Could not watch the configuration: {}", e))?;
+    let files = config_files(config_path.as_deref());
+    let dirs = watch_directories(&inotify, &files);
+    if dirs.is_empty() {
+        return Err(miette!(
+            "None of the configuration directories can be watched"
+        ));
+    }
+    log::info!(
+        "Watching {} for changes",
+        files
+            .iter()
+            .map(|f| f.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    thread::spawn(move || loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => {
+                log::error!("Stopped watching the configuration: {}", e);
+                break;
+            }
+        };
+        if !events.iter().any(|e| touches(e, &dirs, &files)) {
+            continue;
+        }
+        // Swallow the rest of a multi-step save
+        loop {
+            let mut fds = [PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+            let timeout = PollTimeout::from(SETTLE_DELAY.as_millis() as u16);
+            match poll(&mut fds, timeout) {
+                Ok(n) if n > 0 => {
+                    let _ = inotify.read_events();
+                }
+                _ => break,
+            }
+        }
+        match reload_config(&config, config_path.as_deref()) {
+            Ok(()) => log::info!("Configuration changed, reloaded"),
+            Err(e) => log::error!(
+                "Configuration changed but is invalid, keeping the old one: {}",
+                e
+            ),
+        }
+    });
+    Ok(())
+}
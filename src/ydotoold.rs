@@ -0,0 +1,91 @@
+//! ydotoold's socket spoken directly: the daemon writes every `input_event` datagram it receives to
+//! its uinput device, so a drag doesn't spawn a `ydotool` process per event
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use crate::uinput::{
+    evdev_button, input_event, EV_KEY, EV_REL, EV_SYN, KEY_LEFTCTRL, REL_WHEEL, REL_X, REL_Y,
+    SYN_REPORT,
+};
+
+/// Where ydotool 1.x looks for the daemon: `$YDOTOOL_SOCKET`, else in the runtime directory, else
+/// in /tmp
+pub fn socket_candidates(
+    ydotool_socket: Option<OsString>,
+    runtime_dir: Option<OsString>,
+) -> Vec<PathBuf> {
+    if let Some(socket) = ydotool_socket {
+        return vec![PathBuf::from(socket)];
+    }
+    runtime_dir
+        .map(|dir| PathBuf::from(dir).join(".ydotool_socket"))
+        .into_iter()
+        .chain([PathBuf::from("/tmp/.ydotool_socket")])
+        .collect()
+}
+
+/// Pointer events sent to a running ydotoold
+#[derive(Debug)]
+pub struct YdotooldPointer {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl YdotooldPointer {
+    pub fn connect() -> io::Result<Self> {
+        let candidates = socket_candidates(
+            env::var_os("YDOTOOL_SOCKET"),
+            env::var_os("XDG_RUNTIME_DIR"),
+        );
+        let mut last_error = io::Error::from(io::ErrorKind::NotFound);
+        for path in candidates {
+            match Self::connect_to(path) {
+                Ok(pointer) => return Ok(pointer),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn connect_to(path: PathBuf) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        match socket.connect(&path) {
+            Ok(()) => Ok(Self { socket, path }),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            )),
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// One datagram per event, followed by a SYN_REPORT, like `ydotool` itself sends them
+    fn emit(&mut self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        for &(type_, code, value) in events.iter().chain(&[(EV_SYN, SYN_REPORT, 0)]) {
+            self.socket.send(&input_event(type_, code, value))?;
+        }
+        Ok(())
+    }
+
+    pub fn motion(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        self.emit(&[(EV_REL, REL_X, dx), (EV_REL, REL_Y, dy)])
+    }
+
+    pub fn button(&mut self, button: i32, pressed: bool) -> io::Result<()> {
+        self.emit(&[(EV_KEY, evdev_button(button), i32::from(pressed))])
+    }
+
+    /// Ctrl + wheel, positive `notches` zoom in
+    pub fn zoom(&mut self, notches: i32) -> io::Result<()> {
+        self.emit(&[(EV_KEY, KEY_LEFTCTRL, 1)])?;
+        self.emit(&[(EV_REL, REL_WHEEL, notches)])?;
+        self.emit(&[(EV_KEY, KEY_LEFTCTRL, 0)])
+    }
+}